@@ -0,0 +1,295 @@
+use std::{
+    fmt::Display,
+    io::{self, BufRead, IsTerminal, Read, Write},
+};
+
+#[derive(Clone)]
+pub struct Credentials {
+    pub username: String,
+    pub password: String,
+}
+
+#[derive(Debug)]
+pub enum AuthError {
+    NotATerminal(String),
+    ReadPassword(io::Error),
+    ReadToken(String, io::Error),
+    EmptyToken,
+    NoPassphrase(String),
+}
+
+impl Display for AuthError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            AuthError::NotATerminal(user) => write!(
+                f,
+                "No password given for user '{user}' and stdin is not a terminal. Use user:password or --password-stdin"
+            ),
+            AuthError::ReadPassword(e) => write!(f, "Unable to read password: {e}"),
+            AuthError::ReadToken(file, e) => write!(f, "Unable to read bearer token from {file}: {e}"),
+            AuthError::EmptyToken => write!(f, "The bearer token is empty"),
+            AuthError::NoPassphrase(what) => write!(
+                f,
+                "No pass phrase given for {what} and stdin is not a terminal. Use --pass"
+            ),
+        }
+    }
+}
+
+/// Splits a `user[:password]` value, asking for the password when it is missing.
+///
+/// With `password_stdin` the password is the first line of stdin, otherwise the
+/// user is prompted on the terminal with echo disabled. `kind` names what the
+/// credentials are for in the prompt, e.g. "host" or "proxy".
+pub fn credentials(user: &str, kind: &str, password_stdin: bool) -> Result<Credentials, AuthError> {
+    if let Some((username, password)) = user.split_once(':') {
+        return Ok(Credentials {
+            username: username.to_string(),
+            password: password.to_string(),
+        });
+    }
+
+    let password = if password_stdin {
+        read_line()?
+    } else if io::stdin().is_terminal() {
+        prompt_password(&format!("Enter {kind} password for user '{user}':"))?
+    } else {
+        return Err(AuthError::NotATerminal(user.to_string()));
+    };
+
+    Ok(Credentials {
+        username: user.to_string(),
+        password,
+    })
+}
+
+/// Resolves the `--oauth2-bearer` value, falling back to `CURL_OAUTH2_BEARER`.
+///
+/// `@file` reads the token from a file and `@-` from stdin, so it never has
+/// to appear on the command line.
+pub fn bearer_token(arg: Option<&str>) -> Result<Option<String>, AuthError> {
+    let token = match arg {
+        Some(token) => token.to_string(),
+        None => match std::env::var("CURL_OAUTH2_BEARER") {
+            Ok(token) => token,
+            Err(_) => return Ok(None),
+        },
+    };
+
+    let token = match token.strip_prefix('@') {
+        Some("-") => {
+            let mut token = String::new();
+            io::stdin()
+                .lock()
+                .read_to_string(&mut token)
+                .map_err(|e| AuthError::ReadToken("stdin".to_string(), e))?;
+            token
+        }
+        Some(file) => {
+            std::fs::read_to_string(file).map_err(|e| AuthError::ReadToken(file.to_string(), e))?
+        }
+        None => token,
+    };
+
+    let token = token.trim();
+    if token.is_empty() {
+        return Err(AuthError::EmptyToken);
+    }
+
+    Ok(Some(token.to_string()))
+}
+
+/// Asks for the pass phrase that unlocks `what`, a key or certificate file,
+/// on the terminal.
+pub fn passphrase(what: &str) -> Result<String, AuthError> {
+    if !io::stdin().is_terminal() {
+        return Err(AuthError::NoPassphrase(what.to_string()));
+    }
+
+    prompt_password(&format!("Enter PEM pass phrase for {what}:"))
+}
+
+fn read_line() -> Result<String, AuthError> {
+    let mut line = String::new();
+    io::stdin()
+        .lock()
+        .read_line(&mut line)
+        .map_err(AuthError::ReadPassword)?;
+
+    Ok(line.trim_end_matches(['\r', '\n']).to_string())
+}
+
+fn prompt_password(prompt: &str) -> Result<String, AuthError> {
+    eprint!("{prompt}");
+    io::stderr().flush().map_err(AuthError::ReadPassword)?;
+
+    let echo = EchoGuard::disable();
+    let password = read_line();
+    drop(echo);
+
+    // The newline typed by the user was not echoed
+    eprintln!();
+
+    password
+}
+
+/// Turns terminal echo off for stdin until dropped.
+struct EchoGuard {
+    #[cfg(unix)]
+    original: Option<libc::termios>,
+}
+
+impl EchoGuard {
+    #[cfg(unix)]
+    fn disable() -> EchoGuard {
+        let mut term = std::mem::MaybeUninit::<libc::termios>::uninit();
+
+        // SAFETY: tcgetattr fully initializes term when it succeeds
+        let original = unsafe {
+            if libc::tcgetattr(libc::STDIN_FILENO, term.as_mut_ptr()) != 0 {
+                None
+            } else {
+                Some(term.assume_init())
+            }
+        };
+
+        if let Some(original) = original {
+            let mut silent = original;
+            silent.c_lflag &= !libc::ECHO;
+
+            // SAFETY: silent is a valid termios obtained from tcgetattr
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, &silent);
+            }
+        }
+
+        EchoGuard { original }
+    }
+
+    #[cfg(not(unix))]
+    fn disable() -> EchoGuard {
+        EchoGuard {}
+    }
+}
+
+impl Drop for EchoGuard {
+    fn drop(&mut self) {
+        #[cfg(unix)]
+        if let Some(original) = &self.original {
+            // SAFETY: original was obtained from tcgetattr on the same descriptor
+            unsafe {
+                libc::tcsetattr(libc::STDIN_FILENO, libc::TCSANOW, original);
+            }
+        }
+    }
+}
+
+/// One authentication scheme offered in a `WWW-Authenticate` header.
+pub struct Challenge {
+    pub scheme: String,
+    pub params: Vec<(String, String)>,
+}
+
+impl Challenge {
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Parses every challenge in a `WWW-Authenticate` value.
+///
+/// A single header may offer several schemes, e.g.
+/// `Basic realm="a", Digest realm="b", nonce="c"`, so a new challenge starts
+/// whenever a comma-separated item begins with a bare token.
+pub fn parse_challenges(value: &str) -> Vec<Challenge> {
+    let mut challenges: Vec<Challenge> = Vec::new();
+
+    for item in split_quoted(value, ',') {
+        let item = item.trim();
+        if item.is_empty() {
+            continue;
+        }
+
+        let param = match item.find(|c: char| c == '=' || c.is_whitespace()) {
+            // "Scheme" or "Scheme param=value" starts a new challenge
+            Some(i) if !item[i..].starts_with('=') => {
+                challenges.push(Challenge {
+                    scheme: item[..i].to_string(),
+                    params: Vec::new(),
+                });
+                item[i..].trim_start()
+            }
+            None => {
+                challenges.push(Challenge {
+                    scheme: item.to_string(),
+                    params: Vec::new(),
+                });
+                continue;
+            }
+            Some(_) => item,
+        };
+
+        if let (Some(challenge), Some((key, value))) =
+            (challenges.last_mut(), param.split_once('='))
+        {
+            challenge
+                .params
+                .push((key.trim().to_string(), unquote(value.trim())));
+        }
+    }
+
+    challenges
+}
+
+/// Splits `value` on `separator`, ignoring separators inside quoted strings.
+pub fn split_quoted(value: &str, separator: char) -> Vec<&str> {
+    let mut items = Vec::new();
+    let mut start = 0;
+    let mut quoted = false;
+    let mut escaped = false;
+
+    for (i, c) in value.char_indices() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' if quoted => escaped = true,
+            '"' => quoted = !quoted,
+            c if c == separator && !quoted => {
+                items.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    items.push(&value[start..]);
+
+    items
+}
+
+/// Strips the quotes and backslash escapes of a quoted string, returning
+/// tokens unchanged.
+pub fn unquote(value: &str) -> String {
+    match value.strip_prefix('"').and_then(|v| v.strip_suffix('"')) {
+        Some(inner) => {
+            let mut unquoted = String::with_capacity(inner.len());
+            let mut chars = inner.chars();
+            while let Some(c) = chars.next() {
+                if c == '\\' {
+                    if let Some(next) = chars.next() {
+                        unquoted.push(next);
+                    }
+                } else {
+                    unquoted.push(c);
+                }
+            }
+            unquoted
+        }
+        None => value.to_string(),
+    }
+}
+
+pub fn hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{b:02x}")).collect()
+}
@@ -0,0 +1,378 @@
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    io::Read,
+    path::{Path, PathBuf},
+};
+
+/// How deep -K files may name other -K files, so two that name each other
+/// stop with an error.
+const MAX_DEPTH: usize = 8;
+
+/// A -K file that cannot be read, or a line in it that is not a valid
+/// option.
+#[derive(Debug)]
+pub struct ConfigError {
+    file: String,
+    // Counted from 1, none when the file itself could not be read
+    line: Option<usize>,
+    reason: String,
+}
+
+impl Display for ConfigError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "{}:{}: {}", self.file, line, self.reason),
+            None => write!(
+                f,
+                "Cannot read the config file {}: {}",
+                self.file, self.reason
+            ),
+        }
+    }
+}
+
+/// Puts the options of the default .curlrc file and then of every -K file
+/// on the command line in front of it, as the arguments they stand for, so
+/// the ones given on the command line come later and win. `check` is given
+/// the arguments of each line, and an error it returns is reported with the
+/// file and line.
+///
+/// A line is an option and its value, with or without the dashes and
+/// separated by spaces, `=` or `:`, like `--header "X-Custom: 1"` or
+/// `max-time = 10` or `url = https://example.com`, and lines starting with
+/// `#` are comments.
+///
+/// A .curlrc that cannot be used is left out rather than stopping the
+/// invocation, and its error is returned along with the arguments to warn
+/// about. -q anywhere on the command line leaves it out too.
+pub fn with_files(
+    args: Vec<OsString>,
+    check: impl Fn(&[OsString]) -> Result<(), String>,
+) -> Result<(Vec<OsString>, Option<ConfigError>), ConfigError> {
+    let mut expanded: Vec<OsString> = args.iter().take(1).cloned().collect();
+
+    let mut rc_error = None;
+    if !disabled(&args) {
+        if let Some(rc) = default_file() {
+            let mut rc_args = Vec::new();
+            match read(&rc.to_string_lossy(), 0, &check, &mut rc_args) {
+                Ok(()) => expanded.extend(rc_args),
+                Err(e) => rc_error = Some(e),
+            }
+        }
+    }
+
+    for file in files(&args) {
+        read(&file, 0, &check, &mut expanded)?;
+    }
+    expanded.extend(args.into_iter().skip(1));
+
+    Ok((expanded, rc_error))
+}
+
+/// The .curlrc read without -K, the first that exists of the one in
+/// $CURL_HOME, in $XDG_CONFIG_HOME and in $HOME.
+fn default_file() -> Option<PathBuf> {
+    rc_in(std::env::var_os)
+}
+
+// The .curlrc of the first of the directories `var` gives that has one
+fn rc_in(var: impl Fn(&'static str) -> Option<OsString>) -> Option<PathBuf> {
+    ["CURL_HOME", "XDG_CONFIG_HOME", "HOME"]
+        .into_iter()
+        .filter_map(var)
+        .filter(|dir| !dir.is_empty())
+        .map(|dir| Path::new(&dir).join(".curlrc"))
+        .find(|rc| rc.is_file())
+}
+
+// Whether -q or --disable is on the command line, before any "--"
+fn disabled(args: &[OsString]) -> bool {
+    args.iter()
+        .skip(1)
+        .take_while(|arg| *arg != "--")
+        .any(|arg| arg == "-q" || arg == "--disable")
+}
+
+// The -K files named on the command line, in order
+fn files(args: &[OsString]) -> Vec<String> {
+    let mut files = Vec::new();
+    let mut args = args.iter().skip(1).map(|arg| arg.to_string_lossy());
+    while let Some(arg) = args.next() {
+        if arg == "--" {
+            break;
+        }
+        let file = if arg == "-K" || arg == "--config" {
+            args.next().map(|file| file.into_owned())
+        } else if let Some(file) = arg.strip_prefix("--config=") {
+            Some(file.to_string())
+        } else {
+            arg.strip_prefix("-K").map(str::to_string)
+        };
+        files.extend(file);
+    }
+
+    files
+}
+
+fn read(
+    file: &str,
+    depth: usize,
+    check: &impl Fn(&[OsString]) -> Result<(), String>,
+    expanded: &mut Vec<OsString>,
+) -> Result<(), ConfigError> {
+    let name = if file == "-" { "<stdin>" } else { file };
+    let error = |line: Option<usize>, reason: String| ConfigError {
+        file: name.to_string(),
+        line,
+        reason,
+    };
+
+    let mut text = String::new();
+    let result = if file == "-" {
+        std::io::stdin().read_to_string(&mut text).map(|_| ())
+    } else {
+        std::fs::read_to_string(file).map(|contents| text = contents)
+    };
+    result.map_err(|e| error(None, e.to_string()))?;
+
+    for (n, line) in text.lines().enumerate() {
+        let at = |reason| error(Some(n + 1), reason);
+        let Some((option, value)) = parse_line(line).map_err(at)? else {
+            continue;
+        };
+
+        let long = option.strip_prefix("--").unwrap_or(&option);
+        if matches!(long, "config" | "-K") {
+            let nested = value.ok_or_else(|| at(format!("'{option}' needs a file name")))?;
+            if depth == MAX_DEPTH {
+                return Err(at(format!(
+                    "config files are nested more than {MAX_DEPTH} deep"
+                )));
+            }
+            read(&nested, depth + 1, check, expanded)?;
+            continue;
+        }
+
+        let args: Vec<OsString> = match (long, value) {
+            // A short option takes its value joined on, like -XPOST
+            (short, value) if short.starts_with('-') => {
+                vec![format!("{short}{}", value.unwrap_or_default()).into()]
+            }
+            (long, Some(value)) => vec![format!("--{long}={value}").into()],
+            (long, None) => vec![format!("--{long}").into()],
+        };
+        check(&args).map_err(at)?;
+        expanded.extend(args);
+    }
+
+    Ok(())
+}
+
+// The option and value on a line, none for blank and comment lines
+fn parse_line(line: &str) -> Result<Option<(String, Option<String>)>, String> {
+    let line = line.trim();
+    if line.is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let end = line
+        .find(|c: char| c.is_whitespace() || c == '=' || c == ':')
+        .unwrap_or(line.len());
+    let (option, rest) = line.split_at(end);
+    let rest = rest.trim_start();
+    let rest = rest.strip_prefix(['=', ':']).map_or(rest, str::trim_start);
+
+    let value = match rest.strip_prefix('"') {
+        _ if rest.is_empty() => None,
+        Some(quoted) => Some(unquote(quoted)?),
+        // An unquoted value ends at the first space
+        None => rest.split_whitespace().next().map(str::to_string),
+    };
+
+    Ok(Some((option.to_string(), value)))
+}
+
+// The value of a quoted string up to its closing quote, with \", \\, \t, \n and \r escapes
+fn unquote(quoted: &str) -> Result<String, String> {
+    let mut value = String::new();
+    let mut chars = quoted.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => return Ok(value),
+            '\\' => match chars.next() {
+                Some('t') => value.push('\t'),
+                Some('n') => value.push('\n'),
+                Some('r') => value.push('\r'),
+                Some(c) => value.push(c),
+                None => break,
+            },
+            c => value.push(c),
+        }
+    }
+
+    Err("the quoted value has no closing quote".to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A directory of its own in the temporary directory, made empty
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn accept(_: &[OsString]) -> Result<(), String> {
+        Ok(())
+    }
+
+    #[test]
+    fn curlrc_is_looked_for_in_curl_home_then_xdg_config_home_then_home() {
+        let dirs = temp_dir("rc-order");
+        let home = |name: &str| {
+            let dir = dirs.join(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            std::fs::write(dir.join(".curlrc"), "").unwrap();
+            dir.into_os_string()
+        };
+        let (curl, xdg, user) = (home("curl"), home("xdg"), home("home"));
+        let vars = |curl: Option<&OsString>| {
+            let (xdg, user) = (xdg.clone(), user.clone());
+            let curl = curl.cloned();
+            move |name: &str| match name {
+                "CURL_HOME" => curl.clone(),
+                "XDG_CONFIG_HOME" => Some(xdg.clone()),
+                "HOME" => Some(user.clone()),
+                _ => None,
+            }
+        };
+
+        assert_eq!(rc_in(vars(Some(&curl))), Some(dirs.join("curl/.curlrc")));
+        assert_eq!(rc_in(vars(None)), Some(dirs.join("xdg/.curlrc")));
+        assert_eq!(
+            rc_in(vars(Some(&OsString::new()))),
+            Some(dirs.join("xdg/.curlrc"))
+        );
+        std::fs::remove_file(dirs.join("xdg/.curlrc")).unwrap();
+        assert_eq!(rc_in(vars(None)), Some(dirs.join("home/.curlrc")));
+        std::fs::remove_file(dirs.join("home/.curlrc")).unwrap();
+        assert_eq!(rc_in(vars(None)), None);
+        let _ = std::fs::remove_dir_all(dirs);
+    }
+
+    #[test]
+    fn q_leaves_out_the_curlrc_unless_it_comes_after_dashes() {
+        assert!(disabled(&args(&["curl", "-s", "-q", "http://a"])));
+        assert!(disabled(&args(&["curl", "--disable", "http://a"])));
+        assert!(!disabled(&args(&["curl", "--", "-q"])));
+    }
+
+    #[test]
+    fn lines_become_the_arguments_they_stand_for() {
+        let dir = temp_dir("config-lines");
+        let file = dir.join("config");
+        std::fs::write(
+            &file,
+            "# a comment\n\
+             \n\
+             --header \"X-Custom: a \\\"quoted\\\" value\"\n\
+             max-time = 10\n\
+             url: http://example.com/ ignored\n\
+             -XPUT\n\
+             -A agent\n\
+             silent\n",
+        )
+        .unwrap();
+        let file = file.to_string_lossy().into_owned();
+
+        let (expanded, rc_error) = with_files(args(&["curl", "-q", "-K", &file]), accept).unwrap();
+
+        assert!(rc_error.is_none());
+        assert_eq!(
+            expanded,
+            args(&[
+                "curl",
+                "--header=X-Custom: a \"quoted\" value",
+                "--max-time=10",
+                "--url=http://example.com/",
+                "-XPUT",
+                "-Aagent",
+                "--silent",
+                "-q",
+                "-K",
+                &file,
+            ])
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn command_line_comes_after_every_file_so_it_wins() {
+        let dir = temp_dir("config-order");
+        let (first, second) = (dir.join("first"), dir.join("second"));
+        std::fs::write(&first, "-A first\n").unwrap();
+        std::fs::write(&second, "-A second\n").unwrap();
+        let (first, second) = (first.to_string_lossy(), second.to_string_lossy());
+
+        let (expanded, _) = with_files(
+            args(&["curl", "-q", "-A", "cli", "-K", &first, "--config", &second]),
+            accept,
+        )
+        .unwrap();
+
+        assert_eq!(
+            expanded,
+            args(&[
+                "curl", "-Afirst", "-Asecond", "-q", "-A", "cli", "-K", &first, "--config", &second
+            ])
+        );
+        let _ = std::fs::remove_dir_all(dir);
+    }
+
+    #[test]
+    fn errors_name_the_file_and_line() {
+        let dir = temp_dir("config-errors");
+        let (bad, loop_, unquoted) = (dir.join("bad"), dir.join("loop"), dir.join("unquoted"));
+        std::fs::write(&bad, "silent\nbogus\n").unwrap();
+        std::fs::write(&loop_, format!("-K {}\n", loop_.display())).unwrap();
+        std::fs::write(&unquoted, "header = \"X-A: 1\n").unwrap();
+        let refuse_bogus = |args: &[OsString]| match args[0] == "--bogus" {
+            true => Err("not an option".to_string()),
+            false => Ok(()),
+        };
+        let error = |file: &Path| {
+            let file = file.to_string_lossy();
+            with_files(args(&["curl", "-q", "-K", &file]), refuse_bogus)
+                .err()
+                .unwrap()
+                .to_string()
+        };
+
+        assert_eq!(error(&bad), format!("{}:2: not an option", bad.display()));
+        assert_eq!(
+            error(&loop_),
+            format!(
+                "{}:1: config files are nested more than {MAX_DEPTH} deep",
+                loop_.display()
+            )
+        );
+        assert_eq!(
+            error(&unquoted),
+            format!(
+                "{}:1: the quoted value has no closing quote",
+                unquoted.display()
+            )
+        );
+        assert!(error(&dir.join("missing")).starts_with("Cannot read the config file "));
+        let _ = std::fs::remove_dir_all(dir);
+    }
+}
@@ -0,0 +1,495 @@
+use crate::date::DateTime;
+use std::{
+    fmt::Display,
+    io::Write,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+use url::Url;
+
+#[derive(Debug)]
+pub enum CookieError {
+    Read(PathBuf, std::io::Error),
+    Syntax(PathBuf, usize, String),
+    Write(PathBuf, std::io::Error),
+}
+
+impl Display for CookieError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            CookieError::Read(path, e) => {
+                write!(f, "Unable to read the cookies in {}: {e}", path.display())
+            }
+            CookieError::Syntax(path, line, msg) => write!(f, "{}:{line}: {msg}", path.display()),
+            CookieError::Write(path, e) => {
+                write!(f, "Unable to save the cookies to {}: {e}", path.display())
+            }
+        }
+    }
+}
+
+// Lines starting with this are cookies the browser hides from scripts, not comments
+const HTTP_ONLY_PREFIX: &str = "#HttpOnly_";
+
+struct Cookie {
+    // Lowercase and without a leading dot
+    domain: String,
+    // Only sent to the domain itself, not to its subdomains
+    host_only: bool,
+    path: String,
+    secure: bool,
+    http_only: bool,
+    // Seconds since the epoch, None for session cookies
+    expires: Option<u64>,
+    name: String,
+    value: String,
+}
+
+impl Cookie {
+    fn matches(&self, url: &Url, now: u64) -> bool {
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return false;
+        };
+
+        let domain = host == self.domain
+            || !self.host_only
+                && host
+                    .strip_suffix(self.domain.as_str())
+                    .is_some_and(|sub| sub.ends_with('.'));
+
+        domain
+            && path_matches(&self.path, url.path())
+            && (!self.secure || url.scheme() == "https")
+            && self.expires.is_none_or(|expires| expires > now)
+    }
+}
+
+// RFC 6265 path matching: the cookie path is the request path or a directory above it
+fn path_matches(cookie_path: &str, path: &str) -> bool {
+    match path.strip_prefix(cookie_path) {
+        Some(rest) => rest.is_empty() || cookie_path.ends_with('/') || rest.starts_with('/'),
+        None => false,
+    }
+}
+
+/// Cookies curl keeps while it runs, loaded from Netscape cookie files and
+/// set by the responses it gets.
+#[derive(Default)]
+pub struct Jar {
+    cookies: Vec<Cookie>,
+}
+
+impl Jar {
+    /// Adds the cookies saved in the Netscape cookie file at `path`, skipping
+    /// the ones that have expired. Like curl, a file that does not exist holds
+    /// no cookies, so the same file can be loaded and saved from the start.
+    pub fn load(&mut self, path: &Path) -> Result<(), CookieError> {
+        let contents = match std::fs::read_to_string(path) {
+            Ok(contents) => contents,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(CookieError::Read(path.to_path_buf(), e)),
+        };
+
+        let now = now();
+        for (at, line) in contents.lines().enumerate() {
+            let cookie = parse_line(line)
+                .map_err(|msg| CookieError::Syntax(path.to_path_buf(), at + 1, msg))?;
+
+            if let Some(cookie) = cookie.filter(|cookie| cookie.expires.is_none_or(|t| t > now)) {
+                self.cookies.push(cookie);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Drops the session cookies, the ones without an expiry time, as if a
+    /// new browser session started.
+    pub fn junk_session_cookies(&mut self) {
+        self.cookies.retain(|cookie| cookie.expires.is_some());
+    }
+
+    /// Stores the cookie a `Set-Cookie` header in the response from `url`
+    /// sets, replacing the one with the same name, domain and path. Cookies
+    /// for other domains are ignored, and ones that expired already remove
+    /// the cookie they replace.
+    pub fn store(&mut self, url: &Url, set_cookie: &str) {
+        let Some(host) = url.host_str().map(str::to_ascii_lowercase) else {
+            return;
+        };
+        let mut attributes = set_cookie.split(';');
+        let Some((name, value)) = attributes.next().and_then(|pair| pair.split_once('=')) else {
+            return;
+        };
+        let (name, value) = (name.trim(), value.trim());
+        if name.is_empty() {
+            return;
+        }
+
+        let mut cookie = Cookie {
+            domain: host.clone(),
+            host_only: true,
+            path: default_path(url.path()),
+            secure: false,
+            http_only: false,
+            expires: None,
+            name: name.to_string(),
+            value: value.to_string(),
+        };
+        let mut max_age = None;
+
+        for attribute in attributes {
+            let (key, value) = match attribute.split_once('=') {
+                Some((key, value)) => (key.trim(), value.trim()),
+                None => (attribute.trim(), ""),
+            };
+
+            match key.to_ascii_lowercase().as_str() {
+                "domain" if !value.is_empty() => {
+                    let domain = value.trim_start_matches('.').to_ascii_lowercase();
+                    let matches = host == domain
+                        || host
+                            .strip_suffix(domain.as_str())
+                            .is_some_and(|sub| sub.ends_with('.'));
+                    if !matches {
+                        return;
+                    }
+                    cookie.domain = domain;
+                    cookie.host_only = false;
+                }
+                "path" if value.starts_with('/') => cookie.path = value.to_string(),
+                "expires" => {
+                    if let Some(expires) = DateTime::parse(value) {
+                        cookie.expires = Some(
+                            expires
+                                .duration_since(UNIX_EPOCH)
+                                .map_or(0, |elapsed| elapsed.as_secs()),
+                        );
+                    }
+                }
+                // Max-Age wins over Expires
+                "max-age" => max_age = value.parse::<i64>().ok().or(max_age),
+                "secure" => cookie.secure = true,
+                "httponly" => cookie.http_only = true,
+                _ => {}
+            }
+        }
+
+        let now = now();
+        if let Some(max_age) = max_age {
+            cookie.expires = Some(now.saturating_add_signed(max_age));
+        }
+
+        self.cookies.retain(|kept| {
+            kept.name != cookie.name || kept.domain != cookie.domain || kept.path != cookie.path
+        });
+        if cookie.expires.is_none_or(|expires| expires > now) {
+            self.cookies.push(cookie);
+        }
+    }
+
+    /// Writes the cookies that have not expired to `path` as a Netscape
+    /// cookie file, or to stdout for `-`.
+    pub fn save(&self, path: &Path) -> Result<(), CookieError> {
+        let mut contents = String::from(
+            "# Netscape HTTP Cookie File\n# This file was generated by curl. Edit at your own risk.\n\n",
+        );
+
+        let now = now();
+        for cookie in self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.expires.is_none_or(|expires| expires > now))
+        {
+            let flag = |set: bool| if set { "TRUE" } else { "FALSE" };
+            contents.push_str(&format!(
+                "{}{}{}\t{}\t{}\t{}\t{}\t{}\t{}\n",
+                if cookie.http_only {
+                    HTTP_ONLY_PREFIX
+                } else {
+                    ""
+                },
+                // Cookies for subdomains too are written with a leading dot
+                if cookie.host_only { "" } else { "." },
+                cookie.domain,
+                flag(!cookie.host_only),
+                cookie.path,
+                flag(cookie.secure),
+                cookie.expires.unwrap_or(0),
+                cookie.name,
+                cookie.value
+            ));
+        }
+
+        let written = if path == Path::new("-") {
+            std::io::stdout().write_all(contents.as_bytes())
+        } else {
+            std::fs::write(path, contents)
+        };
+        written.map_err(|e| CookieError::Write(path.to_path_buf(), e))
+    }
+
+    /// The `Cookie` header value for a request to `url`, if any cookie
+    /// matches it. Cookies with longer paths come first, as RFC 6265 asks.
+    pub fn header(&self, url: &Url) -> Option<String> {
+        let now = now();
+        let mut cookies: Vec<&Cookie> = self
+            .cookies
+            .iter()
+            .filter(|cookie| cookie.matches(url, now))
+            .collect();
+        cookies.sort_by_key(|cookie| std::cmp::Reverse(cookie.path.len()));
+
+        let pairs: Vec<String> = cookies
+            .iter()
+            .map(|cookie| format!("{}={}", cookie.name, cookie.value))
+            .collect();
+
+        (!pairs.is_empty()).then(|| pairs.join("; "))
+    }
+}
+
+// One line of a Netscape cookie file: domain, subdomains flag, path, secure
+// flag, expiry, name and value separated by tabs. Comments and blank lines
+// hold no cookie
+fn parse_line(line: &str) -> Result<Option<Cookie>, String> {
+    let (line, http_only) = match line.strip_prefix(HTTP_ONLY_PREFIX) {
+        Some(line) => (line, true),
+        None => (line, false),
+    };
+    if line.trim().is_empty() || line.starts_with('#') {
+        return Ok(None);
+    }
+
+    let fields: Vec<&str> = line.trim_end_matches('\r').split('\t').collect();
+    let wrong_fields = || format!("Expected 7 tab-separated fields, found {}", fields.len());
+    let [domain, subdomains, path, secure, expires, name, rest @ ..] = fields.as_slice() else {
+        return Err(wrong_fields());
+    };
+    // Cookies without a value may leave out the last field
+    let value = match rest {
+        [] => "",
+        [value] => value,
+        _ => return Err(wrong_fields()),
+    };
+
+    let flag = |field: &str, value: &str| match value.to_ascii_uppercase().as_str() {
+        "TRUE" => Ok(true),
+        "FALSE" => Ok(false),
+        _ => Err(format!(
+            "Invalid {field} flag '{value}', expected TRUE or FALSE"
+        )),
+    };
+    let expires: u64 = expires
+        .parse()
+        .map_err(|_| format!("Invalid expiry time '{expires}'"))?;
+    if name.is_empty() {
+        return Err("Missing cookie name".to_string());
+    }
+
+    Ok(Some(Cookie {
+        domain: domain.trim_start_matches('.').to_ascii_lowercase(),
+        host_only: !flag("subdomains", subdomains)?,
+        path: path.to_string(),
+        secure: flag("secure", secure)?,
+        http_only,
+        // 0 marks a session cookie
+        expires: (expires != 0).then_some(expires),
+        name: name.to_string(),
+        value: value.to_string(),
+    }))
+}
+
+// The path a cookie without a Path attribute gets: the directory of the request path
+fn default_path(path: &str) -> String {
+    match path.rfind('/') {
+        Some(0) | None => "/".to_string(),
+        Some(at) => path[..at].to_string(),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map_or(0, |elapsed| elapsed.as_secs())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        path
+    }
+
+    #[test]
+    fn cookies_are_sent_where_they_match() {
+        let mut jar = Jar::default();
+        let from = url("http://www.example.com/shop/cart");
+        jar.store(&from, "host=1");
+        jar.store(&from, "wide=2; Domain=.Example.com; Path=/");
+        jar.store(&from, "deep=3; Path=/shop/cart/items");
+        jar.store(&from, "secure=4; Secure");
+
+        assert_eq!(
+            jar.header(&url("http://www.example.com/shop/x")).as_deref(),
+            Some("host=1; wide=2")
+        );
+        assert_eq!(
+            jar.header(&url("https://www.example.com/shop")).as_deref(),
+            Some("host=1; secure=4; wide=2")
+        );
+        assert_eq!(
+            jar.header(&url("http://www.example.com/shop/cart/items/1"))
+                .as_deref(),
+            Some("deep=3; host=1; wide=2")
+        );
+        assert_eq!(
+            jar.header(&url("http://api.example.com/")).as_deref(),
+            Some("wide=2")
+        );
+        assert_eq!(
+            jar.header(&url("http://www.example.com/shopping"))
+                .as_deref(),
+            Some("wide=2")
+        );
+        assert_eq!(jar.header(&url("http://example.org/")), None);
+    }
+
+    #[test]
+    fn cookies_for_other_domains_are_ignored() {
+        let mut jar = Jar::default();
+        jar.store(&url("http://example.com/"), "a=1; Domain=other.com");
+        jar.store(&url("http://example.com/"), "b=2; Domain=ample.com");
+        jar.store(&url("http://example.com/"), "noequals");
+
+        assert_eq!(jar.header(&url("http://other.com/")), None);
+        assert_eq!(jar.header(&url("http://ample.com/")), None);
+        assert_eq!(jar.header(&url("http://example.com/")), None);
+    }
+
+    #[test]
+    fn a_cookie_is_replaced_and_removed_by_expiring_it() {
+        let mut jar = Jar::default();
+        let site = url("http://example.com/");
+        jar.store(&site, "a=1");
+        jar.store(&site, "a=2");
+        assert_eq!(jar.header(&site).as_deref(), Some("a=2"));
+
+        jar.store(&site, "a=3; Max-Age=0");
+        assert_eq!(jar.header(&site), None);
+        jar.store(&site, "b=1; Expires=Thu, 01 Jan 1970 00:00:01 GMT");
+        assert_eq!(jar.header(&site), None);
+        // Max-Age wins over an Expires in the past
+        jar.store(
+            &site,
+            "c=1; Expires=Thu, 01 Jan 1970 00:00:01 GMT; Max-Age=60",
+        );
+        assert_eq!(jar.header(&site).as_deref(), Some("c=1"));
+    }
+
+    #[test]
+    fn saved_cookies_load_back_the_same() {
+        let path = temp_file("cookies-round-trip");
+        let mut jar = Jar::default();
+        let site = url("https://www.example.com/app/login");
+        jar.store(&site, "session=s");
+        jar.store(
+            &site,
+            "lasting=l; Max-Age=3600; Domain=example.com; Path=/; Secure; HttpOnly",
+        );
+        jar.save(&path).unwrap();
+
+        let saved = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = saved.lines().filter(|line| line.contains('\t')).collect();
+        assert_eq!(
+            lines[0],
+            "www.example.com\tFALSE\t/app\tFALSE\t0\tsession\ts"
+        );
+        let lasting: Vec<&str> = lines[1].split('\t').collect();
+        assert_eq!(
+            lasting[..4],
+            ["#HttpOnly_.example.com", "TRUE", "/", "TRUE"]
+        );
+        let expires: u64 = lasting[4].parse().unwrap();
+        assert!(expires > now() + 3500 && expires <= now() + 3600);
+        assert_eq!(lasting[5..], ["lasting", "l"]);
+
+        let mut loaded = Jar::default();
+        loaded.load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            loaded
+                .header(&url("https://www.example.com/app/x"))
+                .as_deref(),
+            Some("session=s; lasting=l")
+        );
+        assert_eq!(
+            loaded
+                .header(&url("http://www.example.com/app/x"))
+                .as_deref(),
+            Some("session=s"),
+            "the secure cookie goes over https only"
+        );
+        loaded.junk_session_cookies();
+        assert_eq!(
+            loaded
+                .header(&url("https://www.example.com/app/x"))
+                .as_deref(),
+            Some("lasting=l")
+        );
+    }
+
+    #[test]
+    fn expired_cookies_are_not_loaded() {
+        let path = temp_file("cookies-expired");
+        std::fs::write(
+            &path,
+            "# comment\n\nexample.com\tFALSE\t/\tFALSE\t1\told\tx\nexample.com\tFALSE\t/\tFALSE\t0\tempty\n",
+        )
+        .unwrap();
+
+        let mut jar = Jar::default();
+        jar.load(&path).unwrap();
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(
+            jar.header(&url("http://example.com/")).as_deref(),
+            Some("empty=")
+        );
+    }
+
+    #[test]
+    fn bad_lines_say_where() {
+        let path = temp_file("cookies-bad");
+        let error = |contents: &str| {
+            std::fs::write(&path, contents).unwrap();
+            Jar::default().load(&path).err().unwrap().to_string()
+        };
+
+        let at = |line, msg| format!("{}:{line}: {msg}", path.display());
+        assert_eq!(
+            error("# ok\na\tb\n"),
+            at(2, "Expected 7 tab-separated fields, found 2")
+        );
+        assert_eq!(
+            error("example.com\tyes\t/\tFALSE\t0\tn\tv\n"),
+            at(1, "Invalid subdomains flag 'yes', expected TRUE or FALSE")
+        );
+        assert_eq!(
+            error("example.com\tFALSE\t/\tFALSE\tsoon\tn\tv\n"),
+            at(1, "Invalid expiry time 'soon'")
+        );
+        let _ = std::fs::remove_file(&path);
+        assert!(
+            Jar::default().load(&path).is_ok(),
+            "a missing file holds no cookies"
+        );
+    }
+}
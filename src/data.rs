@@ -0,0 +1,263 @@
+use crate::variable::URL_ENCODE_SET;
+use percent_encoding::percent_encode;
+use std::{fmt::Display, io, io::Read};
+
+/// A -d @file that cannot be read.
+#[derive(Debug)]
+pub struct DataError {
+    file: String,
+    error: io::Error,
+}
+
+impl Display for DataError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let file = if self.file == "-" {
+            "stdin"
+        } else {
+            &self.file
+        };
+        write!(f, "Unable to read the data from {file}: {}", self.error)
+    }
+}
+
+/// The value of a data option, sent the way the option says.
+#[derive(Clone, Debug)]
+pub enum Data {
+    /// -d, which reads `@file` without its carriage returns and newlines
+    Ascii(String),
+    /// --data-binary, which reads `@file` byte for byte
+    Binary(String),
+    /// --data-raw, sent as it is even when it starts with `@`
+    Raw(String),
+    /// --data-urlencode, `content`, `=content`, `name=content`, `@file` or
+    /// `name@file` with the content percent-encoded
+    Encode(String),
+}
+
+/// --json data that is text but not valid JSON, and where in it the
+/// problem is.
+#[derive(Debug)]
+pub struct JsonError {
+    reason: String,
+    // Counted from 1 like serde_json does
+    line: usize,
+    column: usize,
+    text: String,
+}
+
+impl Display for JsonError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Invalid JSON at line {}, column {}: {}",
+            self.line, self.column, self.reason
+        )?;
+        let Some(text) = self.text.lines().nth(self.line.saturating_sub(1)) else {
+            return Ok(());
+        };
+        let before = text
+            .char_indices()
+            .take_while(|(at, _)| *at + 1 < self.column)
+            .count();
+        write!(f, "\n{text}\n{}^", " ".repeat(before))
+    }
+}
+
+/// Joins the data values with `&` like curl does, leaving out empty ones. A
+/// value starting with `@` is read from that file, or from stdin for `@-`.
+pub fn join(values: &[Data]) -> Result<Vec<u8>, DataError> {
+    let mut joined = Vec::new();
+    for value in values {
+        let piece = match value {
+            Data::Raw(text) => text.as_bytes().to_vec(),
+            Data::Encode(text) => encode(text)?,
+            Data::Ascii(text) | Data::Binary(text) => match text.strip_prefix('@') {
+                Some(file) => {
+                    let mut contents = read(file)?;
+                    if let Data::Ascii(_) = value {
+                        contents.retain(|b| *b != b'\r' && *b != b'\n');
+                    }
+                    contents
+                }
+                None => text.as_bytes().to_vec(),
+            },
+        };
+
+        if piece.is_empty() {
+            continue;
+        }
+        if !joined.is_empty() {
+            joined.push(b'&');
+        }
+        joined.extend(piece);
+    }
+
+    Ok(joined)
+}
+
+/// Puts the --json values together as they are, with nothing between them
+/// like curl does. `@file` and `@-` are read byte for byte.
+pub fn concat(values: &[String]) -> Result<Vec<u8>, DataError> {
+    let mut joined = Vec::new();
+    for value in values {
+        match value.strip_prefix('@') {
+            Some(file) => joined.extend(read(file)?),
+            None => joined.extend(value.as_bytes()),
+        }
+    }
+
+    Ok(joined)
+}
+
+/// Checks that --json data is valid JSON. Data that is not UTF-8 is let
+/// through as it is, it is sent byte for byte.
+pub fn check_json(json: &[u8]) -> Result<(), JsonError> {
+    let Ok(text) = std::str::from_utf8(json) else {
+        return Ok(());
+    };
+    let Err(e) = serde_json::from_str::<serde_json::Value>(text) else {
+        return Ok(());
+    };
+
+    // The error ends with where the problem is, which is given separately
+    let reason = e.to_string();
+    let position = format!(" at line {} column {}", e.line(), e.column());
+    Err(JsonError {
+        reason: reason
+            .strip_suffix(&position)
+            .unwrap_or(&reason)
+            .to_string(),
+        line: e.line(),
+        column: e.column(),
+        text: text.to_string(),
+    })
+}
+
+// A --data-urlencode value, with the name before the first = or @ left as it is
+fn encode(text: &str) -> Result<Vec<u8>, DataError> {
+    let (name, content) = match text.find(['=', '@']) {
+        Some(at) if text[at..].starts_with('@') => (&text[..at], read(&text[at + 1..])?),
+        Some(at) => (&text[..at], text.as_bytes()[at + 1..].to_vec()),
+        None => ("", text.as_bytes().to_vec()),
+    };
+
+    let encoded = percent_encode(&content, URL_ENCODE_SET).to_string();
+    Ok(if name.is_empty() {
+        encoded.into_bytes()
+    } else {
+        format!("{name}={encoded}").into_bytes()
+    })
+}
+
+fn read(file: &str) -> Result<Vec<u8>, DataError> {
+    let read = if file == "-" {
+        let mut contents = Vec::new();
+        io::stdin().read_to_end(&mut contents).map(|_| contents)
+    } else {
+        std::fs::read(file)
+    };
+
+    read.map_err(|error| DataError {
+        file: file.to_string(),
+        error,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn valid_json_passes() {
+        for json in [
+            &b"{\"a\": [1, 2.50, null]}"[..],
+            b" \"text\" ",
+            b"\xff\xfe not UTF-8",
+        ] {
+            assert!(check_json(json).is_ok());
+        }
+    }
+
+    #[test]
+    fn invalid_json_points_at_the_problem() {
+        let Err(e) = check_json(b"{\n  \"a\": 1,\n  \"b\": [1 2]\n}") else {
+            panic!("invalid JSON is let through");
+        };
+        assert_eq!(
+            e.to_string(),
+            "Invalid JSON at line 3, column 11: expected `,` or `]`\n  \"b\": [1 2]\n          ^"
+        );
+    }
+
+    #[test]
+    fn caret_counts_characters() {
+        let Err(e) = check_json("[\"é\" 1]".as_bytes()) else {
+            panic!("invalid JSON is let through");
+        };
+        assert!(e.to_string().ends_with("\n[\"é\" 1]\n     ^"), "{e}");
+    }
+
+    fn temp_file(name: &str, contents: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+        std::fs::write(&path, contents).unwrap();
+        path.to_string_lossy().into_owned()
+    }
+
+    fn encoded(text: &str) -> String {
+        String::from_utf8(encode(text).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn urlencode_leaves_the_name_and_encodes_the_content() {
+        assert_eq!(encoded("a b&c"), "a%20b%26c");
+        assert_eq!(encoded("=a=b"), "a%3Db");
+        assert_eq!(encoded("name=a=b c"), "name=a%3Db%20c");
+        assert_eq!(encoded("name=café ☕"), "name=caf%C3%A9%20%E2%98%95");
+        assert_eq!(encoded("name="), "name=");
+        assert_eq!(encoded("-_.~"), "-_.~");
+    }
+
+    #[test]
+    fn urlencode_reads_files_with_their_newlines() {
+        let file = temp_file("urlencode", "line one\nzwei=2\n".as_bytes());
+
+        let named = encoded(&format!("text@{file}"));
+        let unnamed = encoded(&format!("@{file}"));
+        let _ = std::fs::remove_file(&file);
+
+        assert_eq!(named, "text=line%20one%0Azwei%3D2%0A");
+        assert_eq!(unnamed, "line%20one%0Azwei%3D2%0A");
+    }
+
+    #[test]
+    fn data_files_are_read_the_way_the_option_says() {
+        let file = temp_file("data", b"a=1\r\nb=2\n");
+        let at = format!("@{file}");
+
+        let joined = join(&[
+            Data::Ascii(at.clone()),
+            Data::Binary(at.clone()),
+            Data::Raw(at.clone()),
+            Data::Ascii(String::new()),
+        ])
+        .unwrap();
+        let _ = std::fs::remove_file(&file);
+
+        assert_eq!(joined, format!("a=1b=2&a=1\r\nb=2\n&{at}").into_bytes());
+    }
+
+    #[test]
+    fn missing_files_are_errors() {
+        let missing = "/nonexistent/curl-data";
+
+        for data in [
+            Data::Ascii(format!("@{missing}")),
+            Data::Encode(format!("n@{missing}")),
+        ] {
+            let e = join(&[data]).unwrap_err();
+            assert!(e
+                .to_string()
+                .starts_with(&format!("Unable to read the data from {missing}: ")));
+        }
+    }
+}
@@ -0,0 +1,375 @@
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// A UTC calendar date and time with second precision.
+pub struct DateTime {
+    pub year: i64,
+    pub month: u32,
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub second: u32,
+}
+
+impl DateTime {
+    pub fn from_system_time(time: SystemTime) -> DateTime {
+        let secs = match time.duration_since(UNIX_EPOCH) {
+            Ok(elapsed) => elapsed.as_secs() as i64,
+            Err(e) => -(e.duration().as_secs() as i64),
+        };
+
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let rem = secs.rem_euclid(86400) as u32;
+
+        DateTime {
+            year,
+            month,
+            day,
+            hour: rem / 3600,
+            minute: rem % 3600 / 60,
+            second: rem % 60,
+        }
+    }
+
+    pub fn to_system_time(&self) -> SystemTime {
+        let secs = days_from_civil(self.year, self.month, self.day) * 86400
+            + i64::from(self.hour * 3600 + self.minute * 60 + self.second);
+
+        match u64::try_from(secs) {
+            Ok(secs) => UNIX_EPOCH + Duration::from_secs(secs),
+            Err(_) => UNIX_EPOCH - Duration::from_secs(secs.unsigned_abs()),
+        }
+    }
+
+    /// Formats the date as an IMF-fixdate, like `Sun, 06 Nov 1994 08:49:37 GMT`.
+    pub fn to_http_date(&self) -> String {
+        // The epoch was a Thursday
+        let days = days_from_civil(self.year, self.month, self.day);
+        let weekday = WEEKDAYS[(days + 4).rem_euclid(7) as usize];
+
+        format!(
+            "{weekday}, {:02} {} {:04} {:02}:{:02}:{:02} GMT",
+            self.day,
+            MONTHS[self.month as usize - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second
+        )
+    }
+
+    /// Parses a date given on the command line: an HTTP-date, ISO 8601 like
+    /// `2024-01-31` or `2024-01-31T12:00:00+01:00`, or the parts of a date in
+    /// any order, like RFC 2822's `Wed, 31 Jan 2024 12:00:00 +0100` or
+    /// `31 Jan 2024`. Dates without a time zone are in UTC.
+    pub fn parse(value: &str) -> Option<SystemTime> {
+        let value = value.trim();
+
+        match DateTime::parse_http_date(value) {
+            Some(date) => Some(date.to_system_time()),
+            None => parse_iso8601(value).or_else(|| parse_loose(value)),
+        }
+    }
+
+    /// Parses an HTTP-date in any of the three formats RFC 9110 requires
+    /// recipients to accept:
+    ///
+    /// - `Sun, 06 Nov 1994 08:49:37 GMT` (IMF-fixdate)
+    /// - `Sunday, 06-Nov-94 08:49:37 GMT` (RFC 850)
+    /// - `Sun Nov  6 08:49:37 1994` (asctime)
+    pub fn parse_http_date(value: &str) -> Option<DateTime> {
+        let fields: Vec<&str> = value.split_whitespace().collect();
+
+        let (day, month, year, time) = match fields.as_slice() {
+            [_, day, month, year, time, "GMT"] => (*day, *month, year.parse().ok()?, *time),
+            [_, date, time, "GMT"] => {
+                let mut parts = date.split('-');
+                let (day, month, year) = (parts.next()?, parts.next()?, parts.next()?);
+                let year: i64 = year.parse().ok()?;
+                // Two digit years are interpreted as within 50 years of 2000
+                let year = match year {
+                    0..=49 => year + 2000,
+                    50..=99 => year + 1900,
+                    _ => year,
+                };
+                (day, month, year, *time)
+            }
+            [_, month, day, time, year] => (*day, *month, year.parse().ok()?, *time),
+            _ => return None,
+        };
+
+        let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as u32 + 1;
+        let day: u32 = day.parse().ok()?;
+
+        let mut time = time.split(':').map(|part| part.parse::<u32>().ok());
+        let (hour, minute, second) = (time.next()??, time.next()??, time.next()??);
+
+        let valid = (1..=31).contains(&day) && hour < 24 && minute < 60 && second <= 60;
+        valid.then_some(DateTime {
+            year,
+            month,
+            day,
+            hour,
+            minute,
+            second,
+        })
+    }
+}
+
+const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+// `2024-01-31`, optionally followed by a time and a zone after a `T` or a space
+fn parse_iso8601(value: &str) -> Option<SystemTime> {
+    let (date, time) = match value.split_once(['T', 't', ' ']) {
+        Some((date, time)) => (date, Some(time.trim())),
+        None => (value, None),
+    };
+
+    let mut parts = date.split('-');
+    let year = number(parts.next()?, 4..=4)?;
+    let month = number(parts.next()?, 2..=2)?;
+    let day = number(parts.next()?, 2..=2)?;
+    if parts.next().is_some() {
+        return None;
+    }
+
+    let (clock, offset) = match time {
+        Some(time) => {
+            // The zone is a Z or a signed offset right after the time
+            let at = time.find(['Z', 'z', '+', '-']).unwrap_or(time.len());
+            let offset = match &time[at..] {
+                "" => 0,
+                zone => self::zone(zone)?,
+            };
+            (self::clock(&time[..at])?, offset)
+        }
+        None => ((0, 0, 0), 0),
+    };
+
+    at(year as i64, month, day, clock, offset)
+}
+
+// A date with its parts in any order, separated by spaces or commas
+fn parse_loose(value: &str) -> Option<SystemTime> {
+    let (mut year, mut month, mut day) = (None, None, None);
+    let mut clock = (0, 0, 0);
+    let mut offset = 0;
+
+    for token in value.split([' ', ',']).filter(|token| !token.is_empty()) {
+        let name = |names: &[&str]| {
+            names.iter().position(|name| {
+                token.len() >= 3
+                    && token.is_char_boundary(3)
+                    && token[..3].eq_ignore_ascii_case(name)
+                    && token.bytes().all(|b| b.is_ascii_alphabetic())
+            })
+        };
+
+        if name(&WEEKDAYS).is_some() {
+            continue;
+        } else if let Some(at) = name(&MONTHS) {
+            month = Some(at as u32 + 1);
+        } else if token.contains(':') {
+            clock = self::clock(token)?;
+        } else if let Some(zone) = zone(token) {
+            offset = zone;
+        } else if let Some(n) = number(token, 4..=4) {
+            year = Some(i64::from(n));
+        } else if let Some(n) = number(token, 1..=2) {
+            match day {
+                None => day = Some(n),
+                // Two digit years are interpreted as within 50 years of 2000
+                Some(_) if n < 50 => year = Some(i64::from(n) + 2000),
+                Some(_) => year = Some(i64::from(n) + 1900),
+            }
+        } else {
+            return None;
+        }
+    }
+
+    at(year?, month?, day?, clock, offset)
+}
+
+// `12:30` or `12:30:59`, with any fraction of a second dropped
+fn clock(value: &str) -> Option<(u32, u32, u32)> {
+    let mut parts = value.split(':');
+    let hour = number(parts.next()?, 1..=2)?;
+    let minute = number(parts.next()?, 2..=2)?;
+    let second = match parts.next() {
+        Some(second) => number(second.split('.').next()?, 2..=2)?,
+        None => 0,
+    };
+
+    parts.next().is_none().then_some((hour, minute, second))
+}
+
+// Seconds east of UTC for `Z`, `GMT`, `UTC`, `+01:00`, `+0100` or `+01`
+fn zone(value: &str) -> Option<i64> {
+    if ["Z", "GMT", "UTC", "UT"]
+        .iter()
+        .any(|name| value.eq_ignore_ascii_case(name))
+    {
+        return Some(0);
+    }
+
+    let (sign, rest) = match value.split_at_checked(1)? {
+        ("+", rest) => (1, rest),
+        ("-", rest) => (-1, rest),
+        _ => return None,
+    };
+    let rest = rest.replace(':', "");
+    let (hours, minutes) = match rest.len() {
+        2 => (number(&rest, 2..=2)?, 0),
+        4 => (number(&rest[..2], 2..=2)?, number(&rest[2..], 2..=2)?),
+        _ => return None,
+    };
+
+    Some(sign * i64::from(hours * 3600 + minutes * 60))
+}
+
+// An unsigned number written with this many digits
+fn number(value: &str, digits: std::ops::RangeInclusive<usize>) -> Option<u32> {
+    let valid = digits.contains(&value.len()) && value.bytes().all(|b| b.is_ascii_digit());
+    valid.then(|| value.parse().ok())?
+}
+
+// The moment a local date and time `offset` seconds east of UTC stands for
+fn at(
+    year: i64,
+    month: u32,
+    day: u32,
+    (hour, minute, second): (u32, u32, u32),
+    offset: i64,
+) -> Option<SystemTime> {
+    let valid = (1..=12).contains(&month)
+        && (1..=31).contains(&day)
+        && hour < 24
+        && minute < 60
+        && second <= 60;
+    if !valid {
+        return None;
+    }
+
+    let local = DateTime {
+        year,
+        month,
+        day,
+        hour,
+        minute,
+        second,
+    }
+    .to_system_time();
+    let shift = Duration::from_secs(offset.unsigned_abs());
+
+    if offset > 0 {
+        local.checked_sub(shift)
+    } else {
+        local.checked_add(shift)
+    }
+}
+
+// Howard Hinnant's algorithm for converting days since the epoch to a
+// proleptic Gregorian calendar date
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (year, month, day)
+}
+
+// The inverse of civil_from_days
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = year - i64::from(month <= 2);
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let mp = i64::from(if month > 2 { month - 3 } else { month + 9 });
+    let doy = (153 * mp + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SUNDAY: &str = "Sun, 06 Nov 1994 08:49:37 GMT";
+
+    fn http_date(value: &str) -> Option<String> {
+        DateTime::parse(value).map(|time| DateTime::from_system_time(time).to_http_date())
+    }
+
+    #[test]
+    fn every_http_date_format_is_read() {
+        for value in [
+            SUNDAY,
+            "Sunday, 06-Nov-94 08:49:37 GMT",
+            "Sun Nov  6 08:49:37 1994",
+        ] {
+            assert_eq!(http_date(value).as_deref(), Some(SUNDAY), "{value}");
+        }
+        assert_eq!(
+            http_date("Thursday, 01-Jan-70 00:00:00 GMT").as_deref(),
+            Some("Thu, 01 Jan 1970 00:00:00 GMT")
+        );
+        assert_eq!(
+            http_date("Wed, 01-Jan-48 00:00:00 GMT").as_deref(),
+            Some("Wed, 01 Jan 2048 00:00:00 GMT")
+        );
+    }
+
+    #[test]
+    fn command_line_dates_can_be_iso_or_loose() {
+        assert_eq!(http_date("1994-11-06T08:49:37Z").as_deref(), Some(SUNDAY));
+        assert_eq!(
+            http_date("1994-11-06 09:49:37+01:00").as_deref(),
+            Some(SUNDAY)
+        );
+        assert_eq!(
+            http_date("1994-11-06").as_deref(),
+            Some("Sun, 06 Nov 1994 00:00:00 GMT")
+        );
+        assert_eq!(
+            http_date("Sun, 06 Nov 1994 03:49:37 -0500").as_deref(),
+            Some(SUNDAY)
+        );
+        assert_eq!(
+            http_date("6 Nov 1994").as_deref(),
+            Some("Sun, 06 Nov 1994 00:00:00 GMT")
+        );
+    }
+
+    #[test]
+    fn invalid_dates_are_refused() {
+        for value in [
+            "",
+            "yesterday",
+            "Sun, 32 Nov 1994 08:49:37 GMT",
+            "Sun, 06 Nov 1994 24:00:00 GMT",
+            "Sun, 06 Foo 1994 08:49:37 GMT",
+        ] {
+            assert!(DateTime::parse(value).is_none(), "{value}");
+        }
+    }
+
+    #[test]
+    fn dates_before_the_epoch_round_trip() {
+        let date = DateTime::parse_http_date("Fri, 31 Dec 1965 23:59:59 GMT").unwrap();
+        let time = date.to_system_time();
+
+        assert!(time < UNIX_EPOCH);
+        assert_eq!(
+            DateTime::from_system_time(time).to_http_date(),
+            "Fri, 31 Dec 1965 23:59:59 GMT"
+        );
+    }
+}
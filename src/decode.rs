@@ -0,0 +1,463 @@
+use brotli_decompressor::{BrotliDecompressStream, BrotliResult, BrotliState, StandardAlloc};
+use flate2::{Decompress, FlushDecompress, Status};
+use std::{
+    error::Error,
+    fmt::Display,
+    io::{self, Read},
+};
+use zstd::stream::raw::Operation;
+
+/// A Content-Encoding the response body can be decoded from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Encoding {
+    Gzip,
+    Deflate,
+    Brotli,
+    Zstd,
+}
+
+impl Encoding {
+    /// The Accept-Encoding value --compressed sends, every encoding supported.
+    pub const ACCEPT: &'static str = "gzip, deflate, br, zstd";
+
+    fn from_name(name: &str) -> Option<Encoding> {
+        match name.to_ascii_lowercase().as_str() {
+            "gzip" | "x-gzip" => Some(Encoding::Gzip),
+            "deflate" => Some(Encoding::Deflate),
+            "br" => Some(Encoding::Brotli),
+            "zstd" => Some(Encoding::Zstd),
+            _ => None,
+        }
+    }
+
+    fn name(self) -> &'static str {
+        match self {
+            Encoding::Gzip => "gzip",
+            Encoding::Deflate => "deflate",
+            Encoding::Brotli => "br",
+            Encoding::Zstd => "zstd",
+        }
+    }
+}
+
+/// The encodings in a Content-Encoding value, in the order they were applied.
+/// "identity" is skipped, and the first encoding that cannot be decoded is the
+/// error.
+pub fn encodings(value: &str) -> Result<Vec<Encoding>, String> {
+    value
+        .split(',')
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !name.eq_ignore_ascii_case("identity"))
+        .map(|name| Encoding::from_name(name).ok_or_else(|| name.to_string()))
+        .collect()
+}
+
+/// A body that could not be decoded from its Content-Encoding.
+#[derive(Debug)]
+pub struct DecodeError {
+    encoding: Encoding,
+    message: String,
+}
+
+impl Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Unable to decode the {} encoded body: {}",
+            self.encoding.name(),
+            self.message
+        )
+    }
+}
+
+impl Error for DecodeError {}
+
+/// Decodes a response body as it is read, undoing each encoding in turn.
+pub struct Reader<'a> {
+    inner: Box<dyn Read + 'a>,
+    decoded: u64,
+}
+
+impl<'a> Reader<'a> {
+    /// Wraps `body`, encoded with `encodings` in that order. Without any
+    /// encodings the body is passed through as is.
+    pub fn new(body: impl Read + 'a, encodings: &[Encoding]) -> Reader<'a> {
+        let mut inner: Box<dyn Read + 'a> = Box::new(body);
+
+        for &encoding in encodings.iter().rev() {
+            let codec: Box<dyn Codec> = match encoding {
+                Encoding::Gzip | Encoding::Deflate => Box::new(Zlib::new(encoding)),
+                Encoding::Brotli => Box::new(Brotli::new()),
+                Encoding::Zstd => Box::new(Zstd::new()),
+            };
+            inner = Box::new(Decoder::new(inner, encoding, codec));
+        }
+
+        Reader { inner, decoded: 0 }
+    }
+
+    /// The number of decoded bytes read so far.
+    pub fn decoded(&self) -> u64 {
+        self.decoded
+    }
+}
+
+impl Read for Reader<'_> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.decoded += read as u64;
+
+        Ok(read)
+    }
+}
+
+// One step of a streaming decompressor: consumes some of `input` and fills some
+// of `output`, returning how much of each was used and whether the compressed
+// stream is complete
+trait Codec {
+    fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize, bool), String>;
+}
+
+struct Decoder<R> {
+    inner: R,
+    encoding: Encoding,
+    codec: Box<dyn Codec>,
+    buf: Vec<u8>,
+    start: usize,
+    end: usize,
+    eof: bool,
+    finished: bool,
+    received: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    fn new(inner: R, encoding: Encoding, codec: Box<dyn Codec>) -> Decoder<R> {
+        Decoder {
+            inner,
+            encoding,
+            codec,
+            buf: vec![0; 16 * 1024],
+            start: 0,
+            end: 0,
+            eof: false,
+            finished: false,
+            received: false,
+        }
+    }
+
+    fn error(&self, message: impl Into<String>) -> io::Error {
+        io::Error::new(
+            io::ErrorKind::InvalidData,
+            DecodeError {
+                encoding: self.encoding,
+                message: message.into(),
+            },
+        )
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+        // Anything after the end of the compressed stream is ignored
+        while !self.finished && !out.is_empty() {
+            if self.start == self.end && !self.eof {
+                self.start = 0;
+                self.end = self.inner.read(&mut self.buf)?;
+                self.eof = self.end == 0;
+                self.received |= !self.eof;
+            }
+
+            // An empty body, like that of a 204, has nothing to decode
+            if self.eof && !self.received {
+                return Ok(0);
+            }
+
+            let (consumed, produced, finished) = self
+                .codec
+                .decode(&self.buf[self.start..self.end], out)
+                .map_err(|message| self.error(message))?;
+            self.start += consumed;
+            self.finished = finished;
+
+            if produced > 0 {
+                return Ok(produced);
+            }
+            if self.eof && !finished && consumed == 0 {
+                return Err(self.error("the body ends in the middle of the compressed data"));
+            }
+        }
+
+        Ok(0)
+    }
+}
+
+// gzip and deflate through flate2
+struct Zlib {
+    encoding: Encoding,
+    // Made once the first bytes show which framing the data has
+    inflate: Option<Decompress>,
+}
+
+impl Zlib {
+    fn new(encoding: Encoding) -> Zlib {
+        Zlib {
+            encoding,
+            inflate: None,
+        }
+    }
+}
+
+// HTTP's deflate is meant to be zlib wrapped, but some servers send raw deflate data
+fn framing(encoding: Encoding, input: &[u8]) -> Decompress {
+    match (encoding, input) {
+        (Encoding::Gzip, _) => Decompress::new_gzip(15),
+        (_, [cmf, flg, ..])
+            if cmf & 0x0f == 8 && (u16::from(*cmf) << 8 | u16::from(*flg)) % 31 == 0 =>
+        {
+            Decompress::new(true)
+        }
+        _ => Decompress::new(false),
+    }
+}
+
+impl Codec for Zlib {
+    fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize, bool), String> {
+        let encoding = self.encoding;
+        let inflate = self.inflate.get_or_insert_with(|| framing(encoding, input));
+
+        let (total_in, total_out) = (inflate.total_in(), inflate.total_out());
+        let status = inflate
+            .decompress(input, output, FlushDecompress::None)
+            .map_err(|e| match e.needs_dictionary() {
+                Some(_) => "the data needs a preset dictionary".to_string(),
+                None => e.to_string(),
+            })?;
+
+        let consumed = (inflate.total_in() - total_in) as usize;
+        let produced = (inflate.total_out() - total_out) as usize;
+        Ok((consumed, produced, status == Status::StreamEnd))
+    }
+}
+
+// br through brotli-decompressor
+struct Brotli {
+    state: BrotliState<StandardAlloc, StandardAlloc, StandardAlloc>,
+}
+
+impl Brotli {
+    fn new() -> Brotli {
+        Brotli {
+            state: BrotliState::new(
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+                StandardAlloc::default(),
+            ),
+        }
+    }
+}
+
+impl Codec for Brotli {
+    fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize, bool), String> {
+        let (mut available_in, mut consumed) = (input.len(), 0);
+        let (mut available_out, mut produced) = (output.len(), 0);
+        let mut total_out = 0;
+
+        let result = BrotliDecompressStream(
+            &mut available_in,
+            &mut consumed,
+            input,
+            &mut available_out,
+            &mut produced,
+            output,
+            &mut total_out,
+            &mut self.state,
+        );
+
+        match result {
+            BrotliResult::ResultFailure => {
+                Err(format!("the data is corrupt ({:?})", self.state.error_code))
+            }
+            BrotliResult::ResultSuccess => Ok((consumed, produced, true)),
+            // Needs more input or more room for output
+            BrotliResult::NeedsMoreInput | BrotliResult::NeedsMoreOutput => {
+                Ok((consumed, produced, false))
+            }
+        }
+    }
+}
+
+// zstd through the zstd library, one frame like the other encodings have one stream
+struct Zstd {
+    decoder: Result<zstd::stream::raw::Decoder<'static>, String>,
+}
+
+impl Zstd {
+    fn new() -> Zstd {
+        Zstd {
+            decoder: zstd::stream::raw::Decoder::new().map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl Codec for Zstd {
+    fn decode(&mut self, input: &[u8], output: &mut [u8]) -> Result<(usize, usize, bool), String> {
+        let decoder = self.decoder.as_mut().map_err(|e| e.clone())?;
+
+        let status = decoder
+            .run_on_buffers(input, output)
+            .map_err(|e| e.to_string())?;
+        Ok((
+            status.bytes_read,
+            status.bytes_written,
+            status.remaining == 0,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use flate2::{
+        write::{DeflateEncoder, GzEncoder, ZlibEncoder},
+        Compression,
+    };
+    use std::io::Write;
+
+    // Text long enough to take several reads of the decoder's buffer
+    fn text() -> Vec<u8> {
+        (0..20_000)
+            .flat_map(|i: u32| format!("line {i} {}\n", i.wrapping_mul(2654435761)).into_bytes())
+            .collect()
+    }
+
+    fn compress(encoding: Encoding, data: &[u8]) -> Vec<u8> {
+        match encoding {
+            Encoding::Gzip => {
+                let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Encoding::Deflate => {
+                let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+                encoder.write_all(data).unwrap();
+                encoder.finish().unwrap()
+            }
+            Encoding::Zstd => zstd::encode_all(data, 0).unwrap(),
+            Encoding::Brotli => panic!("there is no brotli encoder here"),
+        }
+    }
+
+    fn decode(body: &[u8], encodings: &[Encoding]) -> io::Result<Vec<u8>> {
+        let mut reader = Reader::new(body, encodings);
+        let mut decoded = Vec::new();
+        reader.read_to_end(&mut decoded)?;
+        assert_eq!(reader.decoded(), decoded.len() as u64);
+        Ok(decoded)
+    }
+
+    fn decode_error(body: &[u8], encodings: &[Encoding]) -> String {
+        let e = decode(body, encodings).unwrap_err();
+        assert!(e.get_ref().is_some_and(|e| e.is::<DecodeError>()), "{e}");
+        e.to_string()
+    }
+
+    #[test]
+    fn round_trips() {
+        let text = text();
+        for encoding in [Encoding::Gzip, Encoding::Deflate, Encoding::Zstd] {
+            let compressed = compress(encoding, &text);
+            assert_eq!(
+                decode(&compressed, &[encoding]).unwrap(),
+                text,
+                "{encoding:?}"
+            );
+        }
+    }
+
+    #[test]
+    fn raw_deflate() {
+        let mut encoder = DeflateEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(b"raw deflate data").unwrap();
+        let compressed = encoder.finish().unwrap();
+        assert_eq!(
+            decode(&compressed, &[Encoding::Deflate]).unwrap(),
+            b"raw deflate data"
+        );
+    }
+
+    #[test]
+    fn brotli() {
+        // An uncompressed meta-block holding "hello", then an empty last one
+        let hello = b"\x40\x00\x10hello\x03";
+        assert_eq!(decode(hello, &[Encoding::Brotli]).unwrap(), b"hello");
+        // The reference encoder's output for no data
+        assert_eq!(decode(b"\x06", &[Encoding::Brotli]).unwrap(), b"");
+        assert!(decode_error(b"\xff\xff\xff", &[Encoding::Brotli])
+            .starts_with("Unable to decode the br encoded body"));
+    }
+
+    #[test]
+    fn encodings_are_undone_last_first() {
+        let text = text();
+        let body = compress(Encoding::Gzip, &compress(Encoding::Zstd, &text));
+        assert_eq!(
+            decode(&body, &[Encoding::Zstd, Encoding::Gzip]).unwrap(),
+            text
+        );
+    }
+
+    #[test]
+    fn truncated_and_corrupt_bodies_fail() {
+        for encoding in [Encoding::Gzip, Encoding::Deflate, Encoding::Zstd] {
+            let compressed = compress(encoding, &text());
+            let message = decode_error(&compressed[..compressed.len() / 2], &[encoding]);
+            assert!(
+                message.ends_with("the body ends in the middle of the compressed data"),
+                "{message}"
+            );
+        }
+        let message = decode_error(b"\x1f\x8b\x08\x00garbage follows", &[Encoding::Gzip]);
+        assert!(
+            message.starts_with("Unable to decode the gzip encoded body: "),
+            "{message}"
+        );
+        assert!(decode_error(b"not zstd at all", &[Encoding::Zstd])
+            .starts_with("Unable to decode the zstd"));
+    }
+
+    #[test]
+    fn data_after_the_stream_is_ignored() {
+        for encoding in [Encoding::Gzip, Encoding::Deflate, Encoding::Zstd] {
+            let body = [compress(encoding, b"data"), b"trailing junk".to_vec()].concat();
+            assert_eq!(decode(&body, &[encoding]).unwrap(), b"data", "{encoding:?}");
+        }
+    }
+
+    #[test]
+    fn empty_body_is_empty() {
+        for encoding in [
+            Encoding::Gzip,
+            Encoding::Deflate,
+            Encoding::Brotli,
+            Encoding::Zstd,
+        ] {
+            assert_eq!(decode(b"", &[encoding]).unwrap(), b"");
+        }
+        assert_eq!(decode(b"as is", &[]).unwrap(), b"as is");
+    }
+
+    #[test]
+    fn content_encoding_values() {
+        assert_eq!(
+            encodings("identity, X-GZIP,br ,, zstd,deflate"),
+            Ok(vec![
+                Encoding::Gzip,
+                Encoding::Brotli,
+                Encoding::Zstd,
+                Encoding::Deflate
+            ])
+        );
+        assert_eq!(encodings(""), Ok(vec![]));
+        assert_eq!(encodings("gzip, compress"), Err("compress".to_string()));
+    }
+}
@@ -0,0 +1,262 @@
+use crate::auth::{hex, Challenge, Credentials};
+use openssl::{
+    error::ErrorStack,
+    hash::{hash, MessageDigest},
+};
+
+enum Algorithm {
+    Md5,
+    Sha256,
+}
+
+/// A Digest challenge (RFC 7616) that we know how to answer.
+pub struct DigestChallenge {
+    realm: String,
+    nonce: String,
+    opaque: Option<String>,
+    algorithm: Algorithm,
+    algorithm_name: Option<String>,
+    session: bool,
+    qop: bool,
+    userhash: bool,
+}
+
+impl DigestChallenge {
+    /// Returns `None` for non-Digest challenges and for Digest variants we do
+    /// not implement (unknown algorithms or auth-int only protection).
+    pub fn from_challenge(challenge: &Challenge) -> Option<DigestChallenge> {
+        if !challenge.scheme.eq_ignore_ascii_case("digest") {
+            return None;
+        }
+
+        let algorithm_name = challenge.param("algorithm").map(str::to_string);
+        let (algorithm, session) = match algorithm_name.as_deref().map(str::to_ascii_uppercase) {
+            None => (Algorithm::Md5, false),
+            Some(name) => match name.as_str() {
+                "MD5" => (Algorithm::Md5, false),
+                "MD5-SESS" => (Algorithm::Md5, true),
+                "SHA-256" => (Algorithm::Sha256, false),
+                "SHA-256-SESS" => (Algorithm::Sha256, true),
+                _ => return None,
+            },
+        };
+
+        // Without a qop parameter the server expects the legacy RFC 2069 response
+        let qop = match challenge.param("qop") {
+            Some(qop) => {
+                if !qop
+                    .split(',')
+                    .any(|q| q.trim().eq_ignore_ascii_case("auth"))
+                {
+                    return None;
+                }
+                true
+            }
+            None => false,
+        };
+
+        Some(DigestChallenge {
+            realm: challenge.param("realm").unwrap_or_default().to_string(),
+            nonce: challenge.param("nonce")?.to_string(),
+            opaque: challenge.param("opaque").map(str::to_string),
+            algorithm,
+            algorithm_name,
+            session,
+            qop,
+            userhash: challenge
+                .param("userhash")
+                .is_some_and(|v| v.eq_ignore_ascii_case("true")),
+        })
+    }
+
+    /// Builds the `Authorization` header value answering this challenge.
+    pub fn authorization(
+        &self,
+        credentials: &Credentials,
+        method: &str,
+        uri: &str,
+        cnonce: &str,
+        nc: u32,
+    ) -> Result<String, ErrorStack> {
+        let nc = format!("{nc:08x}");
+
+        let mut ha1 = self.hash(&format!(
+            "{}:{}:{}",
+            credentials.username, self.realm, credentials.password
+        ))?;
+        if self.session {
+            ha1 = self.hash(&format!("{ha1}:{}:{cnonce}", self.nonce))?;
+        }
+
+        let ha2 = self.hash(&format!("{method}:{uri}"))?;
+
+        let response = if self.qop {
+            self.hash(&format!("{ha1}:{}:{nc}:{cnonce}:auth:{ha2}", self.nonce))?
+        } else {
+            self.hash(&format!("{ha1}:{}:{ha2}", self.nonce))?
+        };
+
+        let username = if self.userhash {
+            self.hash(&format!("{}:{}", credentials.username, self.realm))?
+        } else {
+            quote(&credentials.username)
+        };
+
+        let mut header = format!(
+            "Digest username=\"{username}\", realm=\"{}\", nonce=\"{}\", uri=\"{}\", response=\"{response}\"",
+            quote(&self.realm),
+            quote(&self.nonce),
+            quote(uri),
+        );
+
+        if let Some(algorithm) = &self.algorithm_name {
+            header.push_str(&format!(", algorithm={algorithm}"));
+        }
+        if let Some(opaque) = &self.opaque {
+            header.push_str(&format!(", opaque=\"{}\"", quote(opaque)));
+        }
+        if self.qop {
+            header.push_str(&format!(", qop=auth, nc={nc}, cnonce=\"{cnonce}\""));
+        }
+        if self.userhash {
+            header.push_str(", userhash=true");
+        }
+
+        Ok(header)
+    }
+
+    fn hash(&self, data: &str) -> Result<String, ErrorStack> {
+        let digest = match self.algorithm {
+            Algorithm::Md5 => MessageDigest::md5(),
+            Algorithm::Sha256 => MessageDigest::sha256(),
+        };
+
+        Ok(hex(&hash(digest, data.as_bytes())?))
+    }
+}
+
+/// Generates a random client nonce.
+pub fn cnonce() -> Result<String, ErrorStack> {
+    let mut bytes = [0; 16];
+    openssl::rand::rand_bytes(&mut bytes)?;
+
+    Ok(hex(&bytes))
+}
+
+fn quote(value: &str) -> String {
+    value.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::auth::parse_challenges;
+
+    fn challenge(header: &str) -> Option<DigestChallenge> {
+        parse_challenges(header)
+            .iter()
+            .find_map(DigestChallenge::from_challenge)
+    }
+
+    fn mufasa(password: &str) -> Credentials {
+        Credentials {
+            username: "Mufasa".to_string(),
+            password: password.to_string(),
+        }
+    }
+
+    #[test]
+    fn rfc2617_example() {
+        let challenge = challenge(
+            r#"Digest realm="testrealm@host.com", qop="auth,auth-int", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", opaque="5ccc069c403ebaf9f0171e9517f40e41""#,
+        )
+        .unwrap();
+        let authorization = challenge
+            .authorization(
+                &mufasa("Circle Of Life"),
+                "GET",
+                "/dir/index.html",
+                "0a4f113b",
+                1,
+            )
+            .unwrap();
+
+        assert_eq!(
+            authorization,
+            r#"Digest username="Mufasa", realm="testrealm@host.com", nonce="dcd98b7102dd2f0e8b11d0f600bfb0c093", uri="/dir/index.html", response="6629fae49393a05397450978507c4ef1", opaque="5ccc069c403ebaf9f0171e9517f40e41", qop=auth, nc=00000001, cnonce="0a4f113b""#
+        );
+    }
+
+    #[test]
+    fn rfc7616_examples() {
+        let cnonce = "f2/wE4q74E6zIJEtWaHKaf5wv/H5QzzpXusqGemxURZJ";
+        for (algorithm, response) in [
+            ("MD5", "8ca523f5e9506fed4657c9700eebdbec"),
+            (
+                "SHA-256",
+                "753927fa0e85d155564e2e272a28d1802ca10daf4496794697cf8db5856cb6c1",
+            ),
+        ] {
+            let header = format!(
+                r#"Digest realm="http-auth@example.org", qop="auth, auth-int", algorithm={algorithm}, nonce="7ypf/xlj9XXwfDPEoM4URrv/xwf94BcCAzFZH4GiTo0v", opaque="FQhe/qaU925kfnzjCev0ciny7QMkPqMAFRtzCUYo5tdS""#
+            );
+            let authorization = challenge(&header)
+                .unwrap()
+                .authorization(
+                    &mufasa("Circle of Life"),
+                    "GET",
+                    "/dir/index.html",
+                    cnonce,
+                    1,
+                )
+                .unwrap();
+
+            assert!(authorization.contains(&format!("response=\"{response}\"")));
+            assert!(authorization.contains(&format!(", algorithm={algorithm},")));
+            assert!(authorization.contains(&format!("nc=00000001, cnonce=\"{cnonce}\"")));
+        }
+    }
+
+    #[test]
+    fn rfc2069_without_qop() {
+        let authorization = challenge(r#"Digest realm="r", nonce="n""#)
+            .unwrap()
+            .authorization(&mufasa("p"), "GET", "/", "c", 1)
+            .unwrap();
+        let ha1 = hex(&hash(MessageDigest::md5(), b"Mufasa:r:p").unwrap());
+        let ha2 = hex(&hash(MessageDigest::md5(), b"GET:/").unwrap());
+        let response =
+            hex(&hash(MessageDigest::md5(), format!("{ha1}:n:{ha2}").as_bytes()).unwrap());
+
+        assert!(authorization.contains(&format!("response=\"{response}\"")));
+        assert!(!authorization.contains("qop"));
+        assert!(!authorization.contains("algorithm"));
+    }
+
+    #[test]
+    fn userhash_and_quoting() {
+        let authorization = challenge(r#"Digest realm="a\"b", nonce="n", userhash=true"#)
+            .unwrap()
+            .authorization(&mufasa("p"), "GET", "/", "c", 1)
+            .unwrap();
+        let userhash = hex(&hash(MessageDigest::md5(), b"Mufasa:a\"b").unwrap());
+
+        assert!(
+            authorization.starts_with(&format!("Digest username=\"{userhash}\", realm=\"a\\\"b\""))
+        );
+        assert!(authorization.ends_with(", userhash=true"));
+    }
+
+    #[test]
+    fn picks_digest_among_schemes() {
+        assert!(challenge(r#"Basic realm="a", Digest realm="b", nonce="c""#).is_some());
+        assert!(challenge(r#"Basic realm="a""#).is_none());
+    }
+
+    #[test]
+    fn rejects_unsupported_challenges() {
+        assert!(challenge(r#"Digest realm="r""#).is_none());
+        assert!(challenge(r#"Digest realm="r", nonce="n", algorithm=SHA-512-256"#).is_none());
+        assert!(challenge(r#"Digest realm="r", nonce="n", qop="auth-int""#).is_none());
+    }
+}
@@ -0,0 +1,688 @@
+use crate::{
+    exit,
+    local::Local,
+    rate,
+    resolve::Resolver,
+    timing::Clock,
+    tls::{self, TlsVersion},
+};
+use bytes::Bytes;
+use http::{header::EXPECT, HeaderMap, StatusCode};
+use http_body::{Body as HttpBody, Frame, SizeHint};
+use hyper::client::conn::http1;
+use hyper_util::{client::legacy::connect::HttpInfo, rt::TokioIo};
+use reqwest::{
+    blocking::{Body, Request, Response},
+    dns::{Name, Resolve},
+    ResponseBuilderExt, Version,
+};
+use rustls::{
+    pki_types::{CertificateDer, ServerName},
+    ClientConfig,
+};
+use std::{
+    error::Error,
+    fmt::Display,
+    future::Future,
+    io::{self, Cursor, Read},
+    net::SocketAddr,
+    pin::Pin,
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+    task::{Context, Poll},
+    thread,
+    time::Duration,
+};
+use tokio::{
+    io::{AsyncRead, AsyncWrite},
+    net::TcpStream,
+    runtime::Runtime,
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+    time::{Instant, Sleep},
+};
+use tokio_rustls::TlsConnector;
+use url::{Host, Position, Url};
+
+/// Why a request sent over a connection of curl's own failed.
+#[derive(Debug)]
+pub enum DirectError {
+    Start(io::Error),
+    Resolve(Box<dyn Error + Send + Sync>),
+    Bind(String),
+    Connect(io::Error),
+    Tls(String, io::Error),
+    ConnectTimeout(Duration),
+    Timeout(Option<Duration>),
+    Http(hyper::Error),
+    Message(http::Error),
+}
+
+impl Display for DirectError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            DirectError::Start(e) => write!(f, "Unable to start connecting: {e}"),
+            DirectError::Resolve(e) => write!(f, "{e}"),
+            DirectError::Bind(reason) => write!(
+                f,
+                "Unable to bind the local end of the connection: {reason}"
+            ),
+            DirectError::Connect(_) => f.write_str(exit::CONNECT_FAILED),
+            DirectError::Tls(server, _) if exit::pin_mismatch(self) => write!(
+                f,
+                "The public key of the server {server} does not match --pinnedpubkey"
+            ),
+            DirectError::Tls(server, e) => match exit::certificate_error(self) {
+                Some(reason) => write!(
+                    f,
+                    "The certificate of the server {server} failed verification: {reason}"
+                ),
+                None => write!(
+                    f,
+                    "TLS handshake with the server {server} failed: {}",
+                    exit::tls_error(self).unwrap_or_else(|| e.to_string())
+                ),
+            },
+            DirectError::ConnectTimeout(timeout) => write!(
+                f,
+                "Connection timed out after {} seconds.",
+                timeout.as_secs_f64()
+            ),
+            DirectError::Timeout(Some(timeout)) => write!(
+                f,
+                "Operation timed out after {} seconds.",
+                timeout.as_secs_f64()
+            ),
+            DirectError::Timeout(None) => write!(f, "Operation timed out."),
+            DirectError::Http(e) => write!(f, "{e}"),
+            DirectError::Message(e) => write!(f, "Invalid HTTP message: {e}"),
+        }
+    }
+}
+
+impl Error for DirectError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            DirectError::Resolve(e) => Some(e.as_ref()),
+            DirectError::Start(e) | DirectError::Connect(e) | DirectError::Tls(_, e) => Some(e),
+            DirectError::Http(e) => Some(e),
+            DirectError::Message(e) => Some(e),
+            DirectError::Bind(_) | DirectError::ConnectTimeout(_) | DirectError::Timeout(_) => None,
+        }
+    }
+}
+
+/// A request body as it is made, before it is given to a request. A
+/// streamed one is read as it is sent, so it can be sent once.
+pub enum Upload {
+    Buffered(Vec<u8>),
+    Streamed(Box<dyn Read + Send>, Option<u64>),
+}
+
+impl Upload {
+    /// How long the body is, when that is known before it is read.
+    pub fn length(&self) -> Option<u64> {
+        match self {
+            Upload::Buffered(body) => Some(body.len() as u64),
+            Upload::Streamed(_, length) => *length,
+        }
+    }
+}
+
+impl From<Upload> for Body {
+    fn from(upload: Upload) -> Body {
+        match upload {
+            Upload::Buffered(body) => Body::from(body),
+            Upload::Streamed(reader, Some(length)) => Body::sized(reader, length),
+            Upload::Streamed(reader, None) => Body::new(reader),
+        }
+    }
+}
+
+/// The connection a response read by [`Direct`] came over.
+#[derive(Clone)]
+pub struct Connection {
+    pub local: SocketAddr,
+    pub remote: SocketAddr,
+    /// The TLS version and cipher suite, for HTTPS.
+    pub tls: Option<(TlsVersion, String)>,
+    pub certificate: Option<CertificateDer<'static>>,
+}
+
+/// The local and remote address of the connection `resp` came over, by the
+/// client or by [`Direct`].
+pub fn addrs(resp: &Response) -> Option<(SocketAddr, SocketAddr)> {
+    if let Some(connection) = resp.extensions().get::<Connection>() {
+        return Some((connection.local, connection.remote));
+    }
+
+    let info = resp.extensions().get::<HttpInfo>()?;
+    Some((info.local_addr(), info.remote_addr()))
+}
+
+/// Whether `headers` ask the server to tell when to send the body, with
+/// `Expect: 100-continue`.
+pub fn expects_continue(headers: &HeaderMap) -> bool {
+    headers
+        .get(EXPECT)
+        .is_some_and(|value| value.as_bytes().eq_ignore_ascii_case(b"100-continue"))
+}
+
+/// Sends requests over HTTP/1.1 connections of its own, for what the client
+/// cannot do: connecting from a port of the `--local-port` range, and
+/// holding the body of an `Expect: 100-continue` request back until the
+/// server asks for it. The connections go to the addresses the client's
+/// resolver gives, with its TLS configuration, and are closed after the
+/// response.
+pub struct Direct {
+    // Started with the first connection, most invocations never make one
+    runtime: OnceLock<Runtime>,
+    clock: Arc<Clock>,
+    resolver: Arc<Resolver>,
+    local: Local,
+    tls: TlsConnector,
+    connect_timeout: Option<Duration>,
+    max_time: Option<Duration>,
+    rate: Option<u64>,
+    expect_timeout: Duration,
+    // The reader of the streamed body made last, shared with the body the request was given
+    stream: Mutex<Option<(Shared, Option<u64>)>>,
+}
+
+/// How [`Direct`] connects and sends.
+pub struct DirectOptions {
+    pub local: Local,
+    pub tls: ClientConfig,
+    pub connect_timeout: Option<Duration>,
+    pub max_time: Option<Duration>,
+    pub rate: Option<u64>,
+    /// How long a body waits for the server's 100 Continue.
+    pub expect_timeout: Duration,
+}
+
+impl Direct {
+    pub fn new(clock: Arc<Clock>, resolver: Arc<Resolver>, options: DirectOptions) -> Direct {
+        let mut tls = options.tls;
+        tls.alpn_protocols = vec![b"http/1.1".to_vec()];
+
+        Direct {
+            runtime: OnceLock::new(),
+            clock,
+            resolver,
+            local: options.local,
+            tls: TlsConnector::from(Arc::new(tls)),
+            connect_timeout: options.connect_timeout,
+            max_time: options.max_time,
+            rate: options.rate,
+            expect_timeout: options.expect_timeout,
+            stream: Mutex::new(None),
+        }
+    }
+
+    /// Whether every request must be sent with [`Direct::execute`], as
+    /// connections come from the `--local-port` range.
+    pub fn binds(&self) -> bool {
+        self.local.has_ports()
+    }
+
+    /// The body for a request to send with [`Direct::execute`]. The reader
+    /// of a streamed one is kept, the request's own body cannot be read.
+    pub fn body(&self, upload: Upload) -> Body {
+        let Upload::Streamed(reader, length) = upload else {
+            return Body::from(upload);
+        };
+
+        let shared = Shared(Arc::new(Mutex::new(reader)));
+        if let Ok(mut stream) = self.stream.lock() {
+            *stream = Some((shared.clone(), length));
+        }
+        Body::from(Upload::Streamed(Box::new(shared), length))
+    }
+
+    /// Lets go of the reader of the body made last, for a request the
+    /// client sends instead.
+    pub fn forget(&self) {
+        if let Ok(mut stream) = self.stream.lock() {
+            *stream = None;
+        }
+    }
+
+    // The runtime the connections are driven on
+    fn runtime(&self) -> Result<&Runtime, DirectError> {
+        if let Some(runtime) = self.runtime.get() {
+            return Ok(runtime);
+        }
+
+        // One thread drives the connection while the response is read on the caller's
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(1)
+            .enable_all()
+            .build()
+            .map_err(DirectError::Start)?;
+        Ok(self.runtime.get_or_init(|| runtime))
+    }
+
+    /// Sends `request` and reads the response head, within the request's
+    /// timeout, which the response body is read within too.
+    pub fn execute(&self, mut request: Request) -> Result<Response, DirectError> {
+        let body = self.outgoing(&mut request);
+        let timeout = request.timeout().copied();
+
+        self.runtime()?.block_on(async {
+            let deadline = timeout.map(|timeout| Instant::now() + timeout);
+            let sending = self.send(request, body, deadline);
+            match deadline {
+                Some(deadline) => tokio::time::timeout_at(deadline, sending)
+                    .await
+                    .map_err(|_| DirectError::Timeout(self.max_time))?,
+                None => sending.await,
+            }
+        })
+    }
+
+    /// Whether the server of `url` can be reached, looking it up and
+    /// connecting as a request would, and hanging up again. The client
+    /// reports a connection it could not make for a streamed body as the
+    /// body failing, which this finds the cause of.
+    pub fn reach(&self, url: &Url) -> Result<(), DirectError> {
+        self.runtime()?.block_on(async {
+            let addrs = self.lookup(url).await?;
+            let opening = self.open(url.host_str().unwrap_or_default(), addrs);
+            match self.connect_timeout {
+                Some(timeout) => tokio::time::timeout(timeout, opening)
+                    .await
+                    .map_err(|_| DirectError::ConnectTimeout(timeout))??,
+                None => opening.await?,
+            };
+            Ok(())
+        })
+    }
+
+    // The request body as it goes out, no faster than --limit-rate
+    fn outgoing(&self, request: &mut Request) -> Outgoing {
+        let Some(body) = request.body_mut() else {
+            return Outgoing::Bytes(None);
+        };
+
+        let stream = self.stream.lock().ok().and_then(|mut stream| stream.take());
+        if let Some((reader, length)) = stream.filter(|_| body.as_bytes().is_none()) {
+            return Outgoing::stream(reader, length);
+        }
+        // Any other streamed body is made from memory, so reading it whole cannot fail
+        let body = Bytes::copy_from_slice(body.buffer().unwrap_or_default());
+        match self.rate {
+            Some(rate) => {
+                let length = body.len() as u64;
+                Outgoing::stream(
+                    rate::Reader::new(Cursor::new(body), Some(rate)),
+                    Some(length),
+                )
+            }
+            None => Outgoing::Bytes(Some(body)),
+        }
+    }
+
+    async fn send(
+        &self,
+        request: Request,
+        body: Outgoing,
+        deadline: Option<Instant>,
+    ) -> Result<Response, DirectError> {
+        let url = request.url().clone();
+        let addrs = self.lookup(&url).await?;
+
+        self.clock.connecting();
+        let connecting = self.connect(&url, addrs);
+        let (io, connection) = match self.connect_timeout {
+            Some(timeout) => tokio::time::timeout(timeout, connecting)
+                .await
+                .map_err(|_| DirectError::ConnectTimeout(timeout))??,
+            None => connecting.await?,
+        };
+        self.clock.connected();
+
+        let (mut sender, conn) = http1::handshake(TokioIo::new(io))
+            .await
+            .map_err(DirectError::Http)?;
+        let conn = Ends(tokio::spawn(async move {
+            let _ = conn.await;
+        }));
+
+        let version = match request.version() {
+            Version::HTTP_10 => Version::HTTP_10,
+            _ => Version::HTTP_11,
+        };
+        let mut outgoing = http::Request::builder()
+            .method(request.method().clone())
+            .uri(&url[Position::BeforePath..Position::AfterQuery])
+            .version(version);
+        if let Some(headers) = outgoing.headers_mut() {
+            *headers = request.headers().clone();
+        }
+        // With "Expect: 100-continue" the body waits for the server to ask for it, or for the
+        // final response when that comes first
+        let expects = expects_continue(request.headers());
+        let (answer, answered) = oneshot::channel();
+        let answer = Arc::new(Mutex::new(Some(answer).filter(|_| expects)));
+        let body = Held {
+            body,
+            answered: Some(answered).filter(|_| expects),
+            timeout: self.expect_timeout,
+            waiting: None,
+            refused: false,
+        };
+        let mut outgoing = outgoing.body(body).map_err(DirectError::Message)?;
+        let continued = answer.clone();
+        hyper::ext::on_informational(&mut outgoing, move |resp| {
+            if resp.status() == StatusCode::CONTINUE {
+                tell(&continued, true);
+            }
+        });
+
+        let resp = sender
+            .send_request(outgoing)
+            .await
+            .map_err(DirectError::Http)?;
+        // A body the server refuses before asking for it is never sent
+        tell(
+            &answer,
+            !resp.status().is_client_error() && !resp.status().is_server_error(),
+        );
+        let (parts, body) = resp.into_parts();
+        let mut resp = http::Response::builder()
+            .status(parts.status)
+            .version(parts.version)
+            .url(url)
+            .extension(connection);
+        if let Some(headers) = resp.headers_mut() {
+            *headers = parts.headers;
+        }
+        let body = Incoming {
+            body,
+            deadline: deadline.map(|deadline| Box::pin(tokio::time::sleep_until(deadline))),
+            _conn: conn,
+        };
+        let resp = resp
+            .body(reqwest::Body::wrap(body))
+            .map_err(DirectError::Message)?;
+
+        Ok(Response::from(resp))
+    }
+
+    // The addresses to connect to for `url`, with its port unless the resolver gives another for
+    // a URL without one, as the client does
+    async fn lookup(&self, url: &Url) -> Result<Vec<SocketAddr>, DirectError> {
+        let port = url.port_or_known_default().unwrap_or(80);
+        let name = match url.host() {
+            Some(Host::Domain(name)) => name,
+            Some(Host::Ipv4(ip)) => return Ok(vec![SocketAddr::new(ip.into(), port)]),
+            Some(Host::Ipv6(ip)) => return Ok(vec![SocketAddr::new(ip.into(), port)]),
+            None => return Err(DirectError::Resolve(format!("No host in {url}").into())),
+        };
+
+        let name = Name::from_str(name).map_err(|e| DirectError::Resolve(Box::new(e)))?;
+        let addrs = self
+            .resolver
+            .resolve(name)
+            .await
+            .map_err(DirectError::Resolve)?;
+        Ok(addrs
+            .map(|mut addr| {
+                if url.port().is_some() || addr.port() == 0 {
+                    addr.set_port(port);
+                }
+                addr
+            })
+            .collect())
+    }
+
+    // Connects to the first of `addrs` that answers, from the local end
+    async fn open(
+        &self,
+        host: &str,
+        addrs: Vec<SocketAddr>,
+    ) -> Result<std::net::TcpStream, DirectError> {
+        let mut failure = DirectError::Resolve(format!("Could not resolve host: {host}").into());
+        for addr in addrs {
+            let (local, timeout) = (self.local.clone(), self.connect_timeout);
+            // Binding and connecting block, which the thread driving connections must not
+            let connecting = tokio::task::spawn_blocking(move || local.connect(addr, timeout));
+            match connecting
+                .await
+                .map_err(|e| DirectError::Start(io::Error::other(e)))?
+            {
+                Ok(stream) => return Ok(stream),
+                Err(e) => failure = e,
+            }
+        }
+
+        Err(failure)
+    }
+
+    // Connects to the first of `addrs` that answers from the local end, with TLS for HTTPS
+    async fn connect(
+        &self,
+        url: &Url,
+        addrs: Vec<SocketAddr>,
+    ) -> Result<(Box<dyn Io>, Connection), DirectError> {
+        let host = url.host_str().unwrap_or_default();
+        let stream = self.open(host, addrs).await?;
+
+        let addr_error = DirectError::Connect;
+        let (local, remote) = (
+            stream.local_addr().map_err(addr_error)?,
+            stream.peer_addr().map_err(addr_error)?,
+        );
+        stream.set_nonblocking(true).map_err(addr_error)?;
+        let stream = TcpStream::from_std(stream).map_err(addr_error)?;
+        let mut connection = Connection {
+            local,
+            remote,
+            tls: None,
+            certificate: None,
+        };
+        if url.scheme() != "https" {
+            return Ok((Box::new(stream), connection));
+        }
+
+        let server = format!("{host}:{}", url.port_or_known_default().unwrap_or(443));
+        let tls_error = |e| DirectError::Tls(server.clone(), e);
+        let name = ServerName::try_from(host.trim_matches(['[', ']']).to_string())
+            .map_err(|e| tls_error(io::Error::new(io::ErrorKind::InvalidInput, e)))?;
+        let stream = self.tls.connect(name, stream).await.map_err(tls_error)?;
+        let (_, session) = stream.get_ref();
+        connection.tls = tls::negotiated_by(session);
+        connection.certificate = session
+            .peer_certificates()
+            .and_then(|chain| chain.first())
+            .cloned();
+
+        Ok((Box::new(stream), connection))
+    }
+}
+
+// What a connection is read from and written to, with TLS or without
+trait Io: AsyncRead + AsyncWrite + Send + Unpin {}
+
+impl<T: AsyncRead + AsyncWrite + Send + Unpin> Io for T {}
+
+// A streamed body's reader, read through the body the request was given or by the connection
+#[derive(Clone)]
+struct Shared(Arc<Mutex<Box<dyn Read + Send>>>);
+
+impl Read for Shared {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        self.0
+            .lock()
+            .map_err(|_| io::Error::other("the body was read by a thread that panicked"))?
+            .read(buf)
+    }
+}
+
+// The request body as it is sent
+enum Outgoing {
+    Bytes(Option<Bytes>),
+    // Chunks read on a thread of their own, with the length when it is known
+    Stream(mpsc::Receiver<io::Result<Bytes>>, Option<u64>),
+}
+
+impl Outgoing {
+    // The body read from `reader` as the connection takes what was read before
+    fn stream(mut reader: impl Read + Send + 'static, length: Option<u64>) -> Outgoing {
+        let (sender, receiver) = mpsc::channel(1);
+        thread::spawn(move || loop {
+            let mut chunk = vec![0; 64 * 1024];
+            let read = match reader.read(&mut chunk) {
+                Ok(0) => return,
+                Ok(read) => {
+                    chunk.truncate(read);
+                    Ok(Bytes::from(chunk))
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Err(e),
+            };
+            let failed = read.is_err();
+            if sender.blocking_send(read).is_err() || failed {
+                return;
+            }
+        });
+
+        Outgoing::Stream(receiver, length)
+    }
+}
+
+impl HttpBody for Outgoing {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        match self.get_mut() {
+            Outgoing::Bytes(body) => Poll::Ready(body.take().map(|body| Ok(Frame::data(body)))),
+            Outgoing::Stream(chunks, _) => chunks
+                .poll_recv(cx)
+                .map(|chunk| chunk.map(|chunk| chunk.map(Frame::data))),
+        }
+    }
+
+    fn is_end_stream(&self) -> bool {
+        matches!(self, Outgoing::Bytes(None))
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        match self {
+            Outgoing::Bytes(body) => {
+                SizeHint::with_exact(body.as_ref().map_or(0, |body| body.len() as u64))
+            }
+            Outgoing::Stream(_, Some(length)) => SizeHint::with_exact(*length),
+            Outgoing::Stream(_, None) => SizeHint::default(),
+        }
+    }
+}
+
+// Tells a body held for a 100 Continue whether to go, by the first response to say
+fn tell(answer: &Mutex<Option<oneshot::Sender<bool>>>, send: bool) {
+    if let Some(answer) = answer.lock().ok().and_then(|mut answer| answer.take()) {
+        let _ = answer.send(send);
+    }
+}
+
+// A request body held back until the server answers "Expect: 100-continue", or for the timeout
+// after the head went out when it does not
+struct Held {
+    body: Outgoing,
+    // Whether to send, once the interim or final response says, None when not waiting
+    answered: Option<oneshot::Receiver<bool>>,
+    timeout: Duration,
+    // Started when the body is first asked for, which is once the head is written
+    waiting: Option<Pin<Box<Sleep>>>,
+    // Kept back for good, the connection is closed with the response
+    refused: bool,
+}
+
+impl HttpBody for Held {
+    type Data = Bytes;
+    type Error = io::Error;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Bytes>, io::Error>>> {
+        let this = self.get_mut();
+        if this.refused {
+            return Poll::Pending;
+        }
+        if let Some(answered) = &mut this.answered {
+            let timeout = this.timeout;
+            let waiting = this
+                .waiting
+                .get_or_insert_with(|| Box::pin(tokio::time::sleep(timeout)));
+            match Pin::new(answered).poll(cx) {
+                Poll::Ready(Ok(false)) => {
+                    this.refused = true;
+                    return Poll::Pending;
+                }
+                Poll::Ready(_) => {}
+                Poll::Pending if waiting.as_mut().poll(cx).is_ready() => {}
+                Poll::Pending => return Poll::Pending,
+            }
+            this.answered = None;
+        }
+
+        Pin::new(&mut this.body).poll_frame(cx)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
+
+// The task driving a connection, which is ended when the response is done with
+struct Ends(JoinHandle<()>);
+
+impl Drop for Ends {
+    fn drop(&mut self) {
+        self.0.abort();
+    }
+}
+
+// The response body, read by the deadline of the request when it has one
+struct Incoming {
+    body: hyper::body::Incoming,
+    deadline: Option<Pin<Box<Sleep>>>,
+    _conn: Ends,
+}
+
+impl HttpBody for Incoming {
+    type Data = Bytes;
+    type Error = Box<dyn Error + Send + Sync>;
+
+    fn poll_frame(
+        self: Pin<&mut Self>,
+        cx: &mut Context,
+    ) -> Poll<Option<Result<Frame<Bytes>, Self::Error>>> {
+        let this = self.get_mut();
+        if let Some(deadline) = &mut this.deadline {
+            if deadline.as_mut().poll(cx).is_ready() {
+                let e = io::Error::new(io::ErrorKind::TimedOut, "Operation timed out");
+                return Poll::Ready(Some(Err(e.into())));
+            }
+        }
+
+        Pin::new(&mut this.body).poll_frame(cx).map_err(Into::into)
+    }
+
+    fn is_end_stream(&self) -> bool {
+        self.body.is_end_stream()
+    }
+
+    fn size_hint(&self) -> SizeHint {
+        self.body.size_hint()
+    }
+}
@@ -0,0 +1,176 @@
+use reqwest::header::{ACCEPT, CONTENT_TYPE};
+use std::{
+    collections::HashMap,
+    error::Error,
+    fmt::Display,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr},
+    sync::Mutex,
+};
+use url::Url;
+
+const DNS_MESSAGE: &str = "application/dns-message";
+const TYPE_A: u16 = 1;
+const TYPE_AAAA: u16 = 28;
+
+/// A DNS-over-HTTPS server that failed to answer, as opposed to a host it
+/// has no address for.
+#[derive(Debug)]
+pub struct DohError {
+    server: String,
+    reason: String,
+}
+
+impl Display for DohError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "The DNS-over-HTTPS server {} failed: {}",
+            self.server, self.reason
+        )
+    }
+}
+
+impl Error for DohError {}
+
+// Answers for the whole invocation, by host name
+static CACHE: Mutex<Option<HashMap<String, Vec<IpAddr>>>> = Mutex::new(None);
+
+/// Looks host names up with a `--doh-url` server, RFC 8484.
+pub struct Doh {
+    url: Url,
+    client: reqwest::Client,
+}
+
+impl Doh {
+    /// A resolver asking the server at `url` through `client`.
+    pub fn new(url: Url, client: reqwest::Client) -> Doh {
+        Doh { url, client }
+    }
+
+    /// The IPv4 and IPv6 addresses of `host`, empty when it has none. An
+    /// answer is kept for later lookups of the same host.
+    pub async fn lookup(&self, host: &str) -> Result<Vec<IpAddr>, DohError> {
+        let host = host.trim_end_matches('.').to_ascii_lowercase();
+        if let Some(addrs) = CACHE
+            .lock()
+            .ok()
+            .and_then(|cache| cache.as_ref()?.get(&host).cloned())
+        {
+            return Ok(addrs);
+        }
+
+        let mut addrs = self.query(&host, TYPE_A).await?;
+        addrs.extend(self.query(&host, TYPE_AAAA).await?);
+        if let Ok(mut cache) = CACHE.lock() {
+            cache
+                .get_or_insert_with(HashMap::new)
+                .insert(host, addrs.clone());
+        }
+
+        Ok(addrs)
+    }
+
+    // POSTs one query for the records of `record_type` and reads the addresses in the answer
+    async fn query(&self, host: &str, record_type: u16) -> Result<Vec<IpAddr>, DohError> {
+        let fail = |reason: String| DohError {
+            server: self.url.to_string(),
+            reason,
+        };
+
+        let resp = self
+            .client
+            .post(self.url.clone())
+            .header(CONTENT_TYPE, DNS_MESSAGE)
+            .header(ACCEPT, DNS_MESSAGE)
+            .body(encode_query(host, record_type).map_err(fail)?)
+            .send()
+            .await
+            .map_err(|e| fail(crate::exit::cause(&e)))?;
+        if !resp.status().is_success() {
+            return Err(fail(format!("it answered with {}", resp.status())));
+        }
+        let body = resp.bytes().await.map_err(|e| fail(e.to_string()))?;
+
+        decode_answer(&body, record_type).map_err(fail)
+    }
+}
+
+// A query with ID 0, as the RFC recommends for caching, asking for recursion
+fn encode_query(host: &str, record_type: u16) -> Result<Vec<u8>, String> {
+    let mut query = vec![0, 0, 1, 0, 0, 1, 0, 0, 0, 0, 0, 0];
+    for label in host.split('.') {
+        let len = u8::try_from(label.len())
+            .ok()
+            .filter(|len| (1..64).contains(len))
+            .ok_or_else(|| format!("'{host}' is not a valid host name"))?;
+        query.push(len);
+        query.extend_from_slice(label.as_bytes());
+    }
+    query.push(0);
+    query.extend_from_slice(&record_type.to_be_bytes());
+    query.extend_from_slice(&1u16.to_be_bytes());
+
+    Ok(query)
+}
+
+// The addresses in the answer section, skipping the CNAME records that lead to them
+fn decode_answer(message: &[u8], record_type: u16) -> Result<Vec<IpAddr>, String> {
+    let malformed = || "its answer is not a valid DNS message".to_string();
+    let u16_at = |at: usize| {
+        message
+            .get(at..at + 2)
+            .map(|bytes| u16::from_be_bytes([bytes[0], bytes[1]]))
+            .ok_or_else(malformed)
+    };
+
+    // RCODE 3 is NXDOMAIN, a host that does not exist
+    match u16_at(2)? & 0xf {
+        0 => {}
+        3 => return Ok(Vec::new()),
+        code => return Err(format!("it answered with DNS error code {code}")),
+    }
+    let (questions, answers) = (u16_at(4)?, u16_at(6)?);
+
+    let mut at = 12;
+    for _ in 0..questions {
+        at = skip_name(message, at).ok_or_else(malformed)? + 4;
+    }
+    let mut addrs = Vec::new();
+    for _ in 0..answers {
+        at = skip_name(message, at).ok_or_else(malformed)?;
+        let (kind, len) = (u16_at(at)?, usize::from(u16_at(at + 8)?));
+        let data = message.get(at + 10..at + 10 + len).ok_or_else(malformed)?;
+        at += 10 + len;
+
+        if kind != record_type {
+            continue;
+        }
+        if let Ok(octets) = <[u8; 4]>::try_from(data) {
+            addrs.push(IpAddr::V4(Ipv4Addr::from(octets)));
+        } else if let Ok(octets) = <[u8; 16]>::try_from(data) {
+            addrs.push(IpAddr::V6(Ipv6Addr::from(octets)));
+        }
+    }
+
+    Ok(addrs)
+}
+
+// Where the name at `at` ends, which a compression pointer ends at once
+fn skip_name(message: &[u8], mut at: usize) -> Option<usize> {
+    loop {
+        let len = *message.get(at)?;
+        match len {
+            0 => return Some(at + 1),
+            len if len & 0xc0 == 0xc0 => return Some(at + 2),
+            len => at += 1 + usize::from(len),
+        }
+    }
+}
+
+/// Parses a `--doh-url`, which must be an http:// or https:// URL.
+pub fn parse_url(s: &str) -> Result<Url, String> {
+    Url::parse(s)
+        .ok()
+        .filter(|url| matches!(url.scheme(), "http" | "https") && url.host_str().is_some())
+        .ok_or_else(|| format!("'{s}' is not a valid --doh-url, use an https:// URL"))
+}
@@ -0,0 +1,476 @@
+use crate::{
+    auth::AuthError,
+    data::{DataError, JsonError},
+    decode::DecodeError,
+    direct::DirectError,
+    doh::DohError,
+    file::FileError,
+    form::FormError,
+    netrc::NetrcError,
+    output::{OutputError, TooLarge},
+    resolve::ResolveError,
+    tls::{PinMismatch, ProxyCertificate},
+};
+use rustls::{AlertDescription, CertificateError, OtherError};
+use std::{error::Error, io};
+
+/// What a connection to the server that could not be made is reported as,
+/// whether the client or [`crate::direct::Direct`] tried.
+pub const CONNECT_FAILED: &str =
+    "Unable to connect to the server. Perhaps the network is offline or the server is not running.";
+
+/// Why curl failed, for the exit code scripts check.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Exit {
+    UnsupportedProtocol,
+    Init,
+    MalformedUrl,
+    ResolveProxy,
+    ResolveHost,
+    Connect,
+    Http,
+    Write,
+    Read,
+    Timeout,
+    RangeError,
+    SslConnect,
+    FileCouldntRead,
+    InterfaceFailed,
+    TooManyRedirects,
+    PeerFailedVerification,
+    Recv,
+    SslCertProblem,
+    BadContentEncoding,
+    FileTooLarge,
+    BadCaCertFile,
+    PinnedPubKeyMismatch,
+    Proxy,
+}
+
+impl Exit {
+    /// The exit code curl uses for the same failure.
+    pub fn code(self) -> i32 {
+        match self {
+            Exit::UnsupportedProtocol => 1,
+            Exit::Init => 2,
+            Exit::MalformedUrl => 3,
+            Exit::ResolveProxy => 5,
+            Exit::ResolveHost => 6,
+            Exit::Connect => 7,
+            Exit::Http => 22,
+            Exit::Write => 23,
+            Exit::Read => 26,
+            Exit::Timeout => 28,
+            Exit::RangeError => 33,
+            Exit::SslConnect => 35,
+            Exit::FileCouldntRead => 37,
+            Exit::InterfaceFailed => 45,
+            Exit::TooManyRedirects => 47,
+            Exit::PeerFailedVerification => 60,
+            Exit::Recv => 56,
+            Exit::SslCertProblem => 58,
+            Exit::BadContentEncoding => 61,
+            Exit::FileTooLarge => 63,
+            Exit::BadCaCertFile => 77,
+            Exit::PinnedPubKeyMismatch => 90,
+            Exit::Proxy => 97,
+        }
+    }
+}
+
+impl From<&reqwest::Error> for Exit {
+    fn from(e: &reqwest::Error) -> Exit {
+        if e.is_timeout() {
+            Exit::Timeout
+        } else if e.is_connect() && (resolve_error(e).is_some() || doh_error(e).is_some()) {
+            Exit::ResolveHost
+        } else if e.is_connect() && pin_mismatch(e) {
+            Exit::PinnedPubKeyMismatch
+        } else if e.is_connect() && certificate_error(e).is_some() {
+            Exit::PeerFailedVerification
+        } else if e.is_connect() && tls_error(e).is_some() {
+            Exit::SslConnect
+        } else if e.is_connect() && socks_error(e).is_some() {
+            Exit::Proxy
+        } else if e.is_connect() {
+            Exit::Connect
+        } else if e.is_builder() {
+            Exit::MalformedUrl
+        } else if e.is_redirect() {
+            Exit::TooManyRedirects
+        } else {
+            Exit::Recv
+        }
+    }
+}
+
+impl From<&OutputError> for Exit {
+    fn from(e: &OutputError) -> Exit {
+        match e {
+            OutputError::Body(e) if e.get_ref().is_some_and(|e| e.is::<DecodeError>()) => {
+                Exit::BadContentEncoding
+            }
+            OutputError::Body(e) if e.get_ref().is_some_and(|e| e.is::<TooLarge>()) => {
+                Exit::FileTooLarge
+            }
+            OutputError::Body(_) => Exit::Recv,
+            OutputError::NoFileName(_)
+            | OutputError::UnsafeFileName(_)
+            | OutputError::Binary
+            | OutputError::Write(_) => Exit::Write,
+        }
+    }
+}
+
+impl From<&AuthError> for Exit {
+    fn from(e: &AuthError) -> Exit {
+        match e {
+            AuthError::ReadPassword(_) | AuthError::ReadToken(..) => Exit::Read,
+            AuthError::NotATerminal(_) | AuthError::EmptyToken | AuthError::NoPassphrase(_) => {
+                Exit::Init
+            }
+        }
+    }
+}
+
+impl From<&DirectError> for Exit {
+    fn from(e: &DirectError) -> Exit {
+        match e {
+            DirectError::Start(_) => Exit::Init,
+            DirectError::Resolve(_) => Exit::ResolveHost,
+            DirectError::Bind(_) => Exit::InterfaceFailed,
+            DirectError::Connect(_) => Exit::Connect,
+            DirectError::Tls(..) if pin_mismatch(e) => Exit::PinnedPubKeyMismatch,
+            DirectError::Tls(..) if certificate_error(e).is_some() => Exit::PeerFailedVerification,
+            DirectError::Tls(..) => Exit::SslConnect,
+            DirectError::ConnectTimeout(_) | DirectError::Timeout(_) => Exit::Timeout,
+            DirectError::Http(_) | DirectError::Message(_) => Exit::Recv,
+        }
+    }
+}
+
+impl From<&DataError> for Exit {
+    fn from(_: &DataError) -> Exit {
+        Exit::Read
+    }
+}
+
+impl From<&JsonError> for Exit {
+    fn from(_: &JsonError) -> Exit {
+        Exit::Init
+    }
+}
+
+impl From<&FileError> for Exit {
+    fn from(e: &FileError) -> Exit {
+        match e {
+            FileError::NotLocal(_) => Exit::MalformedUrl,
+            FileError::Open(..) => Exit::FileCouldntRead,
+            FileError::Range(_) => Exit::RangeError,
+        }
+    }
+}
+
+impl From<&FormError> for Exit {
+    fn from(e: &FormError) -> Exit {
+        match e {
+            FormError::Invalid(_) => Exit::Init,
+            FormError::Read(..) => Exit::Read,
+        }
+    }
+}
+
+impl From<&NetrcError> for Exit {
+    fn from(_: &NetrcError) -> Exit {
+        Exit::Read
+    }
+}
+
+/// What went wrong in the end, the innermost error behind `e`.
+pub fn cause(e: &reqwest::Error) -> String {
+    let mut cause: &dyn Error = e;
+    while let Some(source) = cause.source() {
+        cause = source;
+    }
+
+    cause.to_string()
+}
+
+/// Whether `e` is the client giving up on a streamed request body it had
+/// no connection to send over, rather than the body failing to be read.
+pub fn unsent_body(e: &reqwest::Error) -> bool {
+    e.is_body() && source_of::<io::Error>(e).is_none()
+}
+
+/// The failed host lookup behind `e`, if that is why it failed.
+pub fn resolve_error(e: &reqwest::Error) -> Option<&ResolveError> {
+    source_of(e)
+}
+
+/// The DNS-over-HTTPS server failure behind `e`, if that is why it failed.
+pub fn doh_error(e: &reqwest::Error) -> Option<&DohError> {
+    source_of(e)
+}
+
+/// Why the SOCKS proxy broke off the handshake, if that is why `e` failed.
+/// The client's SOCKS errors are private, so they are known by what they
+/// say. Not reaching the proxy at all is not one of them.
+pub fn socks_error(e: &reqwest::Error) -> Option<String> {
+    let mut source = e.source();
+    while let Some(e) = source {
+        if let Some(reason) = e.to_string().strip_prefix("SOCKS error: ") {
+            return (reason != "failed to create underlying connection")
+                .then(|| reason.to_string());
+        }
+        source = e.source();
+    }
+
+    None
+}
+
+// The first error of type T in the sources of `e`
+fn source_of<T: Error + 'static>(e: &reqwest::Error) -> Option<&T> {
+    let mut source = e.source();
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<T>() {
+            return Some(e);
+        }
+        source = e.source();
+    }
+
+    None
+}
+
+/// Whether `e` failed because the server sent the TLS `alert`, such as
+/// `CertificateRequired`.
+pub fn tls_alert(e: &reqwest::Error, alert: AlertDescription) -> bool {
+    matches!(rustls_error(e), Some(rustls::Error::AlertReceived(received)) if *received == alert)
+}
+
+/// Why the certificate of the server or proxy failed verification, if that
+/// is why `e` failed.
+pub fn certificate_error(e: &(dyn Error + 'static)) -> Option<String> {
+    let Some(rustls::Error::InvalidCertificate(e)) = rustls_error(e) else {
+        return None;
+    };
+    let e = match e {
+        CertificateError::Other(OtherError(other)) => other
+            .downcast_ref::<ProxyCertificate>()
+            .map_or(e, |ProxyCertificate(e)| e),
+        e => e,
+    };
+
+    Some(match e {
+        CertificateError::UnknownIssuer => {
+            "it is not signed by a trusted CA certificate".to_string()
+        }
+        CertificateError::Expired => "it has expired".to_string(),
+        CertificateError::NotValidYet => "it is not valid yet".to_string(),
+        CertificateError::NotValidForName => "it is not for this host".to_string(),
+        e => e.to_string(),
+    })
+}
+
+/// Whether `e` failed because the server's public key is not a pinned one.
+pub fn pin_mismatch(e: &(dyn Error + 'static)) -> bool {
+    matches!(
+        rustls_error(e),
+        Some(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(e))))
+            if e.is::<PinMismatch>()
+    )
+}
+
+/// Whether `e` failed because the certificate of an HTTPS proxy did, rather
+/// than the server's.
+pub fn proxy_certificate(e: &reqwest::Error) -> bool {
+    matches!(
+        rustls_error(e),
+        Some(rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(e))))
+            if e.is::<ProxyCertificate>()
+    )
+}
+
+/// Whether `e` failed before a tunnel through a proxy was set up, when
+/// connecting to the proxy itself. The client's tunnel errors are private,
+/// so they are known by what they say.
+pub fn tunnel_failed(e: &reqwest::Error) -> bool {
+    let mut source = e.source();
+    while let Some(e) = source {
+        if e.to_string() == "tunnel error: failed to create underlying connection" {
+            return true;
+        }
+        source = e.source();
+    }
+
+    false
+}
+
+/// Why the TLS connection failed, if `e` is a TLS error.
+pub fn tls_error(e: &(dyn Error + 'static)) -> Option<String> {
+    rustls_error(e).map(ToString::to_string)
+}
+
+// The TLS error behind `e`. It is wrapped in I/O errors, whose source skips over what they wrap
+fn rustls_error<'a>(e: &'a (dyn Error + 'static)) -> Option<&'a rustls::Error> {
+    let mut source = e.source();
+    while let Some(mut e) = source {
+        while let Some(inner) = e.downcast_ref::<io::Error>().and_then(|e| e.get_ref()) {
+            e = inner;
+        }
+        if let Some(e) = e.downcast_ref::<rustls::Error>() {
+            return Some(e);
+        }
+        source = e.source();
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::resolve::filter_family;
+    use reqwest::{
+        blocking::Client,
+        dns::{Name, Resolve, Resolving},
+    };
+    use std::{net::TcpListener, sync::Arc, time::Duration};
+
+    #[test]
+    fn codes_match_curl() {
+        let codes = [
+            (Exit::UnsupportedProtocol, 1),
+            (Exit::Init, 2),
+            (Exit::MalformedUrl, 3),
+            (Exit::ResolveProxy, 5),
+            (Exit::ResolveHost, 6),
+            (Exit::Connect, 7),
+            (Exit::Http, 22),
+            (Exit::Write, 23),
+            (Exit::Read, 26),
+            (Exit::Timeout, 28),
+            (Exit::RangeError, 33),
+            (Exit::SslConnect, 35),
+            (Exit::FileCouldntRead, 37),
+            (Exit::InterfaceFailed, 45),
+            (Exit::TooManyRedirects, 47),
+            (Exit::Recv, 56),
+            (Exit::SslCertProblem, 58),
+            (Exit::PeerFailedVerification, 60),
+            (Exit::BadContentEncoding, 61),
+            (Exit::FileTooLarge, 63),
+            (Exit::BadCaCertFile, 77),
+            (Exit::PinnedPubKeyMismatch, 90),
+            (Exit::Proxy, 97),
+        ];
+        for (exit, code) in codes {
+            assert_eq!(exit.code(), code, "{exit:?}");
+        }
+    }
+
+    // Fails every lookup the way the session's resolver does
+    struct Failing;
+
+    impl Resolve for Failing {
+        fn resolve(&self, name: Name) -> Resolving {
+            let e = filter_family(name.as_str(), std::iter::empty(), None);
+            Box::pin(async move { Err(Box::new(e.unwrap_err()) as _) })
+        }
+    }
+
+    fn error(client: Client, url: &str) -> reqwest::Error {
+        client.get(url).send().unwrap_err()
+    }
+
+    #[test]
+    fn request_errors() {
+        let client = Client::builder().no_proxy().build().unwrap();
+        assert_eq!(
+            Exit::from(&error(client.clone(), "http://")),
+            Exit::MalformedUrl
+        );
+
+        // Nothing listens on a port just given up
+        let port = TcpListener::bind("127.0.0.1:0")
+            .unwrap()
+            .local_addr()
+            .unwrap()
+            .port();
+        let refused = error(client, &format!("http://127.0.0.1:{port}/"));
+        assert_eq!(Exit::from(&refused), Exit::Connect);
+
+        let resolver = Client::builder()
+            .dns_resolver(Arc::new(Failing))
+            .build()
+            .unwrap();
+        let unresolved = error(resolver, "http://nowhere.test/");
+        assert_eq!(Exit::from(&unresolved), Exit::ResolveHost);
+        assert!(resolve_error(&unresolved).is_some_and(|e| e.host == "nowhere.test"));
+    }
+
+    #[test]
+    fn timeout() {
+        // Accepted, but never answered
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let client = Client::builder()
+            .timeout(Duration::from_millis(100))
+            .build()
+            .unwrap();
+        assert_eq!(Exit::from(&error(client, &url)), Exit::Timeout);
+    }
+
+    #[test]
+    fn output_errors() {
+        let body = |e: io::Error| Exit::from(&OutputError::Body(e));
+        assert_eq!(body(io::Error::other(TooLarge(10))), Exit::FileTooLarge);
+        assert_eq!(
+            body(io::Error::from(io::ErrorKind::ConnectionReset)),
+            Exit::Recv
+        );
+        for e in [
+            OutputError::NoFileName("http://h/".to_string()),
+            OutputError::UnsafeFileName("..".to_string()),
+            OutputError::Binary,
+            OutputError::Write(io::Error::from(io::ErrorKind::StorageFull)),
+        ] {
+            assert_eq!(Exit::from(&e), Exit::Write, "{e}");
+        }
+    }
+
+    #[test]
+    fn local_errors() {
+        let io = || io::Error::from(io::ErrorKind::NotFound);
+        assert_eq!(Exit::from(&AuthError::ReadPassword(io())), Exit::Read);
+        assert_eq!(
+            Exit::from(&AuthError::ReadToken("t".to_string(), io())),
+            Exit::Read
+        );
+        assert_eq!(Exit::from(&AuthError::EmptyToken), Exit::Init);
+        assert_eq!(
+            Exit::from(&AuthError::NotATerminal("p".to_string())),
+            Exit::Init
+        );
+
+        assert_eq!(
+            Exit::from(&FileError::NotLocal("h".to_string())),
+            Exit::MalformedUrl
+        );
+        assert_eq!(
+            Exit::from(&FileError::Open("/x".into(), io())),
+            Exit::FileCouldntRead
+        );
+        assert_eq!(
+            Exit::from(&FileError::Range("9-".to_string())),
+            Exit::RangeError
+        );
+
+        assert_eq!(Exit::from(&FormError::Invalid("f".to_string())), Exit::Init);
+        assert_eq!(
+            Exit::from(&FormError::Read("f".to_string(), io())),
+            Exit::Read
+        );
+
+        assert_eq!(Exit::from(&NetrcError::NoHome), Exit::Read);
+    }
+}
@@ -0,0 +1,129 @@
+use crate::date::DateTime;
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, Cursor, Read, Seek, SeekFrom},
+    path::PathBuf,
+    time::SystemTime,
+};
+use url::Url;
+
+/// A file:// URL that cannot be read.
+#[derive(Debug)]
+pub enum FileError {
+    NotLocal(String),
+    Open(PathBuf, io::Error),
+    Range(String),
+}
+
+impl Display for FileError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FileError::NotLocal(url) => {
+                write!(f, "The URL {url} does not name a file on this machine")
+            }
+            FileError::Open(path, e) => write!(f, "Couldn't open file {}: {e}", path.display()),
+            FileError::Range(reason) => write!(f, "{reason}"),
+        }
+    }
+}
+
+/// The contents of a file:// URL, or the part of them -r asked for.
+pub struct LocalFile {
+    pub path: PathBuf,
+    pub body: Box<dyn Read>,
+    // Of the whole file, not only the range
+    pub size: u64,
+    pub modified: Option<SystemTime>,
+}
+
+impl LocalFile {
+    /// The headers curl makes up for a file with -i or -I.
+    pub fn head(&self) -> Vec<u8> {
+        let mut head = format!("Content-Length: {}\r\nAccept-ranges: bytes\r\n", self.size);
+        if let Some(modified) = self.modified {
+            head.push_str(&format!(
+                "Last-Modified: {}\r\n",
+                DateTime::from_system_time(modified).to_http_date()
+            ));
+        }
+        head.push_str("\r\n");
+
+        head.into_bytes()
+    }
+}
+
+/// Opens the file a file:// URL names, percent-decoded. A directory reads
+/// as the names in it, one on each line, like curl lists it. `range` is an
+/// -r value, and only a single range can be read from a file.
+pub fn open(url: &Url, range: Option<&str>) -> Result<LocalFile, FileError> {
+    let path = url
+        .to_file_path()
+        .map_err(|_| FileError::NotLocal(url.to_string()))?;
+    let open_error = |e| FileError::Open(path.clone(), e);
+
+    let metadata = std::fs::metadata(&path).map_err(open_error)?;
+    let modified = metadata.modified().ok();
+    let (body, size): (Box<dyn Read>, u64) = if metadata.is_dir() {
+        let mut names = std::fs::read_dir(&path)
+            .and_then(|entries| {
+                entries
+                    .map(|entry| {
+                        entry.map(|entry| entry.file_name().to_string_lossy().into_owned())
+                    })
+                    .collect::<io::Result<Vec<_>>>()
+            })
+            .map_err(open_error)?;
+        names.sort();
+        let listing: String = names.iter().map(|name| format!("{name}\n")).collect();
+        let size = listing.len() as u64;
+        (
+            ranged(Cursor::new(listing.into_bytes()), size, range)?,
+            size,
+        )
+    } else {
+        let file = File::open(&path).map_err(open_error)?;
+        (ranged(file, metadata.len(), range)?, metadata.len())
+    };
+
+    Ok(LocalFile {
+        path,
+        body,
+        size,
+        modified,
+    })
+}
+
+// The bytes of `contents` in `range`, from `first-last`, `first-` or the `-suffix` last bytes
+fn ranged<R: Read + Seek + 'static>(
+    mut contents: R,
+    size: u64,
+    range: Option<&str>,
+) -> Result<Box<dyn Read>, FileError> {
+    let Some(range) = range else {
+        return Ok(Box::new(contents));
+    };
+    if range.contains(',') {
+        return Err(FileError::Range(format!(
+            "The range {range} has more than one part, file:// URLs take a single range"
+        )));
+    }
+
+    let (first, last) = range.split_once('-').unwrap_or((range, ""));
+    let (first, last) = match (first.parse::<u64>(), last.parse::<u64>()) {
+        (Ok(first), Ok(last)) => (first, last.min(size.saturating_sub(1))),
+        (Ok(first), Err(_)) => (first, size.saturating_sub(1)),
+        (Err(_), Ok(suffix)) => (size.saturating_sub(suffix), size.saturating_sub(1)),
+        (Err(_), Err(_)) => (0, size.saturating_sub(1)),
+    };
+    if first >= size {
+        return Err(FileError::Range(format!(
+            "The range {range} starts past the end of the file, it is {size} bytes"
+        )));
+    }
+
+    contents
+        .seek(SeekFrom::Start(first))
+        .map_err(|e| FileError::Range(format!("Unable to read the range {range}: {e}")))?;
+    Ok(Box::new(contents.take(last - first + 1)))
+}
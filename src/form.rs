@@ -0,0 +1,391 @@
+use crate::{direct::Upload, rate};
+use std::{
+    fmt::Display,
+    fs::File,
+    io::{self, Cursor, Read},
+    path::{Path, PathBuf},
+};
+
+/// A -F field that is not `name=content`, or a file in it that cannot be
+/// read.
+#[derive(Debug)]
+pub enum FormError {
+    Invalid(String),
+    Read(String, io::Error),
+}
+
+impl Display for FormError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            FormError::Invalid(field) => write!(
+                f,
+                "'{field}' is not a valid form field, use name=value, name=@file or name=<file"
+            ),
+            FormError::Read(file, e) => write!(f, "Unable to read the form file {file}: {e}"),
+        }
+    }
+}
+
+/// A form field and how its value is taken.
+#[derive(Clone, Debug)]
+pub enum Field {
+    /// -F, where `@` and `<` name files and `;` starts parameters
+    Form(String),
+    /// --form-string, with the value sent as it is
+    String(String),
+}
+
+enum Content {
+    Text(Vec<u8>),
+    // Read as the body is sent, so big files are never all in memory
+    File(PathBuf),
+}
+
+struct Part {
+    // The part headers, each ending with CRLF
+    head: String,
+    content: Content,
+}
+
+/// A multipart/form-data body made of the -F and --form-string fields. It
+/// is made here rather than with the client's multipart forms, which can only
+/// be sent by the client: curl's own connections need the body as an
+/// [`Upload`], with its length known for Content-Length and `Expect`.
+pub struct Form {
+    boundary: String,
+    parts: Vec<Part>,
+}
+
+impl Form {
+    /// Parses the form fields, a part for each in order. -F fields are
+    /// `name=value`, `name=@file` to attach a file or `name=<file` to send
+    /// its contents as a value, "-" for stdin. After the content, `;type=`,
+    /// `;filename=` and `;headers=` set the part's content type, file name
+    /// and extra headers. The `<file` contents are read now, the attached
+    /// files only when the body is sent.
+    pub fn new(fields: &[Field]) -> Result<Form, FormError> {
+        let parts = fields
+            .iter()
+            .map(|field| match field {
+                Field::Form(field) => part(field),
+                Field::String(field) => string_part(field),
+            })
+            .collect::<Result<_, _>>()?;
+
+        // A boundary of zeros still works if there is no randomness, it is only more likely to
+        // turn up in a part
+        let mut random = [0; 12];
+        let _ = openssl::rand::rand_bytes(&mut random);
+        let boundary = random.iter().fold("-".repeat(24), |mut boundary, b| {
+            boundary.push_str(&format!("{b:02x}"));
+            boundary
+        });
+
+        Ok(Form { boundary, parts })
+    }
+
+    /// The Content-Type of the body, with its boundary.
+    pub fn content_type(&self) -> String {
+        format!("multipart/form-data; boundary={}", self.boundary)
+    }
+
+    /// The body and its length. Without attached files it is buffered, so
+    /// it can be sent again on a redirect or retry, otherwise it is read no
+    /// faster than `rate` bytes per second when given.
+    pub fn body(&self, rate: Option<u64>) -> Result<(Upload, u64), FormError> {
+        let mut readers: Vec<Box<dyn Read + Send>> = Vec::new();
+        let mut buffered = Vec::new();
+        let mut length = 0;
+        for part in &self.parts {
+            buffered.extend(format!("--{}\r\n{}\r\n", self.boundary, part.head).as_bytes());
+            match &part.content {
+                Content::Text(text) => buffered.extend(text),
+                Content::File(path) => {
+                    let read_error = |e| FormError::Read(path.display().to_string(), e);
+                    let file = File::open(path).map_err(read_error)?;
+                    let size = file.metadata().map_err(read_error)?.len();
+
+                    length += buffered.len() as u64 + size;
+                    readers.push(Box::new(Cursor::new(std::mem::take(&mut buffered))));
+                    readers.push(Box::new(file.take(size)));
+                }
+            }
+            buffered.extend(b"\r\n");
+        }
+        buffered.extend(format!("--{}--\r\n", self.boundary).as_bytes());
+
+        if readers.is_empty() {
+            let length = buffered.len() as u64;
+            return Ok((Upload::Buffered(buffered), length));
+        }
+        length += buffered.len() as u64;
+        readers.push(Box::new(Cursor::new(buffered)));
+        let body = readers
+            .into_iter()
+            .reduce(|body, next| Box::new(body.chain(next)))
+            .unwrap_or_else(|| Box::new(io::empty()));
+
+        Ok((
+            Upload::Streamed(Box::new(rate::Reader::new(body, rate)), Some(length)),
+            length,
+        ))
+    }
+}
+
+fn part(field: &str) -> Result<Part, FormError> {
+    let (name, value) = field
+        .split_once('=')
+        .ok_or_else(|| FormError::Invalid(field.to_string()))?;
+
+    // The value runs up to the first of the parameters curl knows, a ";" before anything else is
+    // part of it
+    let mut segments = value.split(';');
+    let mut content = segments.next().unwrap_or_default().to_string();
+    let (mut content_type, mut filename, mut headers) = (None, None, Vec::new());
+    let mut in_parameters = false;
+    for segment in segments {
+        let trimmed = segment.trim_start();
+        if let Some(value) = trimmed.strip_prefix("type=") {
+            content_type = Some(value.to_string());
+        } else if let Some(value) = trimmed.strip_prefix("filename=") {
+            filename = Some(unquote(value));
+        } else if let Some(value) = trimmed.strip_prefix("headers=") {
+            headers.push(unquote(value));
+        } else if !in_parameters {
+            content.push(';');
+            content.push_str(segment);
+            continue;
+        }
+        in_parameters = true;
+    }
+
+    let content = if let Some(path) = content.strip_prefix('@') {
+        filename = filename.or_else(|| {
+            Path::new(path)
+                .file_name()
+                .map(|name| name.to_string_lossy().into_owned())
+        });
+        content_type = content_type.or_else(|| Some(guess_type(path).to_string()));
+        if path == "-" {
+            Content::Text(read(path)?)
+        } else {
+            Content::File(PathBuf::from(path))
+        }
+    } else if let Some(path) = content.strip_prefix('<') {
+        Content::Text(read(path)?)
+    } else {
+        Content::Text(content.into_bytes())
+    };
+
+    let mut head = format!("Content-Disposition: form-data; name=\"{}\"", escape(name));
+    if let Some(filename) = filename {
+        head.push_str(&format!("; filename=\"{}\"", escape(&filename)));
+    }
+    head.push_str("\r\n");
+    if let Some(content_type) = content_type {
+        head.push_str(&format!("Content-Type: {content_type}\r\n"));
+    }
+    for header in headers {
+        head.push_str(&format!("{header}\r\n"));
+    }
+
+    Ok(Part { head, content })
+}
+
+fn string_part(field: &str) -> Result<Part, FormError> {
+    let (name, value) = field
+        .split_once('=')
+        .ok_or_else(|| FormError::Invalid(field.to_string()))?;
+
+    Ok(Part {
+        head: format!(
+            "Content-Disposition: form-data; name=\"{}\"\r\n",
+            escape(name)
+        ),
+        content: Content::Text(value.as_bytes().to_vec()),
+    })
+}
+
+fn read(path: &str) -> Result<Vec<u8>, FormError> {
+    let read = if path == "-" {
+        let mut contents = Vec::new();
+        io::stdin().read_to_end(&mut contents).map(|_| contents)
+    } else {
+        std::fs::read(path)
+    };
+
+    read.map_err(|e| FormError::Read(path.to_string(), e))
+}
+
+// A parameter value without the quotes around it
+fn unquote(value: &str) -> String {
+    value
+        .strip_prefix('"')
+        .and_then(|value| value.strip_suffix('"'))
+        .unwrap_or(value)
+        .to_string()
+}
+
+// Quotes cannot be escaped inside the quoted names, so curl percent-encodes them
+fn escape(name: &str) -> String {
+    name.replace('"', "%22")
+        .replace('\r', "%0D")
+        .replace('\n', "%0A")
+}
+
+// The content type curl gives an attached file by its extension
+fn guess_type(path: &str) -> &'static str {
+    let extension = Path::new(path)
+        .extension()
+        .map(|extension| extension.to_string_lossy().to_ascii_lowercase());
+    match extension.as_deref() {
+        Some("gif") => "image/gif",
+        Some("jpg" | "jpeg") => "image/jpeg",
+        Some("png") => "image/png",
+        Some("svg") => "image/svg+xml",
+        Some("txt") => "text/plain",
+        Some("htm" | "html") => "text/html",
+        Some("pdf") => "application/pdf",
+        Some("xml") => "application/xml",
+        _ => "application/octet-stream",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn form(fields: &[Field]) -> Form {
+        Form::new(fields).unwrap()
+    }
+
+    fn fields(fields: &[&str]) -> Vec<Field> {
+        fields
+            .iter()
+            .map(|field| Field::Form(field.to_string()))
+            .collect()
+    }
+
+    // The body as it is sent, with the boundary put back as "BOUNDARY"
+    fn sent(form: &Form) -> String {
+        let (upload, length) = form.body(None).unwrap();
+        let body = match upload {
+            Upload::Buffered(body) => body,
+            Upload::Streamed(mut reader, Some(streamed)) => {
+                assert_eq!(streamed, length);
+                let mut body = Vec::new();
+                reader.read_to_end(&mut body).unwrap();
+                body
+            }
+            Upload::Streamed(_, None) => panic!("a form body has a length"),
+        };
+        assert_eq!(body.len() as u64, length);
+
+        String::from_utf8(body)
+            .unwrap()
+            .replace(&form.boundary, "BOUNDARY")
+    }
+
+    #[test]
+    fn boundary_is_random_and_in_the_content_type() {
+        let (one, two) = (form(&[]), form(&[]));
+
+        assert_eq!(one.boundary.len(), 48);
+        assert!(one.boundary.starts_with(&"-".repeat(24)));
+        assert_ne!(one.boundary, two.boundary);
+        assert_eq!(
+            one.content_type(),
+            format!("multipart/form-data; boundary={}", one.boundary)
+        );
+    }
+
+    #[test]
+    fn parts_are_separated_by_the_boundary() {
+        let form = form(&fields(&["a=1", "b=two words"]));
+
+        assert_eq!(
+            sent(&form),
+            "--BOUNDARY\r\n\
+             Content-Disposition: form-data; name=\"a\"\r\n\r\n\
+             1\r\n\
+             --BOUNDARY\r\n\
+             Content-Disposition: form-data; name=\"b\"\r\n\r\n\
+             two words\r\n\
+             --BOUNDARY--\r\n"
+        );
+    }
+
+    #[test]
+    fn parameters_set_the_part_headers() {
+        let form = form(&fields(&[
+            "a=1;type=text/x-one;headers=\"X-One: 1\"",
+            "b=x;y",
+        ]));
+
+        let sent = sent(&form);
+        assert!(sent.contains("name=\"a\"\r\nContent-Type: text/x-one\r\nX-One: 1\r\n\r\n1\r\n"));
+        // A ";" that starts no parameter curl knows belongs to the value
+        assert!(sent.contains("name=\"b\"\r\n\r\nx;y\r\n"), "{sent}");
+    }
+
+    #[test]
+    fn quotes_and_line_breaks_in_names_are_percent_encoded() {
+        let form = form(&[Field::String("a\"b\r\nc=@not-a-file;type=x".to_string())]);
+
+        let sent = sent(&form);
+        assert!(sent.contains("name=\"a%22b%0D%0Ac\"\r\n\r\n@not-a-file;type=x\r\n"));
+    }
+
+    #[test]
+    fn attached_file_is_streamed_with_its_name_and_type() {
+        let path = std::env::temp_dir().join(format!("curl-form-{}.txt", std::process::id()));
+        std::fs::write(&path, "file contents").unwrap();
+        let field = format!("f=@{}", path.display());
+
+        let sent = sent(&form(&fields(&["a=1", &field])));
+        let _ = std::fs::remove_file(&path);
+
+        let name = path.file_name().unwrap().to_string_lossy();
+        assert!(sent.contains(&format!(
+            "Content-Disposition: form-data; name=\"f\"; filename=\"{name}\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             file contents\r\n--BOUNDARY--\r\n"
+        )));
+    }
+
+    #[test]
+    fn filename_parameter_replaces_the_files_name() {
+        let path = std::env::temp_dir().join(format!("curl-form-{}.bin", std::process::id()));
+        std::fs::write(&path, [0, 1]).unwrap();
+        let field = format!("f=@{};filename=\"renamed.png\"", path.display());
+
+        let sent = sent(&form(&fields(&[&field])));
+        let _ = std::fs::remove_file(&path);
+
+        assert!(
+            sent.contains("filename=\"renamed.png\"\r\nContent-Type: application/octet-stream\r\n")
+        );
+    }
+
+    #[test]
+    fn missing_file_fails_when_the_body_is_made() {
+        let form = form(&fields(&["f=@/nonexistent/file"]));
+
+        assert!(matches!(form.body(None), Err(FormError::Read(_, _))));
+    }
+
+    #[test]
+    fn field_without_a_name_is_invalid() {
+        assert!(matches!(
+            Form::new(&fields(&["novalue"])),
+            Err(FormError::Invalid(_))
+        ));
+    }
+
+    #[test]
+    fn type_is_guessed_by_extension() {
+        assert_eq!(guess_type("a.JPG"), "image/jpeg");
+        assert_eq!(guess_type("dir/page.html"), "text/html");
+        assert_eq!(guess_type("noextension"), "application/octet-stream");
+    }
+}
@@ -0,0 +1,363 @@
+use std::fmt::Display;
+
+/// The most URLs one pattern may expand to, so a typo in a range cannot
+/// start millions of transfers.
+const MAX_URLS: usize = 100_000;
+
+/// A URL pattern curl cannot expand, and where in it the problem is.
+#[derive(Debug)]
+pub struct GlobError {
+    pattern: String,
+    // The character the problem is at, counted from 1 like curl does
+    position: usize,
+    reason: String,
+}
+
+impl Display for GlobError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "{} in URL position {}:\n{}\n{}^",
+            self.reason,
+            self.position,
+            self.pattern,
+            " ".repeat(self.position - 1)
+        )
+    }
+}
+
+/// One URL a pattern expands to, with the value each of its globs took
+/// for the `#1`, `#2`... of an `-o` name.
+#[derive(Clone, Debug)]
+pub struct Expanded {
+    pub url: String,
+    pub values: Vec<String>,
+}
+
+enum Part {
+    Text(String),
+    Set(Vec<String>),
+}
+
+/// Expands the `{one,two}` lists and `[1-10]`, `[001-100:10]` or `[a-z]`
+/// ranges in `pattern` into every URL they make, the last glob changing
+/// fastest. A `\` before a bracket, brace or comma makes it literal, and so
+/// are brackets around an IPv6 address.
+pub fn expand(pattern: &str) -> Result<Vec<Expanded>, GlobError> {
+    let parts = parse(pattern)?;
+
+    let mut expanded = vec![Expanded {
+        url: String::new(),
+        values: Vec::new(),
+    }];
+    for part in &parts {
+        expanded = match part {
+            Part::Text(text) => {
+                for url in &mut expanded {
+                    url.url.push_str(text);
+                }
+                expanded
+            }
+            Part::Set(values) => expanded
+                .iter()
+                .flat_map(|url| {
+                    values.iter().map(|value| {
+                        let mut url = url.clone();
+                        url.url.push_str(value);
+                        url.values.push(value.clone());
+                        url
+                    })
+                })
+                .collect(),
+        };
+    }
+
+    Ok(expanded)
+}
+
+/// Puts the glob values into an `-o` name, `#1` for the first glob and so
+/// on. A `#` without the number of a glob after it is kept as it is.
+pub fn fill(template: &str, values: &[String]) -> String {
+    let mut filled = String::new();
+    let mut rest = template;
+    while let Some(at) = rest.find('#') {
+        filled.push_str(&rest[..at]);
+        rest = &rest[at + 1..];
+
+        let digits = rest.len() - rest.trim_start_matches(|c: char| c.is_ascii_digit()).len();
+        let value = rest[..digits]
+            .parse::<usize>()
+            .ok()
+            .and_then(|n| values.get(n.checked_sub(1)?));
+        match value {
+            Some(value) => {
+                filled.push_str(value);
+                rest = &rest[digits..];
+            }
+            None => filled.push('#'),
+        }
+    }
+    filled.push_str(rest);
+
+    filled
+}
+
+fn parse(pattern: &str) -> Result<Vec<Part>, GlobError> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let error = |at: usize, reason: &str| GlobError {
+        pattern: pattern.to_string(),
+        position: at + 1,
+        reason: reason.to_string(),
+    };
+
+    let mut parts = Vec::new();
+    let mut text = String::new();
+    let mut count: usize = 1;
+    let mut at = 0;
+    while at < chars.len() {
+        let start = at;
+        if let Some(len) = ipv6_literal(&chars[at..]) {
+            text.extend(&chars[at..at + len]);
+            at += len;
+            continue;
+        }
+        let values = match chars[at] {
+            '\\' if chars.get(at + 1).is_some_and(|c| "[]{},".contains(*c)) => {
+                text.push(chars[at + 1]);
+                at += 2;
+                continue;
+            }
+            '{' => {
+                let (mut values, mut value) = (Vec::new(), String::new());
+                at += 1;
+                loop {
+                    match chars.get(at) {
+                        None => return Err(error(start, "unmatched brace")),
+                        Some('}') => {
+                            values.push(value);
+                            break;
+                        }
+                        Some('{' | '[') => return Err(error(at, "nested brace")),
+                        Some(',') => values.push(std::mem::take(&mut value)),
+                        Some('\\') if at + 1 < chars.len() => {
+                            at += 1;
+                            value.push(chars[at]);
+                        }
+                        Some(c) => value.push(*c),
+                    }
+                    at += 1;
+                }
+                at += 1;
+                values
+            }
+            '[' => {
+                let end = chars[at..]
+                    .iter()
+                    .position(|c| *c == ']')
+                    .ok_or_else(|| error(start, "unmatched bracket"))?;
+                let range: String = chars[at + 1..at + end].iter().collect();
+                at += end + 1;
+                expand_range(&range).map_err(|reason| error(start, &reason))?
+            }
+            ']' => return Err(error(at, "unmatched close bracket")),
+            '}' => return Err(error(at, "unmatched close brace")),
+            c => {
+                text.push(c);
+                at += 1;
+                continue;
+            }
+        };
+
+        count = count
+            .checked_mul(values.len())
+            .filter(|count| *count <= MAX_URLS)
+            .ok_or_else(|| error(start, &too_many()))?;
+        if !text.is_empty() {
+            parts.push(Part::Text(std::mem::take(&mut text)));
+        }
+        parts.push(Part::Set(values));
+    }
+    if !text.is_empty() {
+        parts.push(Part::Text(text));
+    }
+
+    Ok(parts)
+}
+
+fn too_many() -> String {
+    format!("too many URLs, the globs expand to more than {MAX_URLS}")
+}
+
+// The values of a range inside brackets: "1-10", "001-100" padded to the width of the first,
+// "a-z", with an optional ":step"
+fn expand_range(range: &str) -> Result<Vec<String>, String> {
+    let bad = || "bad range".to_string();
+    let (range, step) = match range.split_once(':') {
+        Some((range, step)) => (
+            range,
+            step.parse::<usize>()
+                .ok()
+                .filter(|step| *step > 0)
+                .ok_or_else(bad)?,
+        ),
+        None => (range, 1),
+    };
+    let (first, last) = range.split_once('-').ok_or_else(bad)?;
+
+    let mut first_chars = first.chars();
+    let mut last_chars = last.chars();
+    if let (Some(from), None, Some(to), None) = (
+        first_chars.next(),
+        first_chars.next(),
+        last_chars.next(),
+        last_chars.next(),
+    ) {
+        let letters = from.is_ascii_lowercase() && to.is_ascii_lowercase()
+            || from.is_ascii_uppercase() && to.is_ascii_uppercase();
+        if letters && from <= to {
+            return Ok((from..=to)
+                .step_by(step)
+                .map(|letter| letter.to_string())
+                .collect());
+        }
+    }
+
+    if !first
+        .bytes()
+        .chain(last.bytes())
+        .all(|b| b.is_ascii_digit())
+    {
+        return Err(bad());
+    }
+    let (Ok(from), Ok(to)) = (first.parse::<u64>(), last.parse::<u64>()) else {
+        return Err(bad());
+    };
+    if from > to {
+        return Err(bad());
+    }
+    if (to - from) / step as u64 >= MAX_URLS as u64 {
+        return Err(too_many());
+    }
+    let width = if first.len() > 1 && first.starts_with('0') {
+        first.len()
+    } else {
+        0
+    };
+
+    Ok((from..=to)
+        .step_by(step)
+        .map(|n| format!("{n:0width$}"))
+        .collect())
+}
+
+// The length of the bracketed IPv6 address `chars` starts with, like "[::1]" or "[fe80::1%eth0]"
+fn ipv6_literal(chars: &[char]) -> Option<usize> {
+    if chars.first() != Some(&'[') {
+        return None;
+    }
+    let end = chars.iter().position(|c| *c == ']')?;
+    let inside = &chars[1..end];
+    let address = inside.split(|c| *c == '%').next().unwrap_or_default();
+
+    (address.contains(&':')
+        && address
+            .iter()
+            .all(|c| c.is_ascii_hexdigit() || *c == ':' || *c == '.'))
+    .then_some(end + 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn urls(pattern: &str) -> Vec<String> {
+        expand(pattern)
+            .unwrap()
+            .into_iter()
+            .map(|expanded| expanded.url)
+            .collect()
+    }
+
+    fn error(pattern: &str) -> String {
+        expand(pattern).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn lists_expand_in_order() {
+        assert_eq!(
+            urls("http://{a,b}.example/{x,,y}"),
+            [
+                "http://a.example/x",
+                "http://a.example/",
+                "http://a.example/y",
+                "http://b.example/x",
+                "http://b.example/",
+                "http://b.example/y",
+            ]
+        );
+    }
+
+    #[test]
+    fn ranges_expand_with_their_step_and_padding() {
+        assert_eq!(urls("/[1-3]"), ["/1", "/2", "/3"]);
+        assert_eq!(urls("/[01-10:2]"), ["/01", "/03", "/05", "/07", "/09"]);
+        assert_eq!(urls("/[a-c]"), ["/a", "/b", "/c"]);
+        assert_eq!(urls("/[X-Z:2]"), ["/X", "/Z"]);
+    }
+
+    #[test]
+    fn values_are_kept_for_the_output_name() {
+        let expanded = expand("/{a,b}/[1-2]").unwrap();
+        let values: Vec<_> = expanded.iter().map(|e| e.values.join(" ")).collect();
+
+        assert_eq!(values, ["a 1", "a 2", "b 1", "b 2"]);
+        assert_eq!(fill("#2-#1.#3#", &expanded[1].values), "2-a.#3#");
+    }
+
+    #[test]
+    fn escaped_glob_characters_are_literal() {
+        assert_eq!(urls(r"/\[1-3\]\{a\,b\}"), ["/[1-3]{a,b}"]);
+        assert_eq!(urls(r"/{a\,b,c}"), ["/a,b", "/c"]);
+    }
+
+    #[test]
+    fn brackets_around_an_ipv6_address_are_literal() {
+        assert_eq!(urls("http://[::1]:8080/"), ["http://[::1]:8080/"]);
+        assert_eq!(
+            urls("http://[fe80::1%25eth0]/[1-2]"),
+            ["http://[fe80::1%25eth0]/1", "http://[fe80::1%25eth0]/2"]
+        );
+    }
+
+    #[test]
+    fn unbalanced_and_nested_globs_say_where() {
+        assert_eq!(
+            error("/a{b,c"),
+            "unmatched brace in URL position 3:\n/a{b,c\n  ^"
+        );
+        assert_eq!(
+            error("/{a,{b}}"),
+            "nested brace in URL position 5:\n/{a,{b}}\n    ^"
+        );
+        assert!(error("/[1-2").starts_with("unmatched bracket in URL position 2:"));
+        assert!(error("/a]").starts_with("unmatched close bracket in URL position 3:"));
+        assert!(error("/a}").starts_with("unmatched close brace in URL position 3:"));
+    }
+
+    #[test]
+    fn bad_ranges_are_refused() {
+        for range in ["/[3-1]", "/[1-x]", "/[1-3:0]", "/[a-Z]", "/[1]"] {
+            assert!(
+                error(range).starts_with("bad range in URL position 2:"),
+                "{range}"
+            );
+        }
+    }
+
+    #[test]
+    fn more_than_the_most_urls_is_an_error() {
+        assert!(error("/[0-100000]").starts_with("too many URLs"));
+        assert!(error("/[1-1000][1-1000]").starts_with("too many URLs"));
+        assert_eq!(urls("/[1-100000:2]").len(), 50_000);
+    }
+}
@@ -0,0 +1,248 @@
+use crate::{direct::DirectError, resolve::Family};
+use reqwest::blocking::ClientBuilder;
+use socket2::{Domain, Socket, Type};
+use std::{
+    ffi::CStr,
+    fmt::Display,
+    io,
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream, ToSocketAddrs},
+    ops::RangeInclusive,
+    time::Duration,
+};
+
+/// An `--interface` that names no interface, address or host.
+#[derive(Debug)]
+pub struct UnknownInterface(pub String);
+
+impl Display for UnknownInterface {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Unknown interface or address '{}'", self.0)
+    }
+}
+
+/// Where connections are made from, the `--interface` address and the
+/// `--local-port` range.
+#[derive(Clone, Debug)]
+pub struct Local {
+    // Empty for any address
+    addrs: Vec<IpAddr>,
+    // The network interface the addresses are those of, when one is named
+    device: Option<String>,
+    ports: Option<RangeInclusive<u16>>,
+}
+
+impl Local {
+    /// The local end for the `--interface` and `--local-port` values, `None`
+    /// when neither is given. The interface is an IP address, the name of a
+    /// network interface whose addresses are used, or a host name.
+    pub fn new(
+        interface: Option<&str>,
+        ports: Option<RangeInclusive<u16>>,
+    ) -> Result<Option<Local>, UnknownInterface> {
+        let (addrs, device) = match interface {
+            None if ports.is_none() => return Ok(None),
+            None => (Vec::new(), None),
+            Some(interface) => {
+                let unknown = || UnknownInterface(interface.to_string());
+                let (mut addrs, mut device) = match interface.trim_matches(['[', ']']).parse() {
+                    Ok(ip) => (vec![ip], None),
+                    Err(_) => (
+                        interface_addrs(interface).map_err(|_| unknown())?,
+                        Some(interface.to_string()),
+                    ),
+                };
+                if addrs.is_empty() {
+                    device = None;
+                    addrs = (interface, 0)
+                        .to_socket_addrs()
+                        .map_err(|_| unknown())?
+                        .map(|addr| addr.ip())
+                        .collect();
+                }
+                if addrs.is_empty() {
+                    return Err(unknown());
+                }
+                (addrs, device)
+            }
+        };
+
+        Ok(Some(Local {
+            addrs,
+            device,
+            ports,
+        }))
+    }
+
+    /// Connections from any address and port, as the system picks.
+    pub fn any() -> Local {
+        Local {
+            addrs: Vec::new(),
+            device: None,
+            ports: None,
+        }
+    }
+
+    /// Whether connections must come from a port of the `--local-port`
+    /// range, which the client cannot pick.
+    pub fn has_ports(&self) -> bool {
+        self.ports.is_some()
+    }
+
+    /// Makes the client's connections from the interface: bound to the
+    /// network interface itself on Linux, otherwise from its first address,
+    /// of the `-4` or `-6` family when one is given.
+    pub fn bind(&self, builder: ClientBuilder, family: Option<Family>) -> ClientBuilder {
+        #[cfg(target_os = "linux")]
+        if let Some(device) = &self.device {
+            return builder.interface(device);
+        }
+
+        let ip = self
+            .addrs
+            .iter()
+            .find(|ip| family.is_none_or(|family| family.contains(**ip)));
+        match ip {
+            Some(ip) => builder.local_address(*ip),
+            None => builder,
+        }
+    }
+
+    /// Connects to `addr` from the first of the addresses of the same family
+    /// and the first free port in the range, giving up on each port after
+    /// `timeout`.
+    pub fn connect(
+        &self,
+        addr: SocketAddr,
+        timeout: Option<Duration>,
+    ) -> Result<TcpStream, DirectError> {
+        let ip = if self.addrs.is_empty() {
+            match addr {
+                SocketAddr::V4(_) => IpAddr::V4(Ipv4Addr::UNSPECIFIED),
+                SocketAddr::V6(_) => IpAddr::V6(Ipv6Addr::UNSPECIFIED),
+            }
+        } else {
+            *self
+                .addrs
+                .iter()
+                .find(|ip| ip.is_ipv4() == addr.is_ipv4())
+                .ok_or_else(|| {
+                    DirectError::Bind(format!("No local address of the family of {}", addr.ip()))
+                })?
+        };
+        let bind_error = |e: io::Error| DirectError::Bind(format!("{ip}: {e}"));
+        let connect_error = DirectError::Connect;
+        let connect = |socket: &Socket| match timeout {
+            Some(timeout) => socket.connect_timeout(&addr.into(), timeout),
+            None => socket.connect(&addr.into()),
+        };
+
+        let Some(ports) = &self.ports else {
+            let socket = socket(addr).map_err(bind_error)?;
+            socket
+                .bind(&SocketAddr::new(ip, 0).into())
+                .map_err(bind_error)?;
+            connect(&socket).map_err(connect_error)?;
+            return Ok(socket.into());
+        };
+
+        // A port another connection holds fails to bind, or with the same server to connect
+        for port in ports.clone() {
+            let socket = socket(addr).map_err(bind_error)?;
+            match socket.bind(&SocketAddr::new(ip, port).into()) {
+                Err(e) if e.kind() == io::ErrorKind::AddrInUse => continue,
+                Err(e) => return Err(bind_error(e)),
+                Ok(()) => {}
+            }
+            match connect(&socket) {
+                Err(e)
+                    if matches!(
+                        e.kind(),
+                        io::ErrorKind::AddrInUse | io::ErrorKind::AddrNotAvailable
+                    ) =>
+                {
+                    continue
+                }
+                Err(e) => return Err(connect_error(e)),
+                Ok(()) => return Ok(socket.into()),
+            }
+        }
+
+        Err(DirectError::Bind(format!(
+            "No free local port in {}-{} on {ip}",
+            ports.start(),
+            ports.end()
+        )))
+    }
+}
+
+fn socket(addr: SocketAddr) -> io::Result<Socket> {
+    Socket::new(Domain::for_address(addr), Type::STREAM, None)
+}
+
+/// Parses a `--local-port` value, a port or a `first-last` range.
+pub fn parse_ports(s: &str) -> Result<RangeInclusive<u16>, String> {
+    let invalid = || format!("'{s}' is not a valid port or port range, use port or first-last");
+    let port = |port: &str| {
+        port.trim()
+            .parse::<u16>()
+            .ok()
+            .filter(|port| *port > 0)
+            .ok_or_else(invalid)
+    };
+
+    let (first, last) = match s.split_once('-') {
+        Some((first, last)) => (port(first)?, port(last)?),
+        None => (port(s)?, port(s)?),
+    };
+    if first > last {
+        return Err(invalid());
+    }
+
+    Ok(first..=last)
+}
+
+// The addresses of the network interface `name`, IPv4 ones first as curl prefers them
+fn interface_addrs(name: &str) -> io::Result<Vec<IpAddr>> {
+    let mut list: *mut libc::ifaddrs = std::ptr::null_mut();
+    // SAFETY: list is only read after getifaddrs succeeds, and freed once below
+    if unsafe { libc::getifaddrs(&mut list) } != 0 {
+        return Err(io::Error::last_os_error());
+    }
+
+    let mut addrs = Vec::new();
+    let mut entry = list;
+    while !entry.is_null() {
+        // SAFETY: entry is a node of the list getifaddrs returned, which is not freed yet
+        let ifaddr = unsafe { &*entry };
+        entry = ifaddr.ifa_next;
+        if ifaddr.ifa_name.is_null() || ifaddr.ifa_addr.is_null() {
+            continue;
+        }
+        // SAFETY: the name is a NUL terminated string owned by the list
+        if unsafe { CStr::from_ptr(ifaddr.ifa_name) }.to_bytes() != name.as_bytes() {
+            continue;
+        }
+
+        // SAFETY: ifa_addr points to a sockaddr whose family says which kind it is
+        match i32::from(unsafe { (*ifaddr.ifa_addr).sa_family }) {
+            libc::AF_INET => {
+                // SAFETY: an AF_INET address is a sockaddr_in
+                let addr = unsafe { &*ifaddr.ifa_addr.cast::<libc::sockaddr_in>() };
+                addrs.push(IpAddr::V4(Ipv4Addr::from(u32::from_be(
+                    addr.sin_addr.s_addr,
+                ))));
+            }
+            libc::AF_INET6 => {
+                // SAFETY: an AF_INET6 address is a sockaddr_in6
+                let addr = unsafe { &*ifaddr.ifa_addr.cast::<libc::sockaddr_in6>() };
+                addrs.push(IpAddr::V6(Ipv6Addr::from(addr.sin6_addr.s6_addr)));
+            }
+            _ => {}
+        }
+    }
+    // SAFETY: list came from getifaddrs and nothing refers to it any more
+    unsafe { libc::freeifaddrs(list) };
+
+    addrs.sort_by_key(|addr| addr.is_ipv6());
+    Ok(addrs)
+}
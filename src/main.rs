@@ -1,22 +1,117 @@
-use reqwest;
+mod auth;
+mod config;
+mod cookie;
+mod data;
+mod date;
+mod decode;
+mod digest;
+mod direct;
+mod doh;
+mod exit;
+mod file;
+mod form;
+mod glob;
+mod local;
+mod netrc;
+mod output;
+mod progress;
+mod proto;
+mod proxy;
+mod rate;
+mod redirect;
+mod render;
+mod resolve;
+mod retry;
+mod sigv4;
+mod timing;
+mod tls;
+mod trace;
+mod variable;
+mod verbose;
+mod writeout;
+
+use auth::Credentials;
+use cookie::Jar;
+use data::Data;
+use date::DateTime;
+use digest::DigestChallenge;
+use direct::{Direct, DirectError, DirectOptions, Upload};
+use doh::Doh;
+use exit::Exit;
+use form::{Field, Form, FormError};
+use glob::Expanded;
+use local::{Local, UnknownInterface};
+use netrc::{Netrc, NetrcError};
+use output::{Existing, OutputError, TooLarge};
+use percent_encoding::{percent_encode, CONTROLS};
+use progress::Progress;
+use proto::Protocols;
+use proxy::{Proxies, ProxyError, ProxyOptions};
+use rate::Pacer;
+use redirect::Hop;
+use render::Rendering;
+use reqwest::{
+    blocking::{Body, Client, Request, RequestBuilder, Response},
+    header::{
+        HeaderMap, HeaderName, HeaderValue, ACCEPT, ACCEPT_ENCODING, AUTHORIZATION, CONNECTION,
+        CONTENT_DISPOSITION, CONTENT_ENCODING, CONTENT_LENGTH, CONTENT_RANGE, CONTENT_TYPE, COOKIE,
+        ETAG, EXPECT, HOST, IF_MODIFIED_SINCE, IF_NONE_MATCH, IF_UNMODIFIED_SINCE, LAST_MODIFIED,
+        LOCATION, RANGE, REFERER, SET_COOKIE, TRANSFER_ENCODING, USER_AGENT, WWW_AUTHENTICATE,
+    },
+    Version,
+};
+use resolve::{Family, Resolver};
+use retry::Failure;
+use rustls::{client::Resumption, AlertDescription};
 use serde_json::Value;
-use std::{collections::HashMap, fmt::Display, str::FromStr};
-use structopt::StructOpt;
-use url::{ParseError, Url};
+use sigv4::{SigV4, SigV4Error};
+use std::{
+    ffi::OsString,
+    fmt::Display,
+    io::{IsTerminal, Read, Write},
+    ops::RangeInclusive,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{
+        atomic::{AtomicBool, AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    time::{Duration, Instant, SystemTime},
+};
+use structopt::{
+    clap::{AppSettings, ArgMatches, ErrorKind},
+    StructOpt,
+};
+use timing::Clock;
+use tls::{Agreed, CertType, CipherList, Negotiated, ProxyTrust, TlsError, TlsOptions, TlsVersion};
+use url::{form_urlencoded, ParseError, Url};
+use writeout::Transfer;
 
+#[allow(clippy::upper_case_acronyms)]
 enum Method {
     GET,
     POST,
+    PUT,
+    DELETE,
+    PATCH,
+    HEAD,
+    OPTIONS,
+    Custom(reqwest::Method),
 }
 
 #[derive(Debug)]
 enum MethodError {
-    InvalidMethod,
+    InvalidMethod(String),
 }
 
 impl Display for MethodError {
     fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
-        write!(f, "Invalid HTTP method")
+        match self {
+            MethodError::InvalidMethod(method) => write!(
+                f,
+                "Invalid HTTP method '{method}'. Methods must be a token such as GET, POST, PUT, DELETE, PATCH, HEAD, OPTIONS or a custom verb without spaces or separators"
+            ),
+        }
     }
 }
 
@@ -25,6 +120,12 @@ impl Display for Method {
         match self {
             Method::GET => write!(f, "GET"),
             Method::POST => write!(f, "POST"),
+            Method::PUT => write!(f, "PUT"),
+            Method::DELETE => write!(f, "DELETE"),
+            Method::PATCH => write!(f, "PATCH"),
+            Method::HEAD => write!(f, "HEAD"),
+            Method::OPTIONS => write!(f, "OPTIONS"),
+            Method::Custom(method) => write!(f, "{}", method.as_str()),
         }
     }
 }
@@ -36,141 +137,3676 @@ impl FromStr for Method {
         match s {
             "GET" => Ok(Method::GET),
             "POST" => Ok(Method::POST),
-            _ => Err(MethodError::InvalidMethod),
+            "PUT" => Ok(Method::PUT),
+            "DELETE" => Ok(Method::DELETE),
+            "PATCH" => Ok(Method::PATCH),
+            "HEAD" => Ok(Method::HEAD),
+            "OPTIONS" => Ok(Method::OPTIONS),
+            _ => {
+                // Any RFC 7230 token is a valid method, e.g. PROPFIND or PURGE
+                if s.is_empty() || !s.bytes().all(is_token_char) {
+                    return Err(MethodError::InvalidMethod(s.to_string()));
+                }
+
+                reqwest::Method::from_bytes(s.as_bytes())
+                    .map(Method::Custom)
+                    .map_err(|_| MethodError::InvalidMethod(s.to_string()))
+            }
+        }
+    }
+}
+
+const DEFAULT_USER_AGENT: &str = concat!("rust-curl/", env!("CARGO_PKG_VERSION"));
+
+#[derive(Debug)]
+enum HeaderError {
+    MissingColon(String),
+    InvalidName(String),
+    InvalidValue(String),
+    ReadFile(String, std::io::Error),
+    InFile(String, usize, Box<HeaderError>),
+}
+
+impl Display for HeaderError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            HeaderError::MissingColon(header) => {
+                write!(
+                    f,
+                    "Invalid header '{header}'. Headers must be given as 'Name: value'"
+                )
+            }
+            HeaderError::InvalidName(name) => write!(f, "Invalid header name '{name}'"),
+            HeaderError::InvalidValue(name) => write!(f, "Invalid value for header '{name}'"),
+            HeaderError::ReadFile(file, e) => write!(f, "Unable to read headers from {file}: {e}"),
+            HeaderError::InFile(file, line, e) => write!(f, "{file}:{line}: {e}"),
+        }
+    }
+}
+
+impl From<&HeaderError> for Exit {
+    fn from(e: &HeaderError) -> Exit {
+        match e {
+            HeaderError::ReadFile(..) => Exit::Read,
+            HeaderError::InFile(_, _, e) => Exit::from(e.as_ref()),
+            HeaderError::MissingColon(_)
+            | HeaderError::InvalidName(_)
+            | HeaderError::InvalidValue(_) => Exit::Init,
+        }
+    }
+}
+
+enum RequestError {
+    Http(reqwest::Error),
+    Digest(openssl::error::ErrorStack),
+    Signing(SigV4Error),
+    UnsupportedAuth(Vec<String>),
+    TooManyRedirects(i64),
+    RedirectProtocol(String),
+    Form(FormError),
+    Upload(PathBuf, std::io::Error),
+    ChunkedHttp10,
+    Proxy(ProxyError),
+    ProxyHeader(HeaderError),
+    CaCert(TlsError),
+    ClientCert(TlsError),
+    PinnedPubKey(TlsError),
+    Interface(UnknownInterface),
+    Direct(DirectError),
+}
+
+impl Display for RequestError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            RequestError::Http(e) => write!(f, "{e}"),
+            RequestError::Digest(e) => write!(f, "Unable to compute the Digest response: {e}"),
+            RequestError::Signing(e) => write!(f, "{e}"),
+            RequestError::UnsupportedAuth(schemes) => write!(
+                f,
+                "The server only offers unsupported authentication schemes: {}",
+                schemes.join(", ")
+            ),
+            RequestError::TooManyRedirects(max) => write!(f, "Maximum ({max}) redirects followed"),
+            RequestError::RedirectProtocol(scheme) => write!(
+                f,
+                "The redirect to a \"{scheme}\" URL is not allowed by --proto or --proto-redir"
+            ),
+            RequestError::Form(e) => write!(f, "{e}"),
+            RequestError::ChunkedHttp10 => write!(
+                f,
+                "HTTP/1.0 cannot send a chunked body, which Transfer-Encoding: chunked and -T from stdin or a pipe need, leave out --http1.0"
+            ),
+            RequestError::Upload(path, e) => write!(
+                f,
+                "Unable to read the file to upload {}: {e}",
+                if path == Path::new("-") {
+                    "stdin".to_string()
+                } else {
+                    path.display().to_string()
+                }
+            ),
+            RequestError::Proxy(e) => write!(f, "{e}"),
+            RequestError::ProxyHeader(e) => write!(f, "Invalid --proxy-header: {e}"),
+            RequestError::CaCert(e) => write!(f, "Unable to load the CA certificates: {e}"),
+            RequestError::ClientCert(e) => {
+                write!(f, "Unable to use the client certificate: {e}")
+            }
+            RequestError::PinnedPubKey(e) => write!(f, "Unable to use --pinnedpubkey: {e}"),
+            RequestError::Interface(e) => write!(f, "{e}"),
+            RequestError::Direct(e) => write!(f, "{e}"),
+        }
+    }
+}
+
+impl From<reqwest::Error> for RequestError {
+    fn from(e: reqwest::Error) -> Self {
+        RequestError::Http(e)
+    }
+}
+
+impl From<SigV4Error> for RequestError {
+    fn from(e: SigV4Error) -> Self {
+        RequestError::Signing(e)
+    }
+}
+
+impl From<&RequestError> for Exit {
+    fn from(e: &RequestError) -> Exit {
+        match e {
+            RequestError::Http(e) => Exit::from(e),
+            RequestError::Digest(_) | RequestError::Signing(_) => Exit::Init,
+            // The server turned the request down with a 401 nothing can answer
+            RequestError::UnsupportedAuth(_) => Exit::Http,
+            RequestError::TooManyRedirects(_) => Exit::TooManyRedirects,
+            RequestError::RedirectProtocol(_) => Exit::UnsupportedProtocol,
+            RequestError::Form(e) => Exit::from(e),
+            RequestError::Upload(..) => Exit::Read,
+            RequestError::ChunkedHttp10 => Exit::Init,
+            RequestError::Proxy(ProxyError::InvalidUrl(_)) => Exit::MalformedUrl,
+            RequestError::Proxy(ProxyError::UnsupportedScheme(_)) => Exit::UnsupportedProtocol,
+            RequestError::Proxy(ProxyError::Auth(e)) => Exit::from(e),
+            RequestError::Proxy(ProxyError::CaCert(_)) | RequestError::CaCert(_) => {
+                Exit::BadCaCertFile
+            }
+            RequestError::ClientCert(TlsError::Passphrase(e)) => Exit::from(e),
+            RequestError::ClientCert(_) => Exit::SslCertProblem,
+            RequestError::PinnedPubKey(_) => Exit::PinnedPubKeyMismatch,
+            RequestError::Interface(_) => Exit::InterfaceFailed,
+            RequestError::Direct(e) => Exit::from(e),
+            RequestError::ProxyHeader(e) => Exit::from(e),
         }
     }
 }
 
+// Adds the cookies from the jar that match the request URL to any the Cookie header already has
+fn add_cookies(request: &mut Request, jar: &Jar) {
+    let Some(cookies) = jar.header(request.url()) else {
+        return;
+    };
+
+    let cookies = match request.headers().get(COOKIE) {
+        Some(existing) => format!(
+            "{}; {cookies}",
+            String::from_utf8_lossy(existing.as_bytes())
+        ),
+        None => cookies,
+    };
+    if let Ok(value) = HeaderValue::from_str(&cookies) {
+        request.headers_mut().insert(COOKIE, value);
+    }
+}
+
+fn is_token_char(c: u8) -> bool {
+    c.is_ascii_alphanumeric() || b"!#$%&'*+-.^_`|~".contains(&c)
+}
+
 #[derive(StructOpt)]
 #[structopt(name = "curl")]
 struct Opt {
-    url: String,
+    /// The URLs to fetch, one after the other over the same connections where possible
+    #[structopt(name = "url", required_unless_one = &["url-option", "url-file"])]
+    urls: Vec<String>,
+
+    /// A URL to fetch, like the ones given without an option, which can also be put in a -K file
+    #[structopt(
+        long = "url",
+        name = "url-option",
+        value_name = "url",
+        number_of_values = 1
+    )]
+    url_options: Vec<String>,
+
+    /// The protocols URLs may use, like "=https" for only https or "-http" for all but http
+    #[structopt(long, allow_hyphen_values = true, parse(try_from_str = proto::parse))]
+    proto: Option<Protocols>,
+
+    /// The protocols redirects may switch to, in the same form as --proto, like "=https" so an https URL cannot be redirected to http
+    #[structopt(long, allow_hyphen_values = true, parse(try_from_str = proto::parse))]
+    proto_redir: Option<Protocols>,
+
+    /// The scheme for URLs without one, http or https. Otherwise hosts starting with "www." get https and the others http
+    #[structopt(long, parse(try_from_str = parse_proto_default))]
+    proto_default: Option<String>,
+
+    /// Fetch the URLs in this file, "-" for stdin, one per line. Blank lines and lines starting with # are skipped
+    #[structopt(long, number_of_values = 1)]
+    url_file: Vec<PathBuf>,
+
+    /// Send this data in the body, or with -G in the query. @file reads it from a file and @- from stdin, without newlines. Given more than once the values are joined with &
+    #[structopt(short, long, number_of_values = 1)]
+    data: Vec<String>,
 
-    #[structopt(short)]
-    data: Option<String>,
+    /// Send this data in the body exactly as it is, @file reads it from a file byte for byte. Joined with the -d values with & in the order given
+    #[structopt(long, number_of_values = 1)]
+    data_binary: Vec<String>,
+
+    /// Send this data like -d does, but with a leading @ sent as it is rather than naming a file
+    #[structopt(long, number_of_values = 1)]
+    data_raw: Vec<String>,
+
+    /// Send this data percent-encoded: "content", "=content" or "name=content" with only the content encoded, "@file" or "name@file" to encode a file. Joined with the other data with & in the order given
+    #[structopt(long, number_of_values = 1)]
+    data_urlencode: Vec<String>,
+
+    // Every data option's value in the order given, filled in by order_data
+    #[structopt(skip)]
+    data_parts: Vec<Data>,
+
+    /// Put the -d data in the URL query string instead of the body, and send a GET unless -X says otherwise
+    #[structopt(short = "G", long, conflicts_with = "json")]
+    get: bool,
+
+    /// Add name=value to the URL query, percent-encoded, with any method. "+name=value" is added as it is, already encoded, and "=value" replaces the whole query
+    #[structopt(long, number_of_values = 1, allow_hyphen_values = true)]
+    url_query: Vec<String>,
 
     #[structopt(short = "X", default_value = "GET")]
     method: Method,
 
+    /// Send this JSON as a POST body, with JSON Content-Type and Accept headers. @file reads it from a file and @- from stdin, and more than one --json are joined together
+    #[structopt(long, number_of_values = 1)]
+    json: Vec<String>,
+
+    /// Send the --json data even when it is not valid JSON
     #[structopt(long)]
-    json: Option<String>,
-}
+    json_no_validate: bool,
 
-fn main() {
-    let opt = Opt::from_args();
+    /// Sort the keys of the JSON objects in a reformatted body, at every depth
+    #[structopt(long)]
+    sort_keys: bool,
 
-    println!("Requesting URL: {}", &opt.url);
+    /// How to write a JSON body to stdout: pretty, compact or raw as it came. Defaults to pretty on a terminal and raw otherwise
+    #[structopt(long, parse(try_from_str = render::parse_format))]
+    json_output: Option<render::Format>,
 
-    if let Some(json) = &opt.json {
-        println!("Method: {}", Method::POST);
-        println!("JSON: {}", json);
-    } else {
-        println!("Method: {}", opt.method);
+    /// Do not color JSON written to a terminal, like setting NO_COLOR
+    #[structopt(long)]
+    no_color: bool,
+
+    /// Add a multipart form field: name=value, name=@file to attach a file or name=<file for its contents as the value, with ;type=, ;filename= and ;headers= after it
+    #[structopt(
+        short = "F",
+        long = "form",
+        number_of_values = 1,
+        conflicts_with_all = &["data", "data-binary", "data-raw", "data-urlencode", "json", "get"]
+    )]
+    form: Vec<String>,
+
+    /// Upload this file as the body, with a PUT unless -X says otherwise, "-" for stdin. Each -T goes to the URL in the same place, and a URL ending in / gets the file name added
+    #[structopt(
+        short = "T",
+        long,
+        number_of_values = 1,
+        conflicts_with_all = &["data", "data-binary", "data-raw", "data-urlencode", "form", "form-string", "json"]
+    )]
+    upload_file: Vec<PathBuf>,
+
+    /// Add a multipart form field name=value with the value sent as it is, even when it starts with @ or <
+    #[structopt(
+        long,
+        number_of_values = 1,
+        conflicts_with_all = &["data", "data-binary", "data-raw", "data-urlencode", "json", "get"]
+    )]
+    form_string: Vec<String>,
+
+    // The -F and --form-string fields in the order given, filled in by order_data
+    #[structopt(skip)]
+    form_fields: Vec<Field>,
+
+    /// Extra header to include in the request, e.g. -H "Accept: application/json"
+    #[structopt(short = "H", long = "header", number_of_values = 1)]
+    headers: Vec<String>,
+
+    /// Cookies to send, either "name=value; name2=value2" or a Netscape cookie file to load them from
+    #[structopt(short = "b", long = "cookie", number_of_values = 1)]
+    cookies: Vec<String>,
+
+    /// Save all cookies to this Netscape cookie file after the transfer, "-" for stdout
+    #[structopt(short = "c", long, parse(from_os_str))]
+    cookie_jar: Option<PathBuf>,
+
+    /// Leave out the session cookies, those without an expiry time, when loading -b cookie files
+    #[structopt(short = "j", long)]
+    junk_session_cookies: bool,
+
+    /// User-Agent to send, an empty string sends no User-Agent header
+    #[structopt(short = "A", long)]
+    user_agent: Option<String>,
+
+    /// Referer URL to send, a trailing ";auto" also sets it on redirects
+    #[structopt(short = "e", long)]
+    referer: Option<String>,
+
+    /// Server credentials as user[:password], prompting when the password is omitted
+    #[structopt(short = "u", long)]
+    user: Option<String>,
+
+    /// Read the password missing from -u from the first line of stdin
+    #[structopt(long)]
+    password_stdin: bool,
+
+    /// OAuth 2 bearer token, @file or @- to read it from a file or stdin
+    #[structopt(long, conflicts_with = "user")]
+    oauth2_bearer: Option<String>,
+
+    /// Use HTTP Digest authentication with the -u credentials
+    #[structopt(long, requires = "user")]
+    digest: bool,
+
+    /// Sign the request with AWS Signature Version 4, e.g. "aws:amz:us-east-1:s3"
+    #[structopt(long, requires = "user", conflicts_with = "digest")]
+    aws_sigv4: Option<SigV4>,
+
+    /// Let the server pick the authentication scheme for -u (Basic or Digest)
+    #[structopt(long, requires = "user", conflicts_with = "aws-sigv4")]
+    anyauth: bool,
+
+    /// Read credentials for the host from ~/.netrc when -u is not given
+    #[structopt(short = "n", long)]
+    netrc: bool,
+
+    /// Like --netrc but reads the given file
+    #[structopt(long, parse(from_os_str))]
+    netrc_file: Option<PathBuf>,
+
+    /// Like --netrc but carry on without credentials if there is no file or entry
+    #[structopt(long)]
+    netrc_optional: bool,
+
+    /// Follow redirects
+    #[structopt(short = "L", long)]
+    location: bool,
+
+    /// Maximum number of redirects to follow with -L, -1 for unlimited
+    #[structopt(long, default_value = "50", allow_hyphen_values = true)]
+    max_redirs: i64,
+
+    /// Keep sending credentials when -L redirects to a different host
+    #[structopt(long, requires = "location")]
+    location_trusted: bool,
+
+    /// Keep POST when following a 301 redirect instead of switching to GET
+    #[structopt(long)]
+    post301: bool,
+
+    /// Keep POST when following a 302 redirect instead of switching to GET
+    #[structopt(long)]
+    post302: bool,
+
+    /// Keep POST when following a 303 redirect instead of switching to GET
+    #[structopt(long)]
+    post303: bool,
+
+    /// Print details about the transfer to stderr
+    #[structopt(short = "v", long)]
+    verbose: bool,
+
+    /// Retry transient failures (timeouts, connect errors except refused ones, 408, 429 and 5xx)
+    /// this many times
+    #[structopt(long, default_value = "0")]
+    retry: u32,
+
+    /// Also retry when the connection is refused
+    #[structopt(long)]
+    retry_connrefused: bool,
+
+    /// Retry on every error, including 4xx responses
+    #[structopt(long)]
+    retry_all_errors: bool,
+
+    /// Wait this many seconds between retries instead of backing off exponentially
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    retry_delay: Option<Duration>,
+
+    /// Stop retrying once this many seconds have passed since the first attempt
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    retry_max_time: Option<Duration>,
+
+    /// Include the status line and response headers of every response in the output, with the header names in lowercase
+    #[structopt(short = "i", long)]
+    include: bool,
+
+    /// Write the status line and response headers to this file, "-" for stdout, with the header names in lowercase
+    #[structopt(short = "D", long, parse(from_os_str))]
+    dump_header: Option<PathBuf>,
+
+    /// Send a HEAD request and show only the status line and response headers
+    #[structopt(short = "I", long, conflicts_with_all = &["data", "data-binary", "data-raw", "data-urlencode", "form", "form-string", "json", "upload-file"])]
+    head: bool,
+
+    /// Write the response body to this file instead of stdout, "-" for stdout; repeat for more
+    /// URLs, which each take the next one
+    #[structopt(short = "o", long, number_of_values = 1, parse(from_os_str))]
+    output: Vec<PathBuf>,
+
+    /// Write the response body to a file named after the last segment of the URL path; repeat for
+    /// more URLs, which take them after the -o files
+    #[structopt(short = "O", long, parse(from_occurrences))]
+    remote_name: usize,
+
+    /// With -O, use the file name from the Content-Disposition header when there is one
+    #[structopt(short = "J", long, requires = "remote-name")]
+    remote_header_name: bool,
+
+    /// Let -J overwrite existing files
+    #[structopt(long, requires = "remote-header-name")]
+    clobber: bool,
+
+    /// Give -o and -O files the modification time from the Last-Modified header
+    #[structopt(short = "R", long)]
+    remote_time: bool,
+
+    /// Directory that -o and -O files are saved in
+    #[structopt(long, parse(from_os_str))]
+    output_dir: Option<PathBuf>,
+
+    /// Create missing directories for -o and -O files
+    #[structopt(long)]
+    create_dirs: bool,
+
+    /// Ask for a compressed response, gzip, deflate, brotli or zstd, and decode it
+    #[structopt(long, conflicts_with = "raw")]
+    compressed: bool,
+
+    /// Write the body exactly as received, without undoing its Content-Encoding
+    #[structopt(long)]
+    raw: bool,
+
+    /// Limit the download and upload speed to this many bytes per second, with an optional k, M or G suffix
+    #[structopt(long, parse(try_from_str = parse_rate))]
+    limit_rate: Option<u64>,
+
+    /// Start at most this many requests per second, minute or hour when fetching several URLs, like "10/s", "2/m" or "100/h"
+    #[structopt(long, parse(try_from_str = rate::parse_request_rate))]
+    rate: Option<Duration>,
+
+    /// Only get these bytes of the body, like "0-499", "500-", "-500" or several joined with commas
+    #[structopt(short = "r", long, allow_hyphen_values = true, parse(try_from_str = parse_range))]
+    range: Option<String>,
+
+    /// Resume the download at this byte offset, appending to the output file; "-" takes the offset from its size
+    #[structopt(
+        short = "C",
+        long,
+        allow_hyphen_values = true,
+        conflicts_with_all = &["range", "remote-header-name"],
+        parse(try_from_str = parse_resume)
+    )]
+    continue_at: Option<Resume>,
+
+    /// Fail when the server ignores -r or -C and sends the whole body
+    #[structopt(long)]
+    strict_range: bool,
+
+    /// Only download the body if it changed after this date, or after the modification time of this file; a leading "-" means before instead
+    #[structopt(short = "z", long, allow_hyphen_values = true)]
+    time_cond: Option<String>,
+
+    /// Save the ETag of the response to this file
+    #[structopt(long, parse(from_os_str))]
+    etag_save: Option<PathBuf>,
+
+    /// Only download the body if its ETag differs from the one saved in this file by --etag-save
+    #[structopt(long, parse(from_os_str))]
+    etag_compare: Option<PathBuf>,
 
-        if let Some(data) = &opt.data {
-            println!("Data: {}", data);
+    /// Refuse to download bodies larger than this many bytes, with an optional k, M or G suffix
+    #[structopt(long, parse(try_from_str = parse_size))]
+    max_filesize: Option<u64>,
+
+    /// Fail with exit code 22 and no output on HTTP errors, status 400 and up
+    #[structopt(short = "f", long, conflicts_with = "fail-with-body")]
+    fail: bool,
+
+    /// Like --fail, but still write the body of the error response
+    #[structopt(long)]
+    fail_with_body: bool,
+
+    /// Send the URLs as they are, without expanding [] ranges and {} lists
+    #[structopt(short = "g", long)]
+    globoff: bool,
+
+    /// Fetch the URLs in parallel instead of one after the other
+    #[structopt(short = "Z", long)]
+    parallel: bool,
+
+    /// How many transfers -Z runs at once, up to 300
+    #[structopt(long, default_value = "50")]
+    parallel_max: usize,
+
+    /// Read options from this file, "-" for stdin, one per line like "max-time = 10" or "header = \"X-Custom: 1\"". The command line wins over them
+    #[structopt(short = "K", long, number_of_values = 1)]
+    // The files are read by config::with_files before parsing, this is for --help
+    #[allow(dead_code)]
+    config: Vec<String>,
+
+    /// Do not read the default .curlrc file, from $CURL_HOME, $XDG_CONFIG_HOME or $HOME
+    #[structopt(short = "q", long)]
+    // Like -K, this is seen before parsing
+    #[allow(dead_code)]
+    disable: bool,
+
+    /// Define a variable as name=value, name@file or %NAME from the environment. --expand-url, --expand-data, --expand-header, --expand-json and so on put it in their value where it says {{name}}, or {{name:trim}}, {{name:json}}, {{name:url}} and {{name:b64}}
+    #[structopt(long, number_of_values = 1)]
+    // The variables are expanded before parsing
+    #[allow(dead_code)]
+    variable: Vec<String>,
+
+    /// Start a new group of URLs with options of their own, like the method, data, headers and output. The connections and options like -v, proxies and TLS are shared
+    #[structopt(short = ":", long)]
+    // The arguments are split on it before parsing
+    #[allow(dead_code)]
+    next: bool,
+
+    /// Stop at the first URL that fails instead of going on with the rest
+    #[structopt(long)]
+    fail_early: bool,
+
+    /// Silent mode, no progress or error messages
+    #[structopt(short = "s", long)]
+    silent: bool,
+
+    /// Show error messages even with -s
+    #[structopt(short = "S", long)]
+    show_error: bool,
+
+    /// Show a simple progress bar instead of the progress meter
+    #[structopt(short = "#", long)]
+    progress_bar: bool,
+
+    /// Do not show the progress meter
+    #[structopt(long)]
+    no_progress_meter: bool,
+
+    /// Print how long each phase of the transfer took to stderr
+    #[structopt(long)]
+    timings: bool,
+
+    /// Write this to stdout after the transfer, with %{variable}s filled in; "@file" reads it from a file and "@-" from stdin
+    #[structopt(short = "w", long)]
+    write_out: Option<String>,
+
+    /// Write a hex and text dump of all data sent and received to this file, "-" for stderr
+    #[structopt(long, parse(from_os_str), conflicts_with = "trace-ascii")]
+    trace: Option<PathBuf>,
+
+    /// Like --trace, but without the hex part
+    #[structopt(long, parse(from_os_str))]
+    trace_ascii: Option<PathBuf>,
+
+    /// Prefix every --trace line with a timestamp
+    #[structopt(long)]
+    trace_time: bool,
+
+    /// Mask credentials in the headers shown by -v
+    #[structopt(long)]
+    redact: bool,
+
+    /// Send requests through this proxy, [scheme://]host[:port] with http, https, socks5 or socks5h; "" turns proxies off
+    #[structopt(short = "x", long)]
+    proxy: Option<String>,
+
+    /// Hosts to reach without a proxy, comma-separated domains, IP addresses or CIDR blocks; "*" for all
+    #[structopt(long)]
+    noproxy: Option<String>,
+
+    /// User and password for the proxy, "user:password"; without a password it is prompted for
+    #[structopt(short = "U", long)]
+    proxy_user: Option<String>,
+
+    /// Extra header to send to the proxy but never to the server, "Name: value"; repeat for more
+    #[structopt(long = "proxy-header", number_of_values = 1)]
+    proxy_headers: Vec<String>,
+
+    /// CA certificates to verify an HTTPS proxy with, a PEM file used instead of the system ones
+    #[structopt(long, parse(from_os_str))]
+    proxy_cacert: Option<PathBuf>,
+
+    /// Don't verify the certificate of an HTTPS proxy; servers are still verified
+    #[structopt(long)]
+    proxy_insecure: bool,
+
+    /// Don't verify the server's TLS certificate or that it is for the host
+    #[structopt(short = "k", long, conflicts_with_all = &["cacert", "capath"])]
+    insecure: bool,
+
+    /// CA certificates to verify servers with, a PEM file used instead of the system ones
+    #[structopt(long, parse(from_os_str))]
+    cacert: Option<PathBuf>,
+
+    /// A directory of .pem and .crt CA certificates used instead of the system ones
+    #[structopt(long, parse(from_os_str))]
+    capath: Option<PathBuf>,
+
+    /// Client certificate to authenticate with, "file[:password]"
+    #[structopt(short = "E", long)]
+    cert: Option<String>,
+
+    /// The private key for --cert, when the certificate file doesn't hold it
+    #[structopt(long, parse(from_os_str), requires = "cert")]
+    key: Option<PathBuf>,
+
+    /// The format of the --cert file: PEM (the default), DER or P12
+    #[structopt(long, requires = "cert")]
+    cert_type: Option<CertType>,
+
+    /// Password for the private key of --cert; without one it is prompted for when needed
+    #[structopt(long, requires = "cert")]
+    pass: Option<String>,
+
+    /// Public keys the server's certificate must have one of: "sha256//<base64 hash>" pins separated
+    /// by ';', or a PEM or DER public key file
+    #[structopt(long)]
+    pinnedpubkey: Option<String>,
+
+    /// Connect to these addresses for a host and port instead of looking the host up,
+    /// "host:port:address[,address]"; repeat for more
+    #[structopt(long, number_of_values = 1, parse(try_from_str = resolve::parse_override))]
+    resolve: Vec<resolve::Override>,
+
+    /// Connect to another host and port for one, while the request is still for the URL,
+    /// "host:port:connect-host:connect-port" where an empty field means any; repeat for more
+    #[structopt(long, number_of_values = 1, parse(try_from_str = resolve::parse_connect_to))]
+    connect_to: Vec<resolve::ConnectTo>,
+
+    /// Look host names up with this DNS-over-HTTPS server instead of the system resolver
+    #[structopt(long, parse(try_from_str = doh::parse_url))]
+    doh_url: Option<Url>,
+
+    /// Connect to IPv4 addresses only
+    #[structopt(short = "4", long, conflicts_with = "ipv6")]
+    ipv4: bool,
+
+    /// Connect to IPv6 addresses only
+    #[structopt(short = "6", long)]
+    ipv6: bool,
+
+    /// Make connections from this local address: an IP address, a network interface name or a
+    /// host name
+    #[structopt(long)]
+    interface: Option<String>,
+
+    /// Make connections from a local port in this range, "port" or "first-last"
+    #[structopt(long, parse(try_from_str = local::parse_ports))]
+    local_port: Option<RangeInclusive<u16>>,
+
+    /// Connect through this Unix domain socket instead of to the host of the URL
+    #[structopt(long, parse(from_os_str))]
+    unix_socket: Option<PathBuf>,
+
+    /// Connect through this abstract Unix domain socket instead of to the host of the URL, Linux only
+    #[structopt(long, parse(from_os_str), conflicts_with = "unix-socket")]
+    abstract_unix_socket: Option<PathBuf>,
+
+    /// Use HTTP/1.0, closing the connection after each request
+    #[structopt(long = "http1.0", conflicts_with_all = &["http1-1", "http2", "http2-prior-knowledge"])]
+    http1_0: bool,
+
+    /// Use HTTP/1.1 only
+    #[structopt(long = "http1.1", conflicts_with_all = &["http2", "http2-prior-knowledge"])]
+    http1_1: bool,
+
+    /// Use HTTP/2 for HTTPS when the server agrees to it during the TLS handshake
+    #[structopt(long)]
+    http2: bool,
+
+    /// Use HTTP/2 from the start, without asking the server first; works on plain http:// URLs
+    #[structopt(long, conflicts_with = "http2")]
+    http2_prior_knowledge: bool,
+
+    /// The TLS 1.2 cipher suites to offer, IANA names separated by ':'
+    #[structopt(long, parse(try_from_str = CipherList::tls12))]
+    ciphers: Option<CipherList>,
+
+    /// The TLS 1.3 cipher suites to offer, IANA names separated by ':'
+    #[structopt(long, parse(try_from_str = CipherList::tls13))]
+    tls13_ciphers: Option<CipherList>,
+
+    /// Use TLS 1.2 or later
+    #[structopt(long = "tlsv1.2")]
+    tlsv1_2: bool,
+
+    /// Use TLS 1.3 or later
+    #[structopt(long = "tlsv1.3")]
+    tlsv1_3: bool,
+
+    /// The newest TLS version to use, 1.2 or 1.3
+    #[structopt(long)]
+    tls_max: Option<TlsVersion>,
+
+    /// Maximum time in seconds allowed for connecting, fractions allowed
+    #[structopt(long, parse(try_from_str = parse_seconds))]
+    connect_timeout: Option<Duration>,
+
+    /// Maximum time in seconds allowed for the whole transfer, fractions allowed
+    #[structopt(short = "m", long, parse(try_from_str = parse_seconds))]
+    max_time: Option<Duration>,
+
+    /// Seconds to wait for a 100 Continue before sending the body anyway, fractions allowed
+    #[structopt(long, default_value = "1", parse(try_from_str = parse_seconds))]
+    expect100_timeout: Duration,
+
+    /// Bodies longer than this many bytes, or of unknown length, are sent with "Expect: 100-continue"
+    #[structopt(long, default_value = "1024")]
+    expect100_threshold: u64,
+}
+
+impl Opt {
+    // The path of the --unix-socket, or of the --abstract-unix-socket, which starts with a NUL
+    fn socket_path(&self) -> Option<PathBuf> {
+        #[cfg(target_os = "linux")]
+        if let Some(name) = &self.abstract_unix_socket {
+            use std::os::unix::ffi::OsStrExt;
+
+            let mut path = vec![0];
+            path.extend_from_slice(name.as_os_str().as_bytes());
+            return Some(PathBuf::from(std::ffi::OsStr::from_bytes(&path)));
         }
+
+        self.unix_socket.clone()
     }
 
-    match Url::parse(&opt.url) {
-        Ok(url) => {
-            // Restrict to HTTP and HTTPS
-            if url.scheme() != "http" && url.scheme() != "https" {
-                println!("Error: The URL does not have a valid base protocol.");
-            }
+    // The address family -4 or -6 keeps to
+    fn family(&self) -> Option<Family> {
+        if self.ipv4 {
+            Some(Family::V4)
+        } else if self.ipv6 {
+            Some(Family::V6)
+        } else {
+            None
         }
-        Err(e) => match e {
-            ParseError::RelativeUrlWithoutBase
-            | ParseError::RelativeUrlWithCannotBeABaseBase
-            | ParseError::SetHostOnCannotBeABaseUrl => {
-                println!("Error: The URL does not have a valid base protocol.")
-            }
-            ParseError::InvalidIpv4Address => {
-                println!("Error: The URL contains an invalid IPv4 address.")
-            }
-            ParseError::InvalidIpv6Address => {
-                println!("Error: The URL contains an invalid IPv6 address.")
-            }
-            ParseError::InvalidPort => println!("Error: The URL contains an invalid port number."),
-            _ => println!("Error: {e}"),
-        },
-    };
+    }
 
-    match make_request(opt) {
-        Ok(resp) => {
-            if !resp.status().is_success() {
-                println!(
-                    "Error: Request failed with status code: {}.",
-                    resp.status().as_u16()
-                );
-                return;
-            }
+    // Puts the --url URLs among the others in the order they were given, which is the order of
+    // their -o files
+    fn order_urls(&mut self, matches: &ArgMatches) {
+        let positional = matches
+            .indices_of("url")
+            .into_iter()
+            .flatten()
+            .zip(std::mem::take(&mut self.urls));
+        let options = matches
+            .indices_of("url-option")
+            .into_iter()
+            .flatten()
+            .zip(std::mem::take(&mut self.url_options));
 
-            let body = resp.text().unwrap();
+        let mut urls: Vec<(usize, String)> = positional.chain(options).collect();
+        urls.sort_by_key(|(at, _)| *at);
+        self.urls = urls.into_iter().map(|(_, url)| url).collect();
+    }
 
-            // Check if response is JSON
-            match serde_json::from_str::<Value>(&body) {
-                Ok(json) => {
-                    println!("Response body (JSON with sorted keys):");
-                    println!("{:#}", json);
-                }
-                Err(_) => {
-                    println!("Response body:");
-                    println!("{}", body.trim());
-                }
-            };
+    // -T uploads with a PUT unless -X says otherwise, and form parts and data are sent with a POST,
+    // unless -G puts the data in the query instead
+    fn default_method(&mut self, matches: &ArgMatches) {
+        if matches.occurrences_of("method") > 0 {
+            return;
         }
-        Err(e) => {
-            if e.is_timeout() || e.is_connect() {
-                println!("Error: Unable to connect to the server. Perhaps the network is offline or the server hostname cannot be resolved.");
-                return;
-            }
+        if !self.upload_file.is_empty() {
+            self.method = Method::PUT;
+        } else if !self.form_fields.is_empty() || (!self.data_parts.is_empty() && !self.get) {
+            self.method = Method::POST;
         }
     }
-}
 
-fn make_request(opt: Opt) -> Result<reqwest::blocking::Response, reqwest::Error> {
-    // JSON request
-    if let Some(json) = opt.json {
-        let json: Value = match serde_json::from_str(&json) {
-            Ok(json) => json,
-            Err(e) => {
-                panic!("Invalid JSON: {:#?}", e);
-            }
-        };
+    // Puts the values of the data options together in the order they were given, which is the
+    // order curl joins them in, and the form fields in the order of their parts
+    fn order_data(&mut self, matches: &ArgMatches) {
+        let options = [
+            (
+                "data",
+                std::mem::take(&mut self.data),
+                Data::Ascii as fn(String) -> Data,
+            ),
+            (
+                "data-binary",
+                std::mem::take(&mut self.data_binary),
+                Data::Binary,
+            ),
+            ("data-raw", std::mem::take(&mut self.data_raw), Data::Raw),
+            (
+                "data-urlencode",
+                std::mem::take(&mut self.data_urlencode),
+                Data::Encode,
+            ),
+        ];
+
+        let mut parts: Vec<(usize, Data)> = Vec::new();
+        for (name, values, part) in options {
+            let at = matches.indices_of(name).into_iter().flatten();
+            parts.extend(at.zip(values.into_iter().map(part)));
+        }
+        parts.sort_by_key(|(at, _)| *at);
+        self.data_parts = parts.into_iter().map(|(_, part)| part).collect();
+
+        let form = matches
+            .indices_of("form")
+            .into_iter()
+            .flatten()
+            .zip(std::mem::take(&mut self.form).into_iter().map(Field::Form));
+        let strings = matches.indices_of("form-string").into_iter().flatten().zip(
+            std::mem::take(&mut self.form_string)
+                .into_iter()
+                .map(Field::String),
+        );
+        let mut fields: Vec<(usize, Field)> = form.chain(strings).collect();
+        fields.sort_by_key(|(at, _)| *at);
+        self.form_fields = fields.into_iter().map(|(_, field)| field).collect();
+    }
 
-        let client = reqwest::blocking::Client::new();
+    // Takes the options of the first group for the whole invocation, the ones for the output
+    // and the client shared by every group
+    fn inherit(&mut self, first: &Opt) {
+        self.verbose = first.verbose;
+        self.silent = first.silent;
+        self.show_error = first.show_error;
+        self.progress_bar = first.progress_bar;
+        self.no_progress_meter = first.no_progress_meter;
+        self.trace = first.trace.clone();
+        self.trace_ascii = first.trace_ascii.clone();
+        self.trace_time = first.trace_time;
+        self.redact = first.redact;
+        self.parallel = first.parallel;
+        self.parallel_max = first.parallel_max;
+        self.rate = first.rate;
+        self.fail_early = first.fail_early;
+        self.cookie_jar = first.cookie_jar.clone();
+        self.junk_session_cookies = first.junk_session_cookies;
 
-        let resp = client.post(&opt.url).json(&json).send()?;
+        self.proxy = first.proxy.clone();
+        self.noproxy = first.noproxy.clone();
+        self.proxy_user = first.proxy_user.clone();
+        self.proxy_headers = first.proxy_headers.clone();
+        self.proxy_cacert = first.proxy_cacert.clone();
+        self.proxy_insecure = first.proxy_insecure;
+        self.insecure = first.insecure;
+        self.cacert = first.cacert.clone();
+        self.capath = first.capath.clone();
+        self.cert = first.cert.clone();
+        self.key = first.key.clone();
+        self.cert_type = first.cert_type;
+        self.pass = first.pass.clone();
+        self.pinnedpubkey = first.pinnedpubkey.clone();
+        self.ciphers = first.ciphers.clone();
+        self.tls13_ciphers = first.tls13_ciphers.clone();
+        self.tlsv1_2 = first.tlsv1_2;
+        self.tlsv1_3 = first.tlsv1_3;
+        self.tls_max = first.tls_max;
 
-        return Ok(resp);
+        self.resolve = first.resolve.clone();
+        self.connect_to = first.connect_to.clone();
+        self.doh_url = first.doh_url.clone();
+        self.ipv4 = first.ipv4;
+        self.ipv6 = first.ipv6;
+        self.interface = first.interface.clone();
+        self.local_port = first.local_port.clone();
+        self.unix_socket = first.unix_socket.clone();
+        self.abstract_unix_socket = first.abstract_unix_socket.clone();
+        self.http1_0 = first.http1_0;
+        self.http1_1 = first.http1_1;
+        self.http2 = first.http2;
+        self.http2_prior_knowledge = first.http2_prior_knowledge;
+        self.connect_timeout = first.connect_timeout;
+        self.max_time = first.max_time;
+        self.expect100_timeout = first.expect100_timeout;
     }
 
-    // Non-JSON request
-    let resp = match opt.method {
-        Method::GET => reqwest::blocking::get(&opt.url)?,
-        Method::POST => {
-            let client = reqwest::blocking::Client::new();
-            let data = opt.data.unwrap();
-            let params = parse_params(&data);
+    // The oldest TLS version allowed, the newer of --tlsv1.2 and --tlsv1.3
+    fn tls_min(&self) -> Option<TlsVersion> {
+        if self.tlsv1_3 {
+            Some(TlsVersion::Tls13)
+        } else if self.tlsv1_2 {
+            Some(TlsVersion::Tls12)
+        } else {
+            None
+        }
+    }
+}
+
+// A number of bytes with an optional k, M or G suffix for powers of 1024, like curl
+fn parse_size(s: &str) -> Result<u64, String> {
+    let invalid = || {
+        format!(
+            "'{s}' is not a valid size, use a number of bytes with an optional k, M or G suffix"
+        )
+    };
+
+    let (number, unit) = match s.char_indices().last() {
+        Some((at, suffix)) if suffix.is_ascii_alphabetic() => (&s[..at], Some(suffix)),
+        _ => (s, None),
+    };
+    let multiplier: u64 = match unit.map(|unit| unit.to_ascii_lowercase()) {
+        None | Some('b') => 1,
+        Some('k') => 1 << 10,
+        Some('m') => 1 << 20,
+        Some('g') => 1 << 30,
+        Some(_) => return Err(invalid()),
+    };
 
-            client.post(&opt.url).form(&params).send()?
+    match number.parse::<f64>() {
+        Ok(number) if number.is_finite() && number >= 0.0 => {
+            let bytes = number * multiplier as f64;
+            if bytes >= u64::MAX as f64 {
+                Err(invalid())
+            } else {
+                Ok(bytes as u64)
+            }
         }
+        _ => Err(invalid()),
+    }
+}
+
+// --limit-rate needs a speed to limit to, unlike curl where 0 means no limit
+fn parse_rate(s: &str) -> Result<u64, String> {
+    match parse_size(s)? {
+        0 => Err(format!("'{s}' is not a valid rate, it has to be above 0")),
+        rate => Ok(rate),
+    }
+}
+
+// Byte ranges as they go into the Range header: `first-last`, `first-` or `-suffix`, joined by commas
+fn parse_range(s: &str) -> Result<String, String> {
+    let invalid = || format!("'{s}' is not a valid range, use ranges like 0-499, 500- or -500");
+    let number = |n: &str| {
+        (!n.is_empty() && n.bytes().all(|b| b.is_ascii_digit()))
+            .then(|| n.parse::<u64>().ok())
+            .flatten()
     };
 
-    Ok(resp)
+    let ranges = s
+        .split(',')
+        .map(|range| {
+            let (first, last) = range.trim().split_once('-').ok_or_else(invalid)?;
+            match (number(first), number(last)) {
+                (Some(first), Some(last)) if first <= last => Ok(format!("{first}-{last}")),
+                (Some(first), None) if last.is_empty() => Ok(format!("{first}-")),
+                (None, Some(last)) if first.is_empty() => Ok(format!("-{last}")),
+                _ => Err(invalid()),
+            }
+        })
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(ranges.join(","))
+}
+
+/// Where -C resumes the download.
+#[derive(Clone, Copy, Debug)]
+enum Resume {
+    At(u64),
+    // At the end of what the output file already holds
+    Auto,
 }
 
-fn parse_params(data: &str) -> HashMap<&str, &str> {
-    let mut params = HashMap::new();
+fn parse_resume(s: &str) -> Result<Resume, String> {
+    match s {
+        "-" => Ok(Resume::Auto),
+        _ => s
+            .parse()
+            .map(Resume::At)
+            .map_err(|_| format!("'{s}' is not a valid offset, use a number of bytes or \"-\"")),
+    }
+}
 
-    for param in data.split('&') {
-        let parts: Vec<&str> = param.split('=').collect();
+fn parse_proto_default(s: &str) -> Result<String, String> {
+    match s.to_ascii_lowercase().as_str() {
+        scheme @ ("http" | "https") => Ok(scheme.to_string()),
+        _ => Err(format!(
+            "'{s}' is not a supported protocol for --proto-default, use http or https"
+        )),
+    }
+}
 
-        if parts.len() >= 2 {
-            params.insert(parts[0], parts[1]);
-        }
+fn parse_seconds(s: &str) -> Result<Duration, String> {
+    match s.parse::<f64>() {
+        Ok(secs) if secs.is_finite() && secs >= 0.0 => Ok(Duration::from_secs_f64(secs)),
+        _ => Err(format!("'{s}' is not a valid number of seconds")),
     }
+}
 
-    params
+// Parses the arguments of one line of a -K file on their own, to report a
+// bad option or value with the line it is on. What only fails together with
+// other options, like a missing URL, is left to the full parse, and so are --expand- options,
+// which only are options once their variables are expanded.
+fn check_config_line(args: &[OsString]) -> Result<(), String> {
+    if args
+        .first()
+        .is_some_and(|arg| arg.to_string_lossy().starts_with("--expand-"))
+    {
+        return Ok(());
+    }
+    let argv = std::iter::once(OsString::from("curl"))
+        .chain(args.iter().cloned())
+        .chain(std::iter::once(OsString::from("http://localhost")));
+    let Err(e) = Opt::clap()
+        .setting(AppSettings::ColorNever)
+        .get_matches_from_safe(argv)
+    else {
+        return Ok(());
+    };
+
+    match e.kind {
+        ErrorKind::UnknownArgument
+        | ErrorKind::InvalidValue
+        | ErrorKind::ValueValidation
+        | ErrorKind::EmptyValue
+        | ErrorKind::TooManyValues
+        | ErrorKind::TooFewValues
+        | ErrorKind::WrongNumberOfValues
+        | ErrorKind::InvalidUtf8 => {
+            let message = e.message.lines().next().unwrap_or_default();
+            Err(message.trim_start_matches("error: ").to_string())
+        }
+        _ => Ok(()),
+    }
+}
+
+fn main() {
+    let (args, rc_error) =
+        match config::with_files(std::env::args_os().collect(), check_config_line) {
+            Ok(expanded) => expanded,
+            // A -K file is part of the command line, which curl cannot start with
+            Err(e) => {
+                eprintln!("error: {e}");
+                std::process::exit(Exit::Init.code());
+            }
+        };
+    let args = match variable::expand(args) {
+        Ok(args) => args,
+        Err(e) => structopt::clap::Error::with_description(
+            &e.to_string(),
+            structopt::clap::ErrorKind::InvalidValue,
+        )
+        .exit(),
+    };
+    // A later option wins over an earlier one, so the command line wins over -K files
+    let mut opts: Vec<Opt> = Vec::new();
+    for group in split_next(args) {
+        let mut opt = parse_group(group);
+        if let Some(first) = opts.first() {
+            opt.inherit(first);
+        }
+        opts.push(opt);
+    }
+    let opt = &opts[0];
+    if let Some(e) = rc_error {
+        report_warning(opt, format_args!("{e}, it is left out"));
+    }
+
+    if let (Some(min), Some(max)) = (opt.tls_min(), opt.tls_max) {
+        if min > max {
+            structopt::clap::Error::with_description(
+                &format!("The minimum TLS version {min} is above the --tls-max of {max}"),
+                structopt::clap::ErrorKind::ArgumentConflict,
+            )
+            .exit();
+        }
+    }
+
+    if let Err(exit) = run(&opts) {
+        std::process::exit(exit.code());
+    }
+}
+
+// The options of one --next group, with the URLs, data and method in the order given
+fn parse_group(args: Vec<OsString>) -> Opt {
+    let matches = Opt::clap()
+        .setting(AppSettings::AllArgsOverrideSelf)
+        .get_matches_from(args);
+    let mut opt = Opt::from_clap(&matches);
+    opt.order_urls(&matches);
+    opt.order_data(&matches);
+    opt.default_method(&matches);
+    opt
+}
+
+// The arguments of each group of options --next separates, each starting with the program name
+// for parsing on its own
+fn split_next(args: Vec<OsString>) -> Vec<Vec<OsString>> {
+    let mut args = args.into_iter();
+    let program = args.next().unwrap_or_else(|| OsString::from("curl"));
+
+    let mut groups = vec![vec![program.clone()]];
+    for arg in args {
+        if arg == "--next" || arg == "-:" {
+            groups.push(vec![program.clone()]);
+        } else if let Some(group) = groups.last_mut() {
+            group.push(arg);
+        }
+    }
+
+    groups
+}
+
+/// Runs the transfers `opts` describe, one URL after the other and one
+/// group of --next options after the other. Failures have already been
+/// reported by the time the error comes back, which is the one of the last
+/// URL, or of the first failing one with --fail-early.
+fn run(opts: &[Opt]) -> Result<(), Exit> {
+    // The first group has the options for the whole invocation, which the others inherit
+    let Some(opt) = opts.first() else {
+        return Ok(());
+    };
+    let trace = match (&opt.trace, &opt.trace_ascii) {
+        (Some(path), _) => Some((path, false)),
+        (None, Some(path)) => Some((path, true)),
+        (None, None) => None,
+    };
+    if let Some((path, ascii)) = trace {
+        if let Err(e) = trace::init(path, ascii, opt.trace_time) {
+            report_error(
+                opt,
+                format_args!("Unable to open trace file {}: {e}", path.display()),
+            );
+            return Err(Exit::Write);
+        }
+    }
+
+    let groups = opts.iter().map(group).collect::<Result<Vec<_>, _>>()?;
+
+    // Received cookies are only kept with a cookie file to load or save them, and one jar is
+    // shared by every group so each sees the cookies the ones before it received
+    let files: Vec<&String> = opts
+        .iter()
+        .flat_map(|opt| &opt.cookies)
+        .filter(|cookie| !cookie.contains('='))
+        .collect();
+    let mut jar = (!files.is_empty() || opt.cookie_jar.is_some()).then(Jar::default);
+    for path in &files {
+        if let Err(e) = jar.get_or_insert_default().load(Path::new(path)) {
+            report_error(opt, &e);
+            return Err(Exit::Read);
+        }
+    }
+    // Only loaded cookies are junked, the ones received from now on are kept
+    if opt.junk_session_cookies {
+        match &mut jar {
+            Some(jar) if !files.is_empty() => jar.junk_session_cookies(),
+            _ => report_warning(
+                opt,
+                "--junk-session-cookies has no effect without a -b cookie file",
+            ),
+        }
+    }
+
+    if (!cfg!(unix) && opt.unix_socket.is_some())
+        || (!cfg!(target_os = "linux") && opt.abstract_unix_socket.is_some())
+    {
+        report_error(
+            opt,
+            "Unix domain sockets are not supported on this platform",
+        );
+        return Err(Exit::UnsupportedProtocol);
+    }
+
+    if opt.insecure {
+        report_warning(
+            opt,
+            "-k is set, the server's certificate is not verified and the connection may be intercepted",
+        );
+    }
+
+    if opt.verbose {
+        for entry in &opt.resolve {
+            eprintln!("* Added {entry} to the DNS cache");
+        }
+    }
+
+    // Every glob is expanded before the first transfer, so a bad one stops them all. The URLs of
+    // --url-file come after the others
+    let mut targets = Vec::new();
+    for (group, opt) in opts.iter().enumerate() {
+        let mut urls: Vec<(String, Option<String>)> =
+            opt.urls.iter().map(|url| (url.clone(), None)).collect();
+        for path in &opt.url_file {
+            match read_url_file(path) {
+                Ok(lines) => urls.extend(lines.into_iter().map(|(url, line)| (url, Some(line)))),
+                Err(e) => {
+                    report_error(
+                        opt,
+                        format_args!("Unable to read URLs from {}: {e}", path.display()),
+                    );
+                    return Err(Exit::Read);
+                }
+            }
+        }
+
+        for (index, (url, source)) in urls.into_iter().enumerate() {
+            let expanded = if opt.globoff {
+                Ok(vec![Expanded {
+                    url,
+                    values: Vec::new(),
+                }])
+            } else {
+                glob::expand(&url)
+            };
+            match expanded {
+                Ok(expanded) => targets.extend(expanded.into_iter().map(|mut expanded| {
+                    if let Some(url) = guess_scheme(&expanded.url, opt.proto_default.as_deref()) {
+                        if opt.verbose {
+                            eprintln!("* No scheme in '{}', using {url}", expanded.url);
+                        }
+                        expanded.url = url;
+                    }
+                    Target {
+                        group,
+                        index,
+                        expanded,
+                        source: source.clone(),
+                    }
+                })),
+                Err(e) => {
+                    match &source {
+                        Some(source) => report_error(opt, format_args!("{source}: {e}")),
+                        None => report_error(opt, &e),
+                    }
+                    return Err(Exit::MalformedUrl);
+                }
+            }
+        }
+    }
+
+    // A single URL has nothing to be spaced out from
+    let pacer = opt
+        .rate
+        .filter(|_| targets.len() > 1)
+        .map(|interval| Arc::new(Pacer::new(interval)));
+
+    let jar = jar.map(Mutex::new);
+    let result = if opt.parallel {
+        fetch_parallel(&groups, &targets, pacer, jar.as_ref())
+    } else {
+        let session = match session(opt, pacer) {
+            Ok(session) => session,
+            Err(e) => {
+                report_error(opt, &e);
+                return Err(Exit::from(&e));
+            }
+        };
+        let mut result = Ok(());
+        for target in &targets {
+            result = fetch_url(&groups[target.group], &session, target, jar.as_ref());
+            if result.is_err() && opt.fail_early {
+                break;
+            }
+        }
+        result
+    };
+
+    // Like curl, the cookies are saved even when a transfer failed
+    let jar = jar.and_then(|jar| jar.into_inner().ok());
+    if let (Some(jar), Some(path)) = (&jar, &opt.cookie_jar) {
+        if let Err(e) = jar.save(path) {
+            report_warning(opt, e);
+        }
+    }
+
+    result
+}
+
+/// One URL to fetch, of the `group`th group of options and expanded from its
+/// `index`th URL, which says what -o or -O file it is saved to.
+struct Target {
+    group: usize,
+    index: usize,
+    expanded: Expanded,
+    // The file and line a --url-file had it on, for errors
+    source: Option<String>,
+}
+
+// The URL with a scheme put in front when it has none: --proto-default, or https for a host
+// starting with "www." and http for the others. None when it has a scheme, or is no URL even
+// with one
+fn guess_scheme(url: &str, default: Option<&str>) -> Option<String> {
+    let has_scheme = url.split_once("://").is_some_and(|(scheme, _)| {
+        !scheme.is_empty()
+            && scheme
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || "+-.".contains(c))
+    });
+    if has_scheme {
+        return None;
+    }
+
+    let scheme = match default {
+        Some(scheme) => scheme,
+        None if url.to_ascii_lowercase().starts_with("www.") => "https",
+        None => "http",
+    };
+    let guessed = format!("{scheme}://{url}");
+    Url::parse(&guessed).is_ok().then_some(guessed)
+}
+
+// The URLs in a --url-file, each with the file and line it is on
+fn read_url_file(path: &Path) -> std::io::Result<Vec<(String, String)>> {
+    let (name, text) = if path == Path::new("-") {
+        let mut text = String::new();
+        std::io::stdin().read_to_string(&mut text)?;
+        ("<stdin>".to_string(), text)
+    } else {
+        (path.display().to_string(), std::fs::read_to_string(path)?)
+    };
+
+    Ok(text
+        .lines()
+        .enumerate()
+        .map(|(n, line)| (line.trim(), n + 1))
+        .filter(|(line, _)| !line.is_empty() && !line.starts_with('#'))
+        .map(|(line, n)| (line.to_string(), format!("{name}:{n}")))
+        .collect())
+}
+
+/// The options of one group between --next, and what is worked out from
+/// them once for all of its URLs.
+struct Group<'a> {
+    opt: &'a Opt,
+    headers: HeaderMap,
+    // The -d values joined and the --json ones, read once for all the URLs
+    data: Option<Vec<u8>>,
+    json: Option<Vec<u8>>,
+    form: Option<Form>,
+    credentials: Option<Credentials>,
+    write_out: Option<String>,
+}
+
+fn group(opt: &Opt) -> Result<Group<'_>, Exit> {
+    let write_out = match opt.write_out.as_deref().map(writeout::load).transpose() {
+        Ok(write_out) => write_out,
+        Err(e) => {
+            let source = opt.write_out.as_deref().unwrap_or_default();
+            report_error(
+                opt,
+                format_args!("Unable to read the --write-out format from {source}: {e}"),
+            );
+            return Err(Exit::Read);
+        }
+    };
+
+    let mut headers = match parse_headers(&opt.headers) {
+        Ok(headers) => headers,
+        Err(e) => {
+            report_error(opt, &e);
+            return Err(Exit::from(&e));
+        }
+    };
+
+    let data = match data::join(&opt.data_parts) {
+        Ok(data) => Some(data).filter(|_| !opt.data_parts.is_empty()),
+        Err(e) => {
+            report_error(opt, &e);
+            return Err(Exit::from(&e));
+        }
+    };
+    let json = match data::concat(&opt.json) {
+        Ok(json) => Some(json).filter(|_| !opt.json.is_empty()),
+        Err(e) => {
+            report_error(opt, &e);
+            return Err(Exit::from(&e));
+        }
+    };
+    // The JSON is sent as given. It is just checked so a typo is not sent, unless asked not to,
+    // and only when it is text, since curl does not look at it at all
+    if let Some(Err(e)) = json
+        .as_deref()
+        .filter(|_| !opt.json_no_validate)
+        .map(data::check_json)
+    {
+        report_error(opt, &e);
+        return Err(Exit::from(&e));
+    }
+    if json.is_some() {
+        headers
+            .entry(CONTENT_TYPE)
+            .or_insert(HeaderValue::from_static("application/json"));
+        headers
+            .entry(ACCEPT)
+            .or_insert(HeaderValue::from_static("application/json"));
+    }
+    let form = match Form::new(&opt.form_fields) {
+        Ok(form) => Some(form).filter(|_| !opt.form_fields.is_empty()),
+        Err(e) => {
+            report_error(opt, &e);
+            return Err(Exit::from(&e));
+        }
+    };
+
+    if let (Some(referer), _) = parse_referer(opt.referer.as_deref()) {
+        match HeaderValue::from_str(referer) {
+            // An explicit -H "Referer: ..." takes precedence
+            Ok(value) => {
+                headers.entry(REFERER).or_insert(value);
+            }
+            Err(_) => {
+                report_error(opt, format_args!("Invalid referer '{referer}'"));
+                return Err(Exit::Init);
+            }
+        }
+    }
+
+    // Defaults are added here rather than by the client, so -v shows exactly what is sent. An
+    // explicit -H "User-Agent: ..." still takes precedence and an empty -A sends none at all
+    let user_agent = opt.user_agent.as_deref().unwrap_or(DEFAULT_USER_AGENT);
+    if !user_agent.is_empty() {
+        match HeaderValue::from_str(user_agent) {
+            Ok(value) => {
+                headers.entry(USER_AGENT).or_insert(value);
+            }
+            Err(_) => {
+                report_error(opt, format_args!("Invalid user agent '{user_agent}'"));
+                return Err(Exit::Init);
+            }
+        }
+    }
+    headers
+        .entry(ACCEPT)
+        .or_insert(HeaderValue::from_static("*/*"));
+    // Ranges are validated to be only digits, dashes and commas, so they always make a valid value
+    if let Some(Ok(value)) = opt
+        .range
+        .as_ref()
+        .map(|range| HeaderValue::from_str(&format!("bytes={range}")))
+    {
+        headers.entry(RANGE).or_insert(value);
+    }
+    if let Some((name, value)) = opt
+        .time_cond
+        .as_deref()
+        .and_then(|cond| time_condition(opt, cond))
+    {
+        headers.entry(name).or_insert(value);
+    }
+    if let Some(value) = etag_condition(opt)? {
+        headers.entry(IF_NONE_MATCH).or_insert(value);
+    }
+    if opt.compressed {
+        headers
+            .entry(ACCEPT_ENCODING)
+            .or_insert(HeaderValue::from_static(decode::Encoding::ACCEPT));
+    }
+
+    // -u credentials are for every URL, while .netrc is looked up for the host of each
+    let credentials = match &opt.user {
+        Some(user) => match auth::credentials(user, "host", opt.password_stdin) {
+            Ok(credentials) => Some(credentials),
+            Err(e) => {
+                report_error(opt, &e);
+                return Err(Exit::from(&e));
+            }
+        },
+        None => None,
+    };
+
+    // -u takes precedence over a token coming from the environment
+    if opt.user.is_none() {
+        match auth::bearer_token(opt.oauth2_bearer.as_deref()) {
+            Ok(Some(token)) => match HeaderValue::from_str(&format!("Bearer {token}")) {
+                Ok(mut value) => {
+                    value.set_sensitive(true);
+                    headers.entry(AUTHORIZATION).or_insert(value);
+                }
+                Err(_) => {
+                    report_error(opt, "The bearer token contains invalid characters");
+                    return Err(Exit::Init);
+                }
+            },
+            Ok(None) => {}
+            Err(e) => {
+                report_error(opt, &e);
+                return Err(Exit::from(&e));
+            }
+        }
+    }
+
+    // -b values with a "=" are cookies themselves, the others name cookie files read into the jar
+    let cookies: Vec<&String> = opt
+        .cookies
+        .iter()
+        .filter(|cookie| cookie.contains('='))
+        .collect();
+    if !cookies.is_empty() {
+        let cookies = cookies
+            .iter()
+            .map(|cookie| cookie.as_str())
+            .collect::<Vec<_>>()
+            .join("; ");
+        match HeaderValue::from_str(&cookies) {
+            Ok(value) => {
+                headers.entry(COOKIE).or_insert(value);
+            }
+            Err(_) => {
+                report_error(opt, format_args!("Invalid cookies '{cookies}'"));
+                return Err(Exit::Init);
+            }
+        }
+    }
+
+    Ok(Group {
+        opt,
+        headers,
+        data,
+        json,
+        form,
+        credentials,
+        write_out,
+    })
+}
+
+/// Fetches the URLs on up to --parallel-max threads at once for -Z. Each
+/// thread has a client of its own, so the connections and timings of its
+/// transfers are not mixed up with the others'. The error is the one of the
+/// first URL that failed.
+fn fetch_parallel(
+    groups: &[Group],
+    targets: &[Target],
+    pacer: Option<Arc<Pacer>>,
+    jar: Option<&Mutex<Jar>>,
+) -> Result<(), Exit> {
+    let opt = groups[0].opt;
+
+    // Bodies written to stdout as they arrive would be mixed together
+    let to_stdout = targets
+        .iter()
+        .filter(|target| {
+            output_path(groups[target.group].opt, &target.expanded, target.index)
+                .ok()
+                .flatten()
+                .is_none_or(|path| path == Path::new("-"))
+        })
+        .count();
+    if to_stdout > 1 {
+        report_error(
+            opt,
+            "With -Z at most one URL can be written to stdout, give the others an -o or -O file",
+        );
+        return Err(Exit::Init);
+    }
+
+    let next = AtomicUsize::new(0);
+    let stop = AtomicBool::new(false);
+    let setup = Mutex::new(None);
+    let results = Mutex::new(vec![Ok(()); targets.len()]);
+    let progress = progress_style(opt).map(|_| Mutex::new(progress::Parallel::new(targets.len())));
+
+    std::thread::scope(|scope| {
+        for _ in 0..opt.parallel_max.clamp(1, 300).min(targets.len()) {
+            scope.spawn(|| {
+                let session = match session(opt, pacer.clone()) {
+                    Ok(session) => session,
+                    Err(e) => {
+                        if !stop.swap(true, Ordering::SeqCst) {
+                            report_error(opt, &e);
+                            if let Ok(mut setup) = setup.lock() {
+                                *setup = Some(Exit::from(&e));
+                            }
+                        }
+                        return;
+                    }
+                };
+
+                while !stop.load(Ordering::SeqCst) {
+                    let at = next.fetch_add(1, Ordering::SeqCst);
+                    let Some(target) = targets.get(at) else {
+                        break;
+                    };
+                    let result = fetch_url(&groups[target.group], &session, target, jar);
+                    if result.is_err() && opt.fail_early {
+                        stop.store(true, Ordering::SeqCst);
+                    }
+                    if let Some(mut progress) = progress.as_ref().and_then(|p| p.lock().ok()) {
+                        progress.finished(result.is_ok());
+                    }
+                    if let Ok(mut results) = results.lock() {
+                        results[at] = result;
+                    }
+                }
+            });
+        }
+    });
+
+    if let Some(progress) = progress.and_then(|progress| progress.into_inner().ok()) {
+        progress.finish();
+    }
+    if let Some(exit) = setup.into_inner().ok().flatten() {
+        return Err(exit);
+    }
+    results
+        .into_inner()
+        .unwrap_or_default()
+        .into_iter()
+        .find(Result::is_err)
+        .unwrap_or(Ok(()))
+}
+
+/// Fetches one of the URLs and prints its -w output.
+fn fetch_url(
+    group: &Group,
+    session: &Session,
+    target: &Target,
+    jar: Option<&Mutex<Jar>>,
+) -> Result<(), Exit> {
+    let (opt, write_out) = (group.opt, group.write_out.as_deref());
+    let url = target.expanded.url.as_str();
+    if opt.verbose {
+        eprintln!("* Requesting URL: {url}");
+
+        if let Some(json) = &group.json {
+            eprintln!("* Method: {}", Method::POST);
+            eprintln!("* JSON: {}", String::from_utf8_lossy(json));
+        } else if opt.head {
+            eprintln!("* Method: {}", Method::HEAD);
+        } else {
+            eprintln!("* Method: {}", opt.method);
+
+            if let Some(data) = group.data.as_ref().filter(|_| !opt.get) {
+                eprintln!("* Data: {}", String::from_utf8_lossy(data));
+            }
+        }
+    }
+
+    let parsed = match Url::parse(url) {
+        Ok(url)
+            if !opt
+                .proto
+                .as_ref()
+                .is_none_or(|proto| proto.allows(url.scheme())) =>
+        {
+            Err((
+                format!(
+                    "The protocol \"{}\" is not allowed by --proto",
+                    url.scheme()
+                ),
+                Exit::UnsupportedProtocol,
+            ))
+        }
+        // Restrict to HTTP, HTTPS and local files
+        Ok(url) if !proto::SUPPORTED.contains(&url.scheme()) => Err((
+            "The URL does not have a valid base protocol.".to_string(),
+            Exit::UnsupportedProtocol,
+        )),
+        Ok(url) => Ok(url),
+        Err(e) => Err(match e {
+            ParseError::RelativeUrlWithoutBase
+            | ParseError::RelativeUrlWithCannotBeABaseBase
+            | ParseError::SetHostOnCannotBeABaseUrl => (
+                "The URL does not have a valid base protocol.".to_string(),
+                Exit::UnsupportedProtocol,
+            ),
+            ParseError::InvalidIpv4Address => (
+                "The URL contains an invalid IPv4 address.".to_string(),
+                Exit::MalformedUrl,
+            ),
+            ParseError::InvalidIpv6Address => (
+                "The URL contains an invalid IPv6 address.".to_string(),
+                Exit::MalformedUrl,
+            ),
+            ParseError::InvalidPort => (
+                "The URL contains an invalid port number.".to_string(),
+                Exit::MalformedUrl,
+            ),
+            _ => (e.to_string(), Exit::MalformedUrl),
+        }),
+    };
+    let mut parsed = match parsed {
+        Ok(parsed) => parsed,
+        Err((message, exit)) => {
+            match &target.source {
+                Some(source) => report_error(opt, format_args!("{source}: {message}")),
+                None => report_error(opt, message),
+            }
+            return Err(exit);
+        }
+    };
+
+    // Uploading to a URL ending in "/" puts the file there under its own name, like curl does
+    let upload = opt.upload_file.get(target.index).map(PathBuf::as_path);
+    let name = upload
+        .filter(|path| *path != Path::new("-"))
+        .and_then(Path::file_name)
+        .filter(|_| parsed.path().ends_with('/'));
+    let mut rewritten = false;
+    if let (Some(name), Ok(mut segments)) = (name, parsed.path_segments_mut()) {
+        segments.pop_if_empty().push(&name.to_string_lossy());
+        rewritten = true;
+    }
+
+    // --url-query and -G add to any query the URL already has, after the globs are expanded
+    rewritten |= add_query(group, &mut parsed);
+    let rewritten_url;
+    let url = if rewritten {
+        rewritten_url = parsed.to_string();
+        if opt.verbose {
+            eprintln!("* Sending to {rewritten_url}");
+        }
+        rewritten_url.as_str()
+    } else {
+        url
+    };
+
+    let output = match output_path(opt, &target.expanded, target.index) {
+        Ok(output) => output,
+        Err(e) => {
+            report_error(opt, &e);
+            return Err(Exit::from(&e));
+        }
+    };
+
+    let credentials = match &group.credentials {
+        Some(credentials) => Some(credentials.clone()),
+        None if opt.netrc || opt.netrc_file.is_some() || opt.netrc_optional => {
+            match netrc_credentials(opt, &parsed) {
+                Ok(credentials) => credentials,
+                Err(e) => {
+                    report_error(opt, &e);
+                    return Err(Exit::from(&e));
+                }
+            }
+        }
+        None => None,
+    };
+
+    let target = parsed.host_str().zip(parsed.port_or_known_default());
+    if let Some((host, port)) = target.filter(|_| opt.verbose) {
+        if let Some((to_host, to_port)) = resolve::connect_to(&opt.connect_to, host, port) {
+            eprintln!("* Connecting to {to_host}:{to_port} instead of {host}:{port}");
+        }
+    }
+
+    let mut transfer = Transfer::new(url);
+    let result = if parsed.scheme() == "file" {
+        fetch_file(opt, &parsed, output.as_deref(), &mut transfer)
+    } else {
+        let outgoing = Outgoing {
+            url,
+            credentials,
+            upload,
+        };
+        fetch(
+            group,
+            session,
+            &outgoing,
+            output.as_deref(),
+            jar,
+            &mut transfer,
+        )
+    };
+    transfer.exitcode = result.err().map_or(0, Exit::code);
+
+    if opt.timings || write_out.is_some() {
+        let tls = transfer.url_effective.starts_with("https:");
+        transfer.timings = session.clock.timings(tls);
+
+        if opt.timings {
+            transfer.timings.print(tls);
+        }
+    }
+
+    // -w output comes last, after the body and on failures too
+    if let Some(format) = write_out {
+        let text = writeout::render(format, &transfer, |name| {
+            report_warning(opt, format_args!("Unknown --write-out variable '{name}'"))
+        });
+        let mut stdout = std::io::stdout().lock();
+        let _ = stdout
+            .write_all(text.as_bytes())
+            .and_then(|_| stdout.flush());
+    }
+
+    result
+}
+
+/// What is sent to one URL, besides the options of its group.
+struct Outgoing<'a> {
+    url: &'a str,
+    credentials: Option<Credentials>,
+    // The -T file for this URL
+    upload: Option<&'a Path>,
+}
+
+/// Sends the request and handles the response.
+fn fetch(
+    group: &Group,
+    session: &Session,
+    outgoing: &Outgoing,
+    output: Option<&Path>,
+    jar: Option<&Mutex<Jar>>,
+    transfer: &mut Transfer,
+) -> Result<(), Exit> {
+    let opt = group.opt;
+    let mut headers = group.headers.clone();
+    // HEAD responses never carry a body
+    let is_head = opt.head || opt.json.is_empty() && matches!(opt.method, Method::HEAD);
+
+    let resume = resume_offset(opt, output)?;
+    if resume > 0 {
+        if let Ok(value) = HeaderValue::from_str(&format!("bytes={resume}-")) {
+            headers.entry(RANGE).or_insert(value);
+        }
+    }
+
+    match make_request(group, session, outgoing, headers, jar, transfer) {
+        Ok((resp, hops)) => {
+            transfer.http_code = resp.status().as_u16();
+            transfer.http_version = Some(resp.version());
+            transfer.content_type = resp
+                .headers()
+                .get(CONTENT_TYPE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            if let Some(hop) = hops.last() {
+                transfer.url_effective = hop.to.to_string();
+            }
+            transfer.num_redirects = hops.len();
+            transfer.headers = resp.headers().clone();
+            transfer.remote_addr = direct::addrs(&resp).map(|(_, remote)| remote);
+
+            if opt.verbose && !hops.is_empty() {
+                eprintln!(
+                    "* Followed {} redirect{}, final URL: {}",
+                    hops.len(),
+                    if hops.len() == 1 { "" } else { "s" },
+                    transfer.url_effective
+                );
+            }
+
+            if opt.verbose {
+                for hop in &hops {
+                    eprintln!("* {} {} -> {}", hop.status.as_u16(), hop.from, hop.to);
+                }
+            }
+
+            let status = resp.status();
+            let content_range = resp
+                .headers()
+                .get(CONTENT_RANGE)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+
+            // Resuming a download that is already complete leaves nothing to get
+            if resume > 0
+                && status == reqwest::StatusCode::RANGE_NOT_SATISFIABLE
+                && content_range
+                    .as_deref()
+                    .and_then(range_total)
+                    .is_none_or(|total| total == resume)
+            {
+                if opt.verbose {
+                    eprintln!("* The download is already complete, nothing to resume");
+                }
+                return Ok(());
+            }
+
+            // -f leaves no trace of the error response, not even an empty -o file
+            if status.as_u16() >= 400 && opt.fail {
+                report_error(opt, status_error(status));
+                return Err(Exit::Http);
+            }
+
+            // A server ignoring the range sends the whole body, which must not be appended
+            let mut append = false;
+            if opt.range.is_some() || resume > 0 {
+                if status == reqwest::StatusCode::PARTIAL_CONTENT {
+                    if let Some(content_range) = content_range.as_deref().filter(|_| opt.verbose) {
+                        eprintln!("* Content-Range: {content_range}");
+                    }
+
+                    let start = content_range.as_deref().and_then(range_start);
+                    if resume > 0 && start.is_some_and(|start| start != resume) {
+                        report_error(
+                            opt,
+                            format_args!(
+                                "The server resumed at byte {} instead of {resume}",
+                                start.unwrap_or_default()
+                            ),
+                        );
+                        return Err(Exit::RangeError);
+                    }
+                    append = resume > 0;
+                } else if status == reqwest::StatusCode::OK {
+                    if opt.strict_range {
+                        report_error(opt, "The server ignored the range and sent the whole body");
+                        return Err(Exit::RangeError);
+                    }
+                    if resume > 0 {
+                        report_warning(
+                            opt,
+                            "The server does not support resuming, downloading the whole body again",
+                        );
+                    } else {
+                        report_warning(opt, "The server ignored the range, writing the whole body");
+                    }
+                }
+            }
+
+            if let Some(path) = &opt.dump_header {
+                let heads = output::heads(&hops, &resp);
+                let written = if path == Path::new("-") {
+                    std::io::stdout().write_all(&heads)
+                } else {
+                    std::fs::write(path, &heads)
+                };
+
+                if let Err(e) = written {
+                    report_error(
+                        opt,
+                        format_args!(
+                            "Unable to write the response headers to {}: {e}",
+                            path.display()
+                        ),
+                    );
+                    return Err(Exit::Write);
+                }
+            }
+
+            // Headers saved with -o go into the file together with the body
+            let to_file = output.is_some_and(|path| path != Path::new("-"));
+            let head = if opt.include || opt.head {
+                output::heads(&hops, &resp)
+            } else {
+                Vec::new()
+            };
+            if !to_file {
+                if let Err(e) = std::io::stdout().write_all(&head) {
+                    report_error(
+                        opt,
+                        format_args!("Unable to write the response headers: {e}"),
+                    );
+                    return Err(Exit::Write);
+                }
+            }
+
+            if let Some(path) = opt.etag_save.as_deref().filter(|_| status.is_success()) {
+                let etag = resp
+                    .headers()
+                    .get(ETAG)
+                    .map_or(&[][..], HeaderValue::as_bytes);
+                if let Err(e) = std::fs::write(path, [etag, b"\n"].concat()) {
+                    report_error(
+                        opt,
+                        format_args!("Unable to save the ETag to {}: {e}", path.display()),
+                    );
+                    return Err(Exit::Write);
+                }
+            }
+
+            // -I -o still saves the headers, while an unchanged file is left as it is
+            let not_modified = status == reqwest::StatusCode::NOT_MODIFIED;
+            if not_modified && opt.verbose {
+                eprintln!("* The remote file did not change, nothing to download");
+            }
+            if is_head && !to_file || not_modified {
+                return check_status(opt, status);
+            }
+
+            // A body known to be too large is not even started, so no -o file is created
+            if let Some(max) = opt
+                .max_filesize
+                .filter(|max| resp.content_length() > Some(*max))
+            {
+                report_error(opt, TooLarge(max));
+                return Err(Exit::FileTooLarge);
+            }
+
+            let disposition = resp
+                .headers()
+                .get(CONTENT_DISPOSITION)
+                .filter(|_| opt.remote_header_name)
+                .and_then(|value| value.to_str().ok())
+                .map(output::disposition_name);
+            let output = match (output, disposition) {
+                (Some(path), Some(Ok(Some(name)))) if path != Path::new("-") => {
+                    Some(path.with_file_name(name))
+                }
+                (_, Some(Err(e))) => {
+                    report_error(opt, &e);
+                    return Err(Exit::from(&e));
+                }
+                (output, _) => output.map(Path::to_path_buf),
+            };
+            let last_modified = resp
+                .headers()
+                .get(LAST_MODIFIED)
+                .and_then(|value| value.to_str().ok())
+                .map(str::to_string);
+            let charset = output::charset(&resp).map(str::to_string);
+            let json = output::is_json(&resp);
+
+            // The body is shown as it is read, unless it is going to the terminal itself
+            // -Z shows how many transfers are done instead
+            let progress = progress_style(opt)
+                .filter(|_| !opt.parallel)
+                .filter(|_| to_file || !std::io::stdout().is_terminal())
+                .map(|style| Progress::new(style, resp.content_length()));
+            let encodings = content_encodings(opt, &resp);
+            let mut body = progress::Reader::new(
+                rate::Reader::new(trace::Reader::new(resp), opt.limit_rate),
+                progress,
+            );
+
+            // The progress meter and size_download count the bytes on the wire, before decoding,
+            // while --max-filesize limits what is written
+            let mut decoded = decode::Reader::new(&mut body, &encodings);
+            let result = write_body(
+                opt,
+                &mut output::Limited::new(&mut decoded, opt.max_filesize),
+                output.as_deref(),
+                &head,
+                charset.as_deref(),
+                json,
+                append,
+            );
+            transfer.size_decoded = decoded.decoded();
+            drop(decoded);
+            transfer.size_download = body.received();
+            result?;
+
+            if let Some(path) = output.filter(|path| opt.remote_time && path != Path::new("-")) {
+                set_remote_time(opt, &path, last_modified.as_deref());
+            }
+
+            check_status(opt, status)
+        }
+        Err(RequestError::Http(e)) => {
+            if e.is_timeout() {
+                if e.is_connect() {
+                    match opt.connect_timeout {
+                        Some(timeout) => report_error(
+                            opt,
+                            format_args!(
+                                "Connection timed out after {} seconds.",
+                                timeout.as_secs_f64()
+                            ),
+                        ),
+                        None => report_error(opt, "Connection timed out."),
+                    }
+                } else {
+                    match opt.max_time {
+                        Some(timeout) => report_error(
+                            opt,
+                            format_args!(
+                                "Operation timed out after {} seconds.",
+                                timeout.as_secs_f64()
+                            ),
+                        ),
+                        None => report_error(opt, "Operation timed out."),
+                    }
+                }
+                return Err(Exit::Timeout);
+            }
+
+            let server = e.url().map_or(String::new(), |url| {
+                format!(
+                    "{}:{}",
+                    url.host_str().unwrap_or_default(),
+                    url.port_or_known_default().unwrap_or(443)
+                )
+            });
+            // An HTTPS proxy fails verification or the handshake like a server does, but is
+            // named instead. Only the proxy is talked to with TLS for a plain HTTP URL
+            let tls_proxy = e
+                .url()
+                .zip(proxies(opt).ok())
+                .and_then(|(url, proxies)| proxies.for_url(url).cloned())
+                .filter(|proxy| proxy.scheme() == "https" && e.is_connect());
+            if let Some(proxy) = tls_proxy {
+                let name = format!(
+                    "{}:{}",
+                    proxy.host_str().unwrap_or_default(),
+                    proxy.port_or_known_default().unwrap_or(443)
+                );
+                if let Some(reason) =
+                    exit::certificate_error(&e).filter(|_| exit::proxy_certificate(&e))
+                {
+                    report_error(
+                        opt,
+                        format_args!(
+                            "The certificate of the proxy {name} failed verification: {reason}"
+                        ),
+                    );
+                    return Err(Exit::PeerFailedVerification);
+                }
+                let origin_tls = e.url().is_some_and(|url| url.scheme() == "https");
+                if let Some(reason) =
+                    exit::tls_error(&e).filter(|_| !origin_tls || exit::tunnel_failed(&e))
+                {
+                    report_error(
+                        opt,
+                        format_args!("TLS handshake with the proxy {name} failed: {reason}"),
+                    );
+                    return Err(Exit::SslConnect);
+                }
+            }
+            if exit::pin_mismatch(&e) {
+                report_error(
+                    opt,
+                    format_args!(
+                        "The public key of the server {server} does not match --pinnedpubkey"
+                    ),
+                );
+                return Err(Exit::PinnedPubKeyMismatch);
+            }
+            if let Some(reason) = exit::certificate_error(&e) {
+                report_error(
+                    opt,
+                    format_args!(
+                        "The certificate of the server {server} failed verification: {reason}"
+                    ),
+                );
+                return Err(Exit::PeerFailedVerification);
+            }
+            if exit::tls_alert(&e, AlertDescription::CertificateRequired) && opt.cert.is_none() {
+                report_error(
+                    opt,
+                    "The server requires a client certificate, give one with --cert",
+                );
+                return Err(Exit::from(&e));
+            }
+            if let Some(reason) = exit::tls_error(&e).filter(|_| e.is_connect()) {
+                report_error(
+                    opt,
+                    format_args!("TLS handshake with the server {server} failed: {reason}"),
+                );
+                return Err(Exit::SslConnect);
+            }
+
+            // With a proxy every connection goes to the proxy, and HTTPS is tunneled through it
+            let proxies = proxies(opt).unwrap_or_default();
+            let proxy = e
+                .url()
+                .and_then(|url| proxies.for_url(url))
+                .filter(|_| e.is_connect());
+            if let Some(proxy) = proxy {
+                let host = proxy.host_str().unwrap_or_default();
+                if exit::resolve_error(&e).is_some_and(|e| e.host == host) {
+                    report_error(opt, format_args!("Could not resolve proxy: {host}"));
+                    return Err(Exit::ResolveProxy);
+                }
+                if let Some(reason) = exit::socks_error(&e) {
+                    report_error(
+                        opt,
+                        format_args!(
+                            "SOCKS proxy {host}:{} error: {reason}",
+                            proxy.port().unwrap_or(1080)
+                        ),
+                    );
+                    return Err(Exit::Proxy);
+                }
+                report_error(
+                    opt,
+                    format_args!(
+                        "Unable to connect through the proxy {}: {}",
+                        proxy.authority(),
+                        exit::cause(&e)
+                    ),
+                );
+            } else if let Some(e) = exit::resolve_error(&e) {
+                report_error(opt, e);
+            } else if let Some(e) = exit::doh_error(&e) {
+                report_error(opt, e);
+            } else if let Some(path) = opt.socket_path().filter(|_| e.is_connect()) {
+                report_error(
+                    opt,
+                    format_args!(
+                        "Unable to connect to the Unix socket {}: {}",
+                        path.to_string_lossy().trim_start_matches('\0'),
+                        exit::cause(&e)
+                    ),
+                );
+            } else if e.is_connect() {
+                report_error(opt, exit::CONNECT_FAILED);
+            } else {
+                report_error(opt, &e);
+            }
+            Err(Exit::from(&e))
+        }
+        Err(e) => {
+            report_error(opt, &e);
+            Err(Exit::from(&e))
+        }
+    }
+}
+
+/// Adds the --url-query pairs and then the -G data to the query of `url`,
+/// returning whether there were any.
+fn add_query(group: &Group, url: &mut Url) -> bool {
+    let opt = group.opt;
+    for query in &opt.url_query {
+        if let Some(raw) = query.strip_prefix('+') {
+            let query = match url.query().filter(|query| !query.is_empty()) {
+                Some(existing) => format!("{existing}&{raw}"),
+                None => raw.to_string(),
+            };
+            url.set_query(Some(&query));
+        } else if let Some(value) = query.strip_prefix('=') {
+            let encoded: String = form_urlencoded::byte_serialize(value.as_bytes()).collect();
+            url.set_query(Some(&encoded));
+        } else {
+            match query.split_once('=') {
+                Some((name, value)) => url.query_pairs_mut().append_pair(name, value),
+                None => url.query_pairs_mut().append_key_only(query),
+            };
+        }
+    }
+
+    // The -G data goes on the end as it is, like curl leaves it to the user to encode it. Only bytes
+    // that are not ASCII are percent-encoded, as a URL cannot hold them
+    let data = group.data.as_ref().filter(|_| opt.get);
+    if let Some(data) = data.filter(|data| !data.is_empty()) {
+        let data = percent_encode(data, CONTROLS).to_string();
+        let query = match url.query().filter(|query| !query.is_empty()) {
+            Some(existing) => format!("{existing}&{data}"),
+            None => data,
+        };
+        url.set_query(Some(&query));
+    }
+
+    !opt.url_query.is_empty() || data.is_some()
+}
+
+/// Reads a file:// URL from the local disk, with no request at all.
+fn fetch_file(
+    opt: &Opt,
+    url: &Url,
+    output: Option<&Path>,
+    transfer: &mut Transfer,
+) -> Result<(), Exit> {
+    let file = match file::open(url, opt.range.as_deref()) {
+        Ok(file) => file,
+        Err(e) => {
+            report_error(opt, &e);
+            return Err(Exit::from(&e));
+        }
+    };
+    if opt.verbose {
+        eprintln!("* Reading {} ({} bytes)", file.path.display(), file.size);
+    }
+
+    let to_file = output.is_some_and(|path| path != Path::new("-"));
+    let head = if opt.include || opt.head {
+        file.head()
+    } else {
+        Vec::new()
+    };
+    if !to_file {
+        if let Err(e) = std::io::stdout().write_all(&head) {
+            report_error(
+                opt,
+                format_args!("Unable to write the response headers: {e}"),
+            );
+            return Err(Exit::Write);
+        }
+        if opt.head {
+            return Ok(());
+        }
+    }
+
+    if let Some(max) = opt.max_filesize.filter(|max| file.size > *max) {
+        report_error(opt, TooLarge(max));
+        return Err(Exit::FileTooLarge);
+    }
+
+    let json = file
+        .path
+        .extension()
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("json"));
+    let progress = progress_style(opt)
+        .filter(|_| !opt.parallel)
+        .filter(|_| to_file || !std::io::stdout().is_terminal())
+        .map(|style| Progress::new(style, Some(file.size)));
+    // -I -o saves only the made up headers
+    let contents: Box<dyn Read> = if opt.head {
+        Box::new(std::io::empty())
+    } else {
+        file.body
+    };
+    let mut body = progress::Reader::new(rate::Reader::new(contents, opt.limit_rate), progress);
+    let result = write_body(
+        opt,
+        &mut output::Limited::new(&mut body, opt.max_filesize),
+        output,
+        &head,
+        None,
+        json,
+        false,
+    );
+    transfer.size_download = body.received();
+    transfer.size_decoded = transfer.size_download;
+    drop(body);
+    result?;
+
+    if let (Some(path), Some(modified)) =
+        (output.filter(|_| opt.remote_time && to_file), file.modified)
+    {
+        let modified = DateTime::from_system_time(modified).to_http_date();
+        set_remote_time(opt, path, Some(&modified));
+    }
+
+    Ok(())
+}
+
+/// Writes the response body where -o and -O say, or to stdout with JSON
+/// formatted as --json-output says.
+fn write_body(
+    opt: &Opt,
+    body: &mut impl Read,
+    output: Option<&Path>,
+    head: &[u8],
+    charset: Option<&str>,
+    json: bool,
+    append: bool,
+) -> Result<(), Exit> {
+    // Anything written with -o is saved byte for byte, without pretty-printing
+    match output {
+        Some(path) if path == Path::new("-") => {
+            if let Err(e) = output::print(body) {
+                report_error(opt, &e);
+                return Err(Exit::from(&e));
+            }
+            return Ok(());
+        }
+        Some(path) => {
+            // Server-chosen names never replace existing files unless asked to
+            let existing = if append {
+                Existing::Append
+            } else if !opt.remote_header_name || opt.clobber {
+                Existing::Overwrite
+            } else {
+                Existing::Refuse
+            };
+            return match output::save(body, head, path, opt.create_dirs, existing) {
+                Ok(written) => {
+                    if opt.verbose {
+                        eprintln!("* Saved {written} bytes to {}", path.display());
+                    }
+                    Ok(())
+                }
+                Err(OutputError::Write(e)) => {
+                    if e.kind() == std::io::ErrorKind::AlreadyExists && existing == Existing::Refuse
+                    {
+                        report_error(
+                            opt,
+                            format_args!(
+                                "Refusing to overwrite {}, use --clobber to allow it",
+                                path.display()
+                            ),
+                        );
+                    } else {
+                        report_error(
+                            opt,
+                            format_args!(
+                                "Unable to save the response body to {}: {e}",
+                                path.display()
+                            ),
+                        );
+                    }
+                    Err(Exit::Write)
+                }
+                Err(e) => {
+                    report_error(opt, &e);
+                    Err(Exit::from(&e))
+                }
+            };
+        }
+        None => {}
+    }
+
+    // Only JSON that gets reformatted is buffered, everything else is streamed as it arrives, so
+    // by default a redirected stdout gets the body byte for byte
+    let no_color = opt.no_color || std::env::var_os("NO_COLOR").is_some_and(|v| !v.is_empty());
+    let terminal = std::io::stdout().is_terminal();
+    let (pretty, color) = match render::rendering(json, opt.json_output, no_color, terminal) {
+        Rendering::Json { pretty, color } => (pretty, color),
+        Rendering::Raw => return display(opt, body, charset),
+    };
+
+    let mut bytes = Vec::new();
+    if let Err(e) = body.read_to_end(&mut bytes) {
+        let e = OutputError::Body(e);
+        report_error(opt, &e);
+        return Err(Exit::from(&e));
+    }
+
+    // Keys stay in the order the server sent them unless --sort-keys, and numbers as they were. A
+    // body that is not JSON after all is written exactly as it came
+    let formatted = match serde_json::from_str::<Value>(&output::text(&bytes, charset)) {
+        Ok(mut json) => {
+            if opt.sort_keys {
+                json.sort_all_objects();
+            }
+            format!("{}\n", render::json(&json, pretty, color))
+        }
+        Err(_) => return display(opt, &mut bytes.as_slice(), charset),
+    };
+    let mut stdout = std::io::stdout().lock();
+    if let Err(e) = stdout
+        .write_all(formatted.as_bytes())
+        .and_then(|_| stdout.flush())
+    {
+        let e = OutputError::Write(e);
+        report_error(opt, &e);
+        return Err(Exit::from(&e));
+    }
+    Ok(())
+}
+
+// The encodings to decode the body from. Like curl's, bodies are only decoded with --compressed,
+// and ones that cannot be decoded are left as received, as they are with --raw. Chunked transfer
+// encoding is always undone by the client itself
+fn content_encodings(opt: &Opt, resp: &Response) -> Vec<decode::Encoding> {
+    if !opt.compressed || opt.raw {
+        return Vec::new();
+    }
+
+    let value = resp
+        .headers()
+        .get_all(CONTENT_ENCODING)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .collect::<Vec<_>>()
+        .join(",");
+
+    decode::encodings(&value).unwrap_or_else(|name| {
+        report_warning(
+            opt,
+            format_args!("Unsupported Content-Encoding '{name}', writing the body as received"),
+        );
+        Vec::new()
+    })
+}
+
+// Error responses have been written like any other by now, and only fail with --fail-with-body
+fn check_status(opt: &Opt, status: reqwest::StatusCode) -> Result<(), Exit> {
+    if status.as_u16() < 400 {
+        return Ok(());
+    }
+
+    let message = status_error(status);
+    if opt.fail_with_body {
+        report_error(opt, message);
+        Err(Exit::Http)
+    } else {
+        report_warning(opt, message);
+        Ok(())
+    }
+}
+
+fn proxies(opt: &Opt) -> Result<Proxies, ProxyError> {
+    Proxies::new(opt.proxy.as_deref(), opt.noproxy.as_deref(), |name| {
+        std::env::var(name).ok()
+    })
+}
+
+// What an error status means, telling the proxy asking for credentials from the server refusing
+fn status_error(status: reqwest::StatusCode) -> String {
+    if status == reqwest::StatusCode::PROXY_AUTHENTICATION_REQUIRED {
+        "The proxy requires authentication (407), use --proxy-user to give credentials".to_string()
+    } else {
+        format!("The requested URL returned error: {}", status.as_u16())
+    }
+}
+
+// The progress display curl would show on stderr, if any
+fn progress_style(opt: &Opt) -> Option<progress::Style> {
+    if opt.silent || opt.no_progress_meter || !std::io::stderr().is_terminal() {
+        None
+    } else if opt.progress_bar {
+        Some(progress::Style::Bar)
+    } else {
+        Some(progress::Style::Meter)
+    }
+}
+
+/// Prints an error to stderr, unless -s is given without -S.
+fn report_error(opt: &Opt, message: impl Display) {
+    if !opt.silent || opt.show_error {
+        eprintln!("Error: {message}");
+    }
+}
+
+/// Prints a warning to stderr, unless -s is given.
+fn report_warning(opt: &Opt, message: impl Display) {
+    if !opt.silent {
+        eprintln!("Warning: {message}");
+    }
+}
+
+// The If-None-Match value for --etag-compare. Like curl, a missing file just means there is
+// nothing to compare with
+fn etag_condition(opt: &Opt) -> Result<Option<HeaderValue>, Exit> {
+    let Some(path) = &opt.etag_compare else {
+        return Ok(None);
+    };
+
+    let etag = match std::fs::read_to_string(path) {
+        Ok(etag) => etag,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+        Err(e) => {
+            report_error(
+                opt,
+                format_args!("Unable to read the ETag from {}: {e}", path.display()),
+            );
+            return Err(Exit::Read);
+        }
+    };
+
+    let etag = etag.lines().next().unwrap_or_default().trim();
+    if etag.is_empty() {
+        return Ok(None);
+    }
+    match HeaderValue::from_str(etag) {
+        Ok(value) => Ok(Some(value)),
+        Err(_) => {
+            report_error(
+                opt,
+                format_args!("The ETag in {} is not a valid header value", path.display()),
+            );
+            Err(Exit::Read)
+        }
+    }
+}
+
+// The conditional header for -z, from the modification time of the file it names or the date it is
+fn time_condition(opt: &Opt, cond: &str) -> Option<(HeaderName, HeaderValue)> {
+    let (name, cond) = match cond.strip_prefix('-') {
+        Some(cond) => (IF_UNMODIFIED_SINCE, cond),
+        None => (IF_MODIFIED_SINCE, cond.strip_prefix('+').unwrap_or(cond)),
+    };
+
+    let time = std::fs::metadata(cond)
+        .and_then(|metadata| metadata.modified())
+        .ok()
+        .or_else(|| DateTime::parse(cond));
+    let Some(time) = time else {
+        report_warning(
+            opt,
+            format_args!("'{cond}' is neither a file nor a valid date, ignoring --time-cond"),
+        );
+        return None;
+    };
+
+    let date = DateTime::from_system_time(time).to_http_date();
+    Some((name, HeaderValue::from_str(&date).ok()?))
+}
+
+// Writes a body to stdout as it is, reporting why it could not be
+fn display(opt: &Opt, body: &mut impl Read, charset: Option<&str>) -> Result<(), Exit> {
+    match output::display(body, charset) {
+        Ok(()) => Ok(()),
+        Err(OutputError::Binary) => {
+            report_warning(opt, OutputError::Binary);
+            Err(Exit::Write)
+        }
+        Err(e) => {
+            report_error(opt, &e);
+            Err(Exit::from(&e))
+        }
+    }
+}
+
+// Sets the modification time of a saved file for --remote-time, when the server says what it is
+fn set_remote_time(opt: &Opt, path: &Path, last_modified: Option<&str>) {
+    let Some(date) = last_modified.and_then(DateTime::parse_http_date) else {
+        if opt.verbose {
+            eprintln!(
+                "* No valid Last-Modified header, leaving the time of {} alone",
+                path.display()
+            );
+        }
+        return;
+    };
+
+    let set = std::fs::File::options()
+        .write(true)
+        .open(path)
+        .and_then(|file| file.set_modified(date.to_system_time()));
+    if let Err(e) = set {
+        report_warning(
+            opt,
+            format_args!(
+                "Unable to set the modification time of {}: {e}",
+                path.display()
+            ),
+        );
+    }
+}
+
+// The offset -C resumes at, 0 when starting from scratch. Resuming a file that does not exist yet
+// starts it from scratch
+fn resume_offset(opt: &Opt, output: Option<&Path>) -> Result<u64, Exit> {
+    match (opt.continue_at, output) {
+        (None, _) => Ok(0),
+        (Some(Resume::At(offset)), _) => Ok(offset),
+        (Some(Resume::Auto), Some(path)) if path != Path::new("-") => match std::fs::metadata(path)
+        {
+            Ok(metadata) => Ok(metadata.len()),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(0),
+            Err(e) => {
+                report_error(
+                    opt,
+                    format_args!(
+                        "Unable to get the size of {} to resume: {e}",
+                        path.display()
+                    ),
+                );
+                Err(Exit::Read)
+            }
+        },
+        (Some(Resume::Auto), _) => Ok(0),
+    }
+}
+
+// The first byte of a `bytes first-last/total` Content-Range
+fn range_start(content_range: &str) -> Option<u64> {
+    let (first, _) = content_range
+        .trim()
+        .strip_prefix("bytes ")?
+        .split_once('-')?;
+    first.trim().parse().ok()
+}
+
+// The total length in a Content-Range, when the server knows it
+fn range_total(content_range: &str) -> Option<u64> {
+    let (_, total) = content_range.rsplit_once('/')?;
+    total.trim().parse().ok()
+}
+
+/// Where -o, -O and --output-dir say the response body goes, `None` meaning
+/// stdout with pretty-printing.
+fn output_path(opt: &Opt, target: &Expanded, index: usize) -> Result<Option<PathBuf>, OutputError> {
+    // The URLs after the ones with an -o file each take one of the -O. An -o name gets the values
+    // of the globs for #1, #2 and so on
+    let path = match opt.output.get(index) {
+        Some(path) if path == Path::new("-") => return Ok(Some(path.clone())),
+        Some(path) => match path.to_str() {
+            Some(template) => PathBuf::from(glob::fill(template, &target.values)),
+            None => path.clone(),
+        },
+        None if index < opt.output.len() + opt.remote_name => output::remote_name(&target.url)?,
+        None => return Ok(None),
+    };
+
+    // Absolute -o paths are used as given
+    Ok(Some(match &opt.output_dir {
+        Some(dir) => dir.join(path),
+        None => path,
+    }))
+}
+
+fn netrc_credentials(opt: &Opt, url: &Url) -> Result<Option<Credentials>, NetrcError> {
+    let path = match &opt.netrc_file {
+        Some(path) => path.clone(),
+        None => netrc::default_path()?,
+    };
+
+    let netrc = match Netrc::load(path) {
+        Ok(netrc) => netrc,
+        Err(NetrcError::Read(..)) if opt.netrc_optional => return Ok(None),
+        Err(e) => return Err(e),
+    };
+
+    let host = url.host_str().unwrap_or_default().to_string();
+
+    match netrc.credentials(&host) {
+        Some(credentials) => Ok(Some(credentials)),
+        None if opt.netrc_optional => Ok(None),
+        None => Err(NetrcError::NoEntry(host)),
+    }
+}
+
+// The TLS settings for servers, from -k, the CA certificate, client certificate and version options.
+// The handshakes note what they agree on in `negotiated`
+fn tls_config(
+    opt: &Opt,
+    negotiated: Arc<Negotiated>,
+) -> Result<rustls::ClientConfig, RequestError> {
+    let roots = if opt.insecure {
+        Vec::new()
+    } else {
+        tls::ca_certificates(opt.cacert.as_deref(), opt.capath.as_deref())
+            .map_err(RequestError::CaCert)?
+    };
+    let identity = opt
+        .cert
+        .as_deref()
+        .map(|cert| {
+            tls::identity(
+                cert,
+                opt.key.as_deref(),
+                opt.cert_type.unwrap_or(CertType::Pem),
+                opt.pass.as_deref(),
+            )
+        })
+        .transpose()
+        .map_err(RequestError::ClientCert)?;
+    let pins = opt
+        .pinnedpubkey
+        .as_deref()
+        .map(tls::pinned_keys)
+        .transpose()
+        .map_err(RequestError::PinnedPubKey)?
+        .unwrap_or_default();
+    // HTTPS proxies have CA certificates of their own, the system's unless --proxy-cacert is given
+    let proxy_hosts = proxies(opt).map_err(RequestError::Proxy)?.tls_hosts();
+    let proxy = if proxy_hosts.is_empty() {
+        None
+    } else {
+        let roots = if opt.proxy_insecure {
+            Vec::new()
+        } else {
+            tls::ca_certificates(opt.proxy_cacert.as_deref(), None)
+                .map_err(|e| RequestError::Proxy(ProxyError::CaCert(e)))?
+        };
+        Some(ProxyTrust {
+            hosts: proxy_hosts,
+            insecure: opt.proxy_insecure,
+            roots,
+        })
+    };
+
+    tls::client_config(TlsOptions {
+        insecure: opt.insecure,
+        roots,
+        identity,
+        min_version: opt.tls_min(),
+        max_version: opt.tls_max,
+        pins,
+        tls12_ciphers: opt.ciphers.clone(),
+        tls13_ciphers: opt.tls13_ciphers.clone(),
+        alpn: if opt.http2_prior_knowledge {
+            vec![b"h2".to_vec()]
+        } else if opt.http2 {
+            vec![b"h2".to_vec(), b"http/1.1".to_vec()]
+        } else {
+            vec![b"http/1.1".to_vec()]
+        },
+        proxy,
+        negotiated: Some(negotiated),
+    })
+    .map_err(|e| match e {
+        TlsError::NoSystemCertificates => RequestError::CaCert(e),
+        e => RequestError::ClientCert(e),
+    })
+}
+
+// The resolver for --doh-url. The server is verified with the CA certificates for servers even
+// with -k, and looked up with the system resolver
+fn doh(opt: &Opt) -> Result<Option<Doh>, RequestError> {
+    let Some(url) = &opt.doh_url else {
+        return Ok(None);
+    };
+    let roots = tls::ca_certificates(opt.cacert.as_deref(), opt.capath.as_deref())
+        .map_err(RequestError::CaCert)?;
+    let config = tls::client_config(TlsOptions {
+        insecure: false,
+        roots,
+        identity: None,
+        min_version: None,
+        max_version: None,
+        pins: Vec::new(),
+        tls12_ciphers: None,
+        tls13_ciphers: None,
+        alpn: vec![b"h2".to_vec(), b"http/1.1".to_vec()],
+        proxy: None,
+        negotiated: None,
+    })
+    .map_err(RequestError::CaCert)?;
+    let client = reqwest::Client::builder()
+        .use_preconfigured_tls(config)
+        .no_proxy()
+        .timeout(opt.connect_timeout.unwrap_or(Duration::from_secs(30)))
+        .build()?;
+
+    Ok(Some(Doh::new(url.clone(), client)))
+}
+
+/// What the transfers of one invocation share: the client, which keeps
+/// connections open for the URLs after the first, and the --rate of
+/// requests.
+struct Session {
+    client: Client,
+    // Sends the requests instead of the client when it cannot
+    direct: Option<Direct>,
+    clock: Arc<Clock>,
+    resolver: Arc<Resolver>,
+    pacer: Option<Arc<Pacer>>,
+    negotiated: Arc<Negotiated>,
+}
+
+impl Session {
+    // The body for a request sent by whichever sends it
+    fn body(&self, upload: Upload) -> Body {
+        match &self.direct {
+            Some(direct) => direct.body(upload),
+            None => Body::from(upload),
+        }
+    }
+
+    // Sends `request`, its body no faster than `rate` bytes per second
+    fn execute(&self, mut request: Request, rate: Option<u64>) -> Result<Response, RequestError> {
+        match &self.direct {
+            Some(direct) if direct.binds() || direct::expects_continue(request.headers()) => {
+                direct.execute(request).map_err(RequestError::Direct)
+            }
+            direct => {
+                if let Some(direct) = direct {
+                    direct.forget();
+                }
+                limit_upload(&mut request, rate);
+                let url = request.url().clone();
+                let mut resp = match self.client.execute(request) {
+                    // A connection that could not be made shows as the streamed body failing
+                    Err(e) if exit::unsent_body(&e) => {
+                        match direct.as_ref().map(|direct| direct.reach(&url)) {
+                            Some(Err(unreached)) => return Err(RequestError::Direct(unreached)),
+                            _ => return Err(RequestError::Http(e)),
+                        }
+                    }
+                    resp => resp?,
+                };
+                // Only the session knows what the handshake of an HTTPS connection agreed on
+                let agreed = self
+                    .negotiated
+                    .last()
+                    .filter(|_| resp.extensions().get::<reqwest::tls::TlsInfo>().is_some());
+                if let Some((version, suite)) = agreed {
+                    resp.extensions_mut().insert(Agreed(version, suite));
+                }
+                Ok(resp)
+            }
+        }
+    }
+
+    // Whether a request with "Expect: 100-continue" can wait for the server before sending the body
+    fn continues(&self) -> bool {
+        self.direct.is_some()
+    }
+}
+
+fn session(opt: &Opt, pacer: Option<Arc<Pacer>>) -> Result<Session, RequestError> {
+    // Without -m there is no overall limit, unlike the blocking client's default of 30 seconds
+    let mut builder = Client::builder()
+        .redirect(reqwest::redirect::Policy::none())
+        .timeout(opt.max_time);
+
+    if let Some(connect_timeout) = opt.connect_timeout {
+        builder = builder.connect_timeout(connect_timeout);
+    }
+    if opt.http2_prior_knowledge {
+        builder = builder.http2_prior_knowledge();
+    } else if opt.http1_0 || opt.http1_1 {
+        builder = builder.http1_only();
+    }
+    // The clock sees when each phase starts and ends, through the resolver, the connector and the
+    // TLS session cache
+    let clock = Clock::new();
+    let negotiated = Arc::new(Negotiated::default());
+    let mut tls = tls_config(opt, negotiated.clone())?;
+    tls.resumption = Resumption::store(Arc::new(timing::Handshakes::new(clock.clone())));
+    builder = builder.use_preconfigured_tls(tls.clone());
+    // The client only applies these to TLS settings it makes itself, the ones given are limited
+    // to the same versions already
+    if let Some(min) = opt.tls_min() {
+        builder = builder.min_tls_version(min.into());
+    }
+    if let Some(max) = opt.tls_max {
+        builder = builder.max_tls_version(max.into());
+    }
+
+    // The URL still gives the Host header and path, only the connection goes to the socket
+    #[cfg(unix)]
+    if let Some(path) = opt.socket_path() {
+        builder = builder.unix_socket(path);
+    }
+
+    // The client's own proxy settings from the environment are replaced by curl's
+    builder = builder.no_proxy();
+    let options = ProxyOptions {
+        user: opt.proxy_user.as_deref(),
+        headers: parse_headers(&opt.proxy_headers).map_err(RequestError::ProxyHeader)?,
+    };
+    let proxy = proxies(opt)
+        .and_then(|proxies| proxies.client_proxy(options))
+        .map_err(RequestError::Proxy)?;
+    let proxied = proxy.is_some();
+    if let Some(proxy) = proxy {
+        builder = builder.proxy(proxy);
+    }
+    let local = Local::new(opt.interface.as_deref(), opt.local_port.clone())
+        .map_err(RequestError::Interface)?;
+    if let Some(local) = &local {
+        builder = local.bind(builder, opt.family());
+    }
+
+    // The resolver sends connections where the --connect-to rules say
+    let resolver = Arc::new(Resolver::new(
+        clock.clone(),
+        opt.resolve.clone(),
+        opt.connect_to.clone(),
+        opt.family(),
+        doh(opt)?,
+    ));
+    let client = builder
+        .dns_resolver(resolver.clone())
+        .connector_layer(timing::ConnectLayer(clock.clone()))
+        .tls_info(opt.verbose)
+        .build()?;
+
+    // The client can neither pick the local port nor hold a body back for a 100 Continue, so
+    // connections from one of the --local-port range and requests expecting one are made apart
+    // from it over HTTP/1.1, unless they go through a proxy or a Unix socket
+    let local = local.unwrap_or_else(Local::any);
+    let http1 = local.has_ports() || !(opt.http2 || opt.http2_prior_knowledge);
+    let direct = (http1 && !proxied && opt.socket_path().is_none()).then(|| {
+        Direct::new(
+            clock.clone(),
+            resolver.clone(),
+            DirectOptions {
+                local,
+                tls,
+                connect_timeout: opt.connect_timeout,
+                max_time: opt.max_time,
+                rate: opt.limit_rate,
+                expect_timeout: opt.expect100_timeout,
+            },
+        )
+    });
+
+    Ok(Session {
+        client,
+        direct,
+        clock,
+        resolver,
+        pacer,
+        negotiated,
+    })
+}
+
+fn make_request(
+    group: &Group,
+    session: &Session,
+    outgoing: &Outgoing,
+    mut headers: HeaderMap,
+    jar: Option<&Mutex<Jar>>,
+    transfer: &mut Transfer,
+) -> Result<(Response, Vec<Hop>), RequestError> {
+    let (opt, client) = (group.opt, &session.client);
+    let url = outgoing.url;
+
+    // JSON requests are always sent as POST, and -I always as HEAD
+    let method = if group.json.is_some() {
+        &Method::POST
+    } else if opt.head {
+        &Method::HEAD
+    } else {
+        &opt.method
+    };
+
+    let mut request = match method {
+        Method::GET => client.get(url),
+        Method::POST => client.post(url),
+        Method::PUT => client.put(url),
+        Method::DELETE => client.delete(url),
+        Method::PATCH => client.patch(url),
+        Method::HEAD => client.head(url),
+        Method::OPTIONS => client.request(reqwest::Method::OPTIONS, url),
+        Method::Custom(method) => client.request(method.clone(), url),
+    };
+
+    // A custom -H "Authorization: ..." replaces the generated one
+    let credentials = outgoing
+        .credentials
+        .clone()
+        .filter(|_| !headers.contains_key(AUTHORIZATION));
+    let basic = !opt.digest && !opt.anyauth && opt.aws_sigv4.is_none();
+    if let Some(credentials) = credentials.as_ref().filter(|_| basic) {
+        request = request.basic_auth(&credentials.username, Some(&credentials.password));
+    }
+
+    // An HTTP/1.0 server may not know keep-alive connections, and the body is never chunked
+    if opt.http1_0 {
+        headers
+            .entry(CONNECTION)
+            .or_insert(HeaderValue::from_static("close"));
+        request = request.version(Version::HTTP_10);
+    }
+
+    // -d data is sent as it is given, curl leaves encoding it to the user, and -F makes a
+    // multipart body. Any method sends it, only -G moves the data to the query and -I has none
+    let sends_body = !matches!(method, Method::HEAD) && !opt.get;
+    let data = group.data.as_ref().filter(|_| sends_body);
+    // -H "Expect:" sends none, so the body always follows the head right away
+    let expect_disabled = headers.get(EXPECT).is_some_and(HeaderValue::is_empty);
+    if expect_disabled {
+        headers.remove(EXPECT);
+    }
+    let form = group.form.as_ref().filter(|_| sends_body);
+    if let Some(value) = form.and_then(|form| HeaderValue::from_str(&form.content_type()).ok()) {
+        headers.entry(CONTENT_TYPE).or_insert(value);
+    }
+    // -H "Transfer-Encoding: chunked" sends even a body of known length in chunks
+    let chunked = headers
+        .get(TRANSFER_ENCODING)
+        .and_then(|value| value.to_str().ok())
+        .is_some_and(|value| value.to_ascii_lowercase().contains("chunked"));
+    if chunked && opt.http1_0 {
+        return Err(RequestError::ChunkedHttp10);
+    }
+    if data.is_some() && group.json.is_none() {
+        headers
+            .entry(CONTENT_TYPE)
+            .or_insert(HeaderValue::from_static(
+                "application/x-www-form-urlencoded",
+            ));
+    }
+    request = request.headers(headers);
+
+    // JSON request
+    if let Some(json) = &group.json {
+        request = request.body(json.clone());
+    } else if let Some(data) = data {
+        request = if chunked {
+            request.body(Body::new(std::io::Cursor::new(data.clone())))
+        } else {
+            request.body(data.clone())
+        };
+    }
+    // Attached and uploaded files are streamed, so their length is only known here
+    let mut streamed = 0;
+    let mut sized = !chunked;
+    if let Some(form) = form {
+        let (body, length) = form.body(opt.limit_rate).map_err(RequestError::Form)?;
+        sized &= body.length().is_some();
+        request = request.body(session.body(body));
+        streamed = length;
+    }
+    if let Some(path) = outgoing.upload {
+        let body = upload_body(path, chunked, opt.http1_0, opt.limit_rate)?;
+        sized &= body.length().is_some();
+        streamed = body.length().unwrap_or_default();
+        request = request.body(session.body(body));
+    }
+
+    let mut request = request.build()?;
+    transfer.url_effective = request.url().to_string();
+    transfer.size_upload = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map_or(streamed, |body| body.len() as u64);
+
+    // Like curl, a big body or one of unknown length waits for the server to ask for it, so one
+    // refused right away is not sent for nothing. HTTP/1.0 has no interim responses and HTTP/2
+    // connections are the client's
+    let big = !sized || transfer.size_upload > opt.expect100_threshold;
+    let http1_1 = !opt.http1_0 && !opt.http2 && !opt.http2_prior_knowledge;
+    if request.body().is_some() && big && http1_1 && !expect_disabled && session.continues() {
+        request
+            .headers_mut()
+            .entry(EXPECT)
+            .or_insert(HeaderValue::from_static("100-continue"));
+    }
+
+    let policy = retry::Policy {
+        retries: opt.retry,
+        all_errors: opt.retry_all_errors,
+        connrefused: opt.retry_connrefused,
+        delay: opt.retry_delay,
+        max_time: opt.retry_max_time,
+    };
+    let started = Instant::now();
+    let mut retries = 0;
+
+    loop {
+        // Form and JSON bodies are buffered, so a failed attempt can be repeated as is
+        let next = request.try_clone();
+        if let Some(pacer) = &session.pacer {
+            pacer.wait();
+        }
+        session.clock.start();
+        let result = follow(session, request, opt, credentials.as_ref(), jar);
+
+        let failure = match &result {
+            Ok((resp, _)) if resp.status().is_client_error() || resp.status().is_server_error() => {
+                Failure::Status(resp.status())
+            }
+            Ok(_) => return result,
+            Err(RequestError::Http(e)) => Failure::from_error(e),
+            Err(RequestError::Direct(e)) => Failure::from_direct(e),
+            Err(_) => Failure::Other,
+        };
+
+        // The server knows best when it will be ready again
+        let retry_after = match &result {
+            Ok((resp, _)) => retry::retry_after(resp.headers(), SystemTime::now()),
+            Err(_) => None,
+        };
+
+        let delay = policy.decide(&failure, retries, started.elapsed(), retry_after);
+        let (Some(delay), Some(next)) = (delay, next) else {
+            if retries > 0 {
+                report_warning(
+                    opt,
+                    format_args!("Giving up after {} attempts.", retries + 1),
+                );
+            }
+            return result;
+        };
+
+        if opt.verbose {
+            let left = opt.retry - retries;
+            eprintln!(
+                "* {failure}. Will retry in {} seconds. {left} {} left.",
+                delay.as_secs_f64(),
+                if left == 1 { "retry" } else { "retries" }
+            );
+        }
+
+        drop(result);
+        std::thread::sleep(delay);
+        retries += 1;
+        request = next;
+    }
+}
+
+/// Sends `request`, following redirects when -L is given.
+fn follow(
+    session: &Session,
+    mut request: Request,
+    opt: &Opt,
+    credentials: Option<&Credentials>,
+    jar: Option<&Mutex<Jar>>,
+) -> Result<(Response, Vec<Hop>), RequestError> {
+    // Automatic referers on redirects are only sent with -e ";auto"
+    let (_, auto_referer) = parse_referer(opt.referer.as_deref());
+
+    let mut hops = Vec::new();
+
+    // Credentials only go to the original host unless --location-trusted is given
+    let original_url = request.url().clone();
+    let original_headers = request.headers().clone();
+    let mut trusted = true;
+
+    // -m covers every hop of a redirect chain together
+    let deadline = opt.max_time.map(|max_time| Instant::now() + max_time);
+
+    loop {
+        if let Some(deadline) = deadline {
+            *request.timeout_mut() = Some(deadline.saturating_duration_since(Instant::now()));
+        }
+
+        // Form and JSON bodies are buffered, so the request can be replayed on the next hop
+        let next = request.try_clone();
+        if let Some(jar) = jar.and_then(|jar| jar.lock().ok()) {
+            add_cookies(&mut request, &jar);
+        }
+        let hop_credentials = credentials.filter(|_| trusted);
+
+        // The client is given another URL when a --connect-to rule needs it, this is the one
+        // fetched
+        let target = request.url().clone();
+        session.resolver.set_target(&target);
+        if let Some(url) = resolve::rewrite(&opt.connect_to, &target) {
+            if let Some(host) = host_header(&target) {
+                request.headers_mut().entry(HOST).or_insert(host);
+            }
+            // The client keeps connections by the URL, which no longer says where this one goes
+            request
+                .headers_mut()
+                .insert(CONNECTION, HeaderValue::from_static("close"));
+            *request.url_mut() = url;
+        }
+
+        session.clock.sending();
+        let resp = send(session, request, opt, hop_credentials)?;
+        session.clock.received();
+
+        // Cookies set by a redirect are sent on to where it leads
+        if let Some(mut jar) = jar.and_then(|jar| jar.lock().ok()) {
+            for set_cookie in resp.headers().get_all(SET_COOKIE) {
+                jar.store(&target, &String::from_utf8_lossy(set_cookie.as_bytes()));
+            }
+        }
+
+        let location = match resp.headers().get(LOCATION) {
+            Some(location) if opt.location && resp.status().is_redirection() => location,
+            _ => return Ok((resp, hops)),
+        };
+
+        // Relative locations are resolved against the URL that was redirected
+        let url = location
+            .to_str()
+            .ok()
+            .and_then(|location| target.join(location).ok());
+        let (Some(mut next), Some(url)) = (next, url) else {
+            return Ok((resp, hops));
+        };
+
+        if opt.max_redirs >= 0 && hops.len() as i64 >= opt.max_redirs {
+            return Err(RequestError::TooManyRedirects(opt.max_redirs));
+        }
+        let allows = |protocols: &Option<Protocols>| {
+            protocols
+                .as_ref()
+                .is_none_or(|protocols| protocols.allows(url.scheme()))
+        };
+        // Only HTTP goes through the client, a server cannot redirect to a local file
+        let remote = matches!(url.scheme(), "http" | "https");
+        if !remote || !allows(&opt.proto) || !allows(&opt.proto_redir) {
+            return Err(RequestError::RedirectProtocol(url.scheme().to_string()));
+        }
+        hops.push(Hop {
+            version: resp.version(),
+            status: resp.status(),
+            headers: resp.headers().clone(),
+            from: target.clone(),
+            to: url.clone(),
+        });
+
+        let keep_post = match resp.status().as_u16() {
+            301 => opt.post301,
+            302 => opt.post302,
+            303 => opt.post303,
+            _ => false,
+        } && next.method() == reqwest::Method::POST;
+
+        let status = resp.status().as_u16();
+        if !keep_post && redirect::switches_to_get(resp.status(), next.method()) {
+            if opt.verbose {
+                eprintln!(
+                    "* Redirect {status} to {url}: switching {} to GET",
+                    next.method()
+                );
+            }
+
+            *next.method_mut() = reqwest::Method::GET;
+            *next.body_mut() = None;
+            next.headers_mut().remove(CONTENT_TYPE);
+            next.headers_mut().remove(CONTENT_LENGTH);
+            next.headers_mut().remove(EXPECT);
+        } else if opt.verbose && next.method() != reqwest::Method::GET {
+            eprintln!("* Redirect {status} to {url}: keeping {}", next.method());
+        }
+
+        if auto_referer {
+            match redirect::referer(&target, &url).and_then(|r| HeaderValue::from_str(&r).ok()) {
+                Some(referer) => next.headers_mut().insert(REFERER, referer),
+                None => next.headers_mut().remove(REFERER),
+            };
+        }
+
+        trusted = opt.location_trusted || redirect::same_host(&original_url, &url);
+        for name in [AUTHORIZATION, COOKIE] {
+            match original_headers.get(&name).filter(|_| trusted) {
+                Some(value) => next.headers_mut().insert(name, value.clone()),
+                None => next.headers_mut().remove(name),
+            };
+        }
+
+        *next.url_mut() = url;
+        request = next;
+    }
+}
+
+/// Sends a single hop, answering authentication challenges and signing as
+/// configured.
+fn send(
+    session: &Session,
+    mut request: Request,
+    opt: &Opt,
+    credentials: Option<&Credentials>,
+) -> Result<Response, RequestError> {
+    let Some(credentials) = credentials else {
+        return execute(session, request, opt);
+    };
+
+    // Digest and --anyauth need the server's challenge, so the first attempt goes out without
+    // credentials
+    if opt.digest || opt.anyauth {
+        return send_authenticated(session, request, credentials, opt);
+    }
+
+    // Signing covers the final header set and body, so it happens last
+    if let Some(sigv4) = &opt.aws_sigv4 {
+        sigv4.sign(&mut request, credentials, SystemTime::now())?;
+    }
+
+    execute(session, request, opt)
+}
+
+// The Host header for a request to `url`
+fn host_header(url: &Url) -> Option<HeaderValue> {
+    let host = match (url.host_str()?, url.port()) {
+        (host, Some(port)) => format!("{host}:{port}"),
+        (host, None) => host.to_string(),
+    };
+
+    HeaderValue::from_str(&host).ok()
+}
+
+/// Executes a single request, printing the exchange with -v.
+///
+/// Host and Content-Length are filled in here instead of by the HTTP stack,
+/// so the headers shown are exactly the ones sent.
+fn execute(session: &Session, mut request: Request, opt: &Opt) -> Result<Response, RequestError> {
+    if let Some(host) = host_header(request.url()) {
+        request.headers_mut().entry(HOST).or_insert(host);
+    }
+
+    let length = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .map(<[u8]>::len);
+    if let Some(length) = length {
+        request
+            .headers_mut()
+            .entry(CONTENT_LENGTH)
+            .or_insert(HeaderValue::from(length));
+    }
+
+    if !opt.verbose && !trace::enabled() {
+        return session.execute(request, opt.limit_rate);
+    }
+
+    let (method, url, headers, version) = (
+        request.method().clone(),
+        request.url().clone(),
+        request.headers().clone(),
+        request.version(),
+    );
+    let body = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .filter(|_| trace::enabled())
+        .map(<[u8]>::to_vec);
+
+    let resp = session.execute(request, opt.limit_rate)?;
+    if let Some(path) = opt.socket_path().filter(|_| opt.verbose) {
+        eprintln!(
+            "* Connected to {} through the Unix socket {}",
+            url.host_str().unwrap_or_default(),
+            path.to_string_lossy().trim_start_matches('\0')
+        );
+    }
+    // HTTP/2 is only known to be used once the connection is made, otherwise the request went out
+    // as the version it has even when the server answers with another
+    let version = if resp.version() >= Version::HTTP_2 {
+        resp.version()
+    } else {
+        version
+    };
+    if opt.verbose {
+        verbose::exchange(&method, &url, &headers, version, &resp, opt.redact);
+    }
+    if trace::enabled() {
+        verbose::trace(&method, &url, &headers, version, body.as_deref(), &resp);
+    }
+
+    Ok(resp)
+}
+
+// The -T body, read no faster than --limit-rate. A file of known size is sent with its length,
+// stdin and other streams in chunks as they are read. HTTP/1.0 has no chunks, so streams cannot be
+// sent with it at all
+fn upload_body(
+    path: &Path,
+    chunked: bool,
+    http1_0: bool,
+    rate: Option<u64>,
+) -> Result<Upload, RequestError> {
+    let read_error = |e| RequestError::Upload(path.to_path_buf(), e);
+    let stream: Box<dyn Read + Send> = if path == Path::new("-") {
+        Box::new(std::io::stdin())
+    } else {
+        let file = std::fs::File::open(path).map_err(read_error)?;
+        let metadata = file.metadata().map_err(read_error)?;
+        if metadata.is_file() && !chunked {
+            return Ok(Upload::Streamed(
+                Box::new(rate::Reader::new(file, rate)),
+                Some(metadata.len()),
+            ));
+        }
+        Box::new(file)
+    };
+
+    if http1_0 {
+        return Err(RequestError::ChunkedHttp10);
+    }
+    Ok(Upload::Streamed(
+        Box::new(rate::Reader::new(stream, rate)),
+        None,
+    ))
+}
+
+// Swaps a buffered request body for one that is read out at no more than `rate` bytes per second.
+// Only the copy being sent is throttled, so retries and redirects can still replay the original
+fn limit_upload(request: &mut Request, rate: Option<u64>) {
+    let Some(body) = request
+        .body()
+        .and_then(|body| body.as_bytes())
+        .filter(|_| rate.is_some())
+        .map(<[u8]>::to_vec)
+    else {
+        return;
+    };
+
+    let length = body.len() as u64;
+    *request.body_mut() = Some(Body::sized(
+        rate::Reader::new(std::io::Cursor::new(body), rate),
+        length,
+    ));
+}
+
+fn send_authenticated(
+    session: &Session,
+    request: Request,
+    credentials: &Credentials,
+    opt: &Opt,
+) -> Result<Response, RequestError> {
+    let retry = request.try_clone();
+    let method = request.method().as_str().to_string();
+    let uri = match request.url().query() {
+        Some(query) => format!("{}?{query}", request.url().path()),
+        None => request.url().path().to_string(),
+    };
+
+    let resp = execute(session, request, opt)?;
+
+    if resp.status() != reqwest::StatusCode::UNAUTHORIZED {
+        return Ok(resp);
+    }
+
+    let Some(mut retry) = retry else {
+        return Ok(resp);
+    };
+
+    let challenges: Vec<_> = resp
+        .headers()
+        .get_all(WWW_AUTHENTICATE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(auth::parse_challenges)
+        .collect();
+
+    // Digest is preferred over Basic since it never sends the password itself
+    if let Some(challenge) = challenges.iter().find_map(DigestChallenge::from_challenge) {
+        let authorization = digest::cnonce()
+            .and_then(|cnonce| challenge.authorization(credentials, &method, &uri, &cnonce, 1))
+            .map_err(RequestError::Digest)?;
+
+        if let Ok(authorization) = HeaderValue::from_str(&authorization) {
+            retry.headers_mut().insert(AUTHORIZATION, authorization);
+        }
+
+        return execute(session, retry, opt);
+    }
+
+    if !opt.anyauth {
+        return Ok(resp);
+    }
+
+    if challenges
+        .iter()
+        .any(|challenge| challenge.scheme.eq_ignore_ascii_case("basic"))
+    {
+        let retry = RequestBuilder::from_parts(session.client.clone(), retry)
+            .basic_auth(&credentials.username, Some(&credentials.password))
+            .build()?;
+        return execute(session, retry, opt);
+    }
+
+    // A 401 without any challenge can't be answered either
+    if challenges.is_empty() {
+        return Ok(resp);
+    }
+
+    Err(RequestError::UnsupportedAuth(
+        challenges
+            .into_iter()
+            .map(|challenge| challenge.scheme)
+            .collect(),
+    ))
+}
+
+fn parse_headers(raw: &[String]) -> Result<HeaderMap, HeaderError> {
+    let mut headers = HeaderMap::new();
+
+    for header in raw {
+        // -H @file reads one header per line, -H @- reads them from stdin
+        if let Some(file) = header.strip_prefix('@') {
+            let (file, contents) = if file == "-" {
+                let mut contents = String::new();
+                std::io::stdin()
+                    .read_to_string(&mut contents)
+                    .map_err(|e| HeaderError::ReadFile("stdin".to_string(), e))?;
+                ("stdin", contents)
+            } else {
+                let contents = std::fs::read_to_string(file)
+                    .map_err(|e| HeaderError::ReadFile(file.to_string(), e))?;
+                (file, contents)
+            };
+
+            for (i, line) in contents.lines().enumerate() {
+                let line = line.trim();
+                if line.is_empty() || line.starts_with('#') {
+                    continue;
+                }
+
+                let (name, value) = parse_header(line)
+                    .map_err(|e| HeaderError::InFile(file.to_string(), i + 1, Box::new(e)))?;
+                headers.insert(name, value);
+            }
+
+            continue;
+        }
+
+        // Later headers override earlier ones with the same name
+        let (name, value) = parse_header(header)?;
+        headers.insert(name, value);
+    }
+
+    Ok(headers)
+}
+
+fn parse_header(header: &str) -> Result<(HeaderName, HeaderValue), HeaderError> {
+    let (name, value) = header
+        .split_once(':')
+        .ok_or_else(|| HeaderError::MissingColon(header.to_string()))?;
+    let name = name.trim();
+
+    let header_name = HeaderName::from_bytes(name.as_bytes())
+        .map_err(|_| HeaderError::InvalidName(name.to_string()))?;
+    let header_value = HeaderValue::from_str(value.trim())
+        .map_err(|_| HeaderError::InvalidValue(name.to_string()))?;
+
+    Ok((header_name, header_value))
+}
+
+/// Splits a -e value into the initial referer and whether ";auto" was requested.
+fn parse_referer(referer: Option<&str>) -> (Option<&str>, bool) {
+    match referer {
+        Some(referer) => match referer.strip_suffix(";auto") {
+            Some("") => (None, true),
+            Some(referer) => (Some(referer), true),
+            None => (Some(referer), false),
+        },
+        None => (None, false),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn opt(args: &[&str]) -> Opt {
+        parse_group(args.iter().map(OsString::from).collect())
+    }
+
+    fn query(args: &[&str], url: &str) -> String {
+        let opt = opt(args);
+        let Ok(group) = group(&opt) else {
+            panic!("the options {args:?} are rejected");
+        };
+        let mut url = Url::parse(url).unwrap();
+        add_query(&group, &mut url);
+        url.to_string()
+    }
+
+    // A file of its own in the temporary directory, for tests running side by side
+    fn temp_file(name: &str) -> PathBuf {
+        let path = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+        std::fs::write(&path, b"body").unwrap();
+        path
+    }
+
+    fn modified(path: &Path) -> SystemTime {
+        std::fs::metadata(path).unwrap().modified().unwrap()
+    }
+
+    #[test]
+    fn remote_time_from_last_modified() {
+        let path = temp_file("remote-time");
+        set_remote_time(
+            &opt(&["curl", "-"]),
+            &path,
+            Some("Sun, 06 Nov 1994 08:49:37 GMT"),
+        );
+
+        let expected = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        assert_eq!(modified(&path), expected);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn remote_time_left_alone_without_a_date() {
+        let path = temp_file("no-remote-time");
+        let before = modified(&path);
+        for last_modified in [None, Some("yesterday"), Some("")] {
+            set_remote_time(&opt(&["curl", "-"]), &path, last_modified);
+            assert_eq!(modified(&path), before);
+        }
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn url_query_encodes_unicode() {
+        assert_eq!(
+            query(&["curl", "--url-query", "name=trésor ✓", "-"], "http://h/p"),
+            "http://h/p?name=tr%C3%A9sor+%E2%9C%93"
+        );
+    }
+
+    #[test]
+    fn url_query_encodes_separators_in_values() {
+        assert_eq!(
+            query(&["curl", "--url-query", "a=x&y=z", "-"], "http://h/p"),
+            "http://h/p?a=x%26y%3Dz"
+        );
+        assert_eq!(
+            query(&["curl", "--url-query", "=a&b", "-"], "http://h/p?old"),
+            "http://h/p?a%26b"
+        );
+    }
+
+    #[test]
+    fn url_query_keeps_existing_query_and_fragment() {
+        assert_eq!(
+            query(
+                &["curl", "--url-query", "b=2", "--url-query", "+c=%20", "-"],
+                "http://h/p?x=1#frag"
+            ),
+            "http://h/p?x=1&b=2&c=%20#frag"
+        );
+    }
+
+    #[test]
+    fn get_data_is_appended_as_given() {
+        assert_eq!(
+            query(
+                &["curl", "-G", "-d", "foo", "-d", "b=x%20y", "-"],
+                "http://h/p?x=1#frag"
+            ),
+            "http://h/p?x=1&foo&b=x%20y#frag"
+        );
+        assert_eq!(
+            query(&["curl", "-G", "-d", "é", "-"], "http://h/p"),
+            "http://h/p?%C3%A9"
+        );
+    }
 }
@@ -0,0 +1,255 @@
+use crate::auth::Credentials;
+use std::{fmt::Display, path::PathBuf};
+
+#[derive(Debug)]
+pub enum NetrcError {
+    Read(PathBuf, std::io::Error),
+    Syntax(PathBuf, usize, String),
+    NoHome,
+    NoEntry(String),
+}
+
+impl Display for NetrcError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            NetrcError::Read(path, e) => write!(f, "Unable to read {}: {e}", path.display()),
+            NetrcError::Syntax(path, line, msg) => write!(f, "{}:{line}: {msg}", path.display()),
+            NetrcError::NoHome => write!(f, "Unable to locate .netrc, HOME is not set"),
+            NetrcError::NoEntry(host) => write!(f, "No .netrc entry with a login for '{host}'"),
+        }
+    }
+}
+
+struct Entry {
+    // None for the catch-all "default" entry
+    machine: Option<String>,
+    login: Option<String>,
+    password: Option<String>,
+}
+
+pub struct Netrc {
+    entries: Vec<Entry>,
+}
+
+impl Netrc {
+    pub fn load(path: PathBuf) -> Result<Netrc, NetrcError> {
+        let contents =
+            std::fs::read_to_string(&path).map_err(|e| NetrcError::Read(path.clone(), e))?;
+
+        Netrc::parse(&contents).map_err(|(line, msg)| NetrcError::Syntax(path, line, msg))
+    }
+
+    /// Parses netrc contents, returning the line number and message on errors.
+    fn parse(contents: &str) -> Result<Netrc, (usize, String)> {
+        let mut entries: Vec<Entry> = Vec::new();
+        let mut tokens = tokenize(contents).into_iter().peekable();
+
+        while let Some((line, token)) = tokens.next() {
+            let mut value = |keyword: &str| match tokens.next() {
+                Some((_, value)) => Ok(value),
+                None => Err((line, format!("Missing value after '{keyword}'"))),
+            };
+
+            match token.as_str() {
+                "machine" => {
+                    let machine = value("machine")?;
+                    entries.push(Entry {
+                        machine: Some(machine),
+                        login: None,
+                        password: None,
+                    });
+                }
+                "default" => entries.push(Entry {
+                    machine: None,
+                    login: None,
+                    password: None,
+                }),
+                "login" | "password" | "account" => {
+                    let value = value(&token)?;
+                    let entry = entries
+                        .last_mut()
+                        .ok_or_else(|| (line, format!("'{token}' outside of a machine entry")))?;
+
+                    match token.as_str() {
+                        "login" => entry.login = Some(value),
+                        "password" => entry.password = Some(value),
+                        _ => {}
+                    }
+                }
+                "macdef" => {
+                    // Macro bodies run until the next blank line and are not used here
+                    value("macdef")?;
+                    let end = contents
+                        .lines()
+                        .enumerate()
+                        .skip(line)
+                        .find(|(_, l)| l.trim().is_empty())
+                        .map_or(usize::MAX, |(i, _)| i + 1);
+                    while tokens.next_if(|(l, _)| *l <= end).is_some() {}
+                }
+                _ => return Err((line, format!("Unexpected token '{token}'"))),
+            }
+        }
+
+        Ok(Netrc { entries })
+    }
+
+    /// Credentials for `host`, falling back to the default entry.
+    pub fn credentials(&self, host: &str) -> Option<Credentials> {
+        let entry = self
+            .entries
+            .iter()
+            .find(|entry| {
+                entry
+                    .machine
+                    .as_deref()
+                    .is_some_and(|m| m.eq_ignore_ascii_case(host))
+            })
+            .or_else(|| self.entries.iter().find(|entry| entry.machine.is_none()))?;
+
+        Some(Credentials {
+            username: entry.login.clone()?,
+            password: entry.password.clone().unwrap_or_default(),
+        })
+    }
+}
+
+pub fn default_path() -> Result<PathBuf, NetrcError> {
+    let home = std::env::var_os("HOME").ok_or(NetrcError::NoHome)?;
+
+    Ok(PathBuf::from(home).join(".netrc"))
+}
+
+/// Splits netrc contents into whitespace separated tokens with their line
+/// numbers, honoring double quotes, backslash escapes and `#` comments.
+fn tokenize(contents: &str) -> Vec<(usize, String)> {
+    let mut tokens = Vec::new();
+
+    for (i, line) in contents.lines().enumerate() {
+        let mut chars = line.chars().peekable();
+
+        while let Some(&c) = chars.peek() {
+            if c.is_whitespace() {
+                chars.next();
+                continue;
+            }
+            if c == '#' {
+                break;
+            }
+
+            let mut token = String::new();
+            if c == '"' {
+                chars.next();
+                while let Some(c) = chars.next() {
+                    match c {
+                        '"' => break,
+                        '\\' => match chars.next() {
+                            Some('n') => token.push('\n'),
+                            Some('t') => token.push('\t'),
+                            Some(c) => token.push(c),
+                            None => {}
+                        },
+                        c => token.push(c),
+                    }
+                }
+            } else {
+                while let Some(c) = chars.next_if(|c| !c.is_whitespace()) {
+                    token.push(c);
+                }
+            }
+
+            tokens.push((i + 1, token));
+        }
+    }
+
+    tokens
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn login(netrc: &Netrc, host: &str) -> Option<(String, String)> {
+        netrc
+            .credentials(host)
+            .map(|credentials| (credentials.username, credentials.password))
+    }
+
+    fn pair(username: &str, password: &str) -> Option<(String, String)> {
+        Some((username.to_string(), password.to_string()))
+    }
+
+    #[test]
+    fn machine_then_default() {
+        let netrc = Netrc::parse(
+            "machine example.com login alice password secret\n\
+             default login anonymous password guest\n",
+        )
+        .unwrap();
+
+        assert_eq!(login(&netrc, "EXAMPLE.com"), pair("alice", "secret"));
+        assert_eq!(login(&netrc, "other.org"), pair("anonymous", "guest"));
+    }
+
+    #[test]
+    fn missing_machine_without_default() {
+        let netrc = Netrc::parse("machine example.com login alice password secret").unwrap();
+        assert_eq!(login(&netrc, "other.org"), None);
+        assert_eq!(login(&Netrc::parse("").unwrap(), "example.com"), None);
+    }
+
+    #[test]
+    fn entry_without_login_has_no_credentials() {
+        let netrc =
+            Netrc::parse("machine example.com password secret\ndefault login anon").unwrap();
+        assert_eq!(login(&netrc, "example.com"), None);
+        assert_eq!(login(&netrc, "other.org"), pair("anon", ""));
+    }
+
+    #[test]
+    fn quoted_values_and_comments() {
+        let netrc = Netrc::parse(
+            "# a comment line\n\
+             machine example.com # trailing comment\n  \
+               login \"bob smith\"\n  \
+               password \"p\\\"w\\\\d\\tx\"\n  \
+               account ignored\n",
+        )
+        .unwrap();
+        assert_eq!(
+            login(&netrc, "example.com"),
+            pair("bob smith", "p\"w\\d\tx")
+        );
+    }
+
+    #[test]
+    fn macdef_body_is_skipped() {
+        let netrc = Netrc::parse(
+            "machine a login x password y\n\
+             macdef init\n\
+             cd /pub\n\
+             login bogus\n\
+             \n\
+             machine b login z password w\n",
+        )
+        .unwrap();
+        assert_eq!(login(&netrc, "a"), pair("x", "y"));
+        assert_eq!(login(&netrc, "b"), pair("z", "w"));
+    }
+
+    #[test]
+    fn syntax_errors_name_the_line() {
+        assert_eq!(
+            Netrc::parse("machine a\nlogin").err(),
+            Some((2, "Missing value after 'login'".to_string()))
+        );
+        assert_eq!(
+            Netrc::parse("login x").err(),
+            Some((1, "'login' outside of a machine entry".to_string()))
+        );
+        assert_eq!(
+            Netrc::parse("machine a\n\nport 21").err(),
+            Some((3, "Unexpected token 'port'".to_string()))
+        );
+    }
+}
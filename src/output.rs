@@ -0,0 +1,570 @@
+use crate::auth::{split_quoted, unquote};
+use crate::decode::DecodeError;
+use crate::redirect::Hop;
+use encoding_rs::{Decoder, Encoding, UTF_16BE, UTF_16LE, UTF_8};
+use percent_encoding::{percent_decode, percent_decode_str};
+use reqwest::{
+    blocking::Response,
+    header::{HeaderMap, CONTENT_TYPE},
+    StatusCode, Version,
+};
+use std::{
+    error::Error,
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+};
+use url::Url;
+
+#[derive(Debug)]
+pub enum OutputError {
+    NoFileName(String),
+    UnsafeFileName(String),
+    Binary,
+    Body(io::Error),
+    Write(io::Error),
+}
+
+impl Display for OutputError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            OutputError::NoFileName(url) => write!(f, "Unable to derive a file name from '{url}'"),
+            OutputError::UnsafeFileName(name) => {
+                write!(f, "Refusing to use '{name}' as a file name")
+            }
+            OutputError::Binary => write!(
+                f,
+                "Binary output can mess up your terminal. Use \"--output -\" to output it to your terminal anyway, or consider \"--output <FILE>\" to save to a file."
+            ),
+            // Decoding and size errors already say what went wrong with the body
+            OutputError::Body(e) => match e
+                .get_ref()
+                .filter(|e| e.is::<DecodeError>() || e.is::<TooLarge>())
+            {
+                Some(e) => write!(f, "{e}"),
+                None => write!(f, "Unable to read the response body: {e}"),
+            },
+            OutputError::Write(e) => write!(f, "Unable to write the response body: {e}"),
+        }
+    }
+}
+
+/// A body bigger than --max-filesize allows.
+#[derive(Debug)]
+pub struct TooLarge(pub u64);
+
+impl Display for TooLarge {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "Maximum file size exceeded, the limit is {} bytes",
+            self.0
+        )
+    }
+}
+
+impl Error for TooLarge {}
+
+/// Wraps a body so reading more than `max` bytes from it fails with [`TooLarge`].
+pub struct Limited<R> {
+    inner: R,
+    max: Option<u64>,
+    read: u64,
+}
+
+impl<R: Read> Limited<R> {
+    pub fn new(inner: R, max: Option<u64>) -> Limited<R> {
+        Limited {
+            inner,
+            max,
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for Limited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.read += read as u64;
+
+        match self.max {
+            Some(max) if self.read > max => Err(io::Error::other(TooLarge(max))),
+            _ => Ok(read),
+        }
+    }
+}
+
+/// The file name -O saves to: the last segment of the URL path, percent-decoded.
+///
+/// Paths ending in `/` have no file name, and names that could leave the
+/// output directory once decoded, like `..` or `a%2Fb`, are rejected.
+pub fn remote_name(url: &str) -> Result<PathBuf, OutputError> {
+    let no_name = || OutputError::NoFileName(url.to_string());
+
+    let parsed = Url::parse(url).map_err(|_| no_name())?;
+    let segment = parsed
+        .path_segments()
+        .and_then(|mut segments| segments.next_back())
+        .filter(|segment| !segment.is_empty())
+        .ok_or_else(no_name)?;
+
+    let name = percent_decode_str(segment)
+        .decode_utf8()
+        .map_err(|_| OutputError::UnsafeFileName(segment.to_string()))?;
+
+    let unsafe_name = name == "."
+        || name == ".."
+        || name.contains(['/', '\\', '\0'])
+        || Path::new(name.as_ref()).is_absolute();
+    if unsafe_name {
+        return Err(OutputError::UnsafeFileName(name.into_owned()));
+    }
+
+    Ok(PathBuf::from(name.into_owned()))
+}
+
+/// The file name -J takes from a `Content-Disposition` value, if it has one.
+///
+/// An RFC 5987 `filename*` in UTF-8 or ISO-8859-1 wins over a plain
+/// `filename`. Directory parts are stripped so only the last component is
+/// used, while absolute paths are rejected outright.
+pub fn disposition_name(value: &str) -> Result<Option<PathBuf>, OutputError> {
+    let mut filename = None;
+    let mut extended = None;
+
+    for param in split_quoted(value, ';').into_iter().skip(1) {
+        let Some((key, value)) = param.split_once('=') else {
+            continue;
+        };
+
+        match key.trim().to_ascii_lowercase().as_str() {
+            "filename" => filename = Some(unquote(value.trim())),
+            "filename*" => extended = extended.or_else(|| decode_ext_value(value.trim())),
+            _ => {}
+        }
+    }
+
+    let Some(name) = extended.or(filename) else {
+        return Ok(None);
+    };
+
+    if name.starts_with(['/', '\\']) || Path::new(&name).is_absolute() {
+        return Err(OutputError::UnsafeFileName(name));
+    }
+
+    match name.rsplit(['/', '\\']).next() {
+        Some(base) if !base.is_empty() && base != "." && base != ".." && !base.contains('\0') => {
+            Ok(Some(PathBuf::from(base)))
+        }
+        _ => Err(OutputError::UnsafeFileName(name)),
+    }
+}
+
+// Decodes an RFC 5987 ext-value, `charset'language'percent-encoded-value`
+fn decode_ext_value(value: &str) -> Option<String> {
+    let mut parts = value.splitn(3, '\'');
+    let (charset, _language, encoded) = (parts.next()?, parts.next()?, parts.next()?);
+    let bytes: Vec<u8> = percent_decode(encoded.as_bytes()).collect();
+
+    match charset.to_ascii_lowercase().as_str() {
+        "utf-8" => String::from_utf8(bytes).ok(),
+        // Every ISO-8859-1 byte is the Unicode code point of the same value
+        "iso-8859-1" => Some(bytes.into_iter().map(char::from).collect()),
+        _ => None,
+    }
+}
+
+/// The status line and headers of every redirect followed and of the final
+/// response, as -i prints them.
+///
+/// They are made from the parsed response rather than the bytes the server
+/// sent, as the client keeps no more than that: header names come out in
+/// lowercase, headers with the same name together, and the reason phrase is
+/// the standard one for the status.
+pub fn heads(hops: &[Hop], resp: &Response) -> Vec<u8> {
+    let mut heads = Vec::new();
+    for hop in hops {
+        head(&mut heads, hop.version, hop.status, &hop.headers);
+    }
+    head(&mut heads, resp.version(), resp.status(), resp.headers());
+
+    heads
+}
+
+/// Appends one status line and header block, ending with the blank line.
+/// Each header value gets its own line, so repeated headers like Set-Cookie
+/// appear as sent.
+pub fn head(out: &mut Vec<u8>, version: Version, status: StatusCode, headers: &HeaderMap) {
+    out.extend_from_slice(status_line(version, status).as_bytes());
+    out.extend_from_slice(b"\r\n");
+
+    for (name, value) in headers {
+        out.extend_from_slice(name.as_str().as_bytes());
+        out.extend_from_slice(b": ");
+        out.extend_from_slice(value.as_bytes());
+        out.extend_from_slice(b"\r\n");
+    }
+    out.extend_from_slice(b"\r\n");
+}
+
+/// A status line like `HTTP/1.1 200 OK`.
+pub fn status_line(version: Version, status: StatusCode) -> String {
+    // HTTP/2 and later have no reason phrase, so curl shows none
+    if version >= Version::HTTP_2 {
+        return format!("{} {}", version_name(version), status.as_u16());
+    }
+    let reason = status.canonical_reason().unwrap_or_default();
+
+    format!("{} {} {reason}", version_name(version), status.as_u16())
+}
+
+/// The protocol name of `version` as it appears on the wire, e.g. `HTTP/1.1`.
+pub fn version_name(version: Version) -> &'static str {
+    match version {
+        Version::HTTP_09 => "HTTP/0.9",
+        Version::HTTP_10 => "HTTP/1.0",
+        Version::HTTP_2 => "HTTP/2",
+        Version::HTTP_3 => "HTTP/3",
+        _ => "HTTP/1.1",
+    }
+}
+
+/// What [`save`] does with a file that already exists.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Existing {
+    Refuse,
+    Overwrite,
+    Append,
+}
+
+/// Writes `head` and the response body to `path` and returns the number of
+/// bytes written. A transfer or write error removes the partial file, so a
+/// file that exists afterwards always holds the complete response. Appended
+/// files are kept instead, so a failed resume can be resumed again.
+///
+/// With `create_dirs` missing parent directories are created first.
+pub fn save(
+    body: &mut impl Read,
+    head: &[u8],
+    path: &Path,
+    create_dirs: bool,
+    existing: Existing,
+) -> Result<u64, OutputError> {
+    if let Some(parent) = path.parent().filter(|_| create_dirs) {
+        std::fs::create_dir_all(parent).map_err(OutputError::Write)?;
+    }
+
+    let mut file = match existing {
+        Existing::Refuse => OpenOptions::new().write(true).create_new(true).open(path),
+        Existing::Overwrite => File::create(path),
+        Existing::Append => OpenOptions::new().append(true).create(true).open(path),
+    }
+    .map_err(OutputError::Write)?;
+
+    let written = file
+        .write_all(head)
+        .map_err(OutputError::Write)
+        .and_then(|_| copy(body, &mut file))
+        .and_then(|written| file.flush().map(|_| written).map_err(OutputError::Write));
+
+    match written {
+        Ok(written) => Ok(head.len() as u64 + written),
+        Err(e) => {
+            drop(file);
+            if existing != Existing::Append {
+                let _ = std::fs::remove_file(path);
+            }
+            Err(e)
+        }
+    }
+}
+
+/// Copies the raw response body to stdout.
+pub fn print(body: &mut impl Read) -> Result<u64, OutputError> {
+    let mut stdout = io::stdout().lock();
+    let written = copy(body, &mut stdout)?;
+    stdout.flush().map_err(OutputError::Write)?;
+
+    Ok(written)
+}
+
+// Like io::copy, but tells failures to read the body from failures to write it
+fn copy(body: &mut impl Read, out: &mut impl Write) -> Result<u64, OutputError> {
+    let mut buf = [0; 8192];
+    let mut written = 0;
+
+    loop {
+        let read = match body.read(&mut buf) {
+            Ok(0) => return Ok(written),
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(OutputError::Body(e)),
+        };
+
+        out.write_all(&buf[..read]).map_err(OutputError::Write)?;
+        written += read as u64;
+    }
+}
+
+/// Streams the response body to stdout for display.
+///
+/// On a terminal the body is decoded from its charset, taken from a byte order
+/// mark or the Content-Type and falling back to UTF-8, bodies that look binary
+/// are refused and a final newline is added when the body does not end with
+/// one. Anything else gets the raw bytes, exactly as received.
+pub fn display(body: &mut impl Read, charset: Option<&str>) -> Result<(), OutputError> {
+    let mut stdout = io::stdout().lock();
+    let terminal = stdout.is_terminal();
+    display_to(&mut stdout, terminal, body, charset)
+}
+
+// What display does, writing to `out`, which is a terminal or not as `terminal` says
+fn display_to(
+    out: &mut impl Write,
+    terminal: bool,
+    body: &mut impl Read,
+    charset: Option<&str>,
+) -> Result<(), OutputError> {
+    // Some(None) is a charset encoding_rs does not know
+    let charset = charset.map(|label| Encoding::for_label(label.as_bytes()));
+    let mut decoder: Option<Decoder> = None;
+    let mut buf = [0; 8192];
+    let mut text = String::new();
+    let mut last = b'\n';
+    let mut first = true;
+
+    loop {
+        let read = match body.read(&mut buf) {
+            Ok(0) => break,
+            Ok(read) => read,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return Err(OutputError::Body(e)),
+        };
+        let chunk = &buf[..read];
+
+        if first && terminal {
+            let bom = Encoding::for_bom(chunk).map(|(encoding, _)| encoding);
+            let encoding = bom.or(charset.flatten()).unwrap_or(UTF_8);
+
+            if looks_binary(chunk, encoding, bom.is_some() || charset.is_some()) {
+                return Err(OutputError::Binary);
+            }
+            decoder = Some(encoding.new_decoder_with_bom_removal());
+        }
+        first = false;
+
+        match &mut decoder {
+            Some(decoder) => {
+                decode(decoder, chunk, &mut text, false);
+                out.write_all(text.as_bytes()).map_err(OutputError::Write)?;
+                last = text.bytes().last().unwrap_or(last);
+            }
+            None => {
+                out.write_all(chunk).map_err(OutputError::Write)?;
+                last = chunk[read - 1];
+            }
+        }
+    }
+
+    // Flush out a character left incomplete at the end of the body
+    if let Some(decoder) = &mut decoder {
+        decode(decoder, &[], &mut text, true);
+        out.write_all(text.as_bytes()).map_err(OutputError::Write)?;
+        last = text.bytes().last().unwrap_or(last);
+    }
+
+    if terminal && last != b'\n' {
+        out.write_all(b"\n").map_err(OutputError::Write)?;
+    }
+    out.flush().map_err(OutputError::Write)
+}
+
+/// The whole response body as text, decoded from its charset like
+/// [`display`] does.
+pub fn text(bytes: &[u8], charset: Option<&str>) -> String {
+    let charset = charset.and_then(|label| Encoding::for_label(label.as_bytes()));
+
+    let (text, _, _) = charset.unwrap_or(UTF_8).decode(bytes);
+    text.into_owned()
+}
+
+// Replaces `text` with the decoded `chunk`, using U+FFFD for malformed input
+fn decode(decoder: &mut Decoder, chunk: &[u8], text: &mut String, last: bool) {
+    text.clear();
+    text.reserve(
+        decoder
+            .max_utf8_buffer_length(chunk.len())
+            .unwrap_or(chunk.len() * 3),
+    );
+    // The reserved space always fits the whole chunk, so all input is consumed
+    let _ = decoder.decode_to_string(chunk, text, last);
+}
+
+// UTF-16 is full of NUL bytes, other text has none. Without a declared charset
+// the body also has to be valid UTF-8, apart from a character cut off at the
+// end of the chunk
+fn looks_binary(chunk: &[u8], encoding: &Encoding, declared: bool) -> bool {
+    if encoding == UTF_16LE || encoding == UTF_16BE {
+        return false;
+    }
+
+    chunk.contains(&0)
+        || !declared && std::str::from_utf8(chunk).is_err_and(|e| e.error_len().is_some())
+}
+
+// The Content-Type value split into its lowercase media type and parameters
+fn content_type(resp: &Response) -> Option<(String, Vec<&str>)> {
+    let value = resp.headers().get(CONTENT_TYPE)?.to_str().ok()?;
+    let mut parts = split_quoted(value, ';').into_iter();
+    let essence = parts.next()?.trim().to_ascii_lowercase();
+
+    Some((essence, parts.collect()))
+}
+
+/// The charset parameter of the Content-Type, if any.
+pub fn charset(resp: &Response) -> Option<&str> {
+    let (_, params) = content_type(resp)?;
+
+    params.into_iter().find_map(|param| {
+        let (key, value) = param.split_once('=')?;
+        key.trim()
+            .eq_ignore_ascii_case("charset")
+            .then(|| value.trim().trim_matches('"'))
+    })
+}
+
+/// Whether the response declares a JSON body, `application/json` or a
+/// `+json` type like `application/problem+json`.
+pub fn is_json(resp: &Response) -> bool {
+    content_type(resp)
+        .is_some_and(|(essence, _)| essence == "application/json" || essence.ends_with("+json"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // What a terminal is shown of `body`
+    fn shown(body: &[u8], charset: Option<&str>) -> Result<String, OutputError> {
+        let mut out = Vec::new();
+        display_to(&mut out, true, &mut &body[..], charset)?;
+        Ok(String::from_utf8(out).unwrap())
+    }
+
+    // Hands out one byte per read, cutting every character apart
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            let Some((first, rest)) = self.0.split_first() else {
+                return Ok(0);
+            };
+            buf[0] = *first;
+            self.0 = rest;
+            Ok(1)
+        }
+    }
+
+    fn response(content_type: &str) -> Response {
+        let resp = http::Response::builder()
+            .header(CONTENT_TYPE, content_type)
+            .body("")
+            .unwrap();
+        Response::from(resp)
+    }
+
+    #[test]
+    fn bom_picks_the_charset() {
+        let utf16: Vec<u8> = [
+            &[0xff, 0xfe][..],
+            &"héllo"
+                .encode_utf16()
+                .flat_map(u16::to_le_bytes)
+                .collect::<Vec<_>>(),
+        ]
+        .concat();
+        assert_eq!(shown(&utf16, None).unwrap(), "héllo\n");
+        assert_eq!(shown(b"\xef\xbb\xbfhi\n", None).unwrap(), "hi\n");
+        // Over the declared charset too
+        assert_eq!(shown(&utf16, Some("iso-8859-1")).unwrap(), "héllo\n");
+    }
+
+    #[test]
+    fn content_type_charset() {
+        assert_eq!(shown(b"caf\xe9\n", Some("ISO-8859-1")).unwrap(), "café\n");
+        assert_eq!(
+            shown(b"\x82\xb1\x82\xf1", Some("shift_jis")).unwrap(),
+            "こん\n"
+        );
+        // Declared UTF-8 is shown even when broken
+        assert_eq!(shown(b"a\xffb", Some("utf-8")).unwrap(), "a\u{fffd}b\n");
+    }
+
+    #[test]
+    fn utf8_fallback() {
+        assert_eq!(shown("naïve".as_bytes(), None).unwrap(), "naïve\n");
+        assert_eq!(
+            shown("naïve".as_bytes(), Some("x-unknown")).unwrap(),
+            "naïve\n"
+        );
+        assert_eq!(shown(b"", None).unwrap(), "");
+        assert!(matches!(shown(b"caf\xe9!", None), Err(OutputError::Binary)));
+        assert!(matches!(
+            shown(b"a\0b", Some("utf-8")),
+            Err(OutputError::Binary)
+        ));
+    }
+
+    #[test]
+    fn characters_split_across_reads() {
+        let mut out = Vec::new();
+        display_to(&mut out, true, &mut Trickle("ñü€😀".as_bytes()), None).unwrap();
+        assert_eq!(out, "ñü€😀\n".as_bytes());
+    }
+
+    #[test]
+    fn pipes_get_the_raw_bytes() {
+        let bodies: [&[u8]; 5] = [
+            b"\xff\xfeh\0i\0",
+            b"\xef\xbb\xbfno newline",
+            b"caf\xe9",
+            b"\0\x01\x02",
+            b"",
+        ];
+        for body in bodies {
+            for charset in [None, Some("iso-8859-1")] {
+                let mut out = Vec::new();
+                display_to(&mut out, false, &mut Trickle(body), charset).unwrap();
+                assert_eq!(out, body);
+            }
+        }
+    }
+
+    #[test]
+    fn text_decodes_like_display() {
+        assert_eq!(text(b"caf\xe9", Some("latin1")), "café");
+        assert_eq!(text(b"\xef\xbb\xbf{}", None), "{}");
+        assert_eq!(text(b"\xfe\xff\0{\0}", Some("utf-8")), "{}");
+        assert_eq!(text(b"a\xff", None), "a\u{fffd}");
+    }
+
+    #[test]
+    fn charset_parameter() {
+        assert_eq!(
+            charset(&response("text/html; charset=ISO-8859-1")),
+            Some("ISO-8859-1")
+        );
+        assert_eq!(
+            charset(&response("text/plain;CHARSET=\"utf-8\"")),
+            Some("utf-8")
+        );
+        assert_eq!(
+            charset(&response(
+                "text/plain; name=\"a;charset=x\"; charset=koi8-r"
+            )),
+            Some("koi8-r")
+        );
+        assert_eq!(charset(&response("text/plain")), None);
+    }
+}
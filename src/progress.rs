@@ -0,0 +1,352 @@
+use std::{
+    collections::VecDeque,
+    io::{self, Read, Write},
+    time::{Duration, Instant},
+};
+
+/// How transfer progress is shown on stderr.
+#[derive(Clone, Copy, PartialEq)]
+pub enum Style {
+    Meter,
+    Bar,
+}
+
+// How often the display is redrawn, and how far back the current speed looks
+const REDRAW: Duration = Duration::from_millis(250);
+const WINDOW: Duration = Duration::from_secs(5);
+
+/// Tracks a download and redraws curl's progress meter or bar as it goes.
+pub struct Progress {
+    style: Style,
+    total: Option<u64>,
+    received: u64,
+    started: Instant,
+    drawn: Option<Instant>,
+    // (time, bytes received so far) samples within WINDOW, oldest first
+    samples: VecDeque<(Instant, u64)>,
+}
+
+impl Progress {
+    /// Starts tracking a download of `total` bytes, when the length is known.
+    pub fn new(style: Style, total: Option<u64>) -> Progress {
+        let started = Instant::now();
+
+        if style == Style::Meter {
+            eprintln!(
+                "  % Total    % Received % Xferd  Average Speed   Time    Time     Time  Current"
+            );
+            eprintln!(
+                "                                 Dload  Upload   Total   Spent    Left  Speed"
+            );
+        }
+
+        let mut progress = Progress {
+            style,
+            total,
+            received: 0,
+            started,
+            drawn: None,
+            samples: VecDeque::from([(started, 0)]),
+        };
+        progress.draw();
+
+        progress
+    }
+
+    pub fn update(&mut self, read: usize) {
+        self.received += read as u64;
+
+        let now = Instant::now();
+        self.samples.push_back((now, self.received));
+        while self
+            .samples
+            .front()
+            .is_some_and(|(time, _)| now.duration_since(*time) > WINDOW)
+            && self.samples.len() > 2
+        {
+            self.samples.pop_front();
+        }
+
+        if self
+            .drawn
+            .is_none_or(|drawn| now.duration_since(drawn) >= REDRAW)
+        {
+            self.draw();
+        }
+    }
+
+    /// Draws the final state and moves to the next line.
+    pub fn finish(&mut self) {
+        self.draw();
+        eprintln!();
+    }
+
+    fn draw(&mut self) {
+        self.drawn = Some(Instant::now());
+
+        let line = match self.style {
+            Style::Meter => self.meter(),
+            Style::Bar => self.bar(),
+        };
+
+        let mut stderr = io::stderr().lock();
+        let _ = write!(stderr, "\r{line}");
+        let _ = stderr.flush();
+    }
+
+    fn meter(&self) -> String {
+        let elapsed = self.started.elapsed();
+        let average = per_second(self.received, elapsed);
+
+        let (total, percent, left) = match self.total {
+            Some(total) => {
+                let left = (average > 0)
+                    .then(|| Duration::from_secs(total.saturating_sub(self.received) / average));
+                (total, percent(self.received, total), left)
+            }
+            None => (self.received, 0, None),
+        };
+
+        format!(
+            "{:3} {:>5}  {:3} {:>5}    0     0  {:>5}      0 {} {} {} {:>5}",
+            percent,
+            size(total),
+            percent,
+            size(self.received),
+            size(average),
+            self.total.map_or_else(unknown_time, |_| {
+                time(left.map_or(elapsed, |left| elapsed + left))
+            }),
+            time(elapsed),
+            left.map_or_else(unknown_time, time),
+            size(self.current_speed()),
+        )
+    }
+
+    fn bar(&self) -> String {
+        let Some(total) = self.total else {
+            return format!("{} bytes", self.received);
+        };
+
+        // Leave room for " 100.0%"
+        let width = terminal_width().saturating_sub(8).max(10);
+        let fraction = if total == 0 {
+            1.0
+        } else {
+            (self.received as f64 / total as f64).min(1.0)
+        };
+        let filled = (fraction * width as f64) as usize;
+
+        format!(
+            "{}{} {:5.1}%",
+            "#".repeat(filled),
+            " ".repeat(width - filled),
+            fraction * 100.0
+        )
+    }
+
+    fn current_speed(&self) -> u64 {
+        match (self.samples.front(), self.samples.back()) {
+            (Some((first, from)), Some((last, to))) if last > first => {
+                per_second(to - from, last.duration_since(*first))
+            }
+            _ => 0,
+        }
+    }
+}
+
+/// The display for -Z, counting the transfers done out of all of them,
+/// since a meter for each would draw over the others. It is drawn on
+/// stderr, or on `W`.
+pub struct Parallel<W: Write = io::Stderr> {
+    out: W,
+    total: usize,
+    done: usize,
+    failed: usize,
+}
+
+impl Parallel {
+    pub fn new(total: usize) -> Parallel {
+        Parallel::drawn_on(io::stderr(), total)
+    }
+}
+
+impl<W: Write> Parallel<W> {
+    fn drawn_on(out: W, total: usize) -> Parallel<W> {
+        let mut parallel = Parallel {
+            out,
+            total,
+            done: 0,
+            failed: 0,
+        };
+        parallel.draw();
+
+        parallel
+    }
+
+    /// Counts a finished transfer, `ok` when it succeeded.
+    pub fn finished(&mut self, ok: bool) {
+        self.done += 1;
+        if !ok {
+            self.failed += 1;
+        }
+        self.draw();
+    }
+
+    /// Moves past the display once every transfer is done, giving back
+    /// what it was drawn on.
+    pub fn finish(mut self) -> W {
+        let _ = writeln!(self.out);
+        self.out
+    }
+
+    fn draw(&mut self) {
+        let line = format!(
+            "\rTransfers: {} of {} done, {} failed",
+            self.done, self.total, self.failed
+        );
+        let _ = self.out.write_all(line.as_bytes());
+        let _ = self.out.flush();
+    }
+}
+
+/// Wraps a response body so reading it advances the progress display. The
+/// display is finished once the body has been read to the end.
+pub struct Reader<R> {
+    inner: R,
+    progress: Option<Progress>,
+    received: u64,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R, progress: Option<Progress>) -> Reader<R> {
+        Reader {
+            inner,
+            progress,
+            received: 0,
+        }
+    }
+
+    /// The number of body bytes read so far, with or without a display.
+    pub fn received(&self) -> u64 {
+        self.received
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        self.received += read as u64;
+
+        if let Some(progress) = &mut self.progress {
+            if read == 0 {
+                progress.finish();
+                self.progress = None;
+            } else {
+                progress.update(read);
+            }
+        }
+
+        Ok(read)
+    }
+}
+
+// A body that fails half way still ends the progress line
+impl<R> Drop for Reader<R> {
+    fn drop(&mut self) {
+        if self.progress.is_some() {
+            eprintln!();
+        }
+    }
+}
+
+fn per_second(bytes: u64, elapsed: Duration) -> u64 {
+    let secs = elapsed.as_secs_f64();
+    if secs > 0.0 {
+        (bytes as f64 / secs) as u64
+    } else {
+        0
+    }
+}
+
+fn percent(received: u64, total: u64) -> u64 {
+    (received.min(total) * 100)
+        .checked_div(total)
+        .unwrap_or(100)
+}
+
+// Sizes in at most five characters, like curl's meter
+fn size(bytes: u64) -> String {
+    const K: u64 = 1024;
+    const M: u64 = K * K;
+    const G: u64 = M * K;
+
+    match bytes {
+        bytes if bytes < 100_000 => bytes.to_string(),
+        bytes if bytes < 10_000 * K => format!("{}k", bytes / K),
+        bytes if bytes < 100 * M => format!("{}.{}M", bytes / M, bytes % M * 10 / M),
+        bytes if bytes < 10_000 * M => format!("{}M", bytes / M),
+        bytes if bytes < 100 * G => format!("{}.{}G", bytes / G, bytes % G * 10 / G),
+        bytes => format!("{}G", bytes / G),
+    }
+}
+
+fn time(duration: Duration) -> String {
+    let secs = duration.as_secs();
+    let hours = secs / 3600;
+
+    if hours < 100 {
+        format!("{hours:2}:{:02}:{:02}", secs % 3600 / 60, secs % 60)
+    } else {
+        format!("{:>4}d {:02}h", hours / 24, hours % 24)
+    }
+}
+
+fn unknown_time() -> String {
+    "--:--:--".to_string()
+}
+
+fn terminal_width() -> usize {
+    #[cfg(unix)]
+    {
+        let mut size = std::mem::MaybeUninit::<libc::winsize>::uninit();
+
+        // SAFETY: TIOCGWINSZ fills in a winsize when it succeeds
+        let columns = unsafe {
+            if libc::ioctl(libc::STDERR_FILENO, libc::TIOCGWINSZ, size.as_mut_ptr()) == 0 {
+                size.assume_init().ws_col
+            } else {
+                0
+            }
+        };
+        if columns > 0 {
+            return columns.into();
+        }
+    }
+
+    std::env::var("COLUMNS")
+        .ok()
+        .and_then(|columns| columns.parse().ok())
+        .unwrap_or(80)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parallel_display_counts_done_and_failed_transfers() {
+        let mut parallel = Parallel::drawn_on(Vec::new(), 3);
+        parallel.finished(true);
+        parallel.finished(false);
+        parallel.finished(true);
+
+        assert_eq!(
+            String::from_utf8(parallel.finish()).unwrap(),
+            "\rTransfers: 0 of 3 done, 0 failed\
+             \rTransfers: 1 of 3 done, 0 failed\
+             \rTransfers: 2 of 3 done, 1 failed\
+             \rTransfers: 3 of 3 done, 1 failed\n"
+        );
+    }
+}
@@ -0,0 +1,99 @@
+/// The protocols this curl speaks, which `all` stands for.
+pub const SUPPORTED: &[&str] = &["http", "https", "file"];
+
+/// The protocols --proto allows URLs to use, or --proto-redir allows
+/// redirects to switch to.
+#[derive(Clone, Debug)]
+pub struct Protocols(Vec<String>);
+
+impl Protocols {
+    /// Whether a URL with `scheme` may be fetched.
+    pub fn allows(&self, scheme: &str) -> bool {
+        self.0
+            .iter()
+            .any(|allowed| allowed.eq_ignore_ascii_case(scheme))
+    }
+}
+
+/// Parses a --proto or --proto-redir list like curl's, protocols separated
+/// by commas and changing the set in order from all of them. `+http` or
+/// just `http` adds one, `-http` removes it and `=https` allows only that
+/// one. `all` stands for every protocol, so `-all,+https` is `=https`.
+pub fn parse(s: &str) -> Result<Protocols, String> {
+    let mut allowed: Vec<String> = SUPPORTED.iter().map(|name| name.to_string()).collect();
+
+    for item in s.split(',').map(str::trim).filter(|item| !item.is_empty()) {
+        let (op, name) = match item.chars().next() {
+            Some(op @ ('+' | '-' | '=')) => (op, &item[1..]),
+            _ => ('+', item),
+        };
+        if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric()) {
+            return Err(format!(
+                "'{item}' is not a valid protocol, use names like https, +http, -http or =https"
+            ));
+        }
+
+        let names: Vec<String> = if name.eq_ignore_ascii_case("all") {
+            SUPPORTED.iter().map(|name| name.to_string()).collect()
+        } else {
+            vec![name.to_ascii_lowercase()]
+        };
+        match op {
+            '=' => allowed = names,
+            '-' => allowed.retain(|allowed| !names.contains(allowed)),
+            _ => {
+                for name in names {
+                    if !allowed.contains(&name) {
+                        allowed.push(name);
+                    }
+                }
+            }
+        }
+    }
+
+    Ok(Protocols(allowed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn allowed(s: &str) -> Vec<&'static str> {
+        let protocols = parse(s).unwrap();
+        ["http", "https", "file", "ftp"]
+            .into_iter()
+            .filter(|scheme| protocols.allows(scheme))
+            .collect()
+    }
+
+    #[test]
+    fn starts_from_all() {
+        assert_eq!(allowed(""), ["http", "https", "file"]);
+        assert_eq!(allowed("http"), ["http", "https", "file"]);
+        assert_eq!(allowed("+ftp"), ["http", "https", "file", "ftp"]);
+    }
+
+    #[test]
+    fn applies_changes_in_order() {
+        assert_eq!(allowed("=https"), ["https"]);
+        assert_eq!(allowed("-all,+https"), ["https"]);
+        assert_eq!(allowed("-http,-file"), ["https"]);
+        assert_eq!(allowed("=https,+http"), ["http", "https"]);
+        assert_eq!(allowed("+http,=file"), ["file"]);
+        assert_eq!(allowed("-all"), Vec::<&str>::new());
+        assert_eq!(allowed("=http,-all,+all"), ["http", "https", "file"]);
+    }
+
+    #[test]
+    fn ignores_case_and_blanks() {
+        assert_eq!(allowed(" =HTTPS , ,+Http "), ["http", "https"]);
+        assert!(parse("=https").unwrap().allows("HTTPS"));
+    }
+
+    #[test]
+    fn rejects_invalid_names() {
+        for s in ["+", "=", "-", "ht tp", "http/2", "+-http"] {
+            assert!(parse(s).is_err(), "{s}");
+        }
+    }
+}
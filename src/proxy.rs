@@ -0,0 +1,408 @@
+use crate::{
+    auth::{self, AuthError},
+    tls::TlsError,
+};
+use reqwest::header::HeaderMap;
+use std::{fmt::Display, net::IpAddr};
+use url::{Host, Url};
+
+#[derive(Debug)]
+pub enum ProxyError {
+    InvalidUrl(String),
+    UnsupportedScheme(String),
+    Auth(AuthError),
+    CaCert(TlsError),
+}
+
+impl Display for ProxyError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            ProxyError::InvalidUrl(proxy) => write!(f, "Invalid proxy '{proxy}'"),
+            ProxyError::UnsupportedScheme(scheme) => {
+                write!(
+                    f,
+                    "Unsupported proxy scheme '{scheme}', use http, https, socks5 or socks5h"
+                )
+            }
+            ProxyError::Auth(e) => write!(f, "{e}"),
+            ProxyError::CaCert(e) => write!(f, "Unable to load the proxy CA certificates: {e}"),
+        }
+    }
+}
+
+/// The proxy URL an `-x` value names, `[scheme://]host[:port]`. Without a
+/// scheme it is an HTTP proxy, and without a port an HTTP or SOCKS proxy
+/// listens on 1080 like curl assumes.
+pub fn parse(proxy: &str) -> Result<Url, ProxyError> {
+    let with_scheme = if proxy.contains("://") {
+        proxy.to_string()
+    } else {
+        format!("http://{proxy}")
+    };
+
+    let mut url = Url::parse(&with_scheme)
+        .ok()
+        .filter(|url| url.host_str().is_some())
+        .ok_or_else(|| ProxyError::InvalidUrl(proxy.to_string()))?;
+
+    match url.scheme() {
+        "http" | "https" | "socks5" | "socks5h" => {}
+        scheme => return Err(ProxyError::UnsupportedScheme(scheme.to_string())),
+    }
+    // The parsed URL forgets a port that is the default for its scheme, so look at what was written
+    let authority = with_scheme
+        .split_once("://")
+        .map_or("", |(_, rest)| rest)
+        .split(['/', '?', '#'])
+        .next()
+        .unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    let has_port = host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| !port.contains(']'));
+    if !has_port && url.scheme() != "https" {
+        let _ = url.set_port(Some(1080));
+    }
+
+    Ok(url)
+}
+
+/// How curl talks to the proxies themselves, whichever ones it uses.
+pub struct ProxyOptions<'a> {
+    /// The `--proxy-user` to authenticate as, which wins over credentials in
+    /// the proxy URLs.
+    pub user: Option<&'a str>,
+    /// Headers for the proxy alone, sent on the CONNECT request for HTTPS.
+    pub headers: HeaderMap,
+}
+
+/// Which proxy each request goes through. An `-x` proxy is used for every
+/// URL, otherwise the `http_proxy`, `https_proxy` and `all_proxy`
+/// environment variables pick one by scheme. Hosts on the `--noproxy` or
+/// `no_proxy` list are always reached directly.
+#[derive(Clone, Default)]
+pub struct Proxies {
+    http: Option<Url>,
+    https: Option<Url>,
+    no_proxy: Vec<String>,
+}
+
+impl Proxies {
+    /// The proxies for the `-x` and `--noproxy` values, looking up the
+    /// environment variables with `env`. `-x ""` turns proxies off, even ones
+    /// from the environment.
+    pub fn new(
+        proxy: Option<&str>,
+        noproxy: Option<&str>,
+        env: impl Fn(&str) -> Option<String>,
+    ) -> Result<Proxies, ProxyError> {
+        // Either case works, apart from HTTP_PROXY, which a CGI program gets from the Proxy request header
+        let var = |names: &[&str]| {
+            names
+                .iter()
+                .find_map(|name| env(name).filter(|value| !value.trim().is_empty()))
+        };
+        let parse_var = |names: &[&str]| var(names).map(|value| parse(value.trim())).transpose();
+
+        let (http, https) = match proxy {
+            Some("") => (None, None),
+            Some(proxy) => {
+                let proxy = parse(proxy)?;
+                (Some(proxy.clone()), Some(proxy))
+            }
+            None => {
+                let all = parse_var(&["all_proxy", "ALL_PROXY"])?;
+                let http = parse_var(&["http_proxy"])?.or_else(|| all.clone());
+                let https = parse_var(&["https_proxy", "HTTPS_PROXY"])?.or(all);
+                (http, https)
+            }
+        };
+
+        let no_proxy = match noproxy {
+            Some(noproxy) => noproxy.to_string(),
+            None => var(&["no_proxy", "NO_PROXY"]).unwrap_or_default(),
+        };
+        let no_proxy = no_proxy
+            .split(',')
+            .map(|entry| entry.trim().trim_start_matches('.').to_ascii_lowercase())
+            .filter(|entry| !entry.is_empty())
+            .collect();
+
+        Ok(Proxies {
+            http,
+            https,
+            no_proxy,
+        })
+    }
+
+    /// The proxy a request to `url` goes through, if any.
+    pub fn for_url(&self, url: &Url) -> Option<&Url> {
+        let proxy = match url.scheme() {
+            "http" => self.http.as_ref(),
+            "https" => self.https.as_ref(),
+            _ => None,
+        };
+
+        proxy.filter(|_| !self.bypass(url))
+    }
+
+    // Whether the host of `url` is on the no proxy list, as a domain with any of its subdomains,
+    // an IP address or a CIDR block. "*" matches every host
+    fn bypass(&self, url: &Url) -> bool {
+        let Some(host) = url.host() else {
+            return false;
+        };
+
+        self.no_proxy.iter().any(|entry| {
+            entry == "*"
+                || match &host {
+                    Host::Domain(domain) => {
+                        let domain = domain.to_ascii_lowercase();
+                        domain == *entry
+                            || domain
+                                .strip_suffix(entry.as_str())
+                                .is_some_and(|sub| sub.ends_with('.'))
+                    }
+                    Host::Ipv4(ip) => in_block(IpAddr::V4(*ip), entry),
+                    Host::Ipv6(ip) => in_block(IpAddr::V6(*ip), entry),
+                }
+        })
+    }
+
+    /// The hosts of the HTTPS proxies, whose certificates are checked apart
+    /// from servers'.
+    pub fn tls_hosts(&self) -> Vec<String> {
+        [&self.http, &self.https]
+            .into_iter()
+            .flatten()
+            .filter(|proxy| proxy.scheme() == "https")
+            .filter_map(|proxy| proxy.host_str().map(str::to_string))
+            .collect()
+    }
+
+    /// The proxy to configure the client with, `None` when no request needs
+    /// one.
+    pub fn client_proxy(
+        &self,
+        options: ProxyOptions,
+    ) -> Result<Option<reqwest::Proxy>, ProxyError> {
+        if self.http.is_none() && self.https.is_none() {
+            return Ok(None);
+        }
+        let credentials = options
+            .user
+            .map(|user| auth::credentials(user, "proxy", false))
+            .transpose()
+            .map_err(ProxyError::Auth)?;
+
+        // The client only takes SOCKS credentials from the URL, and the proxy user wins
+        let mut proxies = self.clone();
+        for proxy in [&mut proxies.http, &mut proxies.https]
+            .into_iter()
+            .flatten()
+            .filter(|proxy| proxy.scheme().starts_with("socks"))
+        {
+            if let Some(credentials) = &credentials {
+                let _ = proxy.set_username(&credentials.username);
+                let _ = proxy.set_password(Some(&credentials.password));
+            }
+        }
+
+        let mut proxy = reqwest::Proxy::custom(move |url| proxies.for_url(url).cloned());
+        if let Some(credentials) = credentials {
+            proxy = proxy.basic_auth(&credentials.username, &credentials.password);
+        }
+        if !options.headers.is_empty() {
+            proxy = proxy.headers(options.headers);
+        }
+
+        Ok(Some(proxy))
+    }
+}
+
+// Whether `ip` is the address `entry` names, or in the `address/bits` block it names
+fn in_block(ip: IpAddr, entry: &str) -> bool {
+    let (address, bits) = match entry.split_once('/') {
+        Some((address, bits)) => (address, bits.parse::<u32>().ok()),
+        None => (entry, None),
+    };
+    let Ok(address) = address.trim_matches(['[', ']']).parse::<IpAddr>() else {
+        return false;
+    };
+
+    match (ip, address) {
+        (IpAddr::V4(ip), IpAddr::V4(address)) => {
+            let bits = bits.unwrap_or(32).min(32);
+            let mask = u32::MAX.checked_shl(32 - bits).unwrap_or(0);
+            u32::from(ip) & mask == u32::from(address) & mask
+        }
+        (IpAddr::V6(ip), IpAddr::V6(address)) => {
+            let bits = bits.unwrap_or(128).min(128);
+            let mask = u128::MAX.checked_shl(128 - bits).unwrap_or(0);
+            u128::from(ip) & mask == u128::from(address) & mask
+        }
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn proxies(proxy: Option<&str>, noproxy: Option<&str>, vars: &[(&str, &str)]) -> Proxies {
+        let vars: Vec<(String, String)> = vars
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.to_string()))
+            .collect();
+        let env = move |name: &str| {
+            vars.iter()
+                .find(|(var, _)| var == name)
+                .map(|(_, value)| value.clone())
+        };
+        Proxies::new(proxy, noproxy, env).unwrap()
+    }
+
+    fn proxy_for(proxies: &Proxies, url: &str) -> Option<String> {
+        proxies
+            .for_url(&Url::parse(url).unwrap())
+            .map(Url::to_string)
+    }
+
+    #[test]
+    fn parse_fills_in_scheme_and_port() {
+        let parsed = |proxy| parse(proxy).unwrap().to_string();
+        assert_eq!(parsed("proxy"), "http://proxy:1080/");
+        assert_eq!(parsed("proxy:80"), "http://proxy/");
+        assert_eq!(parsed("socks5h://u:p@proxy"), "socks5h://u:p@proxy:1080");
+        assert_eq!(parsed("https://proxy"), "https://proxy/");
+        assert_eq!(parsed("[::1]"), "http://[::1]:1080/");
+        assert!(matches!(
+            parse("ftp://proxy"),
+            Err(ProxyError::UnsupportedScheme(_))
+        ));
+        assert!(matches!(parse("http://"), Err(ProxyError::InvalidUrl(_))));
+    }
+
+    #[test]
+    fn option_wins_over_environment() {
+        let vars = [("http_proxy", "env:1"), ("all_proxy", "all:1")];
+        let proxies = proxies(Some("opt:2"), None, &vars);
+        assert_eq!(
+            proxy_for(&proxies, "http://h/").as_deref(),
+            Some("http://opt:2/")
+        );
+        assert_eq!(
+            proxy_for(&proxies, "https://h/").as_deref(),
+            Some("http://opt:2/")
+        );
+
+        let off = self::proxies(Some(""), None, &vars);
+        assert_eq!(proxy_for(&off, "http://h/"), None);
+    }
+
+    #[test]
+    fn environment_by_scheme() {
+        let proxies = proxies(
+            None,
+            None,
+            &[
+                ("http_proxy", "plain:1"),
+                ("HTTPS_PROXY", "secure:2"),
+                ("ALL_PROXY", "all:3"),
+            ],
+        );
+        assert_eq!(
+            proxy_for(&proxies, "http://h/").as_deref(),
+            Some("http://plain:1/")
+        );
+        assert_eq!(
+            proxy_for(&proxies, "https://h/").as_deref(),
+            Some("http://secure:2/")
+        );
+        assert_eq!(proxy_for(&proxies, "file:///etc/hosts"), None);
+
+        let all = self::proxies(None, None, &[("all_proxy", "all:3"), ("https_proxy", " ")]);
+        assert_eq!(
+            proxy_for(&all, "https://h/").as_deref(),
+            Some("http://all:3/")
+        );
+    }
+
+    #[test]
+    fn tls_hosts_are_the_https_proxies() {
+        let proxies = proxies(
+            None,
+            None,
+            &[
+                ("http_proxy", "plain:1"),
+                ("https_proxy", "https://secure:2"),
+            ],
+        );
+        assert_eq!(proxies.tls_hosts(), ["secure"]);
+        assert!(self::proxies(Some("socks5://s"), None, &[])
+            .tls_hosts()
+            .is_empty());
+    }
+
+    #[test]
+    fn uppercase_http_proxy_is_ignored() {
+        let proxies = proxies(None, None, &[("HTTP_PROXY", "cgi:1")]);
+        assert_eq!(proxy_for(&proxies, "http://h/"), None);
+    }
+
+    #[test]
+    fn no_proxy_matches_domains_and_subdomains() {
+        let proxies = proxies(Some("p:1"), Some("example.com, .Other.org"), &[]);
+        for url in [
+            "http://example.com/",
+            "http://www.EXAMPLE.com/",
+            "http://other.org/",
+            "http://a.b.other.org/",
+        ] {
+            assert_eq!(proxy_for(&proxies, url), None, "{url}");
+        }
+        for url in [
+            "http://notexample.com/",
+            "http://example.com.evil/",
+            "http://org/",
+        ] {
+            assert!(proxy_for(&proxies, url).is_some(), "{url}");
+        }
+    }
+
+    #[test]
+    fn no_proxy_matches_addresses_and_blocks() {
+        let proxies = proxies(
+            Some("p:1"),
+            Some("10.0.0.0/8,192.168.1.7,[::1],fe80::/10"),
+            &[],
+        );
+        for url in [
+            "http://10.1.2.3/",
+            "http://192.168.1.7:8080/",
+            "http://[::1]/",
+            "http://[fe80::1]/",
+        ] {
+            assert_eq!(proxy_for(&proxies, url), None, "{url}");
+        }
+        for url in ["http://11.0.0.1/", "http://192.168.1.8/", "http://[::2]/"] {
+            assert!(proxy_for(&proxies, url).is_some(), "{url}");
+        }
+    }
+
+    #[test]
+    fn no_proxy_star_and_environment() {
+        let star = proxies(Some("p:1"), Some("*"), &[]);
+        assert_eq!(proxy_for(&star, "http://anything/"), None);
+
+        let vars = [("http_proxy", "p:1"), ("NO_PROXY", "skip.test")];
+        let from_env = proxies(None, None, &vars);
+        assert_eq!(proxy_for(&from_env, "http://skip.test/"), None);
+
+        // An empty --noproxy still replaces the environment's list
+        let overridden = proxies(None, Some(""), &vars);
+        assert!(proxy_for(&overridden, "http://skip.test/").is_some());
+    }
+}
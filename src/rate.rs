@@ -0,0 +1,122 @@
+use std::{
+    io::{self, Read},
+    sync::Mutex,
+    thread,
+    time::{Duration, Instant},
+};
+
+/// Paces a transfer to an average of `rate` bytes per second.
+///
+/// Rather than sleeping a fixed time per chunk, every chunk is held back until
+/// the time the whole transfer so far should have taken at `rate`, so pauses
+/// for slow disks or networks are made up for and the average stays on target.
+struct Limiter {
+    rate: u64,
+    started: Instant,
+    transferred: u64,
+}
+
+impl Limiter {
+    fn pace(&mut self, bytes: usize) {
+        self.transferred += bytes as u64;
+
+        let due = Duration::from_secs_f64(self.transferred as f64 / self.rate as f64);
+        if let Some(wait) = due.checked_sub(self.started.elapsed()) {
+            thread::sleep(wait);
+        }
+    }
+
+    // Small enough chunks that a slow rate is still a steady trickle, about
+    // ten a second
+    fn chunk(&self, len: usize) -> usize {
+        len.min((self.rate / 10).max(1).try_into().unwrap_or(usize::MAX))
+    }
+}
+
+/// Wraps a body being downloaded or uploaded so it is read no faster than
+/// --limit-rate allows.
+pub struct Reader<R> {
+    inner: R,
+    limiter: Option<Limiter>,
+}
+
+impl<R: Read> Reader<R> {
+    /// Limits `inner` to `rate` bytes per second, if given.
+    pub fn new(inner: R, rate: Option<u64>) -> Reader<R> {
+        let limiter = rate.map(|rate| Limiter {
+            rate,
+            started: Instant::now(),
+            transferred: 0,
+        });
+
+        Reader { inner, limiter }
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let Some(limiter) = &mut self.limiter else {
+            return self.inner.read(buf);
+        };
+
+        let len = limiter.chunk(buf.len());
+        let read = self.inner.read(&mut buf[..len])?;
+        limiter.pace(read);
+
+        Ok(read)
+    }
+}
+
+/// Spaces the starts of requests for --rate, the same interval apart however
+/// long each takes. It is shared by every transfer, and retries count too.
+pub struct Pacer {
+    interval: Duration,
+    last: Mutex<Option<Instant>>,
+}
+
+impl Pacer {
+    /// Lets a request start every `interval`.
+    pub fn new(interval: Duration) -> Pacer {
+        Pacer {
+            interval,
+            last: Mutex::new(None),
+        }
+    }
+
+    /// Waits until the next request may start. The lock is held while
+    /// waiting, so parallel transfers take their turns.
+    pub fn wait(&self) {
+        let Ok(mut last) = self.last.lock() else {
+            return;
+        };
+        if let Some(wait) =
+            last.and_then(|last| (last + self.interval).checked_duration_since(Instant::now()))
+        {
+            thread::sleep(wait);
+        }
+        *last = Some(Instant::now());
+    }
+}
+
+/// Parses a --rate, the number of requests per second, minute or hour like
+/// "10/s", "2/m" or "100/h". Without a unit it is per hour, as in curl.
+pub fn parse_request_rate(s: &str) -> Result<Duration, String> {
+    let invalid =
+        || format!("'{s}' is not a valid rate, use a number of requests like 10/s, 2/m or 100/h");
+
+    let (count, unit) = s.split_once('/').unwrap_or((s, "h"));
+    let period = match unit {
+        "s" => 1,
+        "m" => 60,
+        "h" => 3600,
+        _ => return Err(invalid()),
+    };
+    let count = count
+        .trim()
+        .parse::<u32>()
+        .ok()
+        .filter(|count| *count > 0)
+        .ok_or_else(invalid)?;
+
+    Ok(Duration::from_secs(period) / count)
+}
@@ -0,0 +1,98 @@
+use reqwest::{header::HeaderMap, Method, StatusCode, Version};
+use url::Url;
+
+/// A redirect that was followed.
+pub struct Hop {
+    pub version: Version,
+    pub status: StatusCode,
+    pub headers: HeaderMap,
+    pub from: Url,
+    pub to: Url,
+}
+
+/// Whether following a `status` redirect turns the request into a GET without
+/// a body. Like curl, 301 and 302 only rewrite POST while 303 rewrites every
+/// method except HEAD.
+pub fn switches_to_get(status: StatusCode, method: &Method) -> bool {
+    match status.as_u16() {
+        301 | 302 => method == Method::POST,
+        303 => method != Method::HEAD,
+        _ => false,
+    }
+}
+
+/// The Referer sent to `to` with `-e ";auto"`, never leaking an https URL to
+/// a plain http hop.
+pub fn referer(from: &Url, to: &Url) -> Option<String> {
+    if from.scheme() == "https" && to.scheme() != "https" {
+        return None;
+    }
+
+    let mut referer = from.clone();
+    referer.set_fragment(None);
+    let _ = referer.set_username("");
+    let _ = referer.set_password(None);
+
+    Some(referer.into())
+}
+
+/// Whether `to` is the same host and port as `from`, which decides if
+/// credentials may follow a redirect. Subdomains count as different hosts.
+pub fn same_host(from: &Url, to: &Url) -> bool {
+    let host = |url: &Url| url.host_str().map(str::to_ascii_lowercase);
+
+    host(from) == host(to) && from.port_or_known_default() == to.port_or_known_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn same_host_needs_host_and_port() {
+        let from = url("http://Example.com/a");
+        assert!(same_host(&from, &url("http://example.COM/b?c")));
+        assert!(same_host(&from, &url("http://user@example.com:80/")));
+        assert!(same_host(&url("https://h/"), &url("https://h:443/")));
+
+        assert!(!same_host(&from, &url("http://www.example.com/")));
+        assert!(!same_host(&from, &url("http://example.com:8080/")));
+        assert!(!same_host(&from, &url("https://example.com/")));
+        assert!(!same_host(
+            &url("http://127.0.0.1/"),
+            &url("http://localhost/")
+        ));
+    }
+
+    #[test]
+    fn referer_drops_credentials_and_fragment() {
+        assert_eq!(
+            referer(&url("https://u:p@h/a?q#f"), &url("https://i/")).as_deref(),
+            Some("https://h/a?q")
+        );
+        assert_eq!(
+            referer(&url("http://h/a"), &url("https://i/")).as_deref(),
+            Some("http://h/a")
+        );
+        assert_eq!(referer(&url("https://h/a"), &url("http://i/")), None);
+    }
+
+    #[test]
+    fn methods_switched_to_get() {
+        let switches = |status, method: &Method| {
+            switches_to_get(StatusCode::from_u16(status).unwrap(), method)
+        };
+        for status in [301, 302] {
+            assert!(switches(status, &Method::POST));
+            assert!(!switches(status, &Method::PUT));
+        }
+        assert!(switches(303, &Method::PUT));
+        assert!(!switches(303, &Method::HEAD));
+        assert!(!switches(307, &Method::POST));
+        assert!(!switches(308, &Method::POST));
+    }
+}
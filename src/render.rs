@@ -0,0 +1,197 @@
+use serde_json::Value;
+
+const KEY: &str = "\x1b[1;34m";
+const STRING: &str = "\x1b[32m";
+const NUMBER: &str = "\x1b[36m";
+const RESET: &str = "\x1b[0m";
+
+/// A --json-output choice.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Format {
+    Pretty,
+    Compact,
+    Raw,
+}
+
+/// How a body goes to stdout.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Rendering {
+    /// Byte for byte, as it arrived
+    Raw,
+    /// Parsed and written again, indented or on one line
+    Json { pretty: bool, color: bool },
+}
+
+/// Parses a --json-output value.
+pub fn parse_format(s: &str) -> Result<Format, String> {
+    match s.to_ascii_lowercase().as_str() {
+        "pretty" => Ok(Format::Pretty),
+        "compact" => Ok(Format::Compact),
+        "raw" => Ok(Format::Raw),
+        _ => Err(format!(
+            "'{s}' is not a valid JSON output, use pretty, compact or raw"
+        )),
+    }
+}
+
+/// Decides how a body is written to stdout. `json` is whether its
+/// Content-Type says JSON, `format` is --json-output and `no_color` is
+/// --no-color or NO_COLOR. Without --json-output JSON is pretty-printed on a
+/// terminal and passed through anywhere else, and it is only colored on a
+/// terminal.
+pub fn rendering(json: bool, format: Option<Format>, no_color: bool, terminal: bool) -> Rendering {
+    let pretty = match format {
+        _ if !json => return Rendering::Raw,
+        Some(Format::Pretty) => true,
+        Some(Format::Compact) => false,
+        Some(Format::Raw) => return Rendering::Raw,
+        None if terminal => true,
+        None => return Rendering::Raw,
+    };
+
+    Rendering::Json {
+        pretty,
+        color: terminal && !no_color,
+    }
+}
+
+/// Writes the JSON again, indented by two spaces when `pretty` and with
+/// keys, strings and numbers in ANSI colors when `color`.
+pub fn json(value: &Value, pretty: bool, color: bool) -> String {
+    match (pretty, color) {
+        (true, false) => format!("{value:#}"),
+        (false, false) => value.to_string(),
+        (_, true) => {
+            let mut out = String::new();
+            write(&mut out, value, pretty, 0);
+            out
+        }
+    }
+}
+
+// Lays the value out like serde_json does, with the colors around the tokens
+fn write(out: &mut String, value: &Value, pretty: bool, depth: usize) {
+    match value {
+        Value::String(_) => paint(out, STRING, &value.to_string()),
+        Value::Number(number) => paint(out, NUMBER, &number.to_string()),
+        Value::Array(items) if !items.is_empty() => {
+            out.push('[');
+            for (index, item) in items.iter().enumerate() {
+                separate(out, index, pretty, depth + 1);
+                write(out, item, pretty, depth + 1);
+            }
+            separate(out, 0, pretty, depth);
+            out.push(']');
+        }
+        Value::Object(members) if !members.is_empty() => {
+            out.push('{');
+            for (index, (key, item)) in members.iter().enumerate() {
+                separate(out, index, pretty, depth + 1);
+                paint(out, KEY, &Value::from(key.as_str()).to_string());
+                out.push_str(if pretty { ": " } else { ":" });
+                write(out, item, pretty, depth + 1);
+            }
+            separate(out, 0, pretty, depth);
+            out.push('}');
+        }
+        // true, false, null and the empty [] and {}
+        _ => out.push_str(&value.to_string()),
+    }
+}
+
+fn paint(out: &mut String, color: &str, token: &str) {
+    out.push_str(color);
+    out.push_str(token);
+    out.push_str(RESET);
+}
+
+// The comma before all but the first item, and the line break and indent when pretty
+fn separate(out: &mut String, index: usize, pretty: bool, depth: usize) {
+    if index > 0 {
+        out.push(',');
+    }
+    if pretty {
+        out.push('\n');
+        out.push_str(&"  ".repeat(depth));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rendering_table() {
+        use Format::*;
+
+        let json = |pretty, color| Rendering::Json { pretty, color };
+        #[rustfmt::skip]
+        let table = [
+            // json, format, no_color, terminal => rendering
+            (false, None,          false, true,  Rendering::Raw),
+            (false, Some(Pretty),  false, true,  Rendering::Raw),
+            (false, Some(Compact), false, false, Rendering::Raw),
+            (true,  None,          false, true,  json(true, true)),
+            (true,  None,          true,  true,  json(true, false)),
+            (true,  None,          false, false, Rendering::Raw),
+            (true,  Some(Pretty),  false, true,  json(true, true)),
+            (true,  Some(Pretty),  false, false, json(true, false)),
+            (true,  Some(Pretty),  true,  true,  json(true, false)),
+            (true,  Some(Compact), false, true,  json(false, true)),
+            (true,  Some(Compact), false, false, json(false, false)),
+            (true,  Some(Compact), true,  true,  json(false, false)),
+            (true,  Some(Raw),     false, true,  Rendering::Raw),
+            (true,  Some(Raw),     true,  false, Rendering::Raw),
+        ];
+        for (is_json, format, no_color, terminal, expected) in table {
+            assert_eq!(
+                rendering(is_json, format, no_color, terminal),
+                expected,
+                "json {is_json}, {format:?}, no_color {no_color}, terminal {terminal}"
+            );
+        }
+    }
+
+    #[test]
+    fn parse_format_values() {
+        assert_eq!(parse_format("Pretty"), Ok(Format::Pretty));
+        assert_eq!(parse_format("compact"), Ok(Format::Compact));
+        assert_eq!(parse_format("RAW"), Ok(Format::Raw));
+        assert!(parse_format("color").is_err());
+    }
+
+    #[test]
+    fn json_layout() {
+        let value: Value = serde_json::from_str(r#"{"b":[1,"x",{}],"a":null,"n":1.50}"#).unwrap();
+        assert_eq!(
+            json(&value, false, false),
+            r#"{"b":[1,"x",{}],"a":null,"n":1.50}"#
+        );
+        assert_eq!(
+            json(&value, true, false),
+            "{\n  \"b\": [\n    1,\n    \"x\",\n    {}\n  ],\n  \"a\": null,\n  \"n\": 1.50\n}"
+        );
+    }
+
+    #[test]
+    fn colors_wrap_the_tokens_alone() {
+        let value: Value = serde_json::from_str(r#"{"k":["s",2,true,[]]}"#).unwrap();
+        assert_eq!(
+            json(&value, false, true),
+            format!("{{{KEY}\"k\"{RESET}:[{STRING}\"s\"{RESET},{NUMBER}2{RESET},true,[]]}}")
+        );
+
+        // Without the colors it is laid out like the plain one
+        let strip = |s: String| {
+            [KEY, STRING, NUMBER, RESET]
+                .iter()
+                .fold(s, |s, code| s.replace(code, ""))
+        };
+        for pretty in [true, false] {
+            assert_eq!(
+                strip(json(&value, pretty, true)),
+                json(&value, pretty, false)
+            );
+        }
+    }
+}
@@ -0,0 +1,461 @@
+use crate::{doh::Doh, timing::Clock};
+use reqwest::dns::{Addrs, Name, Resolve, Resolving};
+use std::{
+    error::Error,
+    fmt::Display,
+    io,
+    net::{IpAddr, SocketAddr, ToSocketAddrs},
+    sync::{Arc, Mutex},
+};
+use url::{Host, Url};
+
+/// A host name that could not be resolved.
+#[derive(Debug)]
+pub struct ResolveError {
+    pub host: String,
+    source: io::Error,
+}
+
+impl Display for ResolveError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Could not resolve host: {}", self.host)
+    }
+}
+
+impl Error for ResolveError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        Some(&self.source)
+    }
+}
+
+/// A `--resolve` entry, `host:port:addr[,addr]...`, giving the addresses to
+/// connect to for a host and port instead of looking the host up.
+#[derive(Clone, Debug)]
+pub struct Override {
+    pub host: String,
+    pub port: u16,
+    pub addrs: Vec<IpAddr>,
+}
+
+impl Display for Override {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        let addrs: Vec<String> = self
+            .addrs
+            .iter()
+            .map(|addr| match addr {
+                IpAddr::V4(addr) => addr.to_string(),
+                IpAddr::V6(addr) => format!("[{addr}]"),
+            })
+            .collect();
+        write!(f, "{}:{}:{}", self.host, self.port, addrs.join(","))
+    }
+}
+
+/// Parses a `--resolve` entry. IPv6 addresses may be written in brackets.
+pub fn parse_override(s: &str) -> Result<Override, String> {
+    let mut parts = s.splitn(3, ':');
+    let (Some(host), Some(port), Some(addrs)) = (parts.next(), parts.next(), parts.next()) else {
+        return Err(format!(
+            "'{s}' is not a valid --resolve entry, use host:port:address"
+        ));
+    };
+
+    if host.is_empty() {
+        return Err(format!("'{s}' has no host name"));
+    }
+    if port == "*" {
+        return Err(format!(
+            "'{s}' has a wildcard port, which is not supported, give the port"
+        ));
+    }
+    let port = port
+        .parse()
+        .map_err(|_| format!("'{port}' in '{s}' is not a valid port"))?;
+    let addrs = addrs
+        .split(',')
+        .map(|addr| {
+            addr.trim()
+                .trim_start_matches('[')
+                .trim_end_matches(']')
+                .parse()
+                .map_err(|_| format!("'{addr}' in '{s}' is not an IP address"))
+        })
+        .collect::<Result<_, _>>()?;
+
+    Ok(Override {
+        host: host.to_ascii_lowercase(),
+        port,
+        addrs,
+    })
+}
+
+/// The address family `-4` or `-6` keeps to.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Family {
+    V4,
+    V6,
+}
+
+impl Family {
+    /// Whether `ip` is of this family.
+    pub fn contains(self, ip: IpAddr) -> bool {
+        match self {
+            Family::V4 => ip.is_ipv4(),
+            Family::V6 => ip.is_ipv6(),
+        }
+    }
+}
+
+impl Display for Family {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Family::V4 => write!(f, "IPv4"),
+            Family::V6 => write!(f, "IPv6"),
+        }
+    }
+}
+
+/// The addresses in `addrs` of `family` alone, failing like a lookup
+/// that found nothing when there are none.
+pub fn filter_family(
+    host: &str,
+    addrs: impl Iterator<Item = SocketAddr>,
+    family: Option<Family>,
+) -> Result<Vec<SocketAddr>, ResolveError> {
+    let addrs: Vec<SocketAddr> = addrs
+        .filter(|addr| family.is_none_or(|family| family.contains(addr.ip())))
+        .collect();
+    if addrs.is_empty() {
+        let reason = match family {
+            Some(family) => format!("{host} has no {family} address"),
+            None => format!("{host} has no address"),
+        };
+        return Err(ResolveError {
+            host: host.to_string(),
+            source: io::Error::new(io::ErrorKind::NotFound, reason),
+        });
+    }
+
+    Ok(addrs)
+}
+
+/// Resolves host names for the client, timing each lookup: `--resolve`
+/// entries first, then the DNS-over-HTTPS server if there is one, otherwise
+/// the system resolver. The host of the URL being fetched is looked up as
+/// the `--connect-to` rules say, and with a family only its addresses are
+/// connected to.
+pub struct Resolver {
+    clock: Arc<Clock>,
+    overrides: Vec<Override>,
+    rules: Vec<ConnectTo>,
+    // The host and port of the URL being fetched, none before there is one
+    target: Mutex<Option<(String, u16)>>,
+    family: Option<Family>,
+    doh: Option<Arc<Doh>>,
+}
+
+impl Resolver {
+    /// Resolves with `overrides` and `rules`, of which only those for the
+    /// URL [`Resolver::set_target`] gives are used, the first matching one
+    /// counting.
+    pub fn new(
+        clock: Arc<Clock>,
+        overrides: Vec<Override>,
+        rules: Vec<ConnectTo>,
+        family: Option<Family>,
+        doh: Option<Doh>,
+    ) -> Resolver {
+        Resolver {
+            clock,
+            overrides,
+            rules,
+            target: Mutex::new(None),
+            family,
+            doh: doh.map(Arc::new),
+        }
+    }
+
+    /// Sets the URL about to be fetched. The client asks for the addresses
+    /// of a host alone, so --resolve entries and --connect-to rules are
+    /// picked by its port.
+    pub fn set_target(&self, url: &Url) {
+        if let Ok(mut target) = self.target.lock() {
+            *target = url
+                .host_str()
+                .zip(url.port_or_known_default())
+                .map(|(host, port)| (host.to_ascii_lowercase(), port));
+        }
+    }
+
+    // The host to look up for `host`, the port to connect to, 0 for the one in the URL, and the
+    // port to pick --resolve entries by. A rule for the target sends both the target's host and
+    // the one a rewritten URL has instead to where it says
+    fn lookup(&self, host: &str) -> (String, u16, u16) {
+        let Some((target, port)) = self.target.lock().ok().and_then(|target| target.clone()) else {
+            return (host.to_string(), 0, 0);
+        };
+
+        match connect_to(&self.rules, &target, port) {
+            Some((to_host, to_port)) if host == target || host == to_host => (
+                to_host.trim_matches(['[', ']']).to_string(),
+                to_port,
+                to_port,
+            ),
+            _ => (host.to_string(), 0, port),
+        }
+    }
+}
+
+impl Resolve for Resolver {
+    fn resolve(&self, name: Name) -> Resolving {
+        let (clock, family, doh) = (self.clock.clone(), self.family, self.doh.clone());
+        let (host, port, override_port) = self.lookup(&name.as_str().to_ascii_lowercase());
+        let overridden = self
+            .overrides
+            .iter()
+            .filter(|entry| entry.port == override_port)
+            .find(|entry| entry.host.eq_ignore_ascii_case(&host))
+            .map(|entry| entry.addrs.clone());
+
+        Box::pin(async move {
+            // With port 0 the client puts in the port of the URL, and it keeps any other unless
+            // the URL gives its own, which is then rewritten to the rule's
+            if let Some(addrs) = overridden {
+                clock.resolved();
+                let addrs = addrs.into_iter().map(|ip| SocketAddr::new(ip, port));
+                let addrs = filter_family(&host, addrs, family)?;
+                return Ok(Box::new(addrs.into_iter()) as Addrs);
+            }
+
+            if let Some(doh) = doh {
+                let addrs = doh.lookup(&host).await?;
+                clock.resolved();
+                let addrs = addrs.into_iter().map(|ip| SocketAddr::new(ip, port));
+                let addrs = filter_family(&host, addrs, family)?;
+                return Ok(Box::new(addrs.into_iter()) as Addrs);
+            }
+
+            // getaddrinfo blocks, so keep it off the client's runtime thread
+            let lookup = host.clone();
+            let addrs =
+                tokio::task::spawn_blocking(move || (lookup.as_str(), port).to_socket_addrs())
+                    .await
+                    .map_err(io::Error::other)
+                    .and_then(|addrs| addrs)
+                    .map_err(|source| ResolveError {
+                        host: host.clone(),
+                        source,
+                    })?;
+            clock.resolved();
+            let addrs = filter_family(&host, addrs, family)?;
+
+            Ok(Box::new(addrs.into_iter()) as Addrs)
+        })
+    }
+}
+
+/// A `--connect-to` rule, `host:port:connect-host:connect-port`, sending
+/// connections for one host and port to another. An empty field matches any
+/// host or port, or keeps it.
+#[derive(Clone, Debug)]
+pub struct ConnectTo {
+    host: Option<String>,
+    port: Option<u16>,
+    to_host: Option<String>,
+    to_port: Option<u16>,
+}
+
+/// Parses a `--connect-to` rule. IPv6 addresses are written in brackets.
+pub fn parse_connect_to(s: &str) -> Result<ConnectTo, String> {
+    let invalid = || {
+        format!("'{s}' is not a valid --connect-to rule, use host:port:connect-host:connect-port")
+    };
+
+    let mut fields = Vec::new();
+    let mut rest = s;
+    for at in 0..4 {
+        // A bracketed IPv6 address holds colons of its own
+        let end = match rest.strip_prefix('[') {
+            Some(inner) if at % 2 == 0 => inner.find(']').ok_or_else(invalid)? + 2,
+            _ => rest.find(':').unwrap_or(rest.len()),
+        };
+        fields.push(&rest[..end]);
+        rest = &rest[end..];
+        if at < 3 {
+            rest = rest.strip_prefix(':').ok_or_else(invalid)?;
+        }
+    }
+    if !rest.is_empty() {
+        return Err(invalid());
+    }
+
+    let host = |field: &str| (!field.is_empty()).then(|| field.to_ascii_lowercase());
+    let port = |field: &str| {
+        if field.is_empty() {
+            return Ok(None);
+        }
+        field
+            .parse()
+            .map(Some)
+            .map_err(|_| format!("'{field}' in '{s}' is not a valid port"))
+    };
+
+    Ok(ConnectTo {
+        host: host(fields[0]),
+        port: port(fields[1])?,
+        to_host: host(fields[2]),
+        to_port: port(fields[3])?,
+    })
+}
+
+/// Where a connection for `host` and `port` goes instead, by the first of
+/// `rules` that matches them, if any does.
+pub fn connect_to(rules: &[ConnectTo], host: &str, port: u16) -> Option<(String, u16)> {
+    let host = host.to_ascii_lowercase();
+    let rule = rules.iter().find(|rule| {
+        rule.host.as_ref().is_none_or(|from| *from == host)
+            && rule.port.is_none_or(|from| from == port)
+    })?;
+
+    Some((
+        rule.to_host.clone().unwrap_or(host),
+        rule.to_port.unwrap_or(port),
+    ))
+}
+
+/// The URL to give the client for `url` when a rule sends its connections
+/// elsewhere in a way the resolver cannot, with the host or port of the
+/// connection in place of the URL's. The resolver is not asked about IP
+/// addresses, and the client connects to a port the URL gives whatever it
+/// resolves to. The Host header still has to name `url`, and the certificate
+/// of a server reached in place of an IP address is checked for the host the
+/// rule gives.
+pub fn rewrite(rules: &[ConnectTo], url: &Url) -> Option<Url> {
+    let (host, port) = (url.host_str()?, url.port_or_known_default()?);
+    let (to_host, to_port) = connect_to(rules, host, port)?;
+    let needed = match url.host()? {
+        Host::Domain(_) => url.port().is_some() && to_port != port,
+        _ => to_host != host.to_ascii_lowercase() || to_port != port,
+    };
+    if !needed {
+        return None;
+    }
+
+    let mut url = url.clone();
+    if !matches!(url.host(), Some(Host::Domain(_))) {
+        url.set_host(Some(&to_host)).ok()?;
+    }
+    url.set_port(Some(to_port)).ok()?;
+    Some(url)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rules(rules: &[&str]) -> Vec<ConnectTo> {
+        rules
+            .iter()
+            .map(|rule| parse_connect_to(rule).unwrap())
+            .collect()
+    }
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn connect_to_takes_the_first_matching_rule() {
+        let rules = rules(&[
+            "foo.test:80:bar.test:8080",
+            "foo.test::baz.test:",
+            "::any.test:9",
+        ]);
+        let to = |host, port| connect_to(&rules, host, port);
+
+        assert_eq!(to("FOO.test", 80), Some(("bar.test".to_string(), 8080)));
+        assert_eq!(to("foo.test", 443), Some(("baz.test".to_string(), 443)));
+        assert_eq!(to("other.test", 1), Some(("any.test".to_string(), 9)));
+    }
+
+    #[test]
+    fn connect_to_keeps_what_a_rule_leaves_empty() {
+        let rules = rules(&["foo.test:443::8443", "bar.test:443:[::1]:"]);
+
+        assert_eq!(
+            connect_to(&rules, "foo.test", 443),
+            Some(("foo.test".to_string(), 8443))
+        );
+        assert_eq!(
+            connect_to(&rules, "bar.test", 443),
+            Some(("[::1]".to_string(), 443))
+        );
+        assert_eq!(connect_to(&rules, "foo.test", 80), None);
+        assert_eq!(connect_to(&[], "foo.test", 80), None);
+    }
+
+    #[test]
+    fn parse_connect_to_rejects_malformed_rules() {
+        for rule in ["foo.test:80:bar.test", "a:b:c:d", "[::1:80::", "a:1:b:2:c"] {
+            assert!(parse_connect_to(rule).is_err(), "{rule}");
+        }
+    }
+
+    #[test]
+    fn rewrite_leaves_the_host_of_a_name_to_the_resolver() {
+        let rules = rules(&["foo.test:18471:bar.test:18471", "foo.test:81:bar.test:82"]);
+
+        assert_eq!(rewrite(&rules, &url("http://foo.test:18471/")), None);
+        assert_eq!(
+            rewrite(&rules, &url("http://foo.test:81/path")),
+            Some(url("http://foo.test:82/path"))
+        );
+    }
+
+    #[test]
+    fn rewrite_leaves_the_port_of_a_url_without_one_to_the_resolver() {
+        let rules = rules(&["foo.test:80:bar.test:8080"]);
+
+        assert_eq!(rewrite(&rules, &url("http://foo.test/")), None);
+    }
+
+    #[test]
+    fn rewrite_replaces_an_address_the_resolver_never_sees() {
+        let rules = rules(&["127.0.0.2:80:127.0.0.1:8080", "[::2]:443:bar.test:"]);
+
+        assert_eq!(
+            rewrite(&rules, &url("http://127.0.0.2/")),
+            Some(url("http://127.0.0.1:8080/"))
+        );
+        assert_eq!(
+            rewrite(&rules, &url("https://[::2]/")),
+            Some(url("https://bar.test/"))
+        );
+    }
+
+    #[test]
+    fn resolver_looks_up_where_the_rule_sends_the_target() {
+        let resolver = Resolver::new(
+            Clock::new(),
+            Vec::new(),
+            rules(&["foo.test:81:bar.test:82"]),
+            None,
+            None,
+        );
+        assert_eq!(resolver.lookup("foo.test"), ("foo.test".to_string(), 0, 0));
+
+        resolver.set_target(&url("http://FOO.test:81/"));
+        assert_eq!(
+            resolver.lookup("foo.test"),
+            ("bar.test".to_string(), 82, 82)
+        );
+        // A proxy is looked up as it is
+        assert_eq!(
+            resolver.lookup("proxy.test"),
+            ("proxy.test".to_string(), 0, 81)
+        );
+
+        resolver.set_target(&url("http://foo.test:80/"));
+        assert_eq!(resolver.lookup("foo.test"), ("foo.test".to_string(), 0, 80));
+    }
+}
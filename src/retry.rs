@@ -0,0 +1,333 @@
+use crate::{date::DateTime, direct::DirectError};
+use reqwest::{
+    header::{HeaderMap, RETRY_AFTER},
+    StatusCode,
+};
+use std::{
+    error::Error,
+    fmt::Display,
+    io,
+    time::{Duration, SystemTime},
+};
+
+/// How a transfer attempt failed.
+pub enum Failure {
+    Timeout,
+    Connect { refused: bool },
+    Status(StatusCode),
+    Other,
+}
+
+impl Failure {
+    pub fn from_error(e: &reqwest::Error) -> Failure {
+        if e.is_timeout() {
+            Failure::Timeout
+        } else if e.is_connect() {
+            Failure::Connect {
+                refused: io_error_kind(e) == Some(io::ErrorKind::ConnectionRefused),
+            }
+        } else {
+            Failure::Other
+        }
+    }
+
+    pub fn from_direct(e: &DirectError) -> Failure {
+        match e {
+            DirectError::ConnectTimeout(_) | DirectError::Timeout(_) => Failure::Timeout,
+            DirectError::Connect(e) => Failure::Connect {
+                refused: e.kind() == io::ErrorKind::ConnectionRefused,
+            },
+            DirectError::Resolve(_) | DirectError::Bind(_) | DirectError::Tls(..) => {
+                Failure::Connect { refused: false }
+            }
+            DirectError::Start(_) | DirectError::Http(_) | DirectError::Message(_) => {
+                Failure::Other
+            }
+        }
+    }
+}
+
+impl Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            Failure::Timeout => write!(f, "Timeout"),
+            Failure::Connect { refused: true } => write!(f, "Connection refused"),
+            Failure::Connect { refused: false } => write!(f, "Connection failure"),
+            Failure::Status(status) => write!(f, "HTTP error {}", status.as_u16()),
+            Failure::Other => write!(f, "Transfer failed"),
+        }
+    }
+}
+
+// The io::Error at the bottom of reqwest's error chain, if any
+fn io_error_kind(e: &reqwest::Error) -> Option<io::ErrorKind> {
+    let mut source = e.source();
+    while let Some(e) = source {
+        if let Some(e) = e.downcast_ref::<io::Error>() {
+            return Some(e.kind());
+        }
+        source = e.source();
+    }
+
+    None
+}
+
+/// The retry settings from the command line.
+pub struct Policy {
+    pub retries: u32,
+    pub all_errors: bool,
+    pub connrefused: bool,
+    pub delay: Option<Duration>,
+    pub max_time: Option<Duration>,
+}
+
+impl Policy {
+    /// Decides whether to retry after `failure` and how long to wait first.
+    ///
+    /// `retried` is the number of retries made so far and `elapsed` the time
+    /// since the first attempt started. A `Retry-After` delay from the server
+    /// replaces both the fixed --retry-delay and the exponential backoff, and
+    /// the wait is cut short so it never runs past --retry-max-time.
+    pub fn decide(
+        &self,
+        failure: &Failure,
+        retried: u32,
+        elapsed: Duration,
+        retry_after: Option<Duration>,
+    ) -> Option<Duration> {
+        if retried >= self.retries || !self.retries_on(failure) {
+            return None;
+        }
+
+        let delay = retry_after
+            .or(self.delay)
+            .unwrap_or_else(|| backoff(retried));
+
+        match self.max_time {
+            Some(max_time) if elapsed >= max_time => None,
+            Some(max_time) => Some(delay.min(max_time - elapsed)),
+            None => Some(delay),
+        }
+    }
+
+    fn retries_on(&self, failure: &Failure) -> bool {
+        match failure {
+            Failure::Timeout | Failure::Connect { refused: false } => true,
+            Failure::Connect { refused: true } => self.connrefused || self.all_errors,
+            // The statuses curl retries: 408, 429 and every 5xx
+            Failure::Status(status) => {
+                matches!(status.as_u16(), 408 | 429) || status.is_server_error() || self.all_errors
+            }
+            Failure::Other => self.all_errors,
+        }
+    }
+}
+
+/// The delay before retry number `retry`, counting from zero. Like curl it
+/// starts at one second and doubles every time, up to ten minutes.
+fn backoff(retry: u32) -> Duration {
+    Duration::from_secs(1u64.checked_shl(retry).unwrap_or(u64::MAX).min(600))
+}
+
+/// The delay requested by a `Retry-After` header, given either in seconds or
+/// as an HTTP-date. Dates in the past mean retrying right away.
+pub fn retry_after(headers: &HeaderMap, now: SystemTime) -> Option<Duration> {
+    let value = headers.get(RETRY_AFTER)?.to_str().ok()?.trim();
+
+    if let Ok(secs) = value.parse::<u64>() {
+        return Some(Duration::from_secs(secs));
+    }
+
+    // HTTP-dates have second precision, so round up rather than retry early
+    let date = DateTime::parse_http_date(value)?.to_system_time();
+    let delay = date.duration_since(now).unwrap_or_default();
+    Some(Duration::from_secs(
+        delay.as_secs() + u64::from(delay.subsec_nanos() > 0),
+    ))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use reqwest::header::HeaderValue;
+
+    fn policy(retries: u32) -> Policy {
+        Policy {
+            retries,
+            all_errors: false,
+            connrefused: false,
+            delay: None,
+            max_time: None,
+        }
+    }
+
+    fn status(code: u16) -> Failure {
+        Failure::Status(StatusCode::from_u16(code).unwrap())
+    }
+
+    #[test]
+    fn retries_transient_failures_only() {
+        let policy = policy(3);
+        for failure in [
+            Failure::Timeout,
+            Failure::Connect { refused: false },
+            status(408),
+            status(429),
+            status(500),
+            status(503),
+        ] {
+            assert!(
+                policy.decide(&failure, 0, Duration::ZERO, None).is_some(),
+                "{failure}"
+            );
+        }
+        for failure in [
+            Failure::Connect { refused: true },
+            status(404),
+            status(400),
+            Failure::Other,
+        ] {
+            assert!(
+                policy.decide(&failure, 0, Duration::ZERO, None).is_none(),
+                "{failure}"
+            );
+        }
+    }
+
+    #[test]
+    fn connrefused_and_all_errors_widen_retries() {
+        let refused = Failure::Connect { refused: true };
+        let connrefused = Policy {
+            connrefused: true,
+            ..policy(1)
+        };
+        assert!(connrefused
+            .decide(&refused, 0, Duration::ZERO, None)
+            .is_some());
+        assert!(connrefused
+            .decide(&status(404), 0, Duration::ZERO, None)
+            .is_none());
+
+        let all_errors = Policy {
+            all_errors: true,
+            ..policy(1)
+        };
+        for failure in [refused, status(404), Failure::Other] {
+            assert!(
+                all_errors
+                    .decide(&failure, 0, Duration::ZERO, None)
+                    .is_some(),
+                "{failure}"
+            );
+        }
+    }
+
+    #[test]
+    fn stops_after_the_retry_count() {
+        let policy = policy(2);
+        assert!(policy
+            .decide(&Failure::Timeout, 1, Duration::ZERO, None)
+            .is_some());
+        assert!(policy
+            .decide(&Failure::Timeout, 2, Duration::ZERO, None)
+            .is_none());
+        assert!(self::policy(0)
+            .decide(&Failure::Timeout, 0, Duration::ZERO, None)
+            .is_none());
+    }
+
+    #[test]
+    fn backoff_doubles_up_to_ten_minutes() {
+        let policy = policy(u32::MAX);
+        let delays: Vec<_> = [0, 1, 2, 9, 10, 63, 64, 1000]
+            .into_iter()
+            .map(|retried| policy.decide(&Failure::Timeout, retried, Duration::ZERO, None))
+            .collect();
+        let secs = |secs| Some(Duration::from_secs(secs));
+        assert_eq!(
+            delays,
+            [
+                secs(1),
+                secs(2),
+                secs(4),
+                secs(512),
+                secs(600),
+                secs(600),
+                secs(600),
+                secs(600)
+            ]
+        );
+    }
+
+    #[test]
+    fn retry_after_replaces_delay_and_backoff() {
+        let policy = Policy {
+            delay: Some(Duration::from_secs(5)),
+            ..policy(3)
+        };
+        assert_eq!(
+            policy.decide(&Failure::Timeout, 2, Duration::ZERO, None),
+            Some(Duration::from_secs(5))
+        );
+        assert_eq!(
+            policy.decide(
+                &status(429),
+                2,
+                Duration::ZERO,
+                Some(Duration::from_secs(30))
+            ),
+            Some(Duration::from_secs(30))
+        );
+    }
+
+    #[test]
+    fn max_time_caps_the_wait() {
+        let policy = Policy {
+            max_time: Some(Duration::from_secs(10)),
+            ..policy(5)
+        };
+        assert_eq!(
+            policy.decide(&Failure::Timeout, 4, Duration::from_secs(3), None),
+            Some(Duration::from_secs(7))
+        );
+        assert_eq!(
+            policy.decide(&Failure::Timeout, 0, Duration::from_secs(3), None),
+            Some(Duration::from_secs(1))
+        );
+        assert!(policy
+            .decide(&Failure::Timeout, 0, Duration::from_secs(10), None)
+            .is_none());
+    }
+
+    #[test]
+    fn retry_after_header() {
+        let now = SystemTime::UNIX_EPOCH + Duration::from_secs(784111777);
+        let header = |value| {
+            let mut headers = HeaderMap::new();
+            headers.insert(RETRY_AFTER, HeaderValue::from_static(value));
+            headers
+        };
+
+        assert_eq!(
+            retry_after(&header("120"), now),
+            Some(Duration::from_secs(120))
+        );
+        assert_eq!(
+            retry_after(&header("Sun, 06 Nov 1994 08:49:57 GMT"), now),
+            Some(Duration::from_secs(20))
+        );
+        assert_eq!(
+            retry_after(
+                &header("Sun, 06 Nov 1994 08:49:37 GMT"),
+                now - Duration::from_millis(1)
+            ),
+            Some(Duration::from_secs(1))
+        );
+        assert_eq!(
+            retry_after(&header("Sat, 05 Nov 1994 08:49:37 GMT"), now),
+            Some(Duration::ZERO)
+        );
+        assert_eq!(retry_after(&header("soon"), now), None);
+        assert_eq!(retry_after(&HeaderMap::new(), now), None);
+    }
+}
@@ -0,0 +1,343 @@
+use rustls::{
+    client::{
+        ClientSessionMemoryCache, ClientSessionStore, Tls12ClientSessionValue,
+        Tls13ClientSessionValue,
+    },
+    pki_types::ServerName,
+    NamedGroup,
+};
+use std::{
+    fmt::Debug,
+    future::Future,
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::{Duration, Instant},
+};
+use tower_layer::Layer;
+use tower_service::Service;
+
+/// How long each phase of a transfer took, counted from the start of the
+/// attempt like curl's `time_*` variables.
+#[derive(Clone, Copy, Default)]
+pub struct Timings {
+    pub namelookup: Duration,
+    pub connect: Duration,
+    pub appconnect: Duration,
+    pub pretransfer: Duration,
+    pub starttransfer: Duration,
+    pub redirect: Duration,
+    pub total: Duration,
+}
+
+impl Timings {
+    /// Prints how long each phase took to stderr, for --timings.
+    pub fn print(&self, tls: bool) {
+        let phase = |name: &str, from: Duration, to: Duration| {
+            eprintln!(
+                "{name:<18}{:>10.3} ms",
+                to.saturating_sub(from).as_secs_f64() * 1000.0
+            );
+        };
+
+        phase("DNS lookup", Duration::ZERO, self.namelookup);
+        if tls {
+            phase("TCP connect", self.namelookup, self.connect);
+            phase("TLS handshake", self.connect, self.appconnect);
+        } else {
+            phase("TCP connect", self.namelookup, self.connect);
+        }
+        if self.redirect > Duration::ZERO {
+            phase("Redirects", Duration::ZERO, self.redirect);
+        }
+        phase("Waiting (TTFB)", self.pretransfer, self.starttransfer);
+        phase("Content transfer", self.starttransfer, self.total);
+        phase("Total", Duration::ZERO, self.total);
+    }
+}
+
+#[derive(Clone, Copy)]
+struct Marks {
+    started: Instant,
+    resolved: Option<Instant>,
+    // When the TLS handshake of the connection being made started, right after its TCP connect
+    handshake: Option<Instant>,
+    connected: Option<Instant>,
+    sent: Option<Instant>,
+    received: Option<Instant>,
+    redirected: Option<Instant>,
+}
+
+/// Records when a transfer passes each phase. The client's resolver and
+/// connector report into it while a request is sent, so it is shared.
+pub struct Clock(Mutex<Marks>);
+
+impl Clock {
+    pub fn new() -> Arc<Clock> {
+        Arc::new(Clock(Mutex::new(Marks::new())))
+    }
+
+    /// Starts timing a new attempt, forgetting everything before it.
+    pub fn start(&self) {
+        self.mark(|marks| *marks = Marks::new());
+    }
+
+    /// Marks a request about to be sent. Every request but the first follows a redirect.
+    pub fn sending(&self) {
+        self.mark(|marks| {
+            let now = Instant::now();
+            if marks.sent.is_some() {
+                marks.redirected = Some(now);
+            }
+            marks.sent = Some(now);
+        });
+    }
+
+    /// Marks the host name as resolved.
+    pub fn resolved(&self) {
+        self.mark(|marks| marks.resolved = Some(Instant::now()));
+    }
+
+    /// Marks a new connection about to be made.
+    pub fn connecting(&self) {
+        // A handshake before belongs to a connection made earlier
+        self.mark(|marks| marks.handshake = None);
+    }
+
+    /// Marks the connection being made as ready, after any TLS handshake.
+    pub fn connected(&self) {
+        self.mark(|marks| marks.connected = Some(Instant::now()));
+    }
+
+    /// Marks the response headers as received.
+    pub fn received(&self) {
+        self.mark(|marks| marks.received = Some(Instant::now()));
+    }
+
+    fn mark(&self, f: impl FnOnce(&mut Marks)) {
+        if let Ok(mut marks) = self.0.lock() {
+            f(&mut marks);
+        }
+    }
+
+    /// The phases of the attempt so far, ending now.
+    ///
+    /// The client does the TCP connect and the TLS handshake in one go, so for
+    /// HTTPS the TCP connect ends when the handshake is seen to start.
+    /// Phases that never happened, like the lookup of an IP address or the
+    /// connect for a pooled connection that was reused, take no time.
+    pub fn timings(&self, tls: bool) -> Timings {
+        let now = Instant::now();
+        let marks = match self.0.lock() {
+            Ok(marks) => *marks,
+            Err(_) => return Timings::default(),
+        };
+        let since = |mark: Option<Instant>| {
+            mark.map_or(Duration::ZERO, |mark| mark.duration_since(marks.started))
+        };
+
+        let namelookup = since(marks.resolved);
+        let connected = since(marks.connected).max(namelookup);
+        let connect = match marks.handshake.filter(|_| tls && marks.connected.is_some()) {
+            Some(handshake) => since(Some(handshake)).clamp(namelookup, connected),
+            None => connected,
+        };
+
+        Timings {
+            namelookup,
+            connect,
+            appconnect: if tls { connected } else { Duration::ZERO },
+            pretransfer: since(marks.sent).max(connected),
+            starttransfer: since(marks.received),
+            redirect: since(marks.redirected),
+            total: now.duration_since(marks.started),
+        }
+    }
+}
+
+impl Marks {
+    fn new() -> Marks {
+        Marks {
+            started: Instant::now(),
+            resolved: None,
+            handshake: None,
+            connected: None,
+            sent: None,
+            received: None,
+            redirected: None,
+        }
+    }
+}
+
+/// The TLS session cache of the client, which also marks on the clock when
+/// each handshake starts. rustls looks for a session to resume as soon as it
+/// is handed the connected socket, which is the one sign of the TCP connect
+/// ending the client gives.
+pub struct Handshakes {
+    clock: Arc<Clock>,
+    cache: ClientSessionMemoryCache,
+}
+
+impl Handshakes {
+    pub fn new(clock: Arc<Clock>) -> Handshakes {
+        Handshakes {
+            clock,
+            // As many sessions as rustls keeps by default
+            cache: ClientSessionMemoryCache::new(256),
+        }
+    }
+
+    // Only the first handshake on a connection counts, one through an HTTPS proxy comes later
+    fn started(&self) {
+        self.clock.mark(|marks| {
+            marks.handshake.get_or_insert_with(Instant::now);
+        });
+    }
+}
+
+impl Debug for Handshakes {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.debug_struct("Handshakes")
+            .field("cache", &self.cache)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ClientSessionStore for Handshakes {
+    fn set_kx_hint(&self, server_name: ServerName<'static>, group: NamedGroup) {
+        self.cache.set_kx_hint(server_name, group);
+    }
+
+    fn kx_hint(&self, server_name: &ServerName<'_>) -> Option<NamedGroup> {
+        self.cache.kx_hint(server_name)
+    }
+
+    fn set_tls12_session(&self, server_name: ServerName<'static>, value: Tls12ClientSessionValue) {
+        self.cache.set_tls12_session(server_name, value);
+    }
+
+    fn tls12_session(&self, server_name: &ServerName<'_>) -> Option<Tls12ClientSessionValue> {
+        self.started();
+        self.cache.tls12_session(server_name)
+    }
+
+    fn remove_tls12_session(&self, server_name: &ServerName<'static>) {
+        self.cache.remove_tls12_session(server_name);
+    }
+
+    fn insert_tls13_ticket(
+        &self,
+        server_name: ServerName<'static>,
+        value: Tls13ClientSessionValue,
+    ) {
+        self.cache.insert_tls13_ticket(server_name, value);
+    }
+
+    // Looked up first when a handshake starts
+    fn take_tls13_ticket(
+        &self,
+        server_name: &ServerName<'static>,
+    ) -> Option<Tls13ClientSessionValue> {
+        self.started();
+        self.cache.take_tls13_ticket(server_name)
+    }
+}
+
+/// Wraps the client's connector to mark when each new connection is ready.
+#[derive(Clone)]
+pub struct ConnectLayer(pub Arc<Clock>);
+
+impl<S> Layer<S> for ConnectLayer {
+    type Service = Connect<S>;
+
+    fn layer(&self, inner: S) -> Connect<S> {
+        Connect {
+            inner,
+            clock: self.0.clone(),
+        }
+    }
+}
+
+#[derive(Clone)]
+pub struct Connect<S> {
+    inner: S,
+    clock: Arc<Clock>,
+}
+
+impl<S, R> Service<R> for Connect<S>
+where
+    S: Service<R>,
+    S::Future: Send + 'static,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = Pin<Box<dyn Future<Output = Result<S::Response, S::Error>> + Send>>;
+
+    fn poll_ready(&mut self, cx: &mut Context) -> Poll<Result<(), S::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: R) -> Self::Future {
+        self.clock.connecting();
+        let connecting = self.inner.call(request);
+        let clock = self.clock.clone();
+
+        Box::pin(async move {
+            let conn = connecting.await?;
+            clock.connected();
+
+            Ok(conn)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::thread::sleep;
+
+    fn connect(clock: &Arc<Clock>, handshakes: &Handshakes) {
+        let name = ServerName::try_from("example.com").unwrap();
+        clock.mark(|marks| marks.handshake = None);
+        sleep(Duration::from_millis(20));
+        assert!(handshakes.take_tls13_ticket(&name).is_none());
+        sleep(Duration::from_millis(20));
+        // Through a proxy, the second one is to the origin inside the tunnel
+        assert!(handshakes.tls12_session(&name).is_none());
+        clock.mark(|marks| marks.connected = Some(Instant::now()));
+    }
+
+    #[test]
+    fn tcp_connect_ends_where_the_handshake_starts() {
+        let clock = Clock::new();
+        let handshakes = Handshakes::new(clock.clone());
+        connect(&clock, &handshakes);
+
+        let timings = clock.timings(true);
+        assert!(timings.connect >= Duration::from_millis(20));
+        assert!(timings.appconnect >= timings.connect + Duration::from_millis(20));
+    }
+
+    #[test]
+    fn tcp_connect_is_the_whole_connect_without_tls() {
+        let clock = Clock::new();
+        let handshakes = Handshakes::new(clock.clone());
+        connect(&clock, &handshakes);
+
+        let timings = clock.timings(false);
+        assert!(timings.connect >= Duration::from_millis(40));
+        assert_eq!(timings.appconnect, Duration::ZERO);
+    }
+
+    #[test]
+    fn reused_connection_takes_no_connect_time() {
+        let clock = Clock::new();
+        let handshakes = Handshakes::new(clock.clone());
+        connect(&clock, &handshakes);
+        clock.start();
+
+        let timings = clock.timings(true);
+        assert_eq!(timings.connect, Duration::ZERO);
+        assert_eq!(timings.appconnect, Duration::ZERO);
+    }
+}
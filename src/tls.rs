@@ -0,0 +1,1159 @@
+use crate::auth::{self, AuthError};
+use openssl::{
+    asn1::Asn1Time,
+    nid::Nid,
+    pkcs12::Pkcs12,
+    pkey::{PKey, Private},
+    sha::sha256,
+    x509::X509,
+};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        ResolvesClientCert, WebPkiServerVerifier,
+    },
+    crypto::{
+        self,
+        cipher::{
+            AeadKey, Iv, KeyBlockShape, MessageDecrypter, MessageEncrypter, Tls12AeadAlgorithm,
+            Tls13AeadAlgorithm, UnsupportedOperationError,
+        },
+        CryptoProvider,
+    },
+    pki_types::{CertificateDer, PrivateKeyDer, PrivatePkcs8KeyDer, ServerName, UnixTime},
+    server::ParsedCertificate,
+    sign::CertifiedKey,
+    CertificateError, CipherSuite, CipherSuiteCommon, ClientConfig, ClientConnection,
+    ConnectionTrafficSecrets, DigitallySignedStruct, OtherError, RootCertStore, SignatureScheme,
+    SupportedCipherSuite, SupportedProtocolVersion, Tls12CipherSuite, Tls13CipherSuite,
+};
+use std::{
+    cell::RefCell,
+    fmt::Display,
+    io,
+    path::{Path, PathBuf},
+    str::FromStr,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+#[derive(Debug)]
+pub enum TlsError {
+    Read(PathBuf, io::Error),
+    NoCertificates(PathBuf),
+    InvalidCertificate(PathBuf, String),
+    InvalidKey(PathBuf, String),
+    NoKey(PathBuf),
+    KeyMismatch(PathBuf, PathBuf),
+    WrongPassword(PathBuf),
+    Passphrase(AuthError),
+    NoSystemCertificates,
+    Config(String),
+    InvalidPin(String),
+    InvalidPublicKey(PathBuf),
+}
+
+impl Display for TlsError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TlsError::Read(path, e) => write!(f, "Unable to read {}: {e}", path.display()),
+            TlsError::NoCertificates(path) => {
+                write!(f, "No PEM certificates found in {}", path.display())
+            }
+            TlsError::InvalidCertificate(path, e) => {
+                write!(f, "Invalid certificate in {}: {e}", path.display())
+            }
+            TlsError::InvalidKey(path, e) => {
+                write!(f, "Invalid private key in {}: {e}", path.display())
+            }
+            TlsError::NoKey(path) => write!(
+                f,
+                "No private key in {}, give the file that holds it with --key",
+                path.display()
+            ),
+            TlsError::KeyMismatch(cert, key) => write!(
+                f,
+                "The private key in {} does not belong to the certificate in {}",
+                key.display(),
+                cert.display()
+            ),
+            TlsError::WrongPassword(path) => {
+                write!(f, "Wrong password for {}", path.display())
+            }
+            TlsError::Passphrase(e) => write!(f, "{e}"),
+            TlsError::NoSystemCertificates => write!(
+                f,
+                "No CA certificates found on this system, give them with --cacert"
+            ),
+            TlsError::Config(e) => write!(f, "{e}"),
+            TlsError::InvalidPin(pin) => write!(
+                f,
+                "Invalid pin '{pin}', use sha256// and the base64 SHA-256 hash of a public key"
+            ),
+            TlsError::InvalidPublicKey(path) => {
+                write!(f, "No PEM or DER public key in {}", path.display())
+            }
+        }
+    }
+}
+
+/// The format of a `--cert` file.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum CertType {
+    Pem,
+    Der,
+    P12,
+}
+
+impl FromStr for CertType {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<CertType, String> {
+        match s.to_ascii_uppercase().as_str() {
+            "PEM" => Ok(CertType::Pem),
+            "DER" => Ok(CertType::Der),
+            "P12" => Ok(CertType::P12),
+            _ => Err(format!(
+                "Unknown certificate type '{s}', use PEM, DER or P12"
+            )),
+        }
+    }
+}
+
+/// A TLS protocol version curl can be limited to.
+#[derive(Clone, Copy, Debug, PartialEq, PartialOrd)]
+pub enum TlsVersion {
+    Tls12,
+    Tls13,
+}
+
+impl FromStr for TlsVersion {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TlsVersion, String> {
+        match s {
+            "1.2" => Ok(TlsVersion::Tls12),
+            "1.3" => Ok(TlsVersion::Tls13),
+            _ => Err(format!(
+                "'{s}' is not a supported TLS version, use 1.2 or 1.3"
+            )),
+        }
+    }
+}
+
+impl From<TlsVersion> for reqwest::tls::Version {
+    fn from(version: TlsVersion) -> reqwest::tls::Version {
+        match version {
+            TlsVersion::Tls12 => reqwest::tls::Version::TLS_1_2,
+            TlsVersion::Tls13 => reqwest::tls::Version::TLS_1_3,
+        }
+    }
+}
+
+impl Display for TlsVersion {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            TlsVersion::Tls12 => write!(f, "TLSv1.2"),
+            TlsVersion::Tls13 => write!(f, "TLSv1.3"),
+        }
+    }
+}
+
+/// A client certificate chain and its private key.
+pub struct ClientIdentity {
+    chain: Vec<CertificateDer<'static>>,
+    key: PrivateKeyDer<'static>,
+}
+
+/// How the client's TLS connections to servers are made and checked.
+pub struct TlsOptions {
+    /// Whether to accept any certificate, as -k does.
+    pub insecure: bool,
+    /// The CA certificates that servers' certificates must chain to.
+    pub roots: Vec<CertificateDer<'static>>,
+    pub identity: Option<ClientIdentity>,
+    pub min_version: Option<TlsVersion>,
+    pub max_version: Option<TlsVersion>,
+    /// SHA-256 hashes of the public keys a server's certificate must have
+    /// one of, when not empty.
+    pub pins: Vec<Vec<u8>>,
+    /// The TLS 1.2 cipher suites to offer, in order, instead of all of them.
+    pub tls12_ciphers: Option<CipherList>,
+    /// The TLS 1.3 cipher suites to offer, in order, instead of all of them.
+    pub tls13_ciphers: Option<CipherList>,
+    /// The protocols to offer the server through ALPN, such as `h2`.
+    pub alpn: Vec<Vec<u8>>,
+    /// How HTTPS proxies are checked instead, when there are any.
+    pub proxy: Option<ProxyTrust>,
+    /// Where the handshakes note what they agree on, for -v.
+    pub negotiated: Option<Arc<Negotiated>>,
+}
+
+/// How the certificates of HTTPS proxies are checked, apart from servers'.
+/// The client makes connections to proxies with the same settings, so a
+/// proxy is known by its name.
+pub struct ProxyTrust {
+    /// The hosts of the HTTPS proxies.
+    pub hosts: Vec<String>,
+    /// Whether to accept any certificate, as --proxy-insecure does.
+    pub insecure: bool,
+    /// The CA certificates that proxies' certificates must chain to.
+    pub roots: Vec<CertificateDer<'static>>,
+}
+
+/// Cipher suites for one TLS version, from a `--ciphers` or
+/// `--tls13-ciphers` list.
+#[derive(Clone, Debug)]
+pub struct CipherList(Vec<CipherSuite>);
+
+impl CipherList {
+    /// The TLS 1.2 suites named in `list`, separated by `:`, `,` or spaces.
+    pub fn tls12(list: &str) -> Result<CipherList, String> {
+        CipherList::parse(list, TlsVersion::Tls12)
+    }
+
+    /// The TLS 1.3 suites named in `list`, separated by `:`, `,` or spaces.
+    pub fn tls13(list: &str) -> Result<CipherList, String> {
+        CipherList::parse(list, TlsVersion::Tls13)
+    }
+
+    fn parse(list: &str, version: TlsVersion) -> Result<CipherList, String> {
+        let supported: Vec<CipherSuite> = noted_suites()
+            .iter()
+            .filter(|suite| suite_version(suite) == version)
+            .map(SupportedCipherSuite::suite)
+            .collect();
+
+        let suites = list
+            .split([':', ',', ' '])
+            .filter(|name| !name.is_empty())
+            .map(|name| {
+                supported
+                    .iter()
+                    .find(|suite| suite_name(**suite).eq_ignore_ascii_case(name))
+                    .copied()
+                    .ok_or_else(|| {
+                        let names: Vec<String> =
+                            supported.iter().map(|suite| suite_name(*suite)).collect();
+                        format!(
+                            "'{name}' is not a supported {version} cipher suite, use one of {}",
+                            names.join(", ")
+                        )
+                    })
+            })
+            .collect::<Result<Vec<_>, _>>()?;
+
+        if suites.is_empty() {
+            return Err(format!("No {version} cipher suites given"));
+        }
+        Ok(CipherList(suites))
+    }
+}
+
+/// The server's public key is not one of the `--pinnedpubkey` ones.
+#[derive(Debug)]
+pub struct PinMismatch;
+
+impl Display for PinMismatch {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "its public key does not match --pinnedpubkey")
+    }
+}
+
+impl std::error::Error for PinMismatch {}
+
+/// Why the certificate of an HTTPS proxy, rather than a server's, failed
+/// verification.
+#[derive(Debug)]
+pub struct ProxyCertificate(pub CertificateError);
+
+impl Display for ProxyCertificate {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "the certificate of the proxy is invalid: {}", self.0)
+    }
+}
+
+impl std::error::Error for ProxyCertificate {}
+
+/// The SHA-256 hashes of the public keys a `--pinnedpubkey` value allows:
+/// `sha256//` hashes in base64 separated by `;`, or a PEM or DER public key
+/// file.
+pub fn pinned_keys(pin: &str) -> Result<Vec<Vec<u8>>, TlsError> {
+    if pin.starts_with("sha256//") {
+        return pin
+            .split(';')
+            .map(|hash| {
+                hash.trim()
+                    .strip_prefix("sha256//")
+                    .and_then(|hash| openssl::base64::decode_block(hash).ok())
+                    .filter(|hash| hash.len() == 32)
+                    .ok_or_else(|| TlsError::InvalidPin(hash.to_string()))
+            })
+            .collect();
+    }
+
+    let path = PathBuf::from(pin);
+    let contents = std::fs::read(&path).map_err(|e| TlsError::Read(path.clone(), e))?;
+    let key = PKey::public_key_from_pem(&contents)
+        .or_else(|_| PKey::public_key_from_der(&contents))
+        .and_then(|key| key.public_key_to_der())
+        .map_err(|_| TlsError::InvalidPublicKey(path))?;
+
+    Ok(vec![sha256(&key).to_vec()])
+}
+
+/// The certificates in the PEM file at `path`, which holds one or more.
+pub fn read_pem_certificates(path: &Path) -> Result<Vec<X509>, TlsError> {
+    let pem = std::fs::read(path).map_err(|e| TlsError::Read(path.to_path_buf(), e))?;
+    let certs = X509::stack_from_pem(&pem)
+        .map_err(|e| TlsError::InvalidCertificate(path.to_path_buf(), e.to_string()))?;
+
+    if certs.is_empty() {
+        return Err(TlsError::NoCertificates(path.to_path_buf()));
+    }
+    Ok(certs)
+}
+
+/// The CA certificates to verify servers with, from the `--cacert` file and
+/// every `.pem` and `.crt` file in the `--capath` directory. Like curl, they
+/// replace the system's certificates, which are used without either.
+pub fn ca_certificates(
+    cacert: Option<&Path>,
+    capath: Option<&Path>,
+) -> Result<Vec<CertificateDer<'static>>, TlsError> {
+    if cacert.is_none() && capath.is_none() {
+        return Ok(system_certificates());
+    }
+
+    let mut files = Vec::new();
+    files.extend(cacert.map(Path::to_path_buf));
+
+    if let Some(dir) = capath {
+        let entries = std::fs::read_dir(dir).map_err(|e| TlsError::Read(dir.to_path_buf(), e))?;
+        let mut found: Vec<PathBuf> = entries
+            .flatten()
+            .map(|entry| entry.path())
+            .filter(|path| {
+                path.extension()
+                    .is_some_and(|extension| extension == "pem" || extension == "crt")
+            })
+            .collect();
+        if found.is_empty() {
+            return Err(TlsError::NoCertificates(dir.to_path_buf()));
+        }
+        found.sort();
+        files.extend(found);
+    }
+
+    let mut certificates = Vec::new();
+    for path in files {
+        for cert in read_pem_certificates(&path)? {
+            let der = cert
+                .to_der()
+                .map_err(|e| TlsError::InvalidCertificate(path.clone(), e.to_string()))?;
+            certificates.push(CertificateDer::from(der));
+        }
+    }
+
+    Ok(certificates)
+}
+
+// The CA certificates OpenSSL would trust, from the bundle file or else the hashed directory it
+// finds on this system. Ones that do not parse are left out
+fn system_certificates() -> Vec<CertificateDer<'static>> {
+    let probe = openssl_probe::probe();
+    let files: Vec<PathBuf> = match probe.cert_file {
+        Some(file) => vec![file],
+        None => probe
+            .cert_dir
+            .iter()
+            .filter_map(|dir| std::fs::read_dir(dir).ok())
+            .flat_map(|entries| entries.flatten().map(|entry| entry.path()))
+            .collect(),
+    };
+
+    files
+        .iter()
+        .filter_map(|path| std::fs::read(path).ok())
+        .filter_map(|pem| X509::stack_from_pem(&pem).ok())
+        .flatten()
+        .filter_map(|cert| cert.to_der().ok())
+        .map(CertificateDer::from)
+        .collect()
+}
+
+/// The client certificate to authenticate with, from a `--cert` value,
+/// `file[:password]` where `\:` is a colon in the file name. A PEM or DER
+/// certificate needs its private key, in the same file or the `--key` one.
+/// Without a password, `pass` or one typed at the prompt unlocks the key.
+pub fn identity(
+    cert: &str,
+    key: Option<&Path>,
+    cert_type: CertType,
+    pass: Option<&str>,
+) -> Result<ClientIdentity, TlsError> {
+    let (path, password) = split_cert(cert);
+    let password = password.or_else(|| pass.map(str::to_string));
+    let contents = std::fs::read(&path).map_err(|e| TlsError::Read(path.clone(), e))?;
+    let invalid = |e: String| TlsError::InvalidCertificate(path.clone(), e);
+
+    if cert_type == CertType::P12 {
+        let pkcs12 = Pkcs12::from_der(&contents).map_err(|e| invalid(e.to_string()))?;
+        // Files without a password are common, so only ask when the empty one does not work
+        let password = match password {
+            Some(password) => password,
+            None if pkcs12.parse2("").is_ok() => String::new(),
+            None => auth::passphrase(&path.display().to_string()).map_err(TlsError::Passphrase)?,
+        };
+        let parsed = pkcs12
+            .parse2(&password)
+            .map_err(|_| TlsError::WrongPassword(path.clone()))?;
+        let (Some(cert), Some(key)) = (parsed.cert, parsed.pkey) else {
+            return Err(TlsError::NoKey(path));
+        };
+
+        let mut chain = vec![cert];
+        chain.extend(parsed.ca.into_iter().flatten());
+        return client_identity(&path, &path, &chain, &key);
+    }
+
+    let chain = match cert_type {
+        CertType::Der => vec![X509::from_der(&contents).map_err(|e| invalid(e.to_string()))?],
+        _ => read_pem_certificates(&path)?,
+    };
+    let key_path = key.map_or(path.clone(), Path::to_path_buf);
+    let key_contents = match key {
+        Some(_) => std::fs::read(&key_path).map_err(|e| TlsError::Read(key_path.clone(), e))?,
+        // A DER file holds the certificate alone
+        None if cert_type == CertType::Der => return Err(TlsError::NoKey(path)),
+        None => contents.clone(),
+    };
+    let private_key = private_key(&key_path, &key_contents, password)?;
+
+    client_identity(&path, &key_path, &chain, &private_key)
+}
+
+// Checks that the key belongs to the first certificate of the chain and converts both to DER
+fn client_identity(
+    path: &Path,
+    key_path: &Path,
+    chain: &[X509],
+    key: &PKey<Private>,
+) -> Result<ClientIdentity, TlsError> {
+    let invalid = |e: openssl::error::ErrorStack| {
+        TlsError::InvalidCertificate(path.to_path_buf(), e.to_string())
+    };
+
+    let leaf_key = chain[0].public_key().map_err(invalid)?;
+    if !leaf_key.public_eq(key) {
+        return Err(TlsError::KeyMismatch(
+            path.to_path_buf(),
+            key_path.to_path_buf(),
+        ));
+    }
+
+    let chain = chain
+        .iter()
+        .map(|cert| cert.to_der().map(CertificateDer::from))
+        .collect::<Result<_, _>>()
+        .map_err(invalid)?;
+    let key = key
+        .private_key_to_pkcs8()
+        .map_err(|e| TlsError::InvalidKey(key_path.to_path_buf(), e.to_string()))?;
+
+    Ok(ClientIdentity {
+        chain,
+        key: PrivateKeyDer::Pkcs8(PrivatePkcs8KeyDer::from(key)),
+    })
+}
+
+// Splits "file:password" at the first colon that is not escaped as "\:"
+fn split_cert(value: &str) -> (PathBuf, Option<String>) {
+    let mut file = String::new();
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        match c {
+            '\\' if chars.as_str().starts_with(':') => {
+                file.push(':');
+                chars.next();
+            }
+            ':' => return (PathBuf::from(file), Some(chars.as_str().to_string())),
+            c => file.push(c),
+        }
+    }
+
+    (PathBuf::from(file), None)
+}
+
+// The private key in a PEM or DER file, asking for the pass phrase of an encrypted PEM one when
+// none is given
+fn private_key(
+    path: &Path,
+    contents: &[u8],
+    password: Option<String>,
+) -> Result<PKey<Private>, TlsError> {
+    let text = String::from_utf8_lossy(contents);
+    if !text.contains("-----BEGIN") {
+        return PKey::private_key_from_der(contents)
+            .map_err(|e| TlsError::InvalidKey(path.to_path_buf(), e.to_string()));
+    }
+    if !text.contains("PRIVATE KEY-----") {
+        return Err(TlsError::NoKey(path.to_path_buf()));
+    }
+
+    if text.contains("ENCRYPTED") {
+        let password = match password {
+            Some(password) => password,
+            None => auth::passphrase(&path.display().to_string()).map_err(TlsError::Passphrase)?,
+        };
+        PKey::private_key_from_pem_passphrase(contents, password.as_bytes())
+            .map_err(|_| TlsError::WrongPassword(path.to_path_buf()))
+    } else {
+        PKey::private_key_from_pem(contents)
+            .map_err(|e| TlsError::InvalidKey(path.to_path_buf(), e.to_string()))
+    }
+}
+
+/// The TLS settings for connections to servers.
+pub fn client_config(options: TlsOptions) -> Result<ClientConfig, TlsError> {
+    // A list for a version replaces its suites, the other version keeps all of its own
+    let mut cipher_suites = Vec::new();
+    for (version, list) in [
+        (TlsVersion::Tls13, &options.tls13_ciphers),
+        (TlsVersion::Tls12, &options.tls12_ciphers),
+    ] {
+        let suites = noted_suites()
+            .iter()
+            .filter(|suite| suite_version(suite) == version);
+        match list {
+            Some(CipherList(list)) => cipher_suites.extend(
+                list.iter()
+                    .filter_map(|name| suites.clone().find(|suite| suite.suite() == *name)),
+            ),
+            None => cipher_suites.extend(suites),
+        }
+    }
+    let provider = Arc::new(CryptoProvider {
+        cipher_suites,
+        ..crypto::ring::default_provider()
+    });
+
+    let versions: Vec<&'static SupportedProtocolVersion> = [
+        (TlsVersion::Tls12, &rustls::version::TLS12),
+        (TlsVersion::Tls13, &rustls::version::TLS13),
+    ]
+    .into_iter()
+    .filter(|(version, _)| {
+        options.min_version.is_none_or(|min| *version >= min)
+            && options.max_version.is_none_or(|max| *version <= max)
+    })
+    .map(|(_, supported)| supported)
+    .collect();
+
+    let proxy = options
+        .proxy
+        .map(|proxy| {
+            let trust = Trust::new(proxy.insecure, proxy.roots, &provider)?;
+            let hosts = proxy
+                .hosts
+                .iter()
+                .map(|host| host.trim_matches(['[', ']']).to_ascii_lowercase())
+                .collect();
+            Ok::<_, TlsError>((hosts, trust))
+        })
+        .transpose()?;
+    let verifier = Arc::new(Verifier {
+        servers: Trust::new(options.insecure, options.roots, &provider)?,
+        proxy,
+        pins: options.pins,
+        algorithms: provider.signature_verification_algorithms,
+        negotiated: options.negotiated,
+    });
+
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_protocol_versions(&versions)
+        .map_err(|e| TlsError::Config(e.to_string()))?
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+    let mut config = match options.identity {
+        Some(identity) => {
+            let key = provider
+                .key_provider
+                .load_private_key(identity.key)
+                .map_err(|e| TlsError::Config(e.to_string()))?;
+            builder.with_client_cert_resolver(Arc::new(ClientCert(Arc::new(CertifiedKey::new(
+                identity.chain,
+                key,
+            )))))
+        }
+        None => builder.with_no_client_auth(),
+    };
+    config.alpn_protocols = options.alpn;
+
+    Ok(config)
+}
+
+/// What the handshakes of one client agreed on. The client does not tell,
+/// so the cipher suites note it themselves when a handshake derives its
+/// keys, for the client whose certificate check ran on that thread last.
+/// The client drives every connection on a thread of its own.
+#[derive(Debug, Default)]
+pub struct Negotiated(Mutex<Option<(TlsVersion, CipherSuite)>>);
+
+impl Negotiated {
+    /// The protocol version and cipher suite of the last TLS connection
+    /// made, if any, e.g. ("TLSv1.3", "TLS_AES_256_GCM_SHA384").
+    pub fn last(&self) -> Option<(TlsVersion, String)> {
+        let (version, suite) = (*self.0.lock().ok()?)?;
+        Some((version, suite_name(suite)))
+    }
+}
+
+/// The protocol version and cipher suite of the connection a response came
+/// over, kept with responses of the client.
+#[derive(Clone, Debug)]
+pub struct Agreed(pub TlsVersion, pub String);
+
+/// The protocol version and cipher suite `connection` agreed on, once its
+/// handshake is done.
+pub fn negotiated_by(connection: &ClientConnection) -> Option<(TlsVersion, String)> {
+    let suite = connection.negotiated_cipher_suite()?;
+    Some((suite_version(&suite), suite_name(suite.suite())))
+}
+
+// The IANA name of a cipher suite, which OpenSSL uses too for TLS 1.3 ones
+fn suite_name(suite: CipherSuite) -> String {
+    let name = format!("{suite:?}");
+    match name.strip_prefix("TLS13_") {
+        Some(rest) => format!("TLS_{rest}"),
+        None => name,
+    }
+}
+
+fn suite_version(suite: &SupportedCipherSuite) -> TlsVersion {
+    match suite {
+        SupportedCipherSuite::Tls12(_) => TlsVersion::Tls12,
+        SupportedCipherSuite::Tls13(_) => TlsVersion::Tls13,
+    }
+}
+
+// The CA certificates to check certificates against, none with -k
+#[derive(Debug)]
+struct Trust {
+    webpki: Option<Arc<WebPkiServerVerifier>>,
+    trusted: Vec<CertificateDer<'static>>,
+}
+
+impl Trust {
+    // -k skips the certificate checks, so it needs no CA certificates
+    fn new(
+        insecure: bool,
+        roots: Vec<CertificateDer<'static>>,
+        provider: &Arc<CryptoProvider>,
+    ) -> Result<Trust, TlsError> {
+        if insecure {
+            return Ok(Trust {
+                webpki: None,
+                trusted: Vec::new(),
+            });
+        }
+
+        let mut store = RootCertStore::empty();
+        store.add_parsable_certificates(roots.iter().cloned());
+        if store.is_empty() {
+            return Err(TlsError::NoSystemCertificates);
+        }
+        let webpki = WebPkiServerVerifier::builder_with_provider(Arc::new(store), provider.clone())
+            .build()
+            .map_err(|_| TlsError::NoSystemCertificates)?;
+
+        Ok(Trust {
+            webpki: Some(webpki),
+            trusted: roots,
+        })
+    }
+
+    // Checks the certificate chain and that it is for the server, unless -k is given
+    fn verify_chain(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        let Some(webpki) = &self.webpki else {
+            return Ok(ServerCertVerified::assertion());
+        };
+
+        // A self-signed certificate given with --cacert is trusted as it is, like OpenSSL does,
+        // though it is a CA certificate that cannot be checked as a server's one
+        if self.trusted.contains(end_entity) {
+            let expired = X509::from_der(end_entity)
+                .ok()
+                .zip(Asn1Time::from_unix(now.as_secs() as i64).ok())
+                .is_none_or(|(cert, now)| cert.not_before() > now || cert.not_after() < now);
+            if expired {
+                return Err(CertificateError::Expired.into());
+            }
+            let parsed = ParsedCertificate::try_from(end_entity)?;
+            rustls::client::verify_server_name(&parsed, server_name).or_else(|e| {
+                common_name_matches(end_entity, server_name)
+                    .then_some(())
+                    .ok_or(e)
+            })?;
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        // Any other self-signed certificate fails for the one reason that matters, rather than
+        // for being a CA certificate
+        let self_signed = X509::from_der(end_entity).is_ok_and(|cert| {
+            cert.issuer_name()
+                .try_cmp(cert.subject_name())
+                .is_ok_and(|order| order.is_eq())
+        });
+        if self_signed {
+            return Err(CertificateError::UnknownIssuer.into());
+        }
+
+        webpki.verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+    }
+}
+
+// Checks server certificates against the CA certificates unless -k is given, and proxy ones
+// against the proxy CA certificates unless --proxy-insecure is. A server with the name of a proxy
+// is checked like the proxy. The handshake signatures are always checked
+#[derive(Debug)]
+struct Verifier {
+    servers: Trust,
+    proxy: Option<(Vec<String>, Trust)>,
+    pins: Vec<Vec<u8>>,
+    algorithms: crypto::WebPkiSupportedAlgorithms,
+    negotiated: Option<Arc<Negotiated>>,
+}
+
+impl ServerCertVerifier for Verifier {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        // The keys of this handshake are derived on this thread once the certificate is checked
+        if let Some(negotiated) = &self.negotiated {
+            NOTING.with(|noting| noting.replace(Some(negotiated.clone())));
+        }
+
+        let name = server_name.to_str().to_ascii_lowercase();
+        if let Some((_, trust)) = self
+            .proxy
+            .as_ref()
+            .filter(|(hosts, _)| hosts.contains(&name))
+        {
+            return trust
+                .verify_chain(end_entity, intermediates, server_name, ocsp_response, now)
+                .map_err(|e| match e {
+                    rustls::Error::InvalidCertificate(e) => {
+                        CertificateError::Other(OtherError(Arc::new(ProxyCertificate(e)))).into()
+                    }
+                    e => e,
+                });
+        }
+
+        let verified = self.servers.verify_chain(
+            end_entity,
+            intermediates,
+            server_name,
+            ocsp_response,
+            now,
+        )?;
+
+        // Pins are checked even with -k, like curl does, but only for servers
+        if !self.pins.is_empty() {
+            let pinned = X509::from_der(end_entity)
+                .and_then(|cert| cert.public_key())
+                .and_then(|key| key.public_key_to_der())
+                .is_ok_and(|key| self.pins.iter().any(|pin| pin[..] == sha256(&key)));
+            if !pinned {
+                return Err(CertificateError::Other(OtherError(Arc::new(PinMismatch))).into());
+            }
+        }
+
+        Ok(verified)
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+// Always sends the one client certificate. Unlike the client's own, it does not parse the
+// certificate again to check the key, which fails for older certificates OpenSSL accepts
+#[derive(Debug)]
+struct ClientCert(Arc<CertifiedKey>);
+
+impl ResolvesClientCert for ClientCert {
+    fn resolve(&self, _: &[&[u8]], _: &[SignatureScheme]) -> Option<Arc<CertifiedKey>> {
+        Some(self.0.clone())
+    }
+
+    fn has_certs(&self) -> bool {
+        true
+    }
+}
+
+// Whether a certificate without subject alternative names has the server's name as its common
+// name, which OpenSSL falls back to
+fn common_name_matches(cert: &CertificateDer<'_>, server_name: &ServerName<'_>) -> bool {
+    let Ok(cert) = X509::from_der(cert) else {
+        return false;
+    };
+    if cert.subject_alt_names().is_some() {
+        return false;
+    }
+
+    let name = server_name.to_str();
+    cert.subject_name()
+        .entries_by_nid(Nid::COMMONNAME)
+        .filter_map(|entry| entry.data().to_string().ok())
+        .any(|common_name| common_name.eq_ignore_ascii_case(&name))
+}
+
+thread_local! {
+    // Where the cipher suites note the handshakes on this thread
+    static NOTING: RefCell<Option<Arc<Negotiated>>> = const { RefCell::new(None) };
+}
+
+fn note(version: TlsVersion, suite: CipherSuite) {
+    NOTING.with(|noting| {
+        if let Some(mut negotiated) = noting.borrow().as_ref().and_then(|n| n.0.lock().ok()) {
+            *negotiated = Some((version, suite));
+        }
+    });
+}
+
+// The cipher suites of the ring provider, each with its encryption wrapped to note when it is used.
+// rustls takes them by static reference, so they are made once, in statics of their own
+fn noted_suites() -> &'static [SupportedCipherSuite] {
+    static AEADS13: OnceLock<Vec<Noted<dyn Tls13AeadAlgorithm>>> = OnceLock::new();
+    static AEADS12: OnceLock<Vec<Noted<dyn Tls12AeadAlgorithm>>> = OnceLock::new();
+    static TLS13: OnceLock<Vec<Tls13CipherSuite>> = OnceLock::new();
+    static TLS12: OnceLock<Vec<Tls12CipherSuite>> = OnceLock::new();
+    static SUITES: OnceLock<Vec<SupportedCipherSuite>> = OnceLock::new();
+
+    SUITES.get_or_init(|| {
+        let suites = crypto::ring::ALL_CIPHER_SUITES;
+        let tls13: Vec<&Tls13CipherSuite> = suites.iter().filter_map(|s| s.tls13()).collect();
+        let tls12: Vec<&Tls12CipherSuite> = suites
+            .iter()
+            .filter_map(|suite| match suite {
+                SupportedCipherSuite::Tls12(tls12) => Some(*tls12),
+                SupportedCipherSuite::Tls13(_) => None,
+            })
+            .collect();
+
+        let aeads13 = AEADS13.get_or_init(|| {
+            tls13
+                .iter()
+                .map(|suite| Noted {
+                    inner: suite.aead_alg,
+                    version: TlsVersion::Tls13,
+                    suite: suite.common.suite,
+                })
+                .collect()
+        });
+        let aeads12 = AEADS12.get_or_init(|| {
+            tls12
+                .iter()
+                .map(|suite| Noted {
+                    inner: suite.aead_alg,
+                    version: TlsVersion::Tls12,
+                    suite: suite.common.suite,
+                })
+                .collect()
+        });
+        let noted13 = TLS13.get_or_init(|| {
+            tls13
+                .iter()
+                .zip(aeads13)
+                .map(|(suite, aead_alg)| Tls13CipherSuite {
+                    common: copy_common(&suite.common),
+                    hkdf_provider: suite.hkdf_provider,
+                    aead_alg,
+                    quic: suite.quic,
+                })
+                .collect()
+        });
+        let noted12 = TLS12.get_or_init(|| {
+            tls12
+                .iter()
+                .zip(aeads12)
+                .map(|(suite, aead_alg)| Tls12CipherSuite {
+                    common: copy_common(&suite.common),
+                    prf_provider: suite.prf_provider,
+                    kx: suite.kx,
+                    sign: suite.sign,
+                    aead_alg,
+                })
+                .collect()
+        });
+
+        noted13
+            .iter()
+            .map(SupportedCipherSuite::Tls13)
+            .chain(noted12.iter().map(SupportedCipherSuite::Tls12))
+            .collect()
+    })
+}
+
+fn copy_common(common: &CipherSuiteCommon) -> CipherSuiteCommon {
+    CipherSuiteCommon {
+        suite: common.suite,
+        hash_provider: common.hash_provider,
+        confidentiality_limit: common.confidentiality_limit,
+    }
+}
+
+struct Noted<A: ?Sized + 'static> {
+    inner: &'static A,
+    version: TlsVersion,
+    suite: CipherSuite,
+}
+
+impl Tls13AeadAlgorithm for Noted<dyn Tls13AeadAlgorithm> {
+    fn encrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageEncrypter> {
+        note(self.version, self.suite);
+        self.inner.encrypter(key, iv)
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: Iv) -> Box<dyn MessageDecrypter> {
+        self.inner.decrypter(key, iv)
+    }
+
+    fn key_len(&self) -> usize {
+        self.inner.key_len()
+    }
+
+    fn extract_keys(
+        &self,
+        key: AeadKey,
+        iv: Iv,
+    ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        self.inner.extract_keys(key, iv)
+    }
+
+    fn fips(&self) -> bool {
+        self.inner.fips()
+    }
+}
+
+impl Tls12AeadAlgorithm for Noted<dyn Tls12AeadAlgorithm> {
+    fn encrypter(&self, key: AeadKey, iv: &[u8], extra: &[u8]) -> Box<dyn MessageEncrypter> {
+        note(self.version, self.suite);
+        self.inner.encrypter(key, iv, extra)
+    }
+
+    fn decrypter(&self, key: AeadKey, iv: &[u8]) -> Box<dyn MessageDecrypter> {
+        self.inner.decrypter(key, iv)
+    }
+
+    fn key_block_shape(&self) -> KeyBlockShape {
+        self.inner.key_block_shape()
+    }
+
+    fn extract_keys(
+        &self,
+        key: AeadKey,
+        iv: &[u8],
+        explicit: &[u8],
+    ) -> Result<ConnectionTrafficSecrets, UnsupportedOperationError> {
+        self.inner.extract_keys(key, iv, explicit)
+    }
+
+    fn fips(&self) -> bool {
+        self.inner.fips()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use openssl::{
+        bn::BigNum,
+        ec::{EcGroup, EcKey},
+        hash::MessageDigest,
+        x509::{extension::SubjectAlternativeName, X509Name},
+    };
+
+    // A self-signed certificate for the common name `name`, also with it as a subject
+    // alternative name when `san` is set
+    fn certificate(name: &str, san: bool) -> (CertificateDer<'static>, Vec<u8>) {
+        let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+        let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+        let mut subject = X509Name::builder().unwrap();
+        subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+        let subject = subject.build();
+
+        let mut cert = X509::builder().unwrap();
+        cert.set_version(2).unwrap();
+        let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+        cert.set_serial_number(&serial).unwrap();
+        cert.set_subject_name(&subject).unwrap();
+        cert.set_issuer_name(&subject).unwrap();
+        cert.set_pubkey(&key).unwrap();
+        cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+            .unwrap();
+        cert.set_not_after(&Asn1Time::days_from_now(30).unwrap())
+            .unwrap();
+        if san {
+            let names = SubjectAlternativeName::new()
+                .dns(name)
+                .build(&cert.x509v3_context(None, None))
+                .unwrap();
+            cert.append_extension(names).unwrap();
+        }
+        cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+        let pin = sha256(&key.public_key_to_der().unwrap()).to_vec();
+        (CertificateDer::from(cert.build().to_der().unwrap()), pin)
+    }
+
+    fn verifier(
+        insecure: bool,
+        roots: &[&CertificateDer<'static>],
+        pins: Vec<Vec<u8>>,
+    ) -> Verifier {
+        let provider = Arc::new(crypto::ring::default_provider());
+        let roots = roots.iter().map(|root| (*root).clone()).collect();
+        Verifier {
+            servers: Trust::new(insecure, roots, &provider).unwrap(),
+            proxy: None,
+            pins,
+            algorithms: provider.signature_verification_algorithms,
+            negotiated: None,
+        }
+    }
+
+    fn verify(
+        verifier: &Verifier,
+        cert: &CertificateDer<'_>,
+        name: &str,
+    ) -> Result<(), rustls::Error> {
+        let name = ServerName::try_from(name.to_string()).unwrap();
+        verifier
+            .verify_server_cert(cert, &[], &name, &[], UnixTime::now())
+            .map(|_| ())
+    }
+
+    #[test]
+    fn self_signed_cacert_is_trusted() {
+        let (cert, _) = certificate("localhost", true);
+
+        assert!(verify(&verifier(false, &[&cert], Vec::new()), &cert, "localhost").is_ok());
+        assert!(matches!(
+            verify(&verifier(false, &[&cert], Vec::new()), &cert, "other.test"),
+            Err(rustls::Error::InvalidCertificate(_))
+        ));
+    }
+
+    #[test]
+    fn untrusted_self_signed_certificate_has_an_unknown_issuer() {
+        let (cert, _) = certificate("localhost", true);
+        let (other, _) = certificate("localhost", true);
+
+        assert!(matches!(
+            verify(&verifier(false, &[&other], Vec::new()), &cert, "localhost"),
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::UnknownIssuer
+            ))
+        ));
+    }
+
+    #[test]
+    fn common_name_stands_in_for_missing_alternative_names() {
+        let (cert, _) = certificate("localhost", false);
+
+        assert!(verify(&verifier(false, &[&cert], Vec::new()), &cert, "localhost").is_ok());
+        assert!(verify(&verifier(false, &[&cert], Vec::new()), &cert, "other.test").is_err());
+    }
+
+    #[test]
+    fn insecure_accepts_any_certificate() {
+        let (cert, _) = certificate("localhost", true);
+
+        assert!(verify(&verifier(true, &[], Vec::new()), &cert, "other.test").is_ok());
+    }
+
+    #[test]
+    fn pins_are_checked_even_when_insecure() {
+        let (cert, pin) = certificate("localhost", true);
+        let (_, other) = certificate("localhost", true);
+
+        assert!(verify(
+            &verifier(false, &[&cert], vec![pin.clone()]),
+            &cert,
+            "localhost"
+        )
+        .is_ok());
+        for insecure in [false, true] {
+            let verifier = verifier(insecure, &[&cert], vec![other.clone()]);
+            let e = verify(&verifier, &cert, "localhost").unwrap_err();
+            let rustls::Error::InvalidCertificate(CertificateError::Other(OtherError(e))) = e
+            else {
+                panic!("{e:?}");
+            };
+            assert!(e.downcast_ref::<PinMismatch>().is_some());
+        }
+    }
+
+    #[test]
+    fn handshakes_are_noted_for_the_client_checked_last_on_the_thread() {
+        let (cert, _) = certificate("localhost", true);
+        let (first, second) = (
+            Arc::new(Negotiated::default()),
+            Arc::new(Negotiated::default()),
+        );
+        let mut verifier = verifier(true, &[], Vec::new());
+
+        verifier.negotiated = Some(first.clone());
+        verify(&verifier, &cert, "localhost").unwrap();
+        note(
+            TlsVersion::Tls12,
+            CipherSuite::TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256,
+        );
+        verifier.negotiated = Some(second.clone());
+        verify(&verifier, &cert, "localhost").unwrap();
+        note(TlsVersion::Tls13, CipherSuite::TLS13_AES_128_GCM_SHA256);
+
+        assert_eq!(
+            first.last(),
+            Some((
+                TlsVersion::Tls12,
+                "TLS_ECDHE_ECDSA_WITH_AES_128_GCM_SHA256".to_string()
+            ))
+        );
+        assert_eq!(
+            second.last(),
+            Some((TlsVersion::Tls13, "TLS_AES_128_GCM_SHA256".to_string()))
+        );
+    }
+}
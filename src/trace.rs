@@ -0,0 +1,163 @@
+use crate::date::DateTime;
+use std::{
+    fs::File,
+    io::{self, Read, Write},
+    path::Path,
+    sync::{Mutex, OnceLock},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+/// What a traced chunk of data is.
+#[derive(Clone, Copy)]
+pub enum Kind {
+    SendHeader,
+    SendData,
+    RecvHeader,
+    RecvData,
+}
+
+struct Trace {
+    out: Box<dyn Write + Send>,
+    ascii: bool,
+    time: bool,
+}
+
+static TRACE: OnceLock<Mutex<Trace>> = OnceLock::new();
+
+/// Starts tracing to `path`, or to stderr for "-". With `ascii` data is
+/// dumped as text only, and with `time` every line gets a timestamp.
+pub fn init(path: &Path, ascii: bool, time: bool) -> io::Result<()> {
+    let out: Box<dyn Write + Send> = if path == Path::new("-") {
+        Box::new(io::stderr())
+    } else {
+        Box::new(io::BufWriter::new(File::create(path)?))
+    };
+
+    let _ = TRACE.set(Mutex::new(Trace { out, ascii, time }));
+    Ok(())
+}
+
+pub fn enabled() -> bool {
+    TRACE.get().is_some()
+}
+
+/// Logs an informational line, like the connection a request was sent on.
+pub fn info(text: &str) {
+    with_trace(|trace| {
+        let prefix = trace.timestamp();
+        writeln!(trace.out, "{prefix}== Info: {text}")
+    });
+}
+
+/// Dumps `data` in curl's trace format: a hex and text dump 16 bytes per
+/// line, or with --trace-ascii just the text, 64 bytes or one line at a time.
+pub fn dump(kind: Kind, data: &[u8]) {
+    if data.is_empty() {
+        return;
+    }
+
+    with_trace(|trace| {
+        let name = match kind {
+            Kind::SendHeader => "=> Send header",
+            Kind::SendData => "=> Send data",
+            Kind::RecvHeader => "<= Recv header",
+            Kind::RecvData => "<= Recv data",
+        };
+        let prefix = trace.timestamp();
+        writeln!(
+            trace.out,
+            "{prefix}{name}, {} bytes (0x{:x})",
+            data.len(),
+            data.len()
+        )?;
+
+        let width = if trace.ascii { 64 } else { 16 };
+        let mut start = 0;
+        while start < data.len() {
+            let mut line = format!("{prefix}{start:04x}: ");
+
+            if !trace.ascii {
+                for i in start..start + width {
+                    match data.get(i) {
+                        Some(byte) => line.push_str(&format!("{byte:02x} ")),
+                        None => line.push_str("   "),
+                    }
+                }
+            }
+
+            let mut end = start;
+            while end < data.len() && end - start < width {
+                // Text lines end at CRLF rather than at the full width
+                if trace.ascii && data[end..].starts_with(b"\r\n") {
+                    end += 2;
+                    break;
+                }
+
+                let byte = data[end];
+                line.push(if byte.is_ascii_graphic() || byte == b' ' {
+                    byte as char
+                } else {
+                    '.'
+                });
+                end += 1;
+            }
+
+            writeln!(trace.out, "{line}")?;
+            start = if trace.ascii { end } else { start + width };
+        }
+
+        Ok(())
+    });
+}
+
+// Trace output is best effort and never fails the transfer
+fn with_trace(f: impl FnOnce(&mut Trace) -> io::Result<()>) {
+    let Some(trace) = TRACE.get() else {
+        return;
+    };
+    let Ok(mut trace) = trace.lock() else {
+        return;
+    };
+
+    let _ = f(&mut trace).and_then(|_| trace.out.flush());
+}
+
+impl Trace {
+    // HH:MM:SS.micro in UTC with --trace-time
+    fn timestamp(&self) -> String {
+        if !self.time {
+            return String::new();
+        }
+
+        let now = SystemTime::now();
+        let micros = now
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.subsec_micros());
+        let time = DateTime::from_system_time(now);
+
+        format!(
+            "{:02}:{:02}:{:02}.{micros:06} ",
+            time.hour, time.minute, time.second
+        )
+    }
+}
+
+/// Wraps a response body so everything read from it is traced as received data.
+pub struct Reader<R> {
+    inner: R,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Reader<R> {
+        Reader { inner }
+    }
+}
+
+impl<R: Read> Read for Reader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let read = self.inner.read(buf)?;
+        dump(Kind::RecvData, &buf[..read]);
+
+        Ok(read)
+    }
+}
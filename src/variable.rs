@@ -0,0 +1,316 @@
+use percent_encoding::{utf8_percent_encode, AsciiSet, NON_ALPHANUMERIC};
+use std::{collections::HashMap, ffi::OsString, fmt::Display, io::Read};
+
+/// Everything but the characters RFC 3986 leaves unreserved, for
+/// `{{name:url}}` and --data-urlencode.
+pub const URL_ENCODE_SET: &AsciiSet = &NON_ALPHANUMERIC
+    .remove(b'-')
+    .remove(b'_')
+    .remove(b'.')
+    .remove(b'~');
+
+/// A --variable that cannot be defined, or an `--expand-` value using one
+/// that is not.
+#[derive(Debug)]
+pub struct VariableError(String);
+
+impl Display for VariableError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+/// Defines the --variable values, and turns every `--expand-<option> value`
+/// into `--<option> value` with the `{{name}}` in the value replaced.
+/// `--expand-url` gives a URL. The variables are all defined first, so the
+/// ones on the command line can be used in -K files, which come before it.
+///
+/// A variable is `name=value`, `name@file` with "-" for stdin, or
+/// `%NAME` for the environment variable, `%NAME=default` when it may be
+/// unset. Functions after the name change the value: `{{name:trim}}`,
+/// `{{name:json}}` for inside a JSON string, `{{name:url}}` to percent
+/// encode it and `{{name:b64}}`, in the order given. `\{{` is a literal
+/// `{{`.
+pub fn expand(args: Vec<OsString>) -> Result<Vec<OsString>, VariableError> {
+    let mut variables = HashMap::new();
+    let mut options = args
+        .iter()
+        .map(|arg| arg.to_string_lossy())
+        .take_while(|arg| arg != "--");
+    while let Some(arg) = options.next() {
+        if arg == "--variable" {
+            let definition = options
+                .next()
+                .ok_or_else(|| VariableError("--variable needs a value".to_string()))?;
+            define(&mut variables, &definition)?;
+        } else if let Some(definition) = arg.strip_prefix("--variable=") {
+            define(&mut variables, definition)?;
+        }
+    }
+
+    let mut expanded = Vec::with_capacity(args.len());
+    let mut args = args.into_iter();
+    while let Some(arg) = args.next() {
+        let Some(text) = arg.to_str() else {
+            expanded.push(arg);
+            continue;
+        };
+        if text == "--" {
+            expanded.push(arg);
+            expanded.extend(args);
+            break;
+        }
+        // The value of a --variable is passed over, it is not an option
+        if text == "--variable" {
+            expanded.push(arg);
+            expanded.extend(args.next());
+            continue;
+        }
+
+        let Some(option) = text.strip_prefix("--expand-") else {
+            expanded.push(arg);
+            continue;
+        };
+        let (option, value) = match option.split_once('=') {
+            Some((option, value)) => (option.to_string(), value.to_string()),
+            None => {
+                let value = args
+                    .next()
+                    .ok_or_else(|| VariableError(format!("{text} needs a value")))?;
+                (option.to_string(), value.to_string_lossy().into_owned())
+            }
+        };
+        let value = substitute(&value, &variables)?;
+        if option != "url" {
+            expanded.push(format!("--{option}").into());
+        }
+        expanded.push(value.into());
+    }
+
+    Ok(expanded)
+}
+
+fn define(variables: &mut HashMap<String, String>, definition: &str) -> Result<(), VariableError> {
+    let invalid = || {
+        VariableError(format!(
+            "'{definition}' is not a valid --variable, use name=value, name@file or %NAME"
+        ))
+    };
+
+    let (name, value) = if let Some(env) = definition.strip_prefix('%') {
+        let (name, default) = match env.split_once('=') {
+            Some((name, default)) => (name, Some(default)),
+            None => (env, None),
+        };
+        let value = match (std::env::var(name), default) {
+            (Ok(value), _) => value,
+            (Err(_), Some(default)) => default.to_string(),
+            (Err(_), None) => {
+                return Err(VariableError(format!(
+                    "The environment variable {name} for --variable is not set"
+                )))
+            }
+        };
+        (name, value)
+    } else {
+        let at = definition.find(['=', '@']).ok_or_else(invalid)?;
+        let (name, rest) = definition.split_at(at);
+        let value = match rest.split_at(1) {
+            ("@", file) => read_file(file).map_err(|e| {
+                VariableError(format!(
+                    "Unable to read the --variable {name} from {file}: {e}"
+                ))
+            })?,
+            (_, value) => value.to_string(),
+        };
+        (name, value)
+    };
+
+    if name.is_empty() || !name.bytes().all(|b| b.is_ascii_alphanumeric() || b == b'_') {
+        return Err(invalid());
+    }
+    variables.insert(name.to_string(), value);
+
+    Ok(())
+}
+
+fn read_file(file: &str) -> std::io::Result<String> {
+    if file == "-" {
+        let mut value = String::new();
+        std::io::stdin().read_to_string(&mut value)?;
+        Ok(value)
+    } else {
+        std::fs::read_to_string(file)
+    }
+}
+
+// Replaces the {{name}} and {{name:function...}} in `text`, leaving a "{{" without a "}}" as it is
+fn substitute(text: &str, variables: &HashMap<String, String>) -> Result<String, VariableError> {
+    let mut substituted = String::new();
+    let mut rest = text;
+    while let Some(at) = rest.find("{{") {
+        if rest[..at].ends_with('\\') {
+            substituted.push_str(&rest[..at - 1]);
+            substituted.push_str("{{");
+            rest = &rest[at + 2..];
+            continue;
+        }
+        let Some(len) = rest[at + 2..].find("}}") else {
+            break;
+        };
+        substituted.push_str(&rest[..at]);
+
+        let mut parts = rest[at + 2..at + 2 + len].split(':');
+        let name = parts.next().unwrap_or_default();
+        let mut value = variables
+            .get(name)
+            .cloned()
+            .ok_or_else(|| VariableError(format!("The variable '{name}' is not defined")))?;
+        for function in parts {
+            value = match function {
+                "trim" => value.trim().to_string(),
+                "json" => {
+                    let quoted = serde_json::Value::String(value).to_string();
+                    quoted[1..quoted.len() - 1].to_string()
+                }
+                "url" => utf8_percent_encode(&value, URL_ENCODE_SET).to_string(),
+                "b64" => openssl::base64::encode_block(value.as_bytes()),
+                _ => {
+                    return Err(VariableError(format!(
+                        "Unknown function '{function}' for the variable '{name}', use trim, json, url or b64"
+                    )))
+                }
+            };
+        }
+        substituted.push_str(&value);
+        rest = &rest[at + 2 + len + 2..];
+    }
+    substituted.push_str(rest);
+
+    Ok(substituted)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(args: &[&str]) -> Vec<OsString> {
+        args.iter().map(OsString::from).collect()
+    }
+
+    fn variables(definitions: &[&str]) -> HashMap<String, String> {
+        let mut variables = HashMap::new();
+        for definition in definitions {
+            define(&mut variables, definition).unwrap();
+        }
+        variables
+    }
+
+    #[test]
+    fn expand_options_get_the_variables_defined_anywhere() {
+        let expanded = expand(args(&[
+            "curl",
+            "--expand-header",
+            "X-Name: {{name}}",
+            "--expand-url={{host}}/{{name:url}}",
+            "--variable",
+            "name=a b",
+            "--variable=host=http://example.com",
+            "-H",
+            "{{name}}",
+            "--",
+            "--expand-data",
+        ]))
+        .unwrap();
+
+        assert_eq!(
+            expanded,
+            args(&[
+                "curl",
+                "--header",
+                "X-Name: a b",
+                "http://example.com/a%20b",
+                "--variable",
+                "name=a b",
+                "--variable=host=http://example.com",
+                "-H",
+                "{{name}}",
+                "--",
+                "--expand-data",
+            ])
+        );
+    }
+
+    #[test]
+    fn variables_come_from_values_files_and_the_environment() {
+        let path = std::env::temp_dir().join(format!("curl-{}-variable", std::process::id()));
+        std::fs::write(&path, "from file\n").unwrap();
+        let variables = variables(&[
+            "plain=a=b",
+            &format!("file@{}", path.display()),
+            "%CARGO_PKG_NAME",
+            "%CURL_TEST_UNSET_VARIABLE=fallback",
+        ]);
+        let _ = std::fs::remove_file(&path);
+
+        assert_eq!(variables["plain"], "a=b");
+        assert_eq!(variables["file"], "from file\n");
+        assert_eq!(variables["CARGO_PKG_NAME"], env!("CARGO_PKG_NAME"));
+        assert_eq!(variables["CURL_TEST_UNSET_VARIABLE"], "fallback");
+    }
+
+    #[test]
+    fn invalid_definitions_are_refused() {
+        let mut variables = HashMap::new();
+        for definition in [
+            "novalue",
+            "=value",
+            "bad-name=1",
+            "%CURL_TEST_UNSET_VARIABLE",
+        ] {
+            assert!(define(&mut variables, definition).is_err(), "{definition}");
+        }
+        let missing = define(&mut variables, "file@/nonexistent/curl-variable").unwrap_err();
+        assert!(missing
+            .to_string()
+            .starts_with("Unable to read the --variable file from /nonexistent/curl-variable: "));
+    }
+
+    #[test]
+    fn functions_change_the_value_in_order() {
+        let variables = variables(&["v= a \"b\"/c\n", "u=é&x"]);
+        let substituted = |text| substitute(text, &variables).unwrap();
+
+        assert_eq!(substituted("[{{v:trim}}]"), "[a \"b\"/c]");
+        assert_eq!(substituted("{{v:json}}"), " a \\\"b\\\"/c\\n");
+        assert_eq!(substituted("{{u:url}}"), "%C3%A9%26x");
+        assert_eq!(substituted("{{u:b64}}"), "w6kmeA==");
+        assert_eq!(substituted("{{v:trim:url}}"), "a%20%22b%22%2Fc");
+        assert_eq!(substituted("{{v:trim:b64}}"), "YSAiYiIvYw==");
+    }
+
+    #[test]
+    fn escaped_and_unclosed_braces_are_kept() {
+        let variables = variables(&["v=1"]);
+
+        assert_eq!(substitute(r"\{{v}} {{v}}", &variables).unwrap(), "{{v}} 1");
+        assert_eq!(substitute("{{v}} {{v", &variables).unwrap(), "1 {{v");
+    }
+
+    #[test]
+    fn undefined_variables_and_unknown_functions_are_errors() {
+        let variables = variables(&["v=1"]);
+
+        assert_eq!(
+            substitute("{{missing}}", &variables)
+                .unwrap_err()
+                .to_string(),
+            "The variable 'missing' is not defined"
+        );
+        assert!(substitute("{{v:upper}}", &variables)
+            .unwrap_err()
+            .to_string()
+            .starts_with("Unknown function 'upper' for the variable 'v'"));
+        assert!(expand(args(&["curl", "--expand-header"])).is_err());
+    }
+}
@@ -0,0 +1,145 @@
+use crate::{
+    direct::{self, Connection},
+    output::{self, status_line, version_name},
+    tls::Agreed,
+    trace::{self, Kind},
+};
+use openssl::x509::{X509NameRef, X509};
+use reqwest::{
+    blocking::Response,
+    header::{
+        HeaderMap, HeaderName, HeaderValue, AUTHORIZATION, COOKIE, PROXY_AUTHORIZATION, SET_COOKIE,
+    },
+    tls::TlsInfo,
+    Method, Version,
+};
+use url::{Position, Url};
+
+/// Prints one exchange to stderr the way `curl -v` does: the connection, the
+/// request line and headers as sent, and the status line and headers received.
+///
+/// With `redact` the values of credential carrying headers are masked.
+pub fn exchange(
+    method: &Method,
+    url: &Url,
+    headers: &HeaderMap,
+    version: Version,
+    resp: &Response,
+    redact: bool,
+) {
+    if let Some(connected) = connected(url, resp) {
+        eprintln!("* {connected}");
+    }
+
+    // A connection curl made itself knows what its handshake agreed on
+    let tls_info = resp.extensions().get::<TlsInfo>();
+    let connection = resp.extensions().get::<Connection>();
+    let negotiated = match connection {
+        Some(connection) => connection.tls.clone(),
+        None => resp
+            .extensions()
+            .get::<Agreed>()
+            .map(|Agreed(version, suite)| (*version, suite.clone())),
+    };
+    if let Some((version, suite)) = negotiated {
+        eprintln!("* SSL connection using {version} / {suite}");
+    }
+    let certificate = match connection {
+        Some(connection) => connection.certificate.as_deref(),
+        None => tls_info.and_then(TlsInfo::peer_certificate),
+    };
+    if let Some(certificate) = certificate.and_then(|der| X509::from_der(der).ok()) {
+        eprintln!("* Server certificate:");
+        eprintln!("*  subject: {}", name(certificate.subject_name()));
+        eprintln!("*  start date: {}", certificate.not_before());
+        eprintln!("*  expire date: {}", certificate.not_after());
+        eprintln!("*  issuer: {}", name(certificate.issuer_name()));
+    }
+
+    let target = &url[Position::BeforePath..Position::AfterQuery];
+    eprintln!("> {method} {target} {}", version_name(version));
+    for (name, value) in headers {
+        eprintln!("> {name}: {}", display_value(name, value, redact));
+    }
+    eprintln!(">");
+
+    eprintln!("< {}", status_line(resp.version(), resp.status()));
+    for (name, value) in resp.headers() {
+        eprintln!("< {name}: {}", display_value(name, value, redact));
+    }
+    eprintln!("<");
+}
+
+/// Logs one exchange to the --trace output: the connection, then the request
+/// headers and body as sent and the response headers as received.
+pub fn trace(
+    method: &Method,
+    url: &Url,
+    headers: &HeaderMap,
+    version: Version,
+    body: Option<&[u8]>,
+    resp: &Response,
+) {
+    if let Some(connected) = connected(url, resp) {
+        trace::info(&connected);
+    }
+
+    let target = &url[Position::BeforePath..Position::AfterQuery];
+    let mut head = format!("{method} {target} {}\r\n", version_name(version)).into_bytes();
+    for (name, value) in headers {
+        head.extend_from_slice(format!("{name}: ").as_bytes());
+        head.extend_from_slice(value.as_bytes());
+        head.extend_from_slice(b"\r\n");
+    }
+    head.extend_from_slice(b"\r\n");
+    trace::dump(Kind::SendHeader, &head);
+
+    if let Some(body) = body {
+        trace::dump(Kind::SendData, body);
+    }
+
+    let mut head = Vec::new();
+    output::head(&mut head, resp.version(), resp.status(), resp.headers());
+    trace::dump(Kind::RecvHeader, &head);
+}
+
+fn connected(url: &Url, resp: &Response) -> Option<String> {
+    let (host, (local, addr)) = (url.host_str()?, direct::addrs(resp)?);
+    Some(format!(
+        "Connected to {host} ({}) port {} from {} port {}",
+        addr.ip(),
+        addr.port(),
+        local.ip(),
+        local.port()
+    ))
+}
+
+// Masked values keep the authentication scheme, e.g. "Basic [redacted]"
+fn display_value(name: &HeaderName, value: &HeaderValue, redact: bool) -> String {
+    let text = String::from_utf8_lossy(value.as_bytes());
+
+    let credentials = [AUTHORIZATION, PROXY_AUTHORIZATION, COOKIE, SET_COOKIE].contains(name);
+    if !redact || !(credentials || value.is_sensitive()) {
+        return text.into_owned();
+    }
+
+    match text.split_once(' ') {
+        Some((scheme, _)) if name == AUTHORIZATION || name == PROXY_AUTHORIZATION => {
+            format!("{scheme} [redacted]")
+        }
+        _ => "[redacted]".to_string(),
+    }
+}
+
+fn name(name: &X509NameRef) -> String {
+    name.entries()
+        .map(|entry| {
+            let key = entry.object().nid().short_name().unwrap_or("?");
+            match entry.data().to_string() {
+                Ok(value) => format!("{key}={value}"),
+                Err(_) => format!("{key}=?"),
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("; ")
+}
@@ -0,0 +1,233 @@
+use crate::timing::Timings;
+use reqwest::{header::HeaderMap, Version};
+use serde_json::{Map, Value};
+use std::{
+    fs,
+    io::{self, Read},
+    net::SocketAddr,
+    time::Duration,
+};
+
+// Every variable %{json} includes
+const VARIABLES: &[&str] = &[
+    "content_type",
+    "exitcode",
+    "http_code",
+    "http_version",
+    "num_redirects",
+    "response_code",
+    "size_decoded",
+    "size_download",
+    "size_upload",
+    "time_appconnect",
+    "time_connect",
+    "time_namelookup",
+    "time_pretransfer",
+    "time_redirect",
+    "time_starttransfer",
+    "time_total",
+    "url_effective",
+];
+
+/// What -w can report about a transfer once it is over.
+#[derive(Default)]
+pub struct Transfer {
+    pub http_code: u16,
+    pub http_version: Option<Version>,
+    pub size_download: u64,
+    pub size_decoded: u64,
+    pub size_upload: u64,
+    pub content_type: Option<String>,
+    pub url_effective: String,
+    pub num_redirects: usize,
+    pub exitcode: i32,
+    pub headers: HeaderMap,
+    pub timings: Timings,
+    pub remote_addr: Option<SocketAddr>,
+}
+
+impl Transfer {
+    pub fn new(url: &str) -> Transfer {
+        Transfer {
+            url_effective: url.to_string(),
+            ..Transfer::default()
+        }
+    }
+
+    fn variable(&self, name: &str) -> Option<String> {
+        match name {
+            "json" => return Some(self.json().to_string()),
+            "header_json" => return Some(self.header_json().to_string()),
+            // Like curl, 000 when no response was received
+            "http_code" | "response_code" => return Some(format!("{:03}", self.http_code)),
+            // Seconds with microsecond precision, like curl
+            name if name.starts_with("time_") => {
+                return Some(format!("{:.6}", self.time(name)?.as_secs_f64()))
+            }
+            _ => {}
+        }
+
+        Some(match self.value(name)? {
+            Value::String(text) => text,
+            Value::Null => String::new(),
+            value => value.to_string(),
+        })
+    }
+
+    fn value(&self, name: &str) -> Option<Value> {
+        Some(match name {
+            "http_code" | "response_code" => self.http_code.into(),
+            "http_version" => http_version(self.http_version).into(),
+            "size_download" => self.size_download.into(),
+            "size_decoded" => self.size_decoded.into(),
+            "size_upload" => self.size_upload.into(),
+            "content_type" => self.content_type.clone().into(),
+            "url_effective" => self.url_effective.clone().into(),
+            "num_redirects" => self.num_redirects.into(),
+            "exitcode" => self.exitcode.into(),
+            name if name.starts_with("time_") => self.time(name)?.as_secs_f64().into(),
+            _ => return None,
+        })
+    }
+
+    fn time(&self, name: &str) -> Option<Duration> {
+        let timings = &self.timings;
+
+        Some(match name {
+            "time_namelookup" => timings.namelookup,
+            "time_connect" => timings.connect,
+            "time_appconnect" => timings.appconnect,
+            "time_pretransfer" => timings.pretransfer,
+            "time_starttransfer" => timings.starttransfer,
+            "time_redirect" => timings.redirect,
+            "time_total" => timings.total,
+            _ => return None,
+        })
+    }
+
+    /// Every variable as one JSON object, for %{json}.
+    fn json(&self) -> Value {
+        let variables = VARIABLES
+            .iter()
+            .filter_map(|name| Some((name.to_string(), self.value(name)?)))
+            .collect();
+
+        Value::Object(variables)
+    }
+
+    /// The response headers as a JSON object, for %{header_json}. Names are
+    /// lowercase, and headers received more than once map to an array.
+    fn header_json(&self) -> Value {
+        let mut headers = Map::new();
+
+        for name in self.headers.keys() {
+            let mut values: Vec<Value> = self
+                .headers
+                .get_all(name)
+                .iter()
+                .map(|value| String::from_utf8_lossy(value.as_bytes()).into())
+                .collect();
+
+            let value = if values.len() == 1 {
+                values.remove(0)
+            } else {
+                Value::Array(values)
+            };
+            headers.insert(name.as_str().to_string(), value);
+        }
+        headers.sort_keys();
+
+        Value::Object(headers)
+    }
+
+    // The value of one response header, repeated values joined with ", "
+    fn header(&self, name: &str) -> String {
+        self.headers
+            .get_all(name.trim())
+            .iter()
+            .map(|value| String::from_utf8_lossy(value.as_bytes()))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// The -w format string: given as is, read from a file with `@file`, or from
+/// stdin with `@-`.
+pub fn load(arg: &str) -> io::Result<String> {
+    match arg.strip_prefix('@') {
+        Some("-") => {
+            let mut format = String::new();
+            io::stdin().read_to_string(&mut format)?;
+            Ok(format)
+        }
+        Some(path) => fs::read_to_string(path),
+        None => Ok(arg.to_string()),
+    }
+}
+
+/// Expands `%{variable}`s, `%header{name}`s, `%%` and the `\n`, `\r` and
+/// `\t` escapes in `format`. Unknown variables expand to nothing and are
+/// passed to `unknown`, while missing headers just expand to nothing.
+pub fn render(format: &str, transfer: &Transfer, mut unknown: impl FnMut(&str)) -> String {
+    let mut out = String::new();
+    let mut rest = format;
+
+    while let Some(at) = rest.find(['%', '\\']) {
+        out.push_str(&rest[..at]);
+        rest = &rest[at..];
+
+        if let Some(after) = rest.strip_prefix("%%") {
+            out.push('%');
+            rest = after;
+        } else if let Some((name, after)) = rest
+            .strip_prefix("%{")
+            .and_then(|after| after.split_once('}'))
+        {
+            match transfer.variable(name) {
+                Some(value) => out.push_str(&value),
+                None => unknown(name),
+            }
+            rest = after;
+        } else if let Some((name, after)) = rest
+            .strip_prefix("%header{")
+            .and_then(|after| after.split_once('}'))
+        {
+            out.push_str(&transfer.header(name));
+            rest = after;
+        } else {
+            let escaped = match rest.as_bytes().get(1) {
+                Some(b'n') if rest.starts_with('\\') => Some('\n'),
+                Some(b'r') if rest.starts_with('\\') => Some('\r'),
+                Some(b't') if rest.starts_with('\\') => Some('\t'),
+                _ => None,
+            };
+
+            // Anything else is kept as written
+            match escaped {
+                Some(escaped) => {
+                    out.push(escaped);
+                    rest = &rest[2..];
+                }
+                None => {
+                    out.push_str(&rest[..1]);
+                    rest = &rest[1..];
+                }
+            }
+        }
+    }
+    out.push_str(rest);
+
+    out
+}
+
+// The version as curl writes it, e.g. "1.1" or "2", and "0" without a response
+fn http_version(version: Option<Version>) -> &'static str {
+    match version {
+        Some(Version::HTTP_09) => "0.9",
+        Some(Version::HTTP_10) => "1.0",
+        Some(Version::HTTP_11) => "1.1",
+        Some(Version::HTTP_2) => "2",
+        Some(Version::HTTP_3) => "3",
+        _ => "0",
+    }
+}
@@ -0,0 +1,89 @@
+mod common;
+
+use common::{curl, response, Server};
+
+// The Authorization header of a request head, if it has one
+fn authorization(head: &str) -> Option<&str> {
+    head.lines()
+        .find_map(|line| line.strip_prefix("authorization: "))
+}
+
+#[test]
+fn user_is_sent_with_basic_right_away() {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl(&["-s", "-u", "user:pass", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    // Lowercased like the rest of the head
+    assert_eq!(authorization(&server.request()), Some("basic dxnlcjpwyxnz"));
+}
+
+#[test]
+fn anyauth_answers_a_basic_challenge() {
+    let server = Server::new(vec![
+        response(
+            "401 Unauthorized",
+            &["WWW-Authenticate: Basic realm=\"test\""],
+            b"denied",
+        ),
+        response("200 OK", &[], b"welcome"),
+    ]);
+
+    let output = curl(&["-s", "--anyauth", "-u", "user:pass", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"welcome");
+    assert_eq!(authorization(&server.request()), None);
+    assert_eq!(authorization(&server.request()), Some("basic dxnlcjpwyxnz"));
+}
+
+#[test]
+fn digest_answers_the_challenge() {
+    let server = Server::new(vec![
+        response(
+            "401 Unauthorized",
+            &["WWW-Authenticate: Digest realm=\"test\", nonce=\"abc\", qop=\"auth\""],
+            b"",
+        ),
+        response("200 OK", &[], b"welcome"),
+    ]);
+
+    let output = curl(&[
+        "-s",
+        "--digest",
+        "-u",
+        "user:pass",
+        &format!("{}a?b=1", server.url),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"welcome");
+    assert_eq!(authorization(&server.request()), None);
+    let head = server.request();
+    let answer = authorization(&head).unwrap_or_else(|| panic!("{head}"));
+    assert!(answer.starts_with("digest "), "{answer}");
+    for part in [
+        "username=\"user\"",
+        "realm=\"test\"",
+        "nonce=\"abc\"",
+        "uri=\"/a?b=1\"",
+        "qop=auth",
+    ] {
+        assert!(answer.contains(part), "{answer}");
+    }
+}
+
+#[test]
+fn unanswerable_challenge_is_the_response() {
+    let server = Server::new(vec![response(
+        "401 Unauthorized",
+        &["WWW-Authenticate: Basic realm=\"test\""],
+        b"denied",
+    )]);
+
+    let output = curl(&["-s", "--digest", "-u", "user:pass", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"denied");
+}
@@ -0,0 +1,118 @@
+mod common;
+
+use common::{
+    curl,
+    tls::{certificate, pem_file, TlsServer},
+};
+use std::process::Output;
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn client_certificate_is_presented() {
+    let (cert, key) = certificate("client");
+    let server = TlsServer::with_clients(Some(&cert));
+    let cacert = server.cacert("mtls-cacert");
+    let cert_file = pem_file("mtls-cert", &cert.to_pem().unwrap());
+    let key_file = pem_file("mtls-key", &key.private_key_to_pem_pkcs8().unwrap());
+
+    let output = curl(&[
+        "-sS",
+        "--cacert",
+        cacert.to_str().unwrap(),
+        "--cert",
+        cert_file.to_str().unwrap(),
+        "--key",
+        key_file.to_str().unwrap(),
+        &server.url,
+    ]);
+    for file in [cacert, cert_file, key_file] {
+        let _ = std::fs::remove_file(file);
+    }
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"secret");
+    assert!(server
+        .request()
+        .is_some_and(|head| head.starts_with("GET / ")));
+}
+
+#[test]
+fn certificate_and_key_can_share_a_file() {
+    let (cert, key) = certificate("client");
+    let server = TlsServer::with_clients(Some(&cert));
+    let cacert = server.cacert("mtls-shared-cacert");
+    let both = pem_file(
+        "mtls-both",
+        &[
+            cert.to_pem().unwrap(),
+            key.private_key_to_pem_pkcs8().unwrap(),
+        ]
+        .concat(),
+    );
+
+    let output = curl(&[
+        "-sS",
+        "--cacert",
+        cacert.to_str().unwrap(),
+        "--cert",
+        both.to_str().unwrap(),
+        &server.url,
+    ]);
+    let _ = std::fs::remove_file(cacert);
+    let _ = std::fs::remove_file(both);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"secret");
+}
+
+#[test]
+fn missing_client_certificate_is_asked_for() {
+    let (cert, _) = certificate("client");
+    let server = TlsServer::with_clients(Some(&cert));
+    let cacert = server.cacert("mtls-missing-cacert");
+
+    let output = curl(&["-sS", "--cacert", cacert.to_str().unwrap(), &server.url]);
+    let _ = std::fs::remove_file(cacert);
+
+    assert_eq!(output.status.code(), Some(56), "{}", stderr(&output));
+    assert!(
+        stderr(&output).contains("The server requires a client certificate, give one with --cert"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(server.request().is_none());
+}
+
+#[test]
+fn other_client_certificate_is_refused() {
+    let (trusted, _) = certificate("client");
+    let (other, other_key) = certificate("intruder");
+    let server = TlsServer::with_clients(Some(&trusted));
+    let cacert = server.cacert("mtls-other-cacert");
+    let both = pem_file(
+        "mtls-other",
+        &[
+            other.to_pem().unwrap(),
+            other_key.private_key_to_pem_pkcs8().unwrap(),
+        ]
+        .concat(),
+    );
+
+    let output = curl(&[
+        "-sS",
+        "--cacert",
+        cacert.to_str().unwrap(),
+        "--cert",
+        both.to_str().unwrap(),
+        &server.url,
+    ]);
+    let _ = std::fs::remove_file(cacert);
+    let _ = std::fs::remove_file(both);
+
+    assert_ne!(output.status.code(), Some(0));
+    assert!(output.stdout.is_empty());
+    assert!(server.request().is_none());
+}
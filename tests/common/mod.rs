@@ -0,0 +1,85 @@
+// Each test file is its own crate using only some of these
+#![allow(dead_code)]
+
+pub mod tls;
+
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    process::{Command, Output},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// A local server answering one request per connection with the responses it
+/// is given, in order, and keeping the head of each request it got and the
+/// body its Content-Length says comes after it.
+pub struct Server {
+    pub url: String,
+    requests: Receiver<(String, Vec<u8>)>,
+}
+
+impl Server {
+    pub fn new(responses: Vec<Vec<u8>>) -> Server {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/", listener.local_addr().unwrap());
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            for response in responses {
+                let Ok((stream, _)) = listener.accept() else {
+                    return;
+                };
+                let mut reader = BufReader::new(stream);
+                let mut head = String::new();
+                while reader.read_line(&mut head).is_ok_and(|read| read > 2) {}
+                let length = head
+                    .lines()
+                    .filter_map(|line| line.split_once(':'))
+                    .find(|(name, _)| name.eq_ignore_ascii_case("content-length"))
+                    .and_then(|(_, value)| value.trim().parse().ok());
+                let mut body = vec![0; length.unwrap_or(0)];
+                let _ = reader.read_exact(&mut body);
+                let _ = sender.send((head, body));
+                let _ = reader.get_mut().write_all(&response);
+            }
+        });
+
+        Server { url, requests }
+    }
+
+    /// The head of the next request, lowercased.
+    pub fn request(&self) -> String {
+        self.exchange().0
+    }
+
+    /// The head of the next request, lowercased, and its body.
+    pub fn exchange(&self) -> (String, Vec<u8>) {
+        let (head, body) = self.requests.recv().unwrap();
+        (head.to_ascii_lowercase(), body)
+    }
+}
+
+/// A complete response closing the connection after it.
+pub fn response(status: &str, headers: &[&str], body: &[u8]) -> Vec<u8> {
+    let mut response = format!("HTTP/1.1 {status}\r\n");
+    for header in headers {
+        response.push_str(header);
+        response.push_str("\r\n");
+    }
+    response.push_str(&format!(
+        "Content-Length: {}\r\nConnection: close\r\n\r\n",
+        body.len()
+    ));
+
+    [response.as_bytes(), body].concat()
+}
+
+/// Runs curl with `args`, leaving out any proxy from the environment and
+/// the developer's own .curlrc.
+pub fn curl(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_curl"))
+        .args(["-q", "--noproxy", "*"])
+        .args(args)
+        .output()
+        .unwrap()
+}
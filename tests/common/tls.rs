@@ -0,0 +1,134 @@
+use super::response;
+use openssl::{
+    asn1::Asn1Time,
+    bn::BigNum,
+    ec::{EcGroup, EcKey},
+    hash::MessageDigest,
+    nid::Nid,
+    pkey::{PKey, Private},
+    ssl::{SslAcceptor, SslMethod, SslVerifyMode},
+    x509::{extension::SubjectAlternativeName, store::X509StoreBuilder, X509Name, X509},
+};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    path::PathBuf,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// A self-signed certificate for `name` and its key.
+pub fn certificate(name: &str) -> (X509, PKey<Private>) {
+    let group = EcGroup::from_curve_name(Nid::X9_62_PRIME256V1).unwrap();
+    let key = PKey::from_ec_key(EcKey::generate(&group).unwrap()).unwrap();
+    let mut subject = X509Name::builder().unwrap();
+    subject.append_entry_by_nid(Nid::COMMONNAME, name).unwrap();
+    let subject = subject.build();
+
+    let mut cert = X509::builder().unwrap();
+    cert.set_version(2).unwrap();
+    let serial = BigNum::from_u32(1).unwrap().to_asn1_integer().unwrap();
+    cert.set_serial_number(&serial).unwrap();
+    cert.set_subject_name(&subject).unwrap();
+    cert.set_issuer_name(&subject).unwrap();
+    cert.set_pubkey(&key).unwrap();
+    cert.set_not_before(&Asn1Time::days_from_now(0).unwrap())
+        .unwrap();
+    cert.set_not_after(&Asn1Time::days_from_now(30).unwrap())
+        .unwrap();
+    let names = SubjectAlternativeName::new()
+        .dns(name)
+        .build(&cert.x509v3_context(None, None))
+        .unwrap();
+    cert.append_extension(names).unwrap();
+    cert.sign(&key, MessageDigest::sha256()).unwrap();
+
+    (cert.build(), key)
+}
+
+/// Accepts TLS connections as the holder of `identity`.
+pub fn acceptor((cert, key): &(X509, PKey<Private>)) -> SslAcceptor {
+    acceptor_for_clients(cert, key, None)
+}
+
+// Accepts TLS connections, asking for a client certificate issued by `clients` when there is one
+fn acceptor_for_clients(cert: &X509, key: &PKey<Private>, clients: Option<&X509>) -> SslAcceptor {
+    let mut acceptor = SslAcceptor::mozilla_intermediate_v5(SslMethod::tls()).unwrap();
+    acceptor.set_certificate(cert).unwrap();
+    acceptor.set_private_key(key).unwrap();
+    if let Some(clients) = clients {
+        let mut store = X509StoreBuilder::new().unwrap();
+        store.add_cert(clients.clone()).unwrap();
+        acceptor.set_verify_cert_store(store.build()).unwrap();
+        acceptor.set_verify(SslVerifyMode::PEER | SslVerifyMode::FAIL_IF_NO_PEER_CERT);
+    }
+    acceptor.build()
+}
+
+/// Reads a request head off `stream`.
+pub fn head(stream: impl Read) -> String {
+    let mut reader = BufReader::new(stream);
+    let mut head = String::new();
+    while reader.read_line(&mut head).is_ok_and(|read| read > 2) {}
+    head
+}
+
+/// Writes PEM `contents` to a file of its own in the temporary directory.
+pub fn pem_file(name: &str, contents: &[u8]) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+    std::fs::write(&path, contents).unwrap();
+    path
+}
+
+/// An HTTPS server for `localhost` taking one connection, with a
+/// self-signed certificate. It answers a request with "secret", and keeps
+/// the head, none when the handshake failed.
+pub struct TlsServer {
+    pub url: String,
+    pub cert: X509,
+    requests: Receiver<Option<String>>,
+}
+
+impl TlsServer {
+    pub fn new() -> TlsServer {
+        TlsServer::with_clients(None)
+    }
+
+    /// A server that takes only clients with the certificate `clients`.
+    pub fn with_clients(clients: Option<&X509>) -> TlsServer {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "https://localhost:{}/",
+            listener.local_addr().unwrap().port()
+        );
+        let (cert, key) = certificate("localhost");
+        let acceptor = acceptor_for_clients(&cert, &key, clients);
+
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            let Ok(mut stream) = acceptor.accept(stream) else {
+                let _ = sender.send(None);
+                return;
+            };
+            let _ = sender.send(Some(head(&mut stream)));
+            let _ = stream.write_all(&response("200 OK", &[], b"secret"));
+        });
+
+        TlsServer {
+            url,
+            cert,
+            requests,
+        }
+    }
+
+    /// The server's certificate in a file for --cacert.
+    pub fn cacert(&self, name: &str) -> PathBuf {
+        pem_file(name, &self.cert.to_pem().unwrap())
+    }
+
+    /// The head of the request, none when the handshake failed.
+    pub fn request(&self) -> Option<String> {
+        self.requests.recv().unwrap()
+    }
+}
@@ -0,0 +1,49 @@
+mod common;
+
+use common::{curl, response, Server};
+use flate2::{write::GzEncoder, Compression};
+use std::io::Write;
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder.write_all(data).unwrap();
+    encoder.finish().unwrap()
+}
+
+#[test]
+fn compressed_asks_for_and_decodes_every_encoding() {
+    let body = gzip(b"decoded");
+    let server = Server::new(vec![response("200 OK", &["Content-Encoding: gzip"], &body)]);
+    let output = curl(&["--compressed", &server.url]);
+
+    assert_eq!(output.stdout, b"decoded");
+    let request = server.request();
+    assert!(
+        request.contains("accept-encoding: gzip, deflate, br, zstd\r\n"),
+        "{request}"
+    );
+}
+
+#[test]
+fn encoded_bodies_are_left_alone_without_compressed() {
+    let body = gzip(b"decoded");
+    for args in [&["-o", "-"][..], &["--raw", "-o", "-"]] {
+        let server = Server::new(vec![response("200 OK", &["Content-Encoding: gzip"], &body)]);
+        let output = curl(&[args, &[&server.url]].concat());
+
+        assert_eq!(output.stdout, body, "{args:?}");
+        assert!(!server.request().contains("accept-encoding"));
+    }
+}
+
+#[test]
+fn broken_bodies_fail_decoding() {
+    let server = Server::new(vec![response(
+        "200 OK",
+        &["Content-Encoding: gzip"],
+        b"not gzip",
+    )]);
+    let output = curl(&["--compressed", &server.url]);
+
+    assert_eq!(output.status.code(), Some(61));
+}
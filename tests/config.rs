@@ -0,0 +1,102 @@
+mod common;
+
+use common::{curl, response, Server};
+use std::{
+    path::{Path, PathBuf},
+    process::{Command, Output},
+};
+
+// A directory of its own in the temporary directory, made empty
+fn temp_dir(name: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+// Runs curl with `home` as the only place a .curlrc can come from
+fn curl_at_home(home: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_curl"))
+        .env_remove("CURL_HOME")
+        .env_remove("XDG_CONFIG_HOME")
+        .env("HOME", home)
+        .args(["--noproxy", "*"])
+        .args(args)
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn curlrc_is_read_and_the_command_line_wins() {
+    let home = temp_dir("rc-home");
+    std::fs::write(
+        home.join(".curlrc"),
+        "user-agent = from-rc\nheader = \"X-Rc: 1\"\n",
+    )
+    .unwrap();
+    let server = Server::new(vec![
+        response("200 OK", &[], b""),
+        response("200 OK", &[], b""),
+    ]);
+
+    let from_rc = curl_at_home(&home, &["-s", &server.url]);
+    let overridden = curl_at_home(&home, &["-s", "-A", "from-cli", &server.url]);
+    let _ = std::fs::remove_dir_all(home);
+
+    assert_eq!(from_rc.status.code(), Some(0));
+    assert_eq!(overridden.status.code(), Some(0));
+    let head = server.request();
+    assert!(head.contains("user-agent: from-rc\r\n"), "{head}");
+    assert!(head.contains("x-rc: 1\r\n"), "{head}");
+    let head = server.request();
+    assert!(head.contains("user-agent: from-cli\r\n"), "{head}");
+    assert!(head.contains("x-rc: 1\r\n"), "{head}");
+}
+
+#[test]
+fn q_leaves_the_curlrc_out() {
+    let home = temp_dir("rc-disabled");
+    std::fs::write(home.join(".curlrc"), "header = \"X-Rc: 1\"\n").unwrap();
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl_at_home(&home, &["-q", "-s", &server.url]);
+    let _ = std::fs::remove_dir_all(home);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!server.request().contains("x-rc:"));
+}
+
+#[test]
+fn bad_curlrc_is_warned_about_and_left_out() {
+    let home = temp_dir("rc-bad");
+    std::fs::write(home.join(".curlrc"), "bogus-option\n").unwrap();
+    let server = Server::new(vec![response("200 OK", &[], b"ok")]);
+
+    let output = curl_at_home(&home, &[&server.url]);
+    let _ = std::fs::remove_dir_all(home);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"ok");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.starts_with("Warning: "), "{stderr}");
+}
+
+#[test]
+fn bad_config_file_is_a_startup_error() {
+    let dir = temp_dir("config-bad");
+    let file = dir.join("config");
+    std::fs::write(&file, "bogus-option\n").unwrap();
+
+    let bad = curl(&["-K", file.to_str().unwrap(), "http://localhost/"]);
+    let missing = curl(&[
+        "-K",
+        dir.join("missing").to_str().unwrap(),
+        "http://localhost/",
+    ]);
+    let _ = std::fs::remove_dir_all(dir);
+
+    assert_eq!(bad.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&bad.stderr);
+    assert!(stderr.contains("config:1: "), "{stderr}");
+    assert_eq!(missing.status.code(), Some(2));
+}
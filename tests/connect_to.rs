@@ -0,0 +1,60 @@
+mod common;
+
+use common::{curl, response, Server};
+
+// The port the server listens on
+fn port(server: &Server) -> u16 {
+    server
+        .url
+        .trim_end_matches('/')
+        .rsplit(':')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn rule_sends_the_connection_to_a_resolved_host() {
+    let server = Server::new(vec![response("200 OK", &[], b"body")]);
+    let port = port(&server);
+
+    let output = curl(&[
+        "-s",
+        "--connect-to",
+        &format!("foo.test:{port}:bar.test:{port}"),
+        "--resolve",
+        &format!("bar.test:{port}:127.0.0.1"),
+        &format!("http://foo.test:{port}/"),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"body");
+    let request = server.request();
+    assert!(
+        request.contains(&format!("host: foo.test:{port}\r\n")),
+        "{request}"
+    );
+    assert!(!request.contains("connection: close"), "{request}");
+}
+
+#[test]
+fn rule_changes_the_port_the_url_gives() {
+    let server = Server::new(vec![response("200 OK", &[], b"body")]);
+    let port = port(&server);
+
+    let output = curl(&[
+        "-s",
+        "--connect-to",
+        &format!("foo.test:1:127.0.0.1:{port}"),
+        "-w",
+        "%{url_effective}",
+        "http://foo.test:1/path",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"bodyhttp://foo.test:1/path");
+    let request = server.request();
+    assert!(request.starts_with("get /path "), "{request}");
+    assert!(request.contains("host: foo.test:1\r\n"), "{request}");
+}
@@ -0,0 +1,51 @@
+mod common;
+
+use common::{curl, response, Server};
+
+#[test]
+fn saved_jar_is_sent_back_by_the_next_run() {
+    let jar = std::env::temp_dir().join(format!("curl-{}-jar", std::process::id()));
+    let jar_path = jar.to_str().unwrap();
+    let _ = std::fs::remove_file(&jar);
+    let setting = Server::new(vec![response(
+        "200 OK",
+        &[
+            "Set-Cookie: session=s",
+            "Set-Cookie: lasting=l; Max-Age=3600; HttpOnly",
+            "Set-Cookie: gone=g; Expires=Thu, 01 Jan 1970 00:00:01 GMT",
+        ],
+        b"",
+    )]);
+
+    let saved = curl(&["-s", "-c", jar_path, &setting.url]);
+
+    assert_eq!(saved.status.code(), Some(0));
+    let contents = std::fs::read_to_string(&jar).unwrap();
+    let cookies: Vec<&str> = contents
+        .lines()
+        .filter(|line| line.contains('\t'))
+        .collect();
+    assert_eq!(cookies.len(), 2, "{contents}");
+    assert_eq!(cookies[0], "127.0.0.1\tFALSE\t/\tFALSE\t0\tsession\ts");
+    assert!(
+        cookies[1].starts_with("#HttpOnly_127.0.0.1\tFALSE\t/\tFALSE\t"),
+        "{contents}"
+    );
+    assert!(cookies[1].ends_with("\tlasting\tl"), "{contents}");
+
+    // The server has another port, which cookies do not care about
+    let sending = Server::new(vec![
+        response("200 OK", &[], b""),
+        response("200 OK", &[], b""),
+    ]);
+    let sent = curl(&["-s", "-b", jar_path, &sending.url]);
+    let junked = curl(&["-s", "-b", jar_path, "-j", &sending.url]);
+    let _ = std::fs::remove_file(&jar);
+
+    assert_eq!(sent.status.code(), Some(0));
+    assert_eq!(junked.status.code(), Some(0));
+    let head = sending.request();
+    assert!(head.contains("cookie: session=s; lasting=l\r\n"), "{head}");
+    let head = sending.request();
+    assert!(head.contains("cookie: lasting=l\r\n"), "{head}");
+}
@@ -0,0 +1,71 @@
+mod common;
+
+use common::{curl, response, Server};
+
+fn ok() -> Server {
+    Server::new(vec![response("200 OK", &[], b"")])
+}
+
+#[test]
+fn data_is_posted_joined_with_ampersands() {
+    let server = ok();
+
+    let output = curl(&["-s", "-d", "name=alice", "-d", "role=admin", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let (head, body) = server.exchange();
+    assert!(head.starts_with("post / http/1.1\r\n"), "{head}");
+    assert!(
+        head.contains("content-type: application/x-www-form-urlencoded\r\n"),
+        "{head}"
+    );
+    assert_eq!(body, b"name=alice&role=admin");
+}
+
+#[test]
+fn every_data_option_is_joined_in_order() {
+    let server = ok();
+
+    let output = curl(&[
+        "-s",
+        "--data-raw",
+        "@raw",
+        "--data-urlencode",
+        "q=a b",
+        "--data-binary",
+        "x=1",
+        &server.url,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(server.exchange().1, b"@raw&q=a%20b&x=1");
+}
+
+#[test]
+fn chosen_method_sends_the_data_too() {
+    for method in ["DELETE", "OPTIONS", "GET"] {
+        let server = ok();
+
+        let output = curl(&["-s", "-X", method, "-d", "x=1", &server.url]);
+
+        assert_eq!(output.status.code(), Some(0));
+        let (head, body) = server.exchange();
+        assert!(
+            head.starts_with(&format!("{} / ", method.to_ascii_lowercase())),
+            "{head}"
+        );
+        assert_eq!(body, b"x=1", "{method}");
+    }
+}
+
+#[test]
+fn get_puts_the_data_in_the_query() {
+    let server = ok();
+
+    let output = curl(&["-s", "-G", "-d", "x=1", "-d", "y=2", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let (head, body) = server.exchange();
+    assert!(head.starts_with("get /?x=1&y=2 http/1.1\r\n"), "{head}");
+    assert!(body.is_empty());
+}
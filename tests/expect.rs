@@ -0,0 +1,206 @@
+mod common;
+
+use common::{curl, response, Server};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    thread,
+    time::{Duration, Instant},
+};
+
+/// What a server for one connection got: the request head, lowercased, and
+/// the body that came after it.
+type Received = Receiver<(String, Vec<u8>)>;
+
+/// A server for one connection that answers the request head with
+/// `interim`, when there is one, then reads a body of `length` bytes and
+/// answers that with 200 OK.
+fn server(interim: Option<&'static [u8]>, length: usize) -> (String, Received) {
+    serve(move |reader| {
+        if let Some(interim) = interim {
+            reader.get_mut().write_all(interim).unwrap();
+        }
+        let mut body = vec![0; length];
+        reader.read_exact(&mut body).unwrap();
+        let _ = reader
+            .get_mut()
+            .write_all(&response("200 OK", &[], b"done"));
+        body
+    })
+}
+
+fn serve(
+    answer: impl FnOnce(&mut BufReader<TcpStream>) -> Vec<u8> + Send + 'static,
+) -> (String, Received) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/upload", listener.local_addr().unwrap());
+    let (sender, received) = mpsc::channel();
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        while reader.read_line(&mut head).is_ok_and(|read| read > 2) {}
+        let body = answer(&mut reader);
+        let _ = sender.send((head.to_ascii_lowercase(), body));
+    });
+
+    (url, received)
+}
+
+fn big() -> String {
+    "x".repeat(2000)
+}
+
+#[test]
+fn big_body_waits_for_100_continue() {
+    let (url, received) = server(Some(b"HTTP/1.1 100 Continue\r\n\r\n"), 2000);
+    let started = Instant::now();
+
+    // The body would only go out after the timeout if the interim response were missed
+    let output = curl(&[
+        "-s",
+        "--expect100-timeout",
+        "30",
+        "-X",
+        "POST",
+        "-d",
+        &big(),
+        &url,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"done");
+    assert!(started.elapsed() < Duration::from_secs(20));
+    let (head, body) = received.recv().unwrap();
+    assert!(head.contains("expect: 100-continue\r\n"), "{head}");
+    assert_eq!(body, big().as_bytes());
+}
+
+#[test]
+fn body_goes_out_after_the_timeout() {
+    let (url, received) = server(None, 2000);
+
+    let output = curl(&[
+        "-s",
+        "--expect100-timeout",
+        "0.2",
+        "-X",
+        "POST",
+        "-d",
+        &big(),
+        &url,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"done");
+    let (head, body) = received.recv().unwrap();
+    assert!(head.contains("expect: 100-continue\r\n"), "{head}");
+    assert_eq!(body, big().as_bytes());
+}
+
+#[test]
+fn refused_body_is_never_sent() {
+    let (url, received) = serve(|reader| {
+        let _ = reader
+            .get_mut()
+            .write_all(&response("401 Unauthorized", &[], b"no"));
+        // Whatever comes before curl hangs up, which is nothing
+        let mut rest = Vec::new();
+        let _ = reader.read_to_end(&mut rest);
+        rest
+    });
+
+    let output = curl(&[
+        "-s",
+        "--expect100-timeout",
+        "0.2",
+        "-X",
+        "POST",
+        "-d",
+        &big(),
+        &url,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"no");
+    let (head, body) = received.recv().unwrap();
+    assert!(head.contains("expect: 100-continue\r\n"), "{head}");
+    assert!(body.is_empty(), "{}", body.len());
+}
+
+#[test]
+fn small_body_is_sent_right_away() {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl(&["-s", "-X", "POST", "-d", "data", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!server.request().contains("expect:"));
+}
+
+#[test]
+fn threshold_is_configurable() {
+    let (url, received) = server(Some(b"HTTP/1.1 100 Continue\r\n\r\n"), 4);
+
+    let output = curl(&[
+        "-s",
+        "--expect100-threshold",
+        "3",
+        "-X",
+        "POST",
+        "-d",
+        "data",
+        &url,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let (head, body) = received.recv().unwrap();
+    assert!(head.contains("expect: 100-continue\r\n"), "{head}");
+    assert_eq!(body, b"data");
+}
+
+#[test]
+fn empty_expect_header_sends_none() {
+    let (url, received) = server(None, 2000);
+    let started = Instant::now();
+
+    let output = curl(&[
+        "-s",
+        "--expect100-timeout",
+        "30",
+        "-H",
+        "Expect:",
+        "-X",
+        "POST",
+        "-d",
+        &big(),
+        &url,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(started.elapsed() < Duration::from_secs(20));
+    let (head, body) = received.recv().unwrap();
+    assert!(!head.contains("expect:"), "{head}");
+    assert_eq!(body, big().as_bytes());
+}
+
+#[test]
+fn unreachable_server_is_reported_as_without_expect() {
+    let url = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", listener.local_addr().unwrap())
+    };
+
+    let held = curl(&["-sS", "-X", "POST", "-d", &big(), &url]);
+    let sent = curl(&["-sS", "-X", "POST", "-d", "data", &url]);
+
+    assert_eq!(held.status.code(), Some(7));
+    assert_eq!(held.status.code(), sent.status.code());
+    assert_eq!(held.stderr, sent.stderr);
+    let stderr = String::from_utf8_lossy(&held.stderr);
+    assert!(
+        stderr.contains("Unable to connect to the server"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,90 @@
+mod common;
+
+use common::{curl, response, Server};
+use std::{net::TcpListener, path::PathBuf};
+
+// A file to attach, of its own in the temporary directory
+fn attachment(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+    std::fs::write(&path, "attached\n").unwrap();
+    path
+}
+
+// The boundary of a multipart/form-data request head
+fn boundary(head: &str) -> String {
+    let content_type = head
+        .lines()
+        .find_map(|line| line.strip_prefix("content-type: multipart/form-data; boundary="))
+        .unwrap_or_else(|| panic!("{head}"));
+
+    content_type.trim().to_string()
+}
+
+#[test]
+fn form_is_posted_as_multipart() {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+    let path = attachment("upload.txt");
+
+    let output = curl(&[
+        "-s",
+        "-F",
+        "name=alice",
+        "-F",
+        &format!("file=@{}", path.display()),
+        "--form-string",
+        "raw=@not;type=kept",
+        &server.url,
+    ]);
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(output.status.code(), Some(0));
+    let (head, body) = server.exchange();
+    assert!(head.starts_with("post / http/1.1\r\n"), "{head}");
+    let boundary = boundary(&head);
+    let filename = path.file_name().unwrap().to_string_lossy();
+    assert_eq!(
+        String::from_utf8(body).unwrap(),
+        format!(
+            "--{boundary}\r\n\
+             Content-Disposition: form-data; name=\"name\"\r\n\r\n\
+             alice\r\n\
+             --{boundary}\r\n\
+             Content-Disposition: form-data; name=\"file\"; filename=\"{filename}\"\r\n\
+             Content-Type: text/plain\r\n\r\n\
+             attached\n\r\n\
+             --{boundary}\r\n\
+             Content-Disposition: form-data; name=\"raw\"\r\n\r\n\
+             @not;type=kept\r\n\
+             --{boundary}--\r\n"
+        )
+    );
+}
+
+#[test]
+fn chosen_method_keeps_the_form() {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl(&["-s", "-X", "PUT", "-F", "a=b", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let (head, body) = server.exchange();
+    assert!(head.starts_with("put / "), "{head}");
+    assert!(String::from_utf8_lossy(&body).contains("name=\"a\"\r\n\r\nb\r\n"));
+}
+
+#[test]
+fn attached_file_to_an_unreachable_server_is_a_connection_failure() {
+    let path = attachment("unreachable.txt");
+    let refused = {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        format!("http://{}/", listener.local_addr().unwrap())
+    };
+    let file = format!("f=@{}", path.display());
+
+    let refused = curl(&["-s", "-F", &file, &refused]);
+    let unresolved = curl(&["-s", "-F", &file, "http://nowhere.invalid/"]);
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(refused.status.code(), Some(7));
+    assert_eq!(unresolved.status.code(), Some(6));
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::{curl, response, Server};
+
+#[test]
+fn globs_make_a_request_each() {
+    let server = Server::new(vec![
+        response("200 OK", &[], b"a"),
+        response("200 OK", &[], b"b"),
+    ]);
+
+    let output = curl(&["-s", &format!("{}{{a,b}}", server.url)]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"ab");
+    assert!(server.request().starts_with("get /a "));
+    assert!(server.request().starts_with("get /b "));
+}
+
+#[test]
+fn globoff_sends_the_url_as_it_is() {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl(&["-s", "-g", &format!("{}[1-2]{{a,b}}", server.url)]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let head = server.request();
+    assert!(head.starts_with("get /[1-2]%7ba,b%7d "), "{head}");
+}
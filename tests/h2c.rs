@@ -0,0 +1,100 @@
+mod common;
+
+use common::curl;
+use std::{
+    io::{Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    thread,
+};
+
+const PREFACE: &[u8] = b"PRI * HTTP/2.0\r\n\r\nSM\r\n\r\n";
+const DATA: u8 = 0;
+const HEADERS: u8 = 1;
+const SETTINGS: u8 = 4;
+const END_STREAM: u8 = 1;
+const ACK: u8 = 1;
+const END_HEADERS: u8 = 4;
+
+fn write_frame(stream: &mut TcpStream, kind: u8, flags: u8, id: u32, payload: &[u8]) {
+    let length = (payload.len() as u32).to_be_bytes();
+    let mut frame = vec![length[1], length[2], length[3], kind, flags];
+    frame.extend(id.to_be_bytes());
+    frame.extend(payload);
+    stream.write_all(&frame).unwrap();
+}
+
+// The type, flags and stream of the next frame, after reading past its payload
+fn read_frame(stream: &mut TcpStream) -> Option<(u8, u8, u32)> {
+    let mut header = [0; 9];
+    stream.read_exact(&mut header).ok()?;
+    let length = u32::from_be_bytes([0, header[0], header[1], header[2]]);
+    let id = u32::from_be_bytes([header[5], header[6], header[7], header[8]]) & 0x7fff_ffff;
+    let mut payload = vec![0; length as usize];
+    stream.read_exact(&mut payload).ok()?;
+    Some((header[3], header[4], id))
+}
+
+/// An HTTP/2 server without TLS for one connection, answering the first
+/// request with 200 and "over h2c". It sends what the connection started
+/// with, which is the preface for a client using prior knowledge.
+fn server() -> (String, mpsc::Receiver<Vec<u8>>) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/", listener.local_addr().unwrap());
+    let (sender, started) = mpsc::channel();
+    thread::spawn(move || {
+        let (mut stream, _) = listener.accept().unwrap();
+        let mut start = vec![0; PREFACE.len()];
+        stream.read_exact(&mut start).unwrap();
+        let _ = sender.send(start.clone());
+        if start != PREFACE {
+            return;
+        }
+
+        write_frame(&mut stream, SETTINGS, 0, 0, &[]);
+        while let Some((kind, flags, id)) = read_frame(&mut stream) {
+            match kind {
+                SETTINGS if flags & ACK == 0 => write_frame(&mut stream, SETTINGS, ACK, 0, &[]),
+                HEADERS => {
+                    // 0x88 is ":status: 200" in the static table of HPACK
+                    write_frame(&mut stream, HEADERS, END_HEADERS, id, &[0x88]);
+                    write_frame(&mut stream, DATA, END_STREAM, id, b"over h2c");
+                }
+                _ => {}
+            }
+        }
+    });
+
+    (url, started)
+}
+
+#[test]
+fn prior_knowledge_starts_with_the_http2_preface() {
+    let (url, started) = server();
+
+    let output = curl(&[
+        "-sS",
+        "--http2-prior-knowledge",
+        "-w",
+        "\n%{http_version}",
+        &url,
+    ]);
+
+    assert_eq!(started.recv().unwrap(), PREFACE);
+    assert_eq!(
+        output.status.code(),
+        Some(0),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+    assert_eq!(output.stdout, b"over h2c\n2");
+}
+
+#[test]
+fn without_it_http1_is_spoken() {
+    let (url, started) = server();
+
+    let _ = curl(&["-s", "-m", "5", &url]);
+
+    assert!(started.recv().unwrap().starts_with(b"GET / HTTP/1.1\r\n"));
+}
@@ -0,0 +1,182 @@
+mod common;
+
+use common::{
+    response,
+    tls::{acceptor, certificate, head},
+};
+use openssl::x509::X509;
+use std::{
+    io::Write,
+    net::TcpListener,
+    process::Output,
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// An HTTPS proxy on localhost for one connection. It answers requests for
+/// plain HTTP URLs itself, and tunnels HTTPS ones to a server of its own
+/// for `origin.test`. Both have self-signed certificates.
+struct Proxy {
+    url: String,
+    cert: X509,
+    requests: Receiver<String>,
+}
+
+impl Proxy {
+    fn new() -> Proxy {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!(
+            "https://localhost:{}",
+            listener.local_addr().unwrap().port()
+        );
+        let identity = certificate("localhost");
+        let proxy = acceptor(&identity);
+        let origin = acceptor(&certificate("origin.test"));
+
+        let (sender, requests) = mpsc::channel();
+        thread::spawn(move || {
+            let (stream, _) = listener.accept().unwrap();
+            // The client gives up on a certificate it does not accept
+            let Ok(mut stream) = proxy.accept(stream) else {
+                return;
+            };
+            let request = head(&mut stream);
+            let _ = sender.send(request.clone());
+            if !request.starts_with("CONNECT") {
+                let _ = stream.write_all(&response("200 OK", &[], b"from the proxy"));
+                return;
+            }
+
+            let _ = stream.write_all(b"HTTP/1.1 200 Connection established\r\n\r\n");
+            let Ok(mut tunneled) = origin.accept(stream) else {
+                return;
+            };
+            let request = head(&mut tunneled);
+            let _ = sender.send(request);
+            let _ = tunneled.write_all(&response("200 OK", &[], b"from the server"));
+        });
+
+        Proxy {
+            url,
+            cert: identity.0,
+            requests,
+        }
+    }
+
+    // Runs curl through the proxy
+    fn curl(&self, args: &[&str]) -> Output {
+        let args = [&["--noproxy", "", "-x", &self.url], args].concat();
+        common::curl(&args)
+    }
+
+    // The proxy's certificate in a file for --proxy-cacert
+    fn cacert(&self, name: &str) -> std::path::PathBuf {
+        let path = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+        std::fs::write(&path, self.cert.to_pem().unwrap()).unwrap();
+        path
+    }
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn untrusted_proxy_is_named_as_the_proxy() {
+    let proxy = Proxy::new();
+
+    let output = proxy.curl(&["-sS", "http://origin.test/"]);
+
+    assert_eq!(output.status.code(), Some(60));
+    assert!(
+        stderr(&output).contains("The certificate of the proxy localhost:"),
+        "{}",
+        stderr(&output)
+    );
+}
+
+#[test]
+fn insecure_is_for_servers_alone() {
+    let proxy = Proxy::new();
+
+    let output = proxy.curl(&["-sS", "-k", "http://origin.test/"]);
+
+    assert_eq!(output.status.code(), Some(60));
+    assert!(stderr(&output).contains("proxy"), "{}", stderr(&output));
+}
+
+#[test]
+fn proxy_cacert_trusts_the_proxy() {
+    let proxy = Proxy::new();
+    let cacert = proxy.cacert("proxy-cacert");
+
+    let output = proxy.curl(&[
+        "-sS",
+        "--proxy-cacert",
+        cacert.to_str().unwrap(),
+        "http://origin.test/path",
+    ]);
+    let _ = std::fs::remove_file(&cacert);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"from the proxy");
+    assert!(proxy
+        .requests
+        .recv()
+        .unwrap()
+        .starts_with("GET http://origin.test/path HTTP/1.1"));
+}
+
+#[test]
+fn untrusted_server_behind_the_proxy_is_named_as_the_server() {
+    let proxy = Proxy::new();
+
+    let output = proxy.curl(&["-sS", "--proxy-insecure", "https://origin.test/"]);
+
+    assert_eq!(output.status.code(), Some(60));
+    assert!(
+        stderr(&output).contains("The certificate of the server origin.test:443"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(proxy
+        .requests
+        .recv()
+        .unwrap()
+        .starts_with("CONNECT origin.test:443"));
+}
+
+#[test]
+fn server_behind_the_proxy_is_checked_apart() {
+    let proxy = Proxy::new();
+
+    let output = proxy.curl(&["-sS", "--proxy-insecure", "-k", "https://origin.test/"]);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"from the server");
+}
+
+#[test]
+fn proxy_headers_go_to_the_proxy_alone() {
+    let proxy = Proxy::new();
+
+    let output = proxy.curl(&[
+        "-sS",
+        "--proxy-insecure",
+        "-k",
+        "--proxy-header",
+        "X-For-Proxy: yes",
+        "-H",
+        "X-For-Server: yes",
+        "https://origin.test/",
+    ]);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    let connect = proxy.requests.recv().unwrap().to_ascii_lowercase();
+    assert!(connect.starts_with("connect origin.test:443"), "{connect}");
+    assert!(connect.contains("x-for-proxy: yes\r\n"), "{connect}");
+    assert!(!connect.contains("x-for-server"), "{connect}");
+    let tunneled = proxy.requests.recv().unwrap().to_ascii_lowercase();
+    assert!(tunneled.contains("x-for-server: yes\r\n"), "{tunneled}");
+    assert!(!tunneled.contains("x-for-proxy"), "{tunneled}");
+}
@@ -0,0 +1,29 @@
+mod common;
+
+use common::curl;
+
+// Connecting anywhere would fail with a different code, the JSON is checked before
+const URL: &str = "http://127.0.0.1:9/";
+
+#[test]
+fn invalid_json_exits_cleanly() {
+    for json in ["{\"a\":1,}", "[1, 2", "", "{'a': 1}", "nul"] {
+        let output = curl(&["--json", json, URL]);
+        let stderr = String::from_utf8_lossy(&output.stderr);
+
+        assert_eq!(output.status.code(), Some(2), "{json}: {stderr}");
+        assert!(
+            stderr.starts_with("Error: Invalid JSON at line 1"),
+            "{json}: {stderr}"
+        );
+        assert!(!stderr.contains("panicked"), "{json}: {stderr}");
+        assert!(output.stdout.is_empty(), "{json}");
+    }
+}
+
+#[test]
+fn json_no_validate_sends_it_anyway() {
+    let output = curl(&["--json-no-validate", "--json", "{", URL]);
+
+    assert_eq!(output.status.code(), Some(7));
+}
@@ -0,0 +1,96 @@
+mod common;
+
+use common::{curl, response, Server};
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::TcpListener,
+    sync::mpsc,
+    thread,
+};
+
+// The port the server listens on
+fn port(server: &Server) -> u16 {
+    server
+        .url
+        .trim_end_matches('/')
+        .rsplit(':')
+        .next()
+        .unwrap()
+        .parse()
+        .unwrap()
+}
+
+#[test]
+fn interface_address_keeps_the_resolved_name() {
+    let server = Server::new(vec![response("200 OK", &[], b"body")]);
+    let port = port(&server);
+
+    let output = curl(&[
+        "-s",
+        "--interface",
+        "127.0.0.1",
+        "--resolve",
+        &format!("foo.test:{port}:127.0.0.1"),
+        &format!("http://foo.test:{port}/"),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"body");
+    assert!(server
+        .request()
+        .contains(&format!("host: foo.test:{port}\r\n")));
+}
+
+#[test]
+fn local_port_is_one_of_the_range() {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/upload", listener.local_addr().unwrap());
+    let (sender, received) = mpsc::channel();
+    thread::spawn(move || {
+        let (stream, peer) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        while reader.read_line(&mut head).is_ok_and(|read| read > 2) {}
+        let mut body = [0; 4];
+        reader.read_exact(&mut body).unwrap();
+        let _ = sender.send((peer.port(), body));
+        let _ = reader
+            .get_mut()
+            .write_all(&response("200 OK", &[], b"done"));
+    });
+
+    let output = curl(&[
+        "-sv",
+        "--local-port",
+        "47810-47830",
+        "-X",
+        "POST",
+        "-d",
+        "data",
+        &url,
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"done");
+    let (port, body) = received.recv().unwrap();
+    assert!((47810..=47830).contains(&port), "{port}");
+    assert_eq!(&body, b"data");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains(&format!("from 127.0.0.1 port {port}")),
+        "{stderr}"
+    );
+}
+
+#[test]
+fn taken_local_port_fails_to_bind() {
+    let taken = TcpListener::bind("0.0.0.0:0").unwrap();
+    let port = taken.local_addr().unwrap().port().to_string();
+    let server = Server::new(Vec::new());
+
+    let output = curl(&["-sS", "--local-port", &port, &server.url]);
+
+    assert_eq!(output.status.code(), Some(45));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("No free local port"), "{stderr}");
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use common::{curl, response, Server};
+
+#[test]
+fn chosen_method_is_in_the_request_line() {
+    for method in ["PUT", "DELETE", "PATCH", "HEAD", "OPTIONS"] {
+        let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+        let output = curl(&["-s", "-X", method, &format!("{}path?q=1", server.url)]);
+
+        assert_eq!(output.status.code(), Some(0), "{method}");
+        let head = server.request();
+        assert!(
+            head.starts_with(&format!(
+                "{} /path?q=1 http/1.1\r\n",
+                method.to_ascii_lowercase()
+            )),
+            "{head}"
+        );
+    }
+}
+
+#[test]
+fn head_option_sends_a_head_request() {
+    let server = Server::new(vec![response("200 OK", &["X-Found: yes"], b"")]);
+
+    let output = curl(&["-s", "-I", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(server.request().starts_with("head / http/1.1\r\n"));
+    let stdout = String::from_utf8_lossy(&output.stdout).to_ascii_lowercase();
+    assert!(stdout.contains("x-found: yes\r\n"), "{stdout}");
+}
+
+#[test]
+fn get_is_the_default() {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl(&["-s", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(server.request().starts_with("get / http/1.1\r\n"));
+}
@@ -0,0 +1,148 @@
+mod common;
+
+use common::{curl, response, Server};
+
+#[test]
+fn piped_stdout_is_byte_identical() {
+    let bodies: [&[u8]; 3] = [
+        b"\xff\xfeh\0i\0",
+        b"\xef\xbb\xbfcaf\xe9 without a newline",
+        b"\0\x01\x02\x89PNG\r\n\x1a\n",
+    ];
+    for body in bodies {
+        for verbose in [false, true] {
+            let content_type = "Content-Type: text/plain; charset=iso-8859-1";
+            let server = Server::new(vec![response("200 OK", &[content_type], body)]);
+            let output = match verbose {
+                true => curl(&["-v", &server.url]),
+                false => curl(&[&server.url]),
+            };
+            let stderr = String::from_utf8_lossy(&output.stderr);
+
+            assert!(output.status.success(), "{stderr}");
+            assert_eq!(output.stdout, body, "{stderr}");
+            assert_eq!(stderr.contains("< HTTP/1.1 200 OK"), verbose, "{stderr}");
+        }
+    }
+}
+
+#[test]
+fn json_output_reformats_json() {
+    let json_type = "Content-Type: application/json";
+    let server = Server::new(vec![response("200 OK", &[json_type], b"{\"a\": [1,  2]}")]);
+    let output = curl(&["--json-output", "compact", &server.url]);
+
+    assert_eq!(output.stdout, b"{\"a\":[1,2]}\n");
+}
+
+#[test]
+fn invalid_json_is_passed_through() {
+    let json_type = "Content-Type: application/json; charset=iso-8859-1";
+    let body = b"{\"caf\xe9\": }";
+    for format in ["pretty", "compact"] {
+        let server = Server::new(vec![response("200 OK", &[json_type], body)]);
+        let output = curl(&["--json-output", format, &server.url]);
+
+        assert!(output.status.success());
+        assert_eq!(output.stdout, body, "{format}");
+    }
+}
+
+// A directory of its own in the temporary directory, made empty
+fn temp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+#[test]
+fn remote_name_leaves_out_the_query() {
+    let server = Server::new(vec![response("200 OK", &[], b"saved")]);
+    let dir = temp_dir("remote-query");
+
+    let output = curl(&[
+        "-s",
+        "--output-dir",
+        dir.to_str().unwrap(),
+        "-O",
+        &format!("{}dir/f.txt?x=1&y=/2", server.url),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(server.request().starts_with("get /dir/f.txt?x=1&y=/2 "));
+    let saved: Vec<_> = std::fs::read_dir(&dir)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .collect();
+    assert_eq!(saved, ["f.txt"]);
+    assert_eq!(std::fs::read(dir.join("f.txt")).unwrap(), b"saved");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn remote_name_is_percent_decoded() {
+    let server = Server::new(vec![response("200 OK", &[], b"saved")]);
+    let dir = temp_dir("remote-decoded");
+
+    let output = curl(&[
+        "-s",
+        "--output-dir",
+        dir.to_str().unwrap(),
+        "-O",
+        &format!("{}my%20file.txt", server.url),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::fs::read(dir.join("my file.txt")).unwrap(), b"saved");
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn remote_name_of_a_directory_is_refused() {
+    let server = Server::new(vec![response("200 OK", &[], b"saved")]);
+    let dir = temp_dir("remote-slash");
+
+    let output = curl(&[
+        "-sS",
+        "--output-dir",
+        dir.to_str().unwrap(),
+        "-O",
+        &format!("{}dir/", server.url),
+    ]);
+
+    assert_eq!(output.status.code(), Some(23));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("Unable to derive a file name from"),
+        "{stderr}"
+    );
+    assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+    let _ = std::fs::remove_dir_all(dir);
+}
+
+#[test]
+fn include_shows_the_parsed_head() {
+    let server = Server::new(vec![response(
+        "200 Fine",
+        &[
+            "X-Mixed-Case: 1",
+            "Set-Cookie: a=1",
+            "X-Other: 2",
+            "Set-Cookie: b=2",
+        ],
+        b"body",
+    )]);
+
+    let output = curl(&["-s", "-i", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    assert!(stdout.starts_with("HTTP/1.1 200 OK\r\n"), "{stdout}");
+    assert!(stdout.contains("x-mixed-case: 1\r\n"), "{stdout}");
+    assert!(
+        stdout.contains("set-cookie: a=1\r\nset-cookie: b=2\r\n"),
+        "{stdout}"
+    );
+    assert!(stdout.ends_with("\r\n\r\nbody"), "{stdout}");
+}
@@ -0,0 +1,108 @@
+mod common;
+
+use common::{curl, response, Server};
+use std::{net::TcpListener, path::PathBuf};
+
+// A file of its own in the temporary directory, for tests running side by side
+fn temp_file(name: &str) -> PathBuf {
+    let path = std::env::temp_dir().join(format!("curl-{}-{name}", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path
+}
+
+// A URL nothing listens on
+fn refused_url() -> String {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    format!("http://{}/", listener.local_addr().unwrap())
+}
+
+#[test]
+fn every_transfer_is_saved() {
+    let first = Server::new(vec![response("200 OK", &[], b"first")]);
+    let second = Server::new(vec![response("200 OK", &[], b"second")]);
+    let (one, two) = (temp_file("parallel-one"), temp_file("parallel-two"));
+
+    let output = curl(&[
+        "-sZ",
+        &first.url,
+        "-o",
+        one.to_str().unwrap(),
+        &second.url,
+        "-o",
+        two.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(std::fs::read(&one).unwrap(), b"first");
+    assert_eq!(std::fs::read(&two).unwrap(), b"second");
+    let _ = std::fs::remove_file(one);
+    let _ = std::fs::remove_file(two);
+}
+
+#[test]
+fn exit_code_is_the_first_failed_urls() {
+    let ok = Server::new(vec![response("200 OK", &[], b"ok")]);
+    let missing = Server::new(vec![response("404 Not Found", &[], b"")]);
+    let refused = refused_url();
+    let files = [
+        temp_file("exit-ok"),
+        temp_file("exit-missing"),
+        temp_file("exit-refused"),
+    ];
+
+    let output = curl(&[
+        "-sfZ",
+        &ok.url,
+        "-o",
+        files[0].to_str().unwrap(),
+        &missing.url,
+        "-o",
+        files[1].to_str().unwrap(),
+        &refused,
+        "-o",
+        files[2].to_str().unwrap(),
+    ]);
+
+    // The 404 comes before the refused connection, whichever failed first
+    assert_eq!(output.status.code(), Some(22));
+    assert_eq!(std::fs::read(&files[0]).unwrap(), b"ok");
+    for file in files {
+        let _ = std::fs::remove_file(file);
+    }
+}
+
+#[test]
+fn fail_early_starts_no_more_transfers() {
+    let missing = Server::new(vec![response("404 Not Found", &[], b"")]);
+    let after = Server::new(vec![response("200 OK", &[], b"after")]);
+    let (one, two) = (temp_file("early-one"), temp_file("early-two"));
+
+    let output = curl(&[
+        "-sfZ",
+        "--parallel-max",
+        "1",
+        "--fail-early",
+        &missing.url,
+        "-o",
+        one.to_str().unwrap(),
+        &after.url,
+        "-o",
+        two.to_str().unwrap(),
+    ]);
+
+    assert_eq!(output.status.code(), Some(22));
+    assert!(!two.exists());
+    let _ = std::fs::remove_file(one);
+}
+
+#[test]
+fn two_urls_to_stdout_are_refused() {
+    let first = Server::new(Vec::new());
+    let second = Server::new(Vec::new());
+
+    let output = curl(&["-sSZ", &first.url, &second.url]);
+
+    assert_eq!(output.status.code(), Some(2));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("at most one URL"), "{stderr}");
+}
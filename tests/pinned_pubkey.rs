@@ -0,0 +1,95 @@
+mod common;
+
+use common::{
+    curl,
+    tls::{certificate, pem_file, TlsServer},
+};
+use openssl::{base64, sha::sha256, x509::X509};
+use std::process::Output;
+
+// The --pinnedpubkey hash of the public key of `cert`
+fn pin(cert: &X509) -> String {
+    let key = cert.public_key().unwrap().public_key_to_der().unwrap();
+    format!("sha256//{}", base64::encode_block(&sha256(&key)))
+}
+
+// Runs curl trusting the server, with `--pinnedpubkey pinned`
+fn pinned(server: &TlsServer, name: &str, pinned: &str) -> Output {
+    let cacert = server.cacert(name);
+    let output = curl(&[
+        "-sS",
+        "--cacert",
+        cacert.to_str().unwrap(),
+        "--pinnedpubkey",
+        pinned,
+        &server.url,
+    ]);
+    let _ = std::fs::remove_file(cacert);
+    output
+}
+
+fn stderr(output: &Output) -> String {
+    String::from_utf8_lossy(&output.stderr).into_owned()
+}
+
+#[test]
+fn matching_hash_is_let_through() {
+    let server = TlsServer::new();
+    let (other, _) = certificate("other");
+    let pins = format!("{};{}", pin(&other), pin(&server.cert));
+
+    let output = pinned(&server, "pin-match", &pins);
+
+    assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+    assert_eq!(output.stdout, b"secret");
+}
+
+#[test]
+fn public_key_file_is_let_through() {
+    for pem in [true, false] {
+        let server = TlsServer::new();
+        let key = server.cert.public_key().unwrap();
+        let contents = match pem {
+            true => key.public_key_to_pem().unwrap(),
+            false => key.public_key_to_der().unwrap(),
+        };
+        let file = pem_file("pin-file", &contents);
+
+        let output = pinned(&server, "pin-file-cacert", file.to_str().unwrap());
+        let _ = std::fs::remove_file(file);
+
+        assert_eq!(output.status.code(), Some(0), "{}", stderr(&output));
+        assert_eq!(output.stdout, b"secret");
+    }
+}
+
+#[test]
+fn other_key_is_refused_before_the_request() {
+    let server = TlsServer::new();
+    let (other, _) = certificate("localhost");
+
+    let output = pinned(&server, "pin-mismatch", &pin(&other));
+
+    assert_eq!(output.status.code(), Some(90));
+    assert!(
+        stderr(&output).contains("does not match --pinnedpubkey"),
+        "{}",
+        stderr(&output)
+    );
+    assert!(output.stdout.is_empty());
+    assert!(server.request().is_none());
+}
+
+#[test]
+fn invalid_pin_is_an_error() {
+    let server = TlsServer::new();
+
+    let output = pinned(&server, "pin-invalid", "sha256//not-a-hash");
+
+    assert_eq!(output.status.code(), Some(90));
+    assert!(
+        stderr(&output).contains("Unable to use --pinnedpubkey"),
+        "{}",
+        stderr(&output)
+    );
+}
@@ -0,0 +1,57 @@
+mod common;
+
+use common::{curl, response, Server};
+
+const CREDENTIALS: &str = "authorization: basic dxnlcjpwdw==";
+
+// The heads of the requests to the first and second server for a redirect from one to the other
+fn redirected(args: &[&str]) -> (String, String) {
+    let to = Server::new(vec![response("200 OK", &[], b"done")]);
+    let location = format!("Location: {}next", to.url);
+    let from = Server::new(vec![response("302 Found", &[&location], b"")]);
+
+    let mut args = args.to_vec();
+    args.extend(["-L", "-u", "user:pw", "-H", "Cookie: a=b", &from.url]);
+    let output = curl(&args);
+    assert_eq!(
+        output.stdout,
+        b"done",
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    (from.request(), to.request())
+}
+
+#[test]
+fn credentials_stay_on_the_original_host() {
+    let (first, second) = redirected(&[]);
+    assert!(first.contains(CREDENTIALS), "{first}");
+    assert!(first.contains("cookie: a=b"), "{first}");
+
+    // Another port is another host
+    assert!(!second.contains("authorization:"), "{second}");
+    assert!(!second.contains("cookie:"), "{second}");
+}
+
+#[test]
+fn location_trusted_sends_them_on() {
+    let (_, second) = redirected(&["--location-trusted"]);
+    assert!(second.contains(CREDENTIALS), "{second}");
+    assert!(second.contains("cookie: a=b"), "{second}");
+}
+
+#[test]
+fn same_host_keeps_credentials() {
+    let server = Server::new(vec![
+        response("301 Moved Permanently", &["Location: /next"], b""),
+        response("200 OK", &[], b"done"),
+    ]);
+    let output = curl(&["-L", "-u", "user:pw", &server.url]);
+
+    assert_eq!(output.stdout, b"done");
+    server.request();
+    let second = server.request();
+    assert!(second.starts_with("get /next "), "{second}");
+    assert!(second.contains(CREDENTIALS), "{second}");
+}
@@ -0,0 +1,25 @@
+mod common;
+
+use common::{curl, response, Server};
+use std::time::{Duration, SystemTime};
+
+#[test]
+fn remote_time_sets_the_file_time() {
+    let last_modified = "Last-Modified: Wed, 21 Oct 2015 07:28:00 GMT";
+    let server = Server::new(vec![response("200 OK", &[last_modified], b"body")]);
+    let path = std::env::temp_dir().join(format!("curl-{}-remote-time", std::process::id()));
+
+    let output = curl(&["-R", "-o", path.to_str().unwrap(), &server.url]);
+    assert!(
+        output.status.success(),
+        "{}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+
+    let modified = std::fs::metadata(&path).unwrap().modified().unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(
+        modified,
+        SystemTime::UNIX_EPOCH + Duration::from_secs(1445412480)
+    );
+}
@@ -0,0 +1,168 @@
+mod common;
+
+use common::response;
+use std::{
+    io::{BufRead, BufReader, Read, Write},
+    net::{TcpListener, TcpStream},
+    sync::mpsc::{self, Receiver},
+    thread,
+};
+
+/// What a SOCKS5 client asked the proxy for.
+#[derive(Debug, PartialEq)]
+enum Target {
+    Name(String, u16),
+    Ip([u8; 4], u16),
+}
+
+/// A SOCKS5 proxy for one connection that answers the request sent through
+/// it itself, keeping the target and credentials it was given. It replies
+/// to the connect request with `reply`, where 0 is success.
+struct Proxy {
+    url: String,
+    targets: Receiver<(Target, Option<(String, String)>)>,
+}
+
+impl Proxy {
+    fn new(scheme: &str, reply: u8) -> Proxy {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("{scheme}://{}", listener.local_addr().unwrap());
+        let (sender, targets) = mpsc::channel();
+        thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let login = authenticate(&mut stream);
+
+            let mut request = [0; 4];
+            stream.read_exact(&mut request).unwrap();
+            let target = match request[3] {
+                1 => {
+                    let mut ip = [0; 4];
+                    stream.read_exact(&mut ip).unwrap();
+                    Target::Ip(ip, port(&mut stream))
+                }
+                3 => {
+                    let mut len = [0];
+                    stream.read_exact(&mut len).unwrap();
+                    let mut name = vec![0; usize::from(len[0])];
+                    stream.read_exact(&mut name).unwrap();
+                    Target::Name(String::from_utf8(name).unwrap(), port(&mut stream))
+                }
+                atyp => panic!("address type {atyp}"),
+            };
+            let _ = sender.send((target, login));
+            stream
+                .write_all(&[5, reply, 0, 1, 127, 0, 0, 1, 0, 80])
+                .unwrap();
+            if reply != 0 {
+                return;
+            }
+
+            let mut reader = BufReader::new(stream);
+            let mut head = String::new();
+            while reader.read_line(&mut head).is_ok_and(|read| read > 2) {}
+            let _ = reader
+                .get_mut()
+                .write_all(&response("200 OK", &[], b"proxied"));
+        });
+
+        Proxy { url, targets }
+    }
+}
+
+// Picks no authentication, or the user and password offered, RFC 1929
+fn authenticate(stream: &mut TcpStream) -> Option<(String, String)> {
+    let mut greeting = [0; 2];
+    stream.read_exact(&mut greeting).unwrap();
+    let mut methods = vec![0; usize::from(greeting[1])];
+    stream.read_exact(&mut methods).unwrap();
+    if !methods.contains(&2) {
+        stream.write_all(&[5, 0]).unwrap();
+        return None;
+    }
+
+    stream.write_all(&[5, 2]).unwrap();
+    let mut version = [0];
+    stream.read_exact(&mut version).unwrap();
+    let mut field = || {
+        let mut len = [0];
+        stream.read_exact(&mut len).unwrap();
+        let mut value = vec![0; usize::from(len[0])];
+        stream.read_exact(&mut value).unwrap();
+        String::from_utf8(value).unwrap()
+    };
+    let user = field();
+    let password = field();
+    stream.write_all(&[1, 0]).unwrap();
+    Some((user, password))
+}
+
+fn port(stream: &mut TcpStream) -> u16 {
+    let mut port = [0; 2];
+    stream.read_exact(&mut port).unwrap();
+    u16::from_be_bytes(port)
+}
+
+fn curl(proxy: &Proxy, args: &[&str]) -> std::process::Output {
+    // --noproxy "" undoes the one that keeps proxies from the environment out
+    let args = [&["--noproxy", "", "-x", &proxy.url], args].concat();
+    common::curl(&args)
+}
+
+#[test]
+fn socks5h_leaves_the_name_to_the_proxy() {
+    let proxy = Proxy::new("socks5h", 0);
+
+    // The name resolves nowhere, only the proxy sees it
+    let output = curl(&proxy, &["-s", "http://nowhere.test:8080/"]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert_eq!(output.stdout, b"proxied");
+    let (target, login) = proxy.targets.recv().unwrap();
+    assert_eq!(target, Target::Name("nowhere.test".to_string(), 8080));
+    assert_eq!(login, None);
+}
+
+#[test]
+fn socks5_resolves_the_name_itself() {
+    let proxy = Proxy::new("socks5", 0);
+
+    let output = curl(
+        &proxy,
+        &[
+            "-s",
+            "--resolve",
+            "nowhere.test:80:127.0.0.7",
+            "http://nowhere.test/",
+        ],
+    );
+
+    assert_eq!(output.status.code(), Some(0));
+    let (target, _) = proxy.targets.recv().unwrap();
+    assert_eq!(target, Target::Ip([127, 0, 0, 7], 80));
+}
+
+#[test]
+fn proxy_user_logs_in_to_the_proxy() {
+    let proxy = Proxy::new("socks5h", 0);
+
+    let output = curl(
+        &proxy,
+        &["-s", "--proxy-user", "me:p@ss", "http://nowhere.test/"],
+    );
+
+    assert_eq!(output.status.code(), Some(0));
+    let (_, login) = proxy.targets.recv().unwrap();
+    assert_eq!(login, Some(("me".to_string(), "p@ss".to_string())));
+}
+
+#[test]
+fn refused_request_is_a_proxy_error() {
+    // Connection refused by the destination host
+    let proxy = Proxy::new("socks5h", 5);
+
+    let output = curl(&proxy, &["-sS", "http://nowhere.test/"]);
+
+    assert_eq!(output.status.code(), Some(97));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("SOCKS proxy"), "{stderr}");
+}
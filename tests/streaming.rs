@@ -0,0 +1,88 @@
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::TcpListener,
+    process::{Command, Stdio},
+    sync::mpsc,
+    thread,
+};
+
+const MB: usize = 1 << 20;
+
+// The byte at `at` of the body, which repeats every 251 bytes so a chunk out of place shows
+fn byte(at: usize) -> u8 {
+    (at % 251) as u8
+}
+
+#[test]
+fn big_body_is_saved_as_it_arrives() {
+    let (total, half) = (96 * MB, 64 * MB);
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("http://{}/big", listener.local_addr().unwrap());
+    let (halfway, went_on) = (mpsc::channel(), mpsc::channel::<()>());
+    thread::spawn(move || {
+        let (stream, _) = listener.accept().unwrap();
+        let mut reader = BufReader::new(stream);
+        let mut head = String::new();
+        while reader.read_line(&mut head).is_ok_and(|read| read > 2) {}
+        let stream = reader.get_mut();
+        write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {total}\r\n\r\n").unwrap();
+        let chunk: Vec<u8> = (0..MB).map(byte).collect();
+        for sent in 0..total / MB {
+            if sent * MB == half {
+                halfway.0.send(()).unwrap();
+                went_on.1.recv().unwrap();
+            }
+            stream.write_all(&chunk).unwrap();
+        }
+    });
+    let path = std::env::temp_dir().join(format!("curl-{}-big", std::process::id()));
+
+    let mut child = Command::new(env!("CARGO_BIN_EXE_curl"))
+        .args([
+            "-q",
+            "--noproxy",
+            "*",
+            "-s",
+            "-o",
+            path.to_str().unwrap(),
+            &url,
+        ])
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    halfway.1.recv().unwrap();
+    let resident = resident_memory(child.id());
+    went_on.0.send(()).unwrap();
+    let status = child.wait().unwrap();
+
+    assert_eq!(status.code(), Some(0));
+    // Holding what came would take more than half of it on top of what curl needs to start
+    if let Some(resident) = resident {
+        assert!(
+            resident < half / 2,
+            "{} MB resident with {} MB received",
+            resident / MB,
+            half / MB
+        );
+    }
+    let saved = std::fs::read(&path).unwrap();
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(saved.len(), total);
+    assert!(saved
+        .chunks(MB)
+        .all(|chunk| chunk.iter().enumerate().all(|(at, b)| *b == byte(at))));
+}
+
+// How much memory the process `pid` holds, where /proc tells
+fn resident_memory(pid: u32) -> Option<usize> {
+    let status = std::fs::read_to_string(format!("/proc/{pid}/status")).ok()?;
+    let kb: usize = status
+        .lines()
+        .find_map(|line| line.strip_prefix("VmRSS:"))?
+        .trim()
+        .strip_suffix("kB")?
+        .trim()
+        .parse()
+        .ok()?;
+    Some(kb * 1024)
+}
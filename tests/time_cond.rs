@@ -0,0 +1,74 @@
+mod common;
+
+use common::{curl, response, Server};
+use std::time::{Duration, UNIX_EPOCH};
+
+const SUNDAY: &str = "sun, 06 nov 1994 08:49:37 gmt";
+
+// The conditional header the server got for -z `cond`
+fn condition(cond: &str) -> String {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl(&["-s", "-z", cond, &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    server
+        .request()
+        .lines()
+        .find(|line| line.starts_with("if-"))
+        .unwrap_or_default()
+        .to_string()
+}
+
+#[test]
+fn every_http_date_format_is_sent_as_an_imf_fixdate() {
+    for date in [
+        "Sun, 06 Nov 1994 08:49:37 GMT",
+        "Sunday, 06-Nov-94 08:49:37 GMT",
+        "Sun Nov  6 08:49:37 1994",
+    ] {
+        assert_eq!(
+            condition(date),
+            format!("if-modified-since: {SUNDAY}"),
+            "{date}"
+        );
+    }
+}
+
+#[test]
+fn dash_asks_for_an_unmodified_resource() {
+    assert_eq!(
+        condition("-Sun, 06 Nov 1994 08:49:37 GMT"),
+        format!("if-unmodified-since: {SUNDAY}")
+    );
+}
+
+#[test]
+fn file_gives_its_modification_time() {
+    let path = std::env::temp_dir().join(format!("curl-{}-time-cond", std::process::id()));
+    let file = std::fs::File::create(&path).unwrap();
+    file.set_modified(UNIX_EPOCH + Duration::from_secs(784_111_777))
+        .unwrap();
+
+    let newer = condition(path.to_str().unwrap());
+    let older = condition(&format!("-{}", path.display()));
+    let _ = std::fs::remove_file(&path);
+
+    assert_eq!(newer, format!("if-modified-since: {SUNDAY}"));
+    assert_eq!(older, format!("if-unmodified-since: {SUNDAY}"));
+}
+
+#[test]
+fn neither_file_nor_date_sends_no_condition() {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl(&["-z", "/nonexistent/not a date", &server.url]);
+
+    assert_eq!(output.status.code(), Some(0));
+    assert!(!server.request().contains("if-"));
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(
+        stderr.contains("is neither a file nor a valid date"),
+        "{stderr}"
+    );
+}
@@ -0,0 +1,44 @@
+mod common;
+
+use common::{curl, response, Server};
+
+// The User-Agent headers the server got for a request with `args`
+fn user_agents(args: &[&str]) -> Vec<String> {
+    let server = Server::new(vec![response("200 OK", &[], b"")]);
+
+    let output = curl(&[args, &["-s", &server.url]].concat());
+
+    assert_eq!(output.status.code(), Some(0));
+    server
+        .request()
+        .lines()
+        .filter_map(|line| line.strip_prefix("user-agent: "))
+        .map(str::to_string)
+        .collect()
+}
+
+#[test]
+fn header_wins_over_the_agent_option() {
+    assert_eq!(
+        user_agents(&["-A", "from-option", "-H", "User-Agent: from-header"]),
+        ["from-header"]
+    );
+    assert_eq!(
+        user_agents(&["-H", "User-Agent: from-header", "-A", "from-option"]),
+        ["from-header"]
+    );
+}
+
+#[test]
+fn agent_option_replaces_the_default() {
+    assert_eq!(user_agents(&["-A", "Agent/1.0"]), ["agent/1.0"]);
+    assert_eq!(
+        user_agents(&[]),
+        [concat!("rust-curl/", env!("CARGO_PKG_VERSION"))]
+    );
+}
+
+#[test]
+fn empty_agent_sends_none() {
+    assert!(user_agents(&["-A", ""]).is_empty());
+}